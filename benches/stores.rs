@@ -0,0 +1,336 @@
+// benches/stores.rs
+
+// compares the state backends on the same workloads:
+//   - `dashmap`: RateLimiter's built-in in-memory state
+//   - `memory_store`: StoreLimiter over MemoryStore, i.e. through the StateStore trait
+//   - `direct`: the keyless DirectLimiter, a single atomic TAT with no map
+//   - `sled`: the embedded store's flush cost (run with `--features sled`)
+//   - `redis`: StoreLimiter over RedisStore, one round trip per check (run
+//     with `--features redis`); against GCRA_REDIS_URL if set, otherwise a
+//     throwaway `redis:7-alpine` container started with the docker CLI
+//   - `bypass`: allow-all mode and access-list allows, which should cost a few
+//     nanoseconds since health checks can dominate request volume
+// each is measured on a single hot key, on a keyed workload cycling through many
+// keys, and with several threads contending for the same limiter
+// there is no shared-memory (cross-process) backend in this crate to bench;
+// the `contended` group measures the in-process map shared between threads
+
+// dependencies
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use gcra_rate_limiter::{AccessList, MemoryStore, RateLimiter, StoreLimiter, SystemClock};
+use std::hint::black_box;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// enough keys to spill out of the CPU caches
+const KEYS: u64 = 10_000;
+const THREADS: u64 = 4;
+
+// a quota generous enough that the benches measure bookkeeping, not denials
+fn dashmap_limiter() -> RateLimiter<u64> {
+    RateLimiter::with_system_clock(1_000_000.0, 1_000.0).unwrap()
+}
+
+fn memory_store_limiter() -> StoreLimiter<MemoryStore<u64>> {
+    StoreLimiter::new(1_000_000.0, 1_000.0, MemoryStore::new(), SystemClock).unwrap()
+}
+
+fn single_key(c: &mut Criterion) {
+    let mut group = c.benchmark_group("single_key");
+    group.throughput(Throughput::Elements(1));
+
+    let limiter = dashmap_limiter();
+    group.bench_function("dashmap", |b| {
+        b.iter(|| limiter.is_allowed(black_box(7)).unwrap())
+    });
+
+    let limiter = memory_store_limiter();
+    group.bench_function("memory_store", |b| {
+        b.iter(|| limiter.is_allowed(black_box(&7)).unwrap())
+    });
+
+    // a keep-alive connection checking its key through a handle
+    let limiter = RateLimiter::<String>::with_system_clock(1_000_000.0, 1_000.0).unwrap();
+    let key = String::from("203.0.113.7:api-key-0123456789");
+    group.bench_function("string_key", |b| {
+        b.iter(|| limiter.is_allowed(black_box(key.clone())).unwrap())
+    });
+    group.bench_function("string_key_ref", |b| {
+        b.iter(|| limiter.is_allowed_ref(black_box(key.as_str())).unwrap())
+    });
+    let handle = limiter.handle(key.clone());
+    group.bench_function("string_key_handle", |b| {
+        b.iter(|| black_box(&handle).is_allowed())
+    });
+
+    let direct = RateLimiter::direct(1_000_000.0, 1_000.0).unwrap();
+    group.bench_function("direct", |b| b.iter(|| black_box(&direct).is_allowed()));
+
+    group.finish();
+}
+
+fn keyed(c: &mut Criterion) {
+    let mut group = c.benchmark_group("keyed");
+    group.throughput(Throughput::Elements(1));
+
+    let limiter = dashmap_limiter();
+    let mut key = 0;
+    group.bench_function(BenchmarkId::new("dashmap", KEYS), |b| {
+        b.iter(|| {
+            key = (key + 1) % KEYS;
+            limiter.is_allowed(black_box(key)).unwrap()
+        })
+    });
+
+    let limiter = memory_store_limiter();
+    let mut key = 0;
+    group.bench_function(BenchmarkId::new("memory_store", KEYS), |b| {
+        b.iter(|| {
+            key = (key + 1) % KEYS;
+            limiter.is_allowed(black_box(&key)).unwrap()
+        })
+    });
+
+    let limiter = memory_store_limiter();
+    let batch: Vec<u64> = (0..64).collect();
+    group.throughput(Throughput::Elements(batch.len() as u64));
+    group.bench_function("memory_store_batch_64", |b| {
+        b.iter(|| limiter.is_allowed_many(black_box(&batch)).unwrap())
+    });
+
+    group.finish();
+}
+
+// run `iters` checks split across THREADS threads and return the wall time
+fn contended<F>(iters: u64, check: Arc<F>) -> Duration
+where
+    F: Fn(u64) + Send + Sync + 'static,
+{
+    let per_thread = iters / THREADS + 1;
+    let start = Instant::now();
+    let handles: Vec<_> = (0..THREADS)
+        .map(|thread| {
+            let check = Arc::clone(&check);
+            thread::spawn(move || {
+                for i in 0..per_thread {
+                    check((thread * per_thread + i) % KEYS);
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    start.elapsed()
+}
+
+fn throughput(c: &mut Criterion) {
+    let mut group = c.benchmark_group("contended");
+    group.throughput(Throughput::Elements(1));
+
+    let limiter = Arc::new(dashmap_limiter());
+    group.bench_function(BenchmarkId::new("dashmap", THREADS), |b| {
+        let limiter = Arc::clone(&limiter);
+        b.iter_custom(|iters| {
+            let limiter = Arc::clone(&limiter);
+            contended(
+                iters,
+                Arc::new(move |key| {
+                    black_box(limiter.is_allowed(key).unwrap());
+                }),
+            )
+        })
+    });
+
+    let limiter = Arc::new(memory_store_limiter());
+    group.bench_function(BenchmarkId::new("memory_store", THREADS), |b| {
+        let limiter = Arc::clone(&limiter);
+        b.iter_custom(|iters| {
+            let limiter = Arc::clone(&limiter);
+            contended(
+                iters,
+                Arc::new(move |key| {
+                    black_box(limiter.is_allowed(&key).unwrap());
+                }),
+            )
+        })
+    });
+
+    group.finish();
+}
+
+// bypassed checks perform no state operations, so they should stay within a
+// few nanoseconds no matter how many keys the limiter holds
+fn bypass(c: &mut Criterion) {
+    let mut group = c.benchmark_group("bypass");
+    group.throughput(Throughput::Elements(1));
+
+    let limiter = dashmap_limiter();
+    for key in 0..KEYS {
+        limiter.is_allowed(key).unwrap();
+    }
+    limiter.set_allow_all(true);
+    let mut key = 0;
+    group.bench_function("allow_all", |b| {
+        b.iter(|| {
+            key = (key + 1) % KEYS;
+            limiter.is_allowed(black_box(key)).unwrap()
+        })
+    });
+
+    let access_list = AccessList::parse("allow 10.0.0.0/8\ndeny 192.168.0.0/16\n").unwrap();
+    let peer: IpAddr = "10.1.2.3".parse().unwrap();
+    group.bench_function("access_list_allow", |b| {
+        b.iter(|| access_list.check(black_box(peer), black_box("health")))
+    });
+
+    group.finish();
+}
+
+// the embedded store keeps decisions in memory and persists them in batches,
+// so its cost is the flush of every dirty key rather than a per-check overhead
+#[cfg(feature = "sled")]
+fn embedded(c: &mut Criterion) {
+    use gcra_rate_limiter::SledStore;
+
+    let mut group = c.benchmark_group("sled_flush");
+    group.sample_size(20);
+
+    let dir = std::env::temp_dir().join(format!("gcra-bench-sled-{}", std::process::id()));
+    let limiter = dashmap_limiter();
+    let store = SledStore::open(&dir, &limiter).unwrap();
+    for keys in [100, KEYS] {
+        group.throughput(Throughput::Elements(keys));
+        group.bench_function(BenchmarkId::from_parameter(keys), |b| {
+            b.iter(|| {
+                for key in 0..keys {
+                    limiter.is_allowed(key).unwrap();
+                }
+                store.flush().unwrap()
+            })
+        });
+    }
+    group.finish();
+
+    drop(store);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[cfg(not(feature = "sled"))]
+fn embedded(_c: &mut Criterion) {}
+
+// a Redis server for the bench: the one at GCRA_REDIS_URL, or a container
+// removed again when the harness is dropped
+#[cfg(feature = "redis")]
+struct RedisServer {
+    url: String,
+    container: Option<String>,
+}
+
+#[cfg(feature = "redis")]
+impl RedisServer {
+    fn start() -> Option<Self> {
+        use std::process::Command;
+
+        if let Ok(url) = std::env::var("GCRA_REDIS_URL") {
+            return Some(Self {
+                url,
+                container: None,
+            });
+        }
+        let docker = |args: &[&str]| {
+            let output = Command::new("docker").args(args).output().ok()?;
+            output
+                .status
+                .success()
+                .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        };
+        let id = docker(&[
+            "run",
+            "-d",
+            "--rm",
+            "-p",
+            "127.0.0.1::6379",
+            "redis:7-alpine",
+        ])?;
+        // owned from here on, so the container is removed if it never comes up
+        let mut server = Self {
+            url: String::new(),
+            container: Some(id.clone()),
+        };
+        let port = docker(&["port", &id, "6379/tcp"])?;
+        let port = port.lines().next()?.rsplit(':').next()?.to_string();
+        server.url = format!("redis://127.0.0.1:{}/", port);
+
+        // wait for the server to accept connections
+        for _ in 0..50 {
+            if gcra_rate_limiter::RedisStore::<u64>::open(&server.url, "gcra-bench:").is_ok() {
+                return Some(server);
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        None
+    }
+}
+
+#[cfg(feature = "redis")]
+impl Drop for RedisServer {
+    fn drop(&mut self) {
+        if let Some(id) = &self.container {
+            let _ = std::process::Command::new("docker")
+                .args(["rm", "-f", id])
+                .output();
+        }
+    }
+}
+
+// the shared store makes one round trip per check, so it is measured on the
+// same hot-key and keyed workloads, plus a pipelined batch
+#[cfg(feature = "redis")]
+fn shared(c: &mut Criterion) {
+    use gcra_rate_limiter::RedisStore;
+
+    let Some(server) = RedisServer::start() else {
+        eprintln!("skipping the redis benches: set GCRA_REDIS_URL or install docker");
+        return;
+    };
+    let limiter = |prefix: &str| {
+        let store = RedisStore::<u64>::open(&server.url, prefix).unwrap();
+        StoreLimiter::new(1_000_000.0, 1_000.0, store, SystemClock).unwrap()
+    };
+
+    let mut group = c.benchmark_group("redis");
+    group.throughput(Throughput::Elements(1));
+
+    let hot = limiter("gcra-bench-hot:");
+    group.bench_function("single_key", |b| {
+        b.iter(|| hot.is_allowed(black_box(&7)).unwrap())
+    });
+
+    let keyed = limiter("gcra-bench-keyed:");
+    let mut key = 0;
+    group.bench_function(BenchmarkId::new("keyed", KEYS), |b| {
+        b.iter(|| {
+            key = (key + 1) % KEYS;
+            keyed.is_allowed(black_box(&key)).unwrap()
+        })
+    });
+
+    let batch: Vec<u64> = (0..64).collect();
+    group.throughput(Throughput::Elements(batch.len() as u64));
+    group.bench_function("batch_64", |b| {
+        b.iter(|| keyed.is_allowed_many(black_box(&batch)).unwrap())
+    });
+
+    group.finish();
+}
+
+#[cfg(not(feature = "redis"))]
+fn shared(_c: &mut Criterion) {}
+
+criterion_group!(
+    benches, single_key, keyed, throughput, bypass, embedded, shared
+);
+criterion_main!(benches);
@@ -0,0 +1,30 @@
+// fuzz/fuzz_targets/gcra_sequence.rs
+
+// a run of requests against one key, built from a rate, a burst and a
+// sequence of (time offset, cost) pairs
+
+#![no_main]
+
+// dependencies
+use gcra_rate_limiter::gcra_invariants::check_sequence;
+use libfuzzer_sys::arbitrary::{self, Arbitrary};
+use libfuzzer_sys::fuzz_target;
+
+#[derive(Debug, Arbitrary)]
+struct Input {
+    start: u64,
+    increment: u32,
+    burst: u16,
+    requests: Vec<(u32, u8)>,
+}
+
+fuzz_target!(|input: Input| {
+    let increment = u64::from(input.increment);
+    let tolerance = increment * u64::from(input.burst);
+    let mut requests: Vec<(u64, u32)> = input
+        .requests
+        .iter()
+        .map(|(offset, cost)| (u64::from(*offset), u32::from(*cost)))
+        .collect();
+    check_sequence(input.start, increment, tolerance, &mut requests);
+});
@@ -0,0 +1,14 @@
+// fuzz/fuzz_targets/gcra_step.rs
+
+// one conformance test with arbitrary inputs, including values near u64::MAX
+
+#![no_main]
+
+// dependencies
+use gcra_rate_limiter::gcra_invariants::check_step;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: (u64, u64, u64, u64, u32)| {
+    let (now, tat, increment, tolerance, cost) = input;
+    check_step(now, tat, increment, tolerance, cost);
+});
@@ -1,37 +1,86 @@
 // src/bin/main.rs
 
 // dependencies
-use gcra_rate_limiter::{RateLimiter, SystemClock};
+use gcra_rate_limiter::{
+    Access, Canaries, CanaryConfig, ConcurrencyLimiter, DenialCounter, Exemplar, ForwardedFor,
+    HttpRequest, KeyExtractor, PeerIp, Problem, RateLimiter, ReloadableAccessList,
+    ResponseTemplates, RouteConfig, RouteOutcome, RouteTable, SystemClock, Template, TemplateVars,
+    head_len, normalize_ip,
+};
 use std::error::Error;
 use std::hash::Hash;
-use std::io::{Read, Write};
+use std::io::{ErrorKind, Read, Write};
 use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
 use threadpool::ThreadPool;
 
-fn handle_allowed_request(stream: &mut TcpStream, peer: SocketAddr) {
-    // Read the request (same as before)
-    let mut buf = [0u8; 4096];
-    match stream.read(&mut buf) {
-        Ok(0) => {
-            println!("{}: client closed connection immediately", peer);
-            return;
-        }
-        Ok(n) => {
-            if let Ok(req_str) = std::str::from_utf8(&buf[..n]) {
-                println!("{} sent request:\n{}", peer, req_str);
-            }
+// how much the server prints: errors always, connection and decision lines at
+// `info`, and raw request dumps at `debug`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum LogLevel {
+    Error,
+    Info,
+    Debug,
+}
+
+static LOG_LEVEL: OnceLock<LogLevel> = OnceLock::new();
+
+fn log_level() -> LogLevel {
+    *LOG_LEVEL.get().unwrap_or(&LogLevel::Info)
+}
+
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if log_level() >= LogLevel::Info {
+            println!($($arg)*);
         }
-        Err(e) => {
-            eprintln!("{}: read error: {}", peer, e);
-            return;
+    };
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        if log_level() >= LogLevel::Debug {
+            println!($($arg)*);
         }
-    }
+    };
+}
 
+fn handle_allowed_request(
+    stream: &mut TcpStream,
+    peer: SocketAddr,
+    template: &Template,
+    vars: &TemplateVars,
+) {
     // Send normal response
-    let body = "Hello from Rust GCRA rate-limited server!\n";
+    send_templated_response(stream, peer, "200 OK", "", template, vars);
+}
+
+fn handle_rate_limited_request(
+    stream: &mut TcpStream,
+    peer: SocketAddr,
+    template: &Template,
+    vars: &TemplateVars,
+) {
+    info!("{}: Rate limited!", peer);
+
+    let retry_after = format!("Retry-After: {}\r\n", vars.retry_after.unwrap_or(1));
+    send_templated_response(
+        stream,
+        peer,
+        "429 Too Many Requests",
+        &retry_after,
+        template,
+        vars,
+    );
+}
+
+fn handle_forbidden_request(stream: &mut TcpStream, peer: SocketAddr) {
+    let body = "Forbidden\n";
     let response = format!(
-        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nContent-Type: text/plain\r\nConnection: close\r\n\r\n{}",
+        "HTTP/1.1 403 Forbidden\r\nContent-Length: {}\r\nContent-Type: text/plain\r\nConnection: close\r\n\r\n{}",
         body.len(),
         body
     );
@@ -39,24 +88,43 @@ fn handle_allowed_request(stream: &mut TcpStream, peer: SocketAddr) {
     send_response(stream, peer, &response);
 }
 
-fn handle_rate_limited_request(stream: &mut TcpStream, peer: SocketAddr) {
-    println!("{}: Rate limited!", peer);
-
-    let body = "Rate limit exceeded. Please try again later.\n";
+// render a body template and send it with the given status line and headers
+fn send_templated_response(
+    stream: &mut TcpStream,
+    peer: SocketAddr,
+    status: &str,
+    extra_headers: &str,
+    template: &Template,
+    vars: &TemplateVars,
+) {
+    let body = template.render(vars);
     let response = format!(
-        "HTTP/1.1 429 Too Many Requests\r\nContent-Length: {}\r\nContent-Type: text/plain\r\nConnection: close\r\nRetry-After: 1\r\n\r\n{}",
+        "HTTP/1.1 {}\r\nContent-Length: {}\r\nContent-Type: {}\r\nConnection: close\r\n{}\r\n{}",
+        status,
         body.len(),
+        template.content_type(),
+        extra_headers,
         body
     );
 
     send_response(stream, peer, &response);
 }
 
-fn handle_error_response(stream: &mut TcpStream, peer: SocketAddr) {
-    let body = "Internal server error\n";
+// send an RFC 7807 problem details body
+fn send_problem_response(
+    stream: &mut TcpStream,
+    peer: SocketAddr,
+    status: &str,
+    extra_headers: &str,
+    problem: &Problem,
+) {
+    let body = problem.to_json();
     let response = format!(
-        "HTTP/1.1 500 Internal Server Error\r\nContent-Length: {}\r\nContent-Type: text/plain\r\nConnection: close\r\n\r\n{}",
+        "HTTP/1.1 {}\r\nContent-Length: {}\r\nContent-Type: {}\r\nConnection: close\r\n{}\r\n{}",
+        status,
         body.len(),
+        Problem::CONTENT_TYPE,
+        extra_headers,
         body
     );
 
@@ -73,91 +141,514 @@ fn send_response(stream: &mut TcpStream, peer: SocketAddr, response: &str) {
         eprintln!("{}: flush error: {}", peer, e);
     }
 
-    println!("{}: response sent, closing", peer);
+    info!("{}: response sent, closing", peer);
 }
 
-/// Handle a single connection: read up to a limit, then write a simple HTTP response and close.
-fn handle_connection<T>(
-    mut stream: TcpStream,
-    peer: SocketAddr,
+// read the request head from the stream, returning None if nothing usable arrived
+fn read_request(stream: &mut TcpStream, peer: SocketAddr) -> Option<HttpRequest> {
+    // a head can arrive over several reads, so keep reading until its blank
+    // line or until the buffer is full; the read timeout set on accept bounds
+    // the whole head rather than each read, so a client trickling one byte at
+    // a time cannot hold the thread any longer than a silent one
+    let deadline = stream
+        .read_timeout()
+        .ok()
+        .flatten()
+        .map(|timeout| Instant::now() + timeout);
+    let mut buf = [0u8; 4096];
+    let mut n = 0;
+    while head_len(&buf[..n]).is_none() {
+        if n == buf.len() {
+            info!(
+                "{}: request head larger than {} bytes, closing",
+                peer,
+                buf.len()
+            );
+            return None;
+        }
+        if let Some(deadline) = deadline {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() || stream.set_read_timeout(Some(left)).is_err() {
+                info!(
+                    "{}: request head incomplete at the read timeout, closing",
+                    peer
+                );
+                return None;
+            }
+        }
+        match stream.read(&mut buf[n..]) {
+            Ok(0) if n == 0 => {
+                info!("{}: client closed connection immediately", peer);
+                return None;
+            }
+            Ok(0) => {
+                info!("{}: client closed connection mid-request", peer);
+                return None;
+            }
+            Ok(read) => n += read,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                info!("{}: no request within the read timeout, closing", peer);
+                return None;
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => {
+                eprintln!("{}: read error: {}", peer, e);
+                return None;
+            }
+        }
+    }
+
+    // For debugging: print the request (as text if valid UTF-8)
+    if let Ok(req_str) = std::str::from_utf8(&buf[..n]) {
+        debug!("{} sent request:\n{}", peer, req_str);
+    } else {
+        debug!("{} sent {} bytes (non-UTF8)", peer, n);
+    }
+    HttpRequest::parse(&buf[..n], peer.ip())
+}
+
+// convert a denial's wait into a whole number of seconds for the Retry-After header
+fn retry_after_secs(retry_after: Option<Duration>) -> u64 {
+    retry_after
+        .map(|wait| wait.as_secs() + u64::from(wait.subsec_nanos() > 0))
+        .unwrap_or(1)
+        .max(1)
+}
+
+// how the default limiter keys requests that match no route
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum KeyMode {
+    PeerIp,    // one limit per client IP
+    Forwarded, // one limit per client IP as reported by a proxy in front
+    Global,    // one limit shared by every client
+}
+
+// build the key extractor for a key mode; behind a proxy, requests from
+// peers outside the trusted proxy set are keyed by their own peer address,
+// and so are requests a trusted proxy sent without the header
+fn key_extractor(mode: KeyMode, proxies: ForwardedFor) -> Box<dyn KeyExtractor<Key = IpAddr>> {
+    match mode {
+        KeyMode::PeerIp => Box::new(PeerIp),
+        KeyMode::Forwarded => Box::new(proxies.or(PeerIp)),
+        KeyMode::Global => Box::new(|_: &HttpRequest| Some(IpAddr::from([0, 0, 0, 0]))),
+    }
+}
+
+// state shared by every connection handler
+struct AppState<T>
+where
+    T: Hash + Eq + Clone,
+{
     limiter: Arc<RateLimiter<T, SystemClock>>,
-) where
+    key: Box<dyn KeyExtractor<Key = IpAddr>>,
+    routes: RouteTable,
+    access_list: Option<ReloadableAccessList>,
+    templates: ResponseTemplates,
+    next_decision_id: AtomicU64,
+    denials: DenialCounter,
+}
+
+/// Handle a single connection: read the request, apply the access list and the
+/// matching route limit (or the default per-IP limit), then write a simple HTTP
+/// response and close.
+fn handle_connection<T>(mut stream: TcpStream, peer: SocketAddr, state: Arc<AppState<T>>)
+where
     T: Hash + Eq + Clone + From<IpAddr>,
 {
-    println!("Handling connection from {}", peer);
+    info!("Handling connection from {}", peer);
 
-    // Use IP address as client ID
-    let client_id = peer.ip();
+    let Some(request) = read_request(&mut stream, peer) else {
+        return;
+    };
+
+    // The denial counters are served to scrapers without being rate limited
+    if request.method() == "GET" && request.path() == "/metrics" {
+        let body = state.denials.render();
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nContent-Type: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            DenialCounter::CONTENT_TYPE,
+            body
+        );
+        send_response(&mut stream, peer, &response);
+        return;
+    }
 
-    // Check rate limit
-    match limiter.is_allowed(client_id.into()) {
-        Ok(true) => {
+    let key = state
+        .routes
+        .key_for(&request)
+        .unwrap_or_else(|| request.peer().to_string());
+    let mut vars = TemplateVars {
+        retry_after: None,
+        decision_id: state.next_decision_id.fetch_add(1, Ordering::Relaxed),
+        key_hash: TemplateVars::hash_key(&key),
+    };
+    let templates = &state.templates;
+
+    // The access list can bypass or reject a request before any limiter runs
+    if let Some(access_list) = &state.access_list {
+        match access_list.check(request.peer(), &key) {
+            Some(Access::Allow) => {
+                info!("{}: allowlisted", peer);
+                handle_allowed_request(&mut stream, peer, &templates.allowed, &vars);
+                return;
+            }
+            Some(Access::Deny) => {
+                info!("{}: denylisted", peer);
+                let exemplar = Exemplar::new(request.trace_id(), vars.decision_id);
+                state.denials.record("forbidden", exemplar);
+                if request.accepts_json() {
+                    let problem = Problem::forbidden(vars.decision_id);
+                    send_problem_response(&mut stream, peer, "403 Forbidden", "", &problem);
+                } else {
+                    handle_forbidden_request(&mut stream, peer);
+                }
+                return;
+            }
+            None => {}
+        }
+    }
+
+    // Check the route table first, falling back to the default limit keyed by IP
+    // None means allowed, Some(secs) means denied with a Retry-After
+    let decision: Option<u64> = match state.routes.check(&request) {
+        RouteOutcome::Allowed => None,
+        RouteOutcome::Denied(denied) => Some(retry_after_secs(denied.retry_after())),
+        RouteOutcome::NoMatch => {
+            let key = state.key.extract(&request).unwrap_or(request.peer());
+            let decision = state.limiter.check(key.into());
+            (!decision.is_allowed()).then(|| retry_after_secs(decision.retry_after()))
+        }
+    };
+
+    match decision {
+        None => {
             // Request allowed - proceed normally
-            handle_allowed_request(&mut stream, peer);
+            handle_allowed_request(&mut stream, peer, &templates.allowed, &vars);
         }
-        Ok(false) => {
-            // Request denied - return 429
-            handle_rate_limited_request(&mut stream, peer);
+        Some(retry_after) => {
+            // Request denied - return 429, as problem+json if the client wants JSON
+            vars.retry_after = Some(retry_after);
+            let exemplar = Exemplar::new(request.trace_id(), vars.decision_id);
+            state.denials.record("rate_limited", exemplar);
+            if request.accepts_json() {
+                info!("{}: Rate limited!", peer);
+                let problem = Problem::rate_limited(retry_after, vars.decision_id);
+                let headers = format!("Retry-After: {}\r\n", retry_after);
+                send_problem_response(
+                    &mut stream,
+                    peer,
+                    "429 Too Many Requests",
+                    &headers,
+                    &problem,
+                );
+            } else {
+                handle_rate_limited_request(&mut stream, peer, &templates.rate_limited, &vars);
+            }
         }
-        Err(e) => {
-            // Rate limiter error
-            eprintln!("{}: Rate limiter error: {}", peer, e);
-            handle_error_response(&mut stream, peer);
+    }
+}
+
+// command-line options for the server
+struct Options {
+    rate: f64,
+    burst: f64,
+    bind: String,
+    workers: usize,
+    key_mode: KeyMode,
+    trusted_proxies: ForwardedFor,
+    log_level: LogLevel,
+    routes: Option<String>,
+    access_list: Option<String>,
+    templates: Option<String>,
+    canaries: Option<String>,
+    read_timeout: Duration,
+    write_timeout: Duration,
+    max_connections_per_ip: usize,
+}
+
+// By default the server limits each IP to 2 requests per second on
+// localhost:8000 with 8 workers. Slow clients are cut off after a few seconds,
+// and no single IP may hold more than a few of the pool's threads at once
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            rate: 2.0,
+            burst: 0.0,
+            bind: "127.0.0.1:8000".to_string(),
+            workers: 8,
+            key_mode: KeyMode::PeerIp,
+            trusted_proxies: ForwardedFor::new(1)
+                .trust("127.0.0.1")
+                .and_then(|proxies| proxies.trust("::1"))
+                .expect("loopback addresses parse"),
+            log_level: LogLevel::Info,
+            routes: None,
+            access_list: None,
+            templates: None,
+            canaries: None,
+            read_timeout: Duration::from_secs(5),
+            write_timeout: Duration::from_secs(5),
+            max_connections_per_ip: 4,
         }
     }
+}
 
-    // Read a small amount of the request (we don't fully parse HTTP here)
-    let mut buf = [0u8; 4096];
-    match stream.read(&mut buf) {
-        Ok(0) => {
-            println!("{}: client closed connection immediately", peer);
-            return;
-        }
-        Ok(n) => {
-            // For debugging: print the request (as text if valid UTF-8)
-            if let Ok(req_str) = std::str::from_utf8(&buf[..n]) {
-                println!("{} sent request:\n{}", peer, req_str);
-            } else {
-                println!("{} sent {} bytes (non-UTF8)", peer, n);
+// parse a positive whole number argument for `flag`
+fn parse_number<N>(flag: &str, value: Option<String>) -> Result<N, String>
+where
+    N: std::str::FromStr + Default + PartialEq,
+{
+    value
+        .and_then(|value| value.parse().ok())
+        .filter(|number| *number != N::default())
+        .ok_or_else(|| format!("{} requires a positive whole number", flag))
+}
+
+// parse a non-negative number argument for `flag`; rates must also be positive
+fn parse_quota(flag: &str, value: Option<String>, positive: bool) -> Result<f64, String> {
+    value
+        .and_then(|value| value.parse::<f64>().ok())
+        .filter(|number| number.is_finite() && (*number > 0.0 || !positive && *number == 0.0))
+        .ok_or_else(|| match positive {
+            true => format!("{} requires a positive number", flag),
+            false => format!("{} requires a non-negative number", flag),
+        })
+}
+
+// apply one option given as `flag value`
+fn apply_option(options: &mut Options, flag: &str, value: Option<String>) -> Result<(), String> {
+    let required = |what: &str| format!("{} requires {}", flag, what);
+    match flag {
+        "--rate" => options.rate = parse_quota(flag, value, true)?,
+        "--burst" => options.burst = parse_quota(flag, value, false)?,
+        "--bind" => options.bind = value.ok_or_else(|| required("an address"))?,
+        "--workers" => options.workers = parse_number(flag, value)?,
+        "--key-mode" => {
+            options.key_mode = match value.as_deref() {
+                Some("ip") => KeyMode::PeerIp,
+                Some("forwarded") => KeyMode::Forwarded,
+                Some("global") => KeyMode::Global,
+                _ => return Err(required("`ip`, `forwarded` or `global`")),
             }
         }
-        Err(e) => {
-            eprintln!("{}: read error: {}", peer, e);
-            return;
+        "--trusted-proxies" => {
+            let proxies = value.ok_or_else(|| required("addresses or CIDR prefixes"))?;
+            options.trusted_proxies = proxies
+                .split(',')
+                .try_fold(ForwardedFor::new(1), ForwardedFor::trust)
+                .ok_or_else(|| required("addresses or CIDR prefixes"))?;
+        }
+        "--log-level" => {
+            options.log_level = match value.as_deref() {
+                Some("error") => LogLevel::Error,
+                Some("info") => LogLevel::Info,
+                Some("debug") => LogLevel::Debug,
+                _ => return Err(required("`error`, `info` or `debug`")),
+            }
+        }
+        "--routes" => options.routes = Some(value.ok_or_else(|| required("a path"))?),
+        "--access-list" => options.access_list = Some(value.ok_or_else(|| required("a path"))?),
+        "--templates" => options.templates = Some(value.ok_or_else(|| required("a directory"))?),
+        "--canaries" => options.canaries = Some(value.ok_or_else(|| required("a path"))?),
+        "--read-timeout" => {
+            let secs: u64 = parse_number(flag, value)?;
+            options.read_timeout = Duration::from_secs(secs);
+        }
+        "--write-timeout" => {
+            let secs: u64 = parse_number(flag, value)?;
+            options.write_timeout = Duration::from_secs(secs);
         }
+        "--max-connections-per-ip" => options.max_connections_per_ip = parse_number(flag, value)?,
+        other => return Err(format!("unknown argument: {}", other)),
     }
+    Ok(())
+}
 
-    // Prepare a simple body and response
-    let body = "Hello from Rust threadpool server!\n";
-    let response = format!(
-        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n{}",
-        body.len(),
-        body
-    );
+// environment variables that override the option of the same meaning, so a
+// container can be configured without changing its command line
+const ENV_OPTIONS: [(&str, &str); 7] = [
+    ("RATE", "--rate"),
+    ("BURST", "--burst"),
+    ("BIND", "--bind"),
+    ("WORKERS", "--workers"),
+    ("KEY_MODE", "--key-mode"),
+    ("TRUSTED_PROXIES", "--trusted-proxies"),
+    ("LOG_LEVEL", "--log-level"),
+];
 
-    // Write the response
-    if let Err(e) = stream.write_all(response.as_bytes()) {
-        eprintln!("{}: write error: {}", peer, e);
+// parse the command line, then apply the environment on top of it; every
+// option takes one value, e.g. `--rate 5` or `--routes routes.toml`
+// precedence: environment, then command line, then built-in defaults
+fn parse_args() -> Result<Options, Box<dyn Error>> {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        apply_option(&mut options, &arg, args.next())?;
     }
+    for (var, flag) in ENV_OPTIONS {
+        if let Ok(value) = std::env::var(var) {
+            apply_option(&mut options, flag, Some(value))
+                .map_err(|e| format!("{} (from ${})", e, var))?;
+        }
+    }
+    Ok(options)
+}
 
-    // Ensure data is flushed before we drop the stream
-    if let Err(e) = stream.flush() {
-        eprintln!("{}: flush error: {}", peer, e);
+// load the route table from a TOML file, or an empty table
+fn load_routes(path: Option<&str>) -> Result<RouteTable, Box<dyn Error>> {
+    let mut config = RouteConfig::default();
+    if let Some(path) = path {
+        config = RouteConfig::load(&std::fs::read_to_string(path)?)
+            .map_err(|e| format!("{}: {}", path, e))?;
+        info!("Loaded {} route rules from {}", config.routes.len(), path);
+    }
+    Ok(RouteTable::new(&config, SystemClock)?)
+}
+
+// poll the access list file and swap in the new list when it changes
+fn watch_access_list<T>(state: Arc<AppState<T>>)
+where
+    T: Hash + Eq + Clone + Send + Sync + 'static,
+{
+    thread::spawn(move || {
+        loop {
+            thread::sleep(Duration::from_secs(2));
+            let Some(access_list) = &state.access_list else {
+                return;
+            };
+            match access_list.reload_if_changed() {
+                Ok(true) => info!(
+                    "Reloaded access list ({} entries); matches so far: {} allowed, {} denied",
+                    access_list.current().len(),
+                    access_list.allow_matches(),
+                    access_list.deny_matches()
+                ),
+                Ok(false) => {}
+                Err(e) => eprintln!("Access list reload failed, keeping old list: {}", e),
+            }
+        }
+    });
+}
+
+// check (and with `--repair` fix) a sled state store offline:
+// `fsck <path> [--repair] [--max-ahead <secs>] [--keys ip|string|u64]`
+// exits with an error if problems were found and left in place
+#[cfg(feature = "sled")]
+fn run_fsck(mut args: impl Iterator<Item = String>) -> Result<(), Box<dyn Error>> {
+    use gcra_rate_limiter::{Clock, SledStore};
+
+    let path = args.next().ok_or("fsck requires a store path")?;
+    if !std::path::Path::new(&path).exists() {
+        return Err(format!("{}: no such store", path).into());
+    }
+    let (mut repair, mut max_ahead, mut keys) = (false, 3600, String::from("ip"));
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--repair" => repair = true,
+            "--max-ahead" => max_ahead = parse_number("--max-ahead", args.next())?,
+            "--keys" => {
+                keys = args
+                    .next()
+                    .ok_or("--keys requires `ip`, `string` or `u64`")?
+            }
+            other => return Err(format!("unknown argument: {}", other).into()),
+        }
     }
 
-    println!("{}: response sent, closing", peer);
+    let now = SystemClock.now();
+    let max_ahead = Duration::from_secs(max_ahead);
+    let report = match keys.as_str() {
+        "ip" => SledStore::<IpAddr>::fsck(&path, now, max_ahead, repair)?,
+        "string" => SledStore::<String>::fsck(&path, now, max_ahead, repair)?,
+        "u64" => SledStore::<u64>::fsck(&path, now, max_ahead, repair)?,
+        _ => return Err("--keys requires `ip`, `string` or `u64`".into()),
+    };
+    for issue in &report.issues {
+        println!("{}: {}", path, issue);
+    }
+    println!(
+        "{}: {} entries, {} idle, {} problems{}",
+        path,
+        report.scanned,
+        report.idle,
+        report.issues.len(),
+        if report.repaired { ", repaired" } else { "" }
+    );
+    match report.issues.is_empty() || report.repaired {
+        true => Ok(()),
+        false => Err("problems found; rerun with --repair to fix them".into()),
+    }
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
-    // Bind to localhost:8000
-    let listener = TcpListener::bind(("127.0.0.1", 8000))?;
-    println!("Listening on {}", listener.local_addr()?);
+    if std::env::args().nth(1).as_deref() == Some("fsck") {
+        #[cfg(feature = "sled")]
+        return run_fsck(std::env::args().skip(2));
+        #[cfg(not(feature = "sled"))]
+        return Err("fsck needs the server built with the `sled` feature".into());
+    }
 
-    // Create a thread pool with 8 workers
-    let pool = ThreadPool::new(8);
+    let options = parse_args()?;
+    LOG_LEVEL.get_or_init(|| options.log_level);
 
-    // Create shared rate limiter - 5 requests per second, burst of 10
-    let rate_limiter = Arc::new(RateLimiter::<IpAddr>::with_system_clock(2.0, 0.0).unwrap());
+    let listener = TcpListener::bind(options.bind.as_str())
+        .map_err(|e| format!("cannot bind {}: {}", options.bind, e))?;
+    info!("Listening on {}", listener.local_addr()?);
+
+    let pool = ThreadPool::new(options.workers);
+
+    let state = Arc::new(AppState {
+        // Shared default limiter for requests that match no route
+        limiter: Arc::new(RateLimiter::<IpAddr>::with_system_clock(
+            options.rate,
+            options.burst,
+        )?),
+        key: key_extractor(options.key_mode, options.trusted_proxies.clone()),
+        // Per-route limits take precedence over the default limiter
+        routes: load_routes(options.routes.as_deref())?,
+        access_list: match &options.access_list {
+            Some(path) => {
+                let list = ReloadableAccessList::load(path).map_err(|e| e.to_string())?;
+                info!(
+                    "Loaded {} access list entries from {}",
+                    list.current().len(),
+                    path
+                );
+                Some(list)
+            }
+            None => None,
+        },
+        // Response bodies default to plain text unless a template directory is given
+        templates: match &options.templates {
+            Some(dir) => {
+                let templates = ResponseTemplates::load_dir(dir).map_err(|e| e.to_string())?;
+                info!("Loaded response templates from {}", dir);
+                templates
+            }
+            None => ResponseTemplates::default(),
+        },
+        next_decision_id: AtomicU64::new(1),
+        denials: DenialCounter::new("gcra_denied"),
+    });
+    if state.access_list.is_some() {
+        watch_access_list(Arc::clone(&state));
+    }
+
+    // Canary keys probe the default limiter in the background for the life of the server
+    let _canaries = match &options.canaries {
+        Some(path) => {
+            let config = CanaryConfig::from_toml(&std::fs::read_to_string(path)?)?;
+            let canaries = Canaries::<IpAddr>::from_config(&config)?;
+            info!("Loaded {} canary keys from {}", config.canaries.len(), path);
+            Some(canaries.spawn(Arc::clone(&state.limiter), |anomaly| {
+                eprintln!("Canary anomaly: {}", anomaly)
+            }))
+        }
+        None => None,
+    };
+
+    let connections = ConcurrencyLimiter::<IpAddr>::new(options.max_connections_per_ip);
 
     for stream_res in listener.incoming() {
         match stream_res {
@@ -170,10 +661,25 @@ fn main() -> Result<(), Box<dyn Error>> {
                     }
                 };
 
-                let limiter = Arc::clone(&rate_limiter);
+                // Each IP may only tie up a few pool threads, so a slow-loris
+                // client cannot starve everyone else
+                let Some(permit) = connections.try_acquire(normalize_ip(peer.ip())) else {
+                    info!("{}: too many open connections from this IP; dropping", peer);
+                    continue;
+                };
+                if let Err(e) = stream
+                    .set_read_timeout(Some(options.read_timeout))
+                    .and_then(|()| stream.set_write_timeout(Some(options.write_timeout)))
+                {
+                    eprintln!("{}: failed to set timeouts: {}", peer, e);
+                    continue;
+                }
+
+                let state = Arc::clone(&state);
 
                 pool.execute(move || {
-                    handle_connection(stream, peer, limiter);
+                    handle_connection(stream, peer, state);
+                    drop(permit);
                 });
             }
             Err(e) => eprintln!("Accept error: {}", e),
@@ -182,3 +688,37 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trickled_heads_are_cut_off_at_the_read_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            // one byte every 50ms, each well within the per-read timeout
+            let mut stream = TcpStream::connect(addr).unwrap();
+            for byte in b"GET / HTTP/1.1\r\nHost: localhost\r\n".iter().cycle() {
+                if stream.write_all(&[*byte]).is_err() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+        });
+
+        let (mut stream, peer) = listener.accept().unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_millis(300)))
+            .unwrap();
+        let started = Instant::now();
+        assert!(read_request(&mut stream, peer).is_none());
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(300), "{elapsed:?}");
+        assert!(elapsed < Duration::from_millis(1_000), "{elapsed:?}");
+
+        drop(stream);
+        client.join().unwrap();
+    }
+}
@@ -0,0 +1,301 @@
+// src/lib/access_list.rs
+
+// dependencies
+use crate::http::normalize_ip;
+use std::error::Error;
+use std::fmt;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
+
+// enum type to represent what a matching list entry does to a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Allow, // skip rate limiting entirely
+    Deny,  // reject without consulting the limiter
+}
+
+// enum type to represent what a list entry matches
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Pattern {
+    Network(IpAddr, u8), // an address prefix of the given length
+    Key(String),         // an exact rate-limit key
+}
+
+// methods for the Pattern enum
+impl Pattern {
+    fn parse(text: &str) -> Option<Self> {
+        match parse_network(text) {
+            Some((ip, len)) => Some(Pattern::Network(ip, len)),
+            // anything that is not an address is an exact key
+            None if !text.contains('/') && text.parse::<IpAddr>().is_err() => {
+                Some(Pattern::Key(text.to_string()))
+            }
+            None => None,
+        }
+    }
+
+    fn matches(&self, ip: IpAddr, key: &str) -> bool {
+        match self {
+            Pattern::Key(expected) => expected == key,
+            Pattern::Network(network, len) => in_prefix(ip, *network, *len),
+        }
+    }
+}
+
+// parse an address or a CIDR prefix into the network and prefix length; a
+// bare address is a prefix of its full width
+pub(crate) fn parse_network(text: &str) -> Option<(IpAddr, u8)> {
+    let (addr, prefix) = match text.split_once('/') {
+        Some((addr, prefix)) => (addr, Some(prefix)),
+        None => (text, None),
+    };
+    let ip = addr.parse::<IpAddr>().ok()?;
+    let max = if ip.is_ipv4() { 32 } else { 128 };
+    let len = match prefix {
+        Some(prefix) => prefix.parse::<u8>().ok().filter(|len| *len <= max)?,
+        None => max,
+    };
+    Some((ip, len))
+}
+
+// check whether an address falls inside a network prefix of the same family
+pub(crate) fn in_prefix(ip: IpAddr, network: IpAddr, len: u8) -> bool {
+    match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) => {
+            let mask = u32::MAX.checked_shl(32 - len as u32).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(network) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(network)) => {
+            let mask = u128::MAX.checked_shl(128 - len as u32).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(network) & mask
+        }
+        _ => false,
+    }
+}
+
+// struct type to represent an error in an access list file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessListError {
+    line: usize,
+    message: String,
+}
+
+// implement the Display trait for the AccessListError type
+impl fmt::Display for AccessListError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "access list line {}: {}", self.line, self.message)
+    }
+}
+
+// implement the Error trait for the AccessListError type
+impl Error for AccessListError {}
+
+// struct type to represent a parsed allow/deny list
+// the file format is one `allow <entry>` or `deny <entry>` per line, where an
+// entry is an IP address, a CIDR prefix, or an exact key; `#` starts a comment
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AccessList {
+    allow: Vec<Pattern>,
+    deny: Vec<Pattern>,
+}
+
+// methods for the AccessList struct
+impl AccessList {
+    // method to parse a list from text
+    pub fn parse(text: &str) -> Result<Self, AccessListError> {
+        let mut list = Self::default();
+        for (index, raw) in text.lines().enumerate() {
+            let line = raw.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let error = |message: &str| AccessListError {
+                line: index + 1,
+                message: message.to_string(),
+            };
+            let mut parts = line.split_whitespace();
+            let action = parts.next().unwrap_or("");
+            let entry = parts.next().ok_or_else(|| error("missing entry"))?;
+            if parts.next().is_some() {
+                return Err(error("unexpected text after entry"));
+            }
+            let pattern = Pattern::parse(entry).ok_or_else(|| error("invalid CIDR prefix"))?;
+
+            match action {
+                "allow" => list.allow.push(pattern),
+                "deny" => list.deny.push(pattern),
+                _ => return Err(error("expected `allow` or `deny`")),
+            }
+        }
+        Ok(list)
+    }
+
+    // method to decide a request from its peer address and rate-limit key
+    // deny entries take precedence over allow entries; IPv4-mapped addresses
+    // match IPv4 entries
+    pub fn check(&self, ip: IpAddr, key: &str) -> Option<Access> {
+        let ip = normalize_ip(ip);
+        if self.deny.iter().any(|p| p.matches(ip, key)) {
+            Some(Access::Deny)
+        } else if self.allow.iter().any(|p| p.matches(ip, key)) {
+            Some(Access::Allow)
+        } else {
+            None
+        }
+    }
+
+    // accessor method to return the number of entries
+    pub fn len(&self) -> usize {
+        self.allow.len() + self.deny.len()
+    }
+
+    // method to check whether the list has no entries
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// struct type to represent an access list loaded from a file that can be
+// swapped atomically when the file changes, counting matches as it goes
+#[derive(Debug)]
+pub struct ReloadableAccessList {
+    path: PathBuf,
+    current: RwLock<Arc<AccessList>>,
+    modified: Mutex<Option<SystemTime>>,
+    allow_matches: AtomicU64,
+    deny_matches: AtomicU64,
+}
+
+// methods for the ReloadableAccessList struct
+impl ReloadableAccessList {
+    // method to load the list from a file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let list = Self {
+            path: path.as_ref().to_path_buf(),
+            current: RwLock::new(Arc::new(AccessList::default())),
+            modified: Mutex::new(None),
+            allow_matches: AtomicU64::new(0),
+            deny_matches: AtomicU64::new(0),
+        };
+        list.reload_if_changed()?;
+        Ok(list)
+    }
+
+    // method to re-read the file if its modification time changed
+    // returns whether a new list was installed; on error the old list stays
+    pub fn reload_if_changed(&self) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let modified = std::fs::metadata(&self.path)?.modified()?;
+        let mut last = self.modified.lock().unwrap();
+        if *last == Some(modified) {
+            return Ok(false);
+        }
+
+        let list = AccessList::parse(&std::fs::read_to_string(&self.path)?)?;
+        *self.current.write().unwrap() = Arc::new(list);
+        *last = Some(modified);
+        Ok(true)
+    }
+
+    // method to return the list currently in force
+    pub fn current(&self) -> Arc<AccessList> {
+        Arc::clone(&self.current.read().unwrap())
+    }
+
+    // method to decide a request against the current list, counting matches
+    pub fn check(&self, ip: IpAddr, key: &str) -> Option<Access> {
+        let access = self.current().check(ip, key);
+        match access {
+            Some(Access::Allow) => self.allow_matches.fetch_add(1, Ordering::Relaxed),
+            Some(Access::Deny) => self.deny_matches.fetch_add(1, Ordering::Relaxed),
+            None => 0,
+        };
+        access
+    }
+
+    // accessor method to return the number of requests matched by allow entries
+    pub fn allow_matches(&self) -> u64 {
+        self.allow_matches.load(Ordering::Relaxed)
+    }
+
+    // accessor method to return the number of requests matched by deny entries
+    pub fn deny_matches(&self) -> u64 {
+        self.deny_matches.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(text: &str) -> IpAddr {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_and_matches_entries() {
+        let list = AccessList::parse(
+            "# health checkers\n\
+             allow 127.0.0.1\n\
+             allow 10.0.0.0/8\n\
+             deny 10.6.6.0/24   # bad scraper\n\
+             deny 2001:db8::/32\n\
+             deny stolen-api-key\n",
+        )
+        .unwrap();
+
+        assert_eq!(list.len(), 5);
+        assert_eq!(list.check(ip("127.0.0.1"), ""), Some(Access::Allow));
+        assert_eq!(list.check(ip("10.1.2.3"), ""), Some(Access::Allow));
+        assert_eq!(list.check(ip("10.6.6.7"), ""), Some(Access::Deny));
+        assert_eq!(list.check(ip("::ffff:10.6.6.7"), ""), Some(Access::Deny));
+        assert_eq!(list.check(ip("2001:db8::1"), ""), Some(Access::Deny));
+        assert_eq!(
+            list.check(ip("192.0.2.1"), "stolen-api-key"),
+            Some(Access::Deny)
+        );
+        assert_eq!(list.check(ip("192.0.2.1"), "other"), None);
+    }
+
+    #[test]
+    fn reports_line_of_bad_entry() {
+        let err = AccessList::parse("allow 1.2.3.4\nblock 5.6.7.8\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "access list line 2: expected `allow` or `deny`"
+        );
+
+        let err = AccessList::parse("deny 10.0.0.0/33").unwrap_err();
+        assert_eq!(err.to_string(), "access list line 1: invalid CIDR prefix");
+    }
+
+    #[test]
+    fn reloads_when_file_changes() {
+        let path = std::env::temp_dir().join(format!("gcra-access-{}.txt", std::process::id()));
+        std::fs::write(&path, "deny 192.0.2.1\n").unwrap();
+
+        let list = ReloadableAccessList::load(&path).unwrap();
+        assert_eq!(list.check(ip("192.0.2.1"), ""), Some(Access::Deny));
+        assert!(!list.reload_if_changed().unwrap());
+
+        // make sure the new modification time differs from the first write
+        let later = SystemTime::now() + std::time::Duration::from_secs(5);
+        std::fs::write(&path, "allow 192.0.2.1\n").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+
+        assert!(list.reload_if_changed().unwrap());
+        assert_eq!(list.check(ip("192.0.2.1"), ""), Some(Access::Allow));
+        assert_eq!(list.deny_matches(), 1);
+        assert_eq!(list.allow_matches(), 1);
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
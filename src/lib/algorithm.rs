@@ -0,0 +1,518 @@
+// src/lib/algorithm.rs
+
+// dependencies
+use crate::gcra;
+use std::fmt;
+use std::time::Duration;
+
+// trait for the conformance test a RateLimiter applies to every charge
+// each algorithm keeps a key's state in a single u64 on the limiter's clock,
+// so keying, eviction, snapshots and stores work the same whatever the
+// algorithm. The state must be time-like: a key with no state starts at
+// `now`, a state at or before `now` must behave like a fresh key (so idle
+// keys can be evicted), and a later state means more recent or heavier use.
+// `params` is the key's (increment, tolerance) in nanoseconds
+pub trait LimitAlgorithm: fmt::Debug + Send + Sync {
+    // the new state if `cost` units at `now` conform, or None to deny
+    fn conform(&self, now: u64, state: u64, params: (u64, u64), cost: u32) -> Option<u64>;
+
+    // the earliest time `cost` units would conform, None if they never can
+    fn retry_at(&self, now: u64, state: u64, params: (u64, u64), cost: u32) -> Option<u64>;
+
+    // how many unit requests would conform at `now`
+    fn remaining(&self, now: u64, state: u64, params: (u64, u64)) -> u64;
+
+    // how far ahead of `now` a conforming state can reach; the TAT horizon
+    // is never set below this, so it cannot cut into a key's allowance
+    fn max_ahead(&self, (increment, tolerance): (u64, u64)) -> u64 {
+        increment.saturating_add(tolerance)
+    }
+
+    // the state after handing back `cost` units charged earlier; never
+    // more credit than a fresh key has
+    fn refund(&self, now: u64, state: u64, (increment, _): (u64, u64), cost: u32) -> u64 {
+        let charge = increment.saturating_mul(cost as u64);
+        state.saturating_sub(charge).max(now)
+    }
+
+    // the state after pushing the key's next admission `penalty` nanoseconds
+    // further back, on top of what it already used
+    fn penalize(&self, now: u64, state: u64, _params: (u64, u64), penalty: u64) -> u64 {
+        state.max(now).saturating_add(penalty)
+    }
+
+    // the state after recording `cost` units already admitted elsewhere,
+    // e.g. by a BufferedLimiter buffer, whether or not they still conform
+    fn absorb(&self, now: u64, state: u64, (increment, _): (u64, u64), cost: u32) -> u64 {
+        let charge = increment.saturating_mul(cost as u64);
+        state.max(now).saturating_add(charge)
+    }
+}
+
+// struct type to represent the Generic Cell Rate Algorithm, the default
+// the state is the key's theoretical arrival time (TAT)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Gcra;
+
+impl LimitAlgorithm for Gcra {
+    fn conform(&self, now: u64, state: u64, params: (u64, u64), cost: u32) -> Option<u64> {
+        gcra::conform(now, state, params.0, params.1, cost)
+    }
+
+    fn retry_at(&self, _now: u64, state: u64, params: (u64, u64), cost: u32) -> Option<u64> {
+        gcra::retry_at(state, params.0, params.1, cost)
+    }
+
+    fn remaining(&self, now: u64, state: u64, params: (u64, u64)) -> u64 {
+        gcra::remaining(now, state, params.0, params.1)
+    }
+}
+
+// struct type to represent a classic token bucket refilled in batches
+// the bucket holds burst + 1 tokens and gains `refill_interval / increment`
+// tokens at each multiple of `refill_interval` on the limiter's clock, the way
+// many upstream APIs account; GCRA instead credits each key continuously from
+// its own last request. The state is the time the bucket is full again plus
+// one interval, which keeps a fresh key's state (`now`) full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenBucket {
+    refill_nanos: u64,
+}
+
+// methods for the TokenBucket struct
+impl TokenBucket {
+    // method to refill every `refill_interval` (at least a nanosecond)
+    pub fn new(refill_interval: Duration) -> Self {
+        Self {
+            refill_nanos: (refill_interval.as_nanos() as u64).max(1),
+        }
+    }
+
+    // accessor method to return the refill interval
+    pub fn refill_interval(&self) -> Duration {
+        Duration::from_nanos(self.refill_nanos)
+    }
+
+    // the latest refill at or before `now`
+    fn last_refill(&self, now: u64) -> u64 {
+        now - now % self.refill_nanos
+    }
+
+    // the tokens missing from the bucket at `now`, in nanoseconds of credit
+    fn deficit(&self, now: u64, state: u64) -> u64 {
+        let full_at = state.saturating_sub(self.refill_nanos);
+        full_at.saturating_sub(self.last_refill(now))
+    }
+}
+
+impl LimitAlgorithm for TokenBucket {
+    fn conform(&self, now: u64, state: u64, params: (u64, u64), cost: u32) -> Option<u64> {
+        let (increment, tolerance) = params;
+        let charge = increment.checked_mul(cost as u64)?;
+        let deficit = self.deficit(now, state).checked_add(charge)?;
+        if deficit > increment.saturating_add(tolerance) {
+            return None;
+        }
+        self.last_refill(now)
+            .checked_add(deficit)?
+            .checked_add(self.refill_nanos)
+    }
+
+    fn retry_at(&self, _now: u64, state: u64, params: (u64, u64), cost: u32) -> Option<u64> {
+        let (increment, tolerance) = params;
+        let charge = increment.checked_mul(cost as u64)?;
+        let capacity = increment.saturating_add(tolerance);
+        if charge > capacity {
+            return None;
+        }
+        // the first refill after which the deficit leaves room for the charge
+        let full_at = state.saturating_sub(self.refill_nanos);
+        let room_at = full_at.checked_add(charge)?.saturating_sub(capacity);
+        room_at.checked_next_multiple_of(self.refill_nanos)
+    }
+
+    fn remaining(&self, now: u64, state: u64, (increment, tolerance): (u64, u64)) -> u64 {
+        let capacity = increment.saturating_add(tolerance);
+        capacity.saturating_sub(self.deficit(now, state)) / increment.max(1)
+    }
+
+    fn max_ahead(&self, (increment, tolerance): (u64, u64)) -> u64 {
+        increment
+            .saturating_add(tolerance)
+            .saturating_add(self.refill_nanos)
+    }
+
+    fn penalize(&self, now: u64, state: u64, _params: (u64, u64), penalty: u64) -> u64 {
+        // the penalty is taken out of the bucket as that much credit
+        let deficit = self.deficit(now, state).saturating_add(penalty);
+        self.last_refill(now)
+            .saturating_add(deficit)
+            .saturating_add(self.refill_nanos)
+    }
+
+    fn absorb(&self, now: u64, state: u64, (increment, _): (u64, u64), cost: u32) -> u64 {
+        let charge = increment.saturating_mul(cost as u64);
+        self.penalize(now, state, (increment, 0), charge)
+    }
+}
+
+// struct type to represent a sliding-window counter
+// requests are counted in windows aligned to multiples of `window` on the
+// limiter's clock, and a request is admitted while the previous window's
+// count, weighted by how much of it still overlaps the rolling window, plus
+// the current window's count stays within `window / increment` requests
+// (e.g. rate 100/60 with a 60s window admits 100 per rolling minute); the
+// burst plays no part. Both counts are packed into the state below the end
+// of the window after the last charged one, so at most `sqrt(window in
+// nanoseconds) - 1` requests fit a window: 31,621 a second, 244,947 a minute
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlidingWindow {
+    window_nanos: u64,
+}
+
+// methods for the SlidingWindow struct
+impl SlidingWindow {
+    // method to count requests per rolling `window` (at least a nanosecond)
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window_nanos: (window.as_nanos() as u64).max(1),
+        }
+    }
+
+    // accessor method to return the window length
+    pub fn window(&self) -> Duration {
+        Duration::from_nanos(self.window_nanos)
+    }
+
+    // the base the two counts are packed in; it does not depend on the
+    // quota, so counts survive a quota change
+    fn radix(&self) -> u64 {
+        self.window_nanos.isqrt()
+    }
+
+    // the requests a window admits under `increment`, capped to what the
+    // packed state can hold
+    fn limit(&self, increment: u64) -> u64 {
+        let packable = self.radix().saturating_sub(1);
+        (self.window_nanos / increment.max(1)).min(packable)
+    }
+
+    // the start of the window holding `now`, with the previous and current
+    // window counts as of `now`
+    fn counts(&self, now: u64, state: u64) -> (u64, u64, u64) {
+        let (window, radix) = (self.window_nanos, self.radix());
+        let start = now - now % window;
+        let packed = state % window;
+        let (previous, current) = (packed / radix, packed % radix);
+        // the state sits two windows past the start of its charged window,
+        // so a fresh key's state (`now`) reads as two windows old
+        match (start + 2 * window).saturating_sub(state - packed) / window {
+            0 => (start, previous, current),
+            1 => (start, current, 0),
+            _ => (start, 0, 0),
+        }
+    }
+
+    // the state recording `previous` and `current` for the window at `start`
+    fn pack(&self, start: u64, previous: u64, current: u64) -> Option<u64> {
+        start
+            .checked_add(2 * self.window_nanos)?
+            .checked_add(previous * self.radix() + current)
+    }
+
+    // the earliest offset into a window at which `cost` more requests fit,
+    // given the counts the window starts with; None if not within the window
+    fn fits_at(&self, previous: u64, current: u64, cost: u64, limit: u64) -> Option<u64> {
+        let spare = limit.checked_sub(current + cost)?;
+        if previous == 0 {
+            return Some(0);
+        }
+        // previous * (window - offset) <= spare * window
+        let window = self.window_nanos as u128;
+        let allowed = spare as u128 * window / previous as u128;
+        let offset = window.saturating_sub(allowed) as u64;
+        (offset < self.window_nanos).then_some(offset)
+    }
+}
+
+impl LimitAlgorithm for SlidingWindow {
+    fn conform(&self, now: u64, state: u64, params: (u64, u64), cost: u32) -> Option<u64> {
+        let limit = self.limit(params.0);
+        let (start, previous, current) = self.counts(now, state);
+        let offset = self.fits_at(previous, current, cost as u64, limit)?;
+        if offset > now - start {
+            return None;
+        }
+        self.pack(start, previous, current + cost as u64)
+    }
+
+    fn retry_at(&self, now: u64, state: u64, params: (u64, u64), cost: u32) -> Option<u64> {
+        let limit = self.limit(params.0);
+        let cost = cost as u64;
+        if cost > limit {
+            return None;
+        }
+        let (start, previous, current) = self.counts(now, state);
+        if let Some(offset) = self.fits_at(previous, current, cost, limit) {
+            return Some(start + offset.max(now - start));
+        }
+        // the current window becomes the previous one, and fades out by the
+        // end of the next window at the latest
+        let next = start.checked_add(self.window_nanos)?;
+        let offset = self
+            .fits_at(current, 0, cost, limit)
+            .unwrap_or(self.window_nanos);
+        next.checked_add(offset)
+    }
+
+    fn remaining(&self, now: u64, state: u64, (increment, _): (u64, u64)) -> u64 {
+        let limit = self.limit(increment);
+        let (start, previous, current) = self.counts(now, state);
+        let window = self.window_nanos as u128;
+        let overlap = window - (now - start) as u128;
+        let used = previous as u128 * overlap + current as u128 * window;
+        ((limit as u128 * window).saturating_sub(used) / window) as u64
+    }
+
+    fn max_ahead(&self, _params: (u64, u64)) -> u64 {
+        self.window_nanos.saturating_mul(3)
+    }
+
+    fn refund(&self, now: u64, state: u64, _params: (u64, u64), cost: u32) -> u64 {
+        let (start, previous, current) = self.counts(now, state);
+        let cost = cost as u64;
+        // hand back the current window's requests first
+        let (previous, current) = match current.checked_sub(cost) {
+            Some(current) => (previous, current),
+            None => (previous.saturating_sub(cost - current), 0),
+        };
+        self.pack(start, previous, current).unwrap_or(state)
+    }
+
+    fn penalize(&self, now: u64, state: u64, params: (u64, u64), penalty: u64) -> u64 {
+        // the penalty counts as the requests that much time is worth
+        let cost = penalty.div_ceil(params.0.max(1));
+        self.absorb(now, state, params, u32::try_from(cost).unwrap_or(u32::MAX))
+    }
+
+    fn absorb(&self, now: u64, state: u64, _params: (u64, u64), cost: u32) -> u64 {
+        let (start, previous, current) = self.counts(now, state);
+        // the current count is capped to what the packed state can hold
+        let current = (current + cost as u64).min(self.radix() - 1);
+        self.pack(start, previous, current).unwrap_or(state)
+    }
+}
+
+// struct type to represent a fixed-window counter
+// requests are counted in windows aligned to multiples of `window` on the
+// limiter's clock, and the count resets at each boundary, matching upstream
+// providers that account this way; `window / increment` requests fit a window
+// (e.g. `Quota::per_minute(100)` with a 60s window admits 100 a minute) and
+// the burst plays no part. The state is the end of the charged window plus
+// the nanoseconds of credit used in it, so a fresh key's state (`now`) and a
+// past window's state both read as an empty window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixedWindow {
+    window_nanos: u64,
+}
+
+// methods for the FixedWindow struct
+impl FixedWindow {
+    // method to count requests per `window` (at least a nanosecond)
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window_nanos: (window.as_nanos() as u64).max(1),
+        }
+    }
+
+    // accessor method to return the window length
+    pub fn window(&self) -> Duration {
+        Duration::from_nanos(self.window_nanos)
+    }
+
+    // the end of the window holding `now`, with the credit used in it
+    fn usage(&self, now: u64, state: u64) -> (u64, u64) {
+        let end = now - now % self.window_nanos + self.window_nanos;
+        (end, state.saturating_sub(end))
+    }
+}
+
+impl LimitAlgorithm for FixedWindow {
+    fn conform(&self, now: u64, state: u64, params: (u64, u64), cost: u32) -> Option<u64> {
+        let (end, used) = self.usage(now, state);
+        let used = params.0.checked_mul(cost as u64)?.checked_add(used)?;
+        (used <= self.window_nanos).then_some(end.checked_add(used)?)
+    }
+
+    fn retry_at(&self, now: u64, state: u64, params: (u64, u64), cost: u32) -> Option<u64> {
+        let charge = params.0.checked_mul(cost as u64)?;
+        if charge > self.window_nanos {
+            return None;
+        }
+        // the next window starts empty
+        let (end, used) = self.usage(now, state);
+        match used + charge <= self.window_nanos {
+            true => Some(now),
+            false => Some(end),
+        }
+    }
+
+    fn remaining(&self, now: u64, state: u64, (increment, _): (u64, u64)) -> u64 {
+        let (_, used) = self.usage(now, state);
+        self.window_nanos.saturating_sub(used) / increment.max(1)
+    }
+
+    fn max_ahead(&self, _params: (u64, u64)) -> u64 {
+        self.window_nanos.saturating_mul(2)
+    }
+
+    fn penalize(&self, now: u64, state: u64, _params: (u64, u64), penalty: u64) -> u64 {
+        // the penalty uses up that much of the window's credit, capped to the
+        // window, so nothing spills into the next one
+        let (end, used) = self.usage(now, state);
+        end.saturating_add(used.saturating_add(penalty).min(self.window_nanos))
+    }
+
+    fn absorb(&self, now: u64, state: u64, (increment, _): (u64, u64), cost: u32) -> u64 {
+        let charge = increment.saturating_mul(cost as u64);
+        self.penalize(now, state, (increment, 0), charge)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: u64 = 1_000_000_000;
+
+    #[test]
+    fn token_bucket_refills_on_the_interval() {
+        // one token a second, two at once
+        let bucket = TokenBucket::new(Duration::from_secs(1));
+        let params = (SECOND, SECOND);
+        let now = SECOND / 2;
+        assert_eq!(bucket.remaining(now, now, params), 2);
+        let state = bucket.conform(now, now, params, 2).unwrap();
+        assert_eq!(bucket.remaining(now, state, params), 0);
+        assert_eq!(bucket.conform(now, state, params, 1), None);
+
+        // GCRA would wait until 1.5s; the bucket refills at the 1s tick
+        assert_eq!(bucket.retry_at(now, state, params, 1), Some(SECOND));
+        assert_eq!(bucket.conform(SECOND - 1, state, params, 1), None);
+        let state = bucket.conform(SECOND, state, params, 1).unwrap();
+        assert_eq!(bucket.retry_at(SECOND, state, params, 2), Some(3 * SECOND));
+        assert_eq!(bucket.retry_at(SECOND, state, params, 3), None);
+
+        // once `now` passes the state, the bucket is full again
+        assert_eq!(bucket.remaining(state, state, params), 2);
+    }
+
+    #[test]
+    fn token_bucket_refunds_never_overfill() {
+        let bucket = TokenBucket::new(Duration::from_secs(1));
+        let params = (SECOND, SECOND);
+        let state = bucket.conform(0, 0, params, 2).unwrap();
+        let refunded = bucket.refund(0, state, params, 1);
+        assert_eq!(bucket.remaining(0, refunded, params), 1);
+        let refunded = bucket.refund(0, refunded, params, 5);
+        assert_eq!(bucket.remaining(0, refunded, params), 2);
+    }
+
+    #[test]
+    fn sliding_window_weights_the_previous_window() {
+        // ten requests per rolling second
+        let sliding = SlidingWindow::new(Duration::from_secs(1));
+        let params = (SECOND / 10, 0);
+        let mut state = SECOND / 2;
+        for _ in 0..10 {
+            state = sliding.conform(SECOND / 2, state, params, 1).unwrap();
+        }
+        assert_eq!(sliding.conform(SECOND / 2, state, params, 1), None);
+        assert_eq!(
+            sliding.retry_at(SECOND / 2, state, params, 1),
+            Some(SECOND + SECOND / 10)
+        );
+        assert_eq!(sliding.retry_at(SECOND / 2, state, params, 11), None);
+
+        // a quarter into the next second, 3/4 of the ten still count
+        let now = SECOND + SECOND / 4;
+        assert_eq!(sliding.remaining(now, state, params), 2);
+        state = sliding.conform(now, state, params, 2).unwrap();
+        assert_eq!(sliding.conform(now, state, params, 1), None);
+        assert_eq!(
+            sliding.retry_at(now, state, params, 1),
+            Some(SECOND + 3 * SECOND / 10)
+        );
+
+        // the second window's count becomes the previous one in the third
+        let now = 2 * SECOND + SECOND / 2;
+        assert_eq!(sliding.remaining(now, state, params), 9);
+        let refunded = sliding.refund(now, state, params, 2);
+        assert_eq!(sliding.remaining(now, refunded, params), 10);
+
+        // two windows on nothing counts, and the state passes within a window
+        assert_eq!(sliding.remaining(3 * SECOND, state, params), 10);
+        assert!(state < 4 * SECOND);
+    }
+
+    #[test]
+    fn sliding_window_caps_the_limit_to_the_packed_state() {
+        let sliding = SlidingWindow::new(Duration::from_secs(1));
+        assert_eq!(sliding.limit(1), 31_621);
+        let minute = SlidingWindow::new(Duration::from_secs(60));
+        assert_eq!(minute.limit(1), 244_947);
+        assert_eq!(minute.limit(600_000_000), 100);
+    }
+
+    #[test]
+    fn absorbed_units_count_even_past_the_limit() {
+        let params = (SECOND / 3, 0);
+        let sliding = SlidingWindow::new(Duration::from_secs(1));
+        let state = sliding.conform(0, 0, params, 3).unwrap();
+        let state = sliding.absorb(0, state, params, 2);
+        assert_eq!(sliding.counts(0, state), (0, 0, 5));
+        assert_eq!(
+            sliding.retry_at(0, state, params, 1),
+            Some(SECOND + SECOND * 3 / 5)
+        );
+
+        // a fixed window is used up, but the excess never spills over
+        let fixed = FixedWindow::new(Duration::from_secs(1));
+        let state = fixed.absorb(0, 0, params, 5);
+        assert_eq!(fixed.remaining(0, state, params), 0);
+        assert_eq!(fixed.remaining(SECOND, state, params), 3);
+
+        let bucket = TokenBucket::new(Duration::from_secs(1));
+        let state = bucket.absorb(0, 0, (SECOND, SECOND), 3);
+        assert_eq!(
+            bucket.retry_at(0, state, (SECOND, SECOND), 1),
+            Some(2 * SECOND)
+        );
+        assert_eq!(Gcra.absorb(SECOND, 0, (SECOND, 0), 3), 4 * SECOND);
+    }
+
+    #[test]
+    fn fixed_window_resets_at_the_boundary() {
+        // three requests per second window
+        let fixed = FixedWindow::new(Duration::from_secs(1));
+        let params = (SECOND / 3, 0);
+        let now = SECOND / 2;
+        let state = fixed.conform(now, now, params, 3).unwrap();
+        assert_eq!(fixed.remaining(now, state, params), 0);
+        assert_eq!(fixed.conform(now, state, params, 1), None);
+        assert_eq!(fixed.retry_at(now, state, params, 1), Some(SECOND));
+        assert_eq!(fixed.retry_at(now, state, params, 4), None);
+
+        // unlike a sliding window, nothing carries over the boundary
+        assert_eq!(fixed.conform(SECOND - 1, state, params, 1), None);
+        assert_eq!(fixed.remaining(SECOND, state, params), 3);
+        let state = fixed.conform(SECOND, state, params, 1).unwrap();
+        assert_eq!(fixed.remaining(SECOND, state, params), 2);
+        assert_eq!(fixed.retry_at(SECOND, state, params, 2), Some(SECOND));
+
+        // refunds hand credit back within the window
+        let refunded = fixed.refund(SECOND, state, params, 1);
+        assert_eq!(fixed.remaining(SECOND, refunded, params), 3);
+        assert!(state < 3 * SECOND);
+    }
+}
@@ -0,0 +1,386 @@
+// src/lib/audit.rs
+
+// dependencies
+use crate::clock::Clock;
+use crate::labels::KeyLabel;
+use crate::quota::Quota;
+use crate::rate_limiter::{Listing, RateLimiter, RateLimiterError};
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::hash::{BuildHasher, Hash};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// enum type to represent an administrative change to a limiter
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuditAction {
+    SetOverride {
+        rate: f64,
+        burst: f64,
+        ttl: Option<Duration>, // None for an override that never expires
+    },
+    RemoveOverride,
+    Ban {
+        ttl: Duration,
+    },
+    Unban,
+    Allowlist,
+    Denylist,
+    Unlist,
+    Reset,
+    Remove,
+    Clear,
+    SetDefaultQuota {
+        rate: f64,
+        burst: f64,
+    },
+}
+
+// implement the Display trait for the AuditAction type
+impl fmt::Display for AuditAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuditAction::SetOverride { rate, burst, ttl } => {
+                write!(f, "set_override rate={} burst={}", rate, burst)?;
+                match ttl {
+                    Some(ttl) => write!(f, " ttl={}s", ttl.as_secs_f64()),
+                    None => Ok(()),
+                }
+            }
+            AuditAction::RemoveOverride => write!(f, "remove_override"),
+            AuditAction::Ban { ttl } => write!(f, "ban ttl={}s", ttl.as_secs_f64()),
+            AuditAction::Unban => write!(f, "unban"),
+            AuditAction::Allowlist => write!(f, "allowlist"),
+            AuditAction::Denylist => write!(f, "denylist"),
+            AuditAction::Unlist => write!(f, "unlist"),
+            AuditAction::Reset => write!(f, "reset"),
+            AuditAction::Remove => write!(f, "remove"),
+            AuditAction::Clear => write!(f, "clear"),
+            AuditAction::SetDefaultQuota { rate, burst } => {
+                write!(f, "set_default_quota rate={} burst={}", rate, burst)
+            }
+        }
+    }
+}
+
+// struct type to represent one journaled change
+// `at_nanos` is the limiter clock's reading; `actor` is None for changes made
+// through the limiter directly rather than through `as_actor`; `key` is None
+// for changes to the whole limiter, such as its default quota
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AuditEntry<'a, T> {
+    pub at_nanos: u64,
+    pub actor: Option<&'a str>,
+    pub key: Option<&'a T>,
+    pub action: AuditAction,
+}
+
+// trait for destinations of audit entries
+pub trait AuditSink<T>: Send + Sync {
+    fn record(&self, entry: &AuditEntry<'_, T>);
+}
+
+// closures can be used directly as sinks, e.g. to forward entries to a logger
+impl<T, F> AuditSink<T> for F
+where
+    F: Fn(&AuditEntry<'_, T>) + Send + Sync,
+{
+    fn record(&self, entry: &AuditEntry<'_, T>) {
+        self(entry)
+    }
+}
+
+// struct type to represent an append-only audit journal file
+// each entry is one line: clock nanos, quoted actor (or `-`), action, quoted
+// key (or `-` for a change to the whole limiter)
+#[derive(Debug)]
+pub struct FileJournal {
+    file: Mutex<File>,
+    write_failed: AtomicBool,
+}
+
+// methods for the FileJournal struct
+impl FileJournal {
+    // method to open a journal, creating it if needed and appending otherwise
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            file: Mutex::new(file),
+            write_failed: AtomicBool::new(false),
+        })
+    }
+
+    // accessor method to report whether any entry failed to reach the disk
+    pub fn write_failed(&self) -> bool {
+        self.write_failed.load(Ordering::Relaxed)
+    }
+}
+
+impl<T: KeyLabel> AuditSink<T> for FileJournal {
+    fn record(&self, entry: &AuditEntry<'_, T>) {
+        let key = match entry.key {
+            Some(key) => {
+                let mut label = String::new();
+                key.write_label(&mut label);
+                format!("{:?}", label)
+            }
+            None => String::from("-"),
+        };
+        let actor = match entry.actor {
+            Some(actor) => format!("{:?}", actor),
+            None => String::from("-"),
+        };
+        // quoting the free-form fields keeps one entry per line
+        let line = format!("{} {} {} {}\n", entry.at_nanos, actor, entry.action, key);
+
+        let mut file = self.file.lock().unwrap();
+        if file
+            .write_all(line.as_bytes())
+            .and_then(|_| file.sync_data())
+            .is_err()
+        {
+            self.write_failed.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+// struct type to represent the optional sink attached to a limiter
+pub(crate) struct Journal<T>(Option<Arc<dyn AuditSink<T>>>);
+
+// methods for the Journal struct
+impl<T> Journal<T> {
+    pub(crate) fn new() -> Self {
+        Self(None)
+    }
+
+    pub(crate) fn set(&mut self, sink: Arc<dyn AuditSink<T>>) {
+        self.0 = Some(sink);
+    }
+
+    pub(crate) fn record(
+        &self,
+        at_nanos: u64,
+        actor: Option<&str>,
+        key: Option<&T>,
+        action: AuditAction,
+    ) {
+        if let Some(sink) = &self.0 {
+            sink.record(&AuditEntry {
+                at_nanos,
+                actor,
+                key,
+                action,
+            });
+        }
+    }
+}
+
+// implement the Debug trait for the Journal type
+impl<T> fmt::Debug for Journal<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(if self.0.is_some() {
+            "Journal(enabled)"
+        } else {
+            "Journal(disabled)"
+        })
+    }
+}
+
+// struct type to represent a limiter's administrative operations performed
+// on behalf of a named actor, who is recorded in the audit journal
+#[derive(Debug)]
+pub struct Admin<'a, T, C, S = RandomState>
+where
+    T: Hash + Eq + Clone,
+    C: Clock,
+    S: BuildHasher + Clone,
+{
+    limiter: &'a RateLimiter<T, C, S>,
+    actor: &'a str,
+}
+
+// methods for the Admin struct
+impl<'a, T, C, S> Admin<'a, T, C, S>
+where
+    T: Hash + Eq + Clone,
+    C: Clock,
+    S: BuildHasher + Clone,
+{
+    pub(crate) fn new(limiter: &'a RateLimiter<T, C, S>, actor: &'a str) -> Self {
+        Self { limiter, actor }
+    }
+
+    // see RateLimiter::set_override
+    pub fn set_override(
+        &self,
+        client_id: T,
+        rate_per_second: f64,
+        burst_capacity: f64,
+    ) -> Result<(), RateLimiterError> {
+        self.limiter.set_override_by(
+            Some(self.actor),
+            client_id,
+            rate_per_second,
+            burst_capacity,
+            None,
+        )
+    }
+
+    // see RateLimiter::set_override_for
+    pub fn set_override_for(
+        &self,
+        client_id: T,
+        rate_per_second: f64,
+        burst_capacity: f64,
+        ttl: Duration,
+    ) -> Result<(), RateLimiterError> {
+        self.limiter.set_override_by(
+            Some(self.actor),
+            client_id,
+            rate_per_second,
+            burst_capacity,
+            Some(ttl),
+        )
+    }
+
+    // see RateLimiter::remove_override
+    pub fn remove_override(&self, client_id: &T) {
+        self.limiter.remove_override_by(Some(self.actor), client_id)
+    }
+
+    // see RateLimiter::ban_for
+    pub fn ban_for(&self, client_id: T, ttl: Duration) {
+        self.limiter.ban_by(Some(self.actor), client_id, ttl)
+    }
+
+    // see RateLimiter::unban
+    pub fn unban(&self, client_id: &T) {
+        self.limiter.unban_by(Some(self.actor), client_id)
+    }
+
+    // see RateLimiter::allowlist
+    pub fn allowlist(&self, client_id: T) {
+        self.limiter
+            .list_by(Some(self.actor), client_id, Listing::Allow)
+    }
+
+    // see RateLimiter::denylist
+    pub fn denylist(&self, client_id: T) {
+        self.limiter
+            .list_by(Some(self.actor), client_id, Listing::Deny)
+    }
+
+    // see RateLimiter::unlist
+    pub fn unlist(&self, client_id: &T) {
+        self.limiter.unlist_by(Some(self.actor), client_id)
+    }
+
+    // see RateLimiter::reset
+    pub fn reset(&self, client_id: &T) {
+        self.limiter.reset_by(Some(self.actor), client_id)
+    }
+
+    // see RateLimiter::remove
+    pub fn remove(&self, client_id: &T) {
+        self.limiter.remove_by(Some(self.actor), client_id)
+    }
+
+    // see RateLimiter::clear
+    pub fn clear(&self) {
+        self.limiter.clear_by(Some(self.actor))
+    }
+
+    // see RateLimiter::set_rate
+    pub fn set_rate(&self, rate_per_second: f64) -> Result<(), RateLimiterError> {
+        self.limiter.set_rate_by(Some(self.actor), rate_per_second)
+    }
+
+    // see RateLimiter::set_burst
+    pub fn set_burst(&self, burst_capacity: f64) -> Result<(), RateLimiterError> {
+        self.limiter.set_burst_by(Some(self.actor), burst_capacity)
+    }
+
+    // see RateLimiter::reconfigure
+    pub fn reconfigure(&self, quota: Quota) {
+        self.limiter.reconfigure_by(Some(self.actor), quota)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestClock;
+
+    #[test]
+    fn admin_changes_are_journaled_with_actor() {
+        let clock = TestClock::new(1.0);
+        let mut limiter = RateLimiter::new(1.0, 0.0, clock.clone()).unwrap();
+        let entries = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&entries);
+        limiter.set_audit_sink(move |entry: &AuditEntry<'_, &str>| {
+            sink.lock().unwrap().push(format!(
+                "{} {} {} {}",
+                entry.at_nanos,
+                entry.actor.unwrap_or("-"),
+                entry.action,
+                entry.key.unwrap_or(&"-")
+            ));
+        });
+
+        limiter
+            .as_actor("alice")
+            .ban_for("client1", Duration::from_secs(60));
+        clock.advance(1.0);
+        limiter.unban(&"client1");
+        limiter
+            .as_actor("bob")
+            .set_override_for("client2", 2.0, 1.0, Duration::from_secs(30))
+            .unwrap();
+        // decisions are not administrative changes
+        limiter.is_allowed("client1").unwrap();
+        // default quota changes name no key
+        limiter.as_actor("carol").set_rate(4.0).unwrap();
+        limiter.set_burst(2.0).unwrap();
+        let quota = Quota::per_second(std::num::NonZeroU32::new(10).unwrap());
+        limiter.as_actor("carol").reconfigure(quota);
+
+        assert_eq!(
+            *entries.lock().unwrap(),
+            vec![
+                "1000000000 alice ban ttl=60s client1",
+                "2000000000 - unban client1",
+                "2000000000 bob set_override rate=2 burst=1 ttl=30s client2",
+                "2000000000 carol set_default_quota rate=4 burst=0 -",
+                "2000000000 - set_default_quota rate=4 burst=2 -",
+                "2000000000 carol set_default_quota rate=10 burst=9 -",
+            ]
+        );
+    }
+
+    #[test]
+    fn file_journal_appends_lines() {
+        let path = std::env::temp_dir().join(format!("gcra-audit-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let clock = TestClock::new(0.0);
+        let mut limiter = RateLimiter::<String, _>::new(1.0, 0.0, clock).unwrap();
+        limiter.set_audit_sink(FileJournal::open(&path).unwrap());
+        limiter
+            .as_actor("ops team")
+            .ban_for(String::from("a\nb"), Duration::from_secs(5));
+        limiter.remove_override(&String::from("c"));
+        limiter.as_actor("ops team").clear();
+
+        let journal = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            journal,
+            "0 \"ops team\" ban ttl=5s \"a\\nb\"\n0 - remove_override \"c\"\n\
+             0 \"ops team\" clear -\n"
+        );
+        let _ = std::fs::remove_file(&path);
+    }
+}
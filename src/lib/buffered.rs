@@ -0,0 +1,394 @@
+// src/lib/buffered.rs
+
+// dependencies
+use crate::SystemClock;
+use crate::clock::Clock;
+use crate::handle::Resolved;
+use crate::rate_limiter::{Denied, Listing, RateLimiter};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+// struct type to represent when a buffered limiter flushes to the shared map
+// a buffer flushes once it holds `max_updates` charged units or its oldest
+// unflushed charge is `max_age` old, whichever comes first; `buffers` is how
+// many buffers threads are spread over
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferPolicy {
+    pub max_updates: u32,
+    pub max_age: Duration,
+    pub buffers: usize,
+}
+
+// implement the Default trait to flush every 32 units or 1ms, with one
+// buffer per available core
+impl Default for BufferPolicy {
+    fn default() -> Self {
+        Self {
+            max_updates: 32,
+            max_age: Duration::from_millis(1),
+            buffers: std::thread::available_parallelism().map_or(1, |cores| cores.get()),
+        }
+    }
+}
+
+// struct type to represent one key's state in a buffer
+#[derive(Debug, Clone, Copy)]
+struct Pending {
+    base: u64,  // the key's shared state when this buffer read it
+    state: u64, // the key's state as this buffer sees it
+    units: u64, // units charged since the shared state was read
+    resolved: Resolved,
+}
+
+// struct type to represent the charges one group of threads has not flushed
+#[derive(Debug)]
+struct Buffer<T> {
+    keys: HashMap<T, Pending>,
+    units: u64,          // units charged since the last flush
+    oldest: Option<u64>, // when the oldest unflushed charge was made
+}
+
+// next buffer to hand to a thread seen for the first time
+static NEXT_BUFFER: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    // the buffer slot of the current thread, assigned round-robin
+    static BUFFER_SLOT: usize = NEXT_BUFFER.fetch_add(1, Ordering::Relaxed);
+}
+
+// struct type to represent a rate limiter that trades a bounded admission
+// error for far less cross-core traffic, for extreme write rates
+// each thread charges a buffer of its own, checking requests against the
+// key's shared state as read when the buffer first saw it plus the buffer's
+// own charges, and folds its charges into the shared map when the policy says
+// so: a key no other buffer touched meanwhile gets the buffer's state as is,
+// otherwise the limiter's algorithm records the units on top of the shared
+// state, so a single buffer decides exactly as the unbuffered limiter would.
+// Charges another buffer has not flushed yet are invisible; a buffer flushes
+// as soon as it holds `max_updates` units, so it can hide up to
+// `max_updates - 1 + cost` of them, `cost` being the largest single charge.
+// Per key at most `(buffers - 1) * (max_updates - 1 + cost)` units more than
+// the quota allows are admitted (fewer when max_age flushes first). The
+// limiter's algorithm, allow-all and shadow modes, bans and quota overrides
+// apply, but minimum spacing, pace tracking and decision sinks are skipped
+#[derive(Debug)]
+pub struct BufferedLimiter<T, C = SystemClock>
+where
+    T: Hash + Eq + Clone,
+    C: Clock,
+{
+    limiter: RateLimiter<T, C>,
+    buffers: Box<[Mutex<Buffer<T>>]>,
+    max_updates: u64,
+    max_age_nanos: u64,
+}
+
+// methods for the BufferedLimiter struct
+impl<T, C> BufferedLimiter<T, C>
+where
+    T: Hash + Eq + Clone,
+    C: Clock,
+{
+    // method to buffer the writes of an existing limiter
+    pub fn new(limiter: RateLimiter<T, C>, policy: BufferPolicy) -> Self {
+        let buffers = (0..policy.buffers.max(1))
+            .map(|_| {
+                Mutex::new(Buffer {
+                    keys: HashMap::new(),
+                    units: 0,
+                    oldest: None,
+                })
+            })
+            .collect();
+        Self {
+            limiter,
+            buffers,
+            max_updates: policy.max_updates.max(1) as u64,
+            max_age_nanos: policy.max_age.as_nanos() as u64,
+        }
+    }
+
+    // accessor method to return the underlying limiter, whose state lags
+    // behind by whatever has not been flushed
+    pub fn limiter(&self) -> &RateLimiter<T, C> {
+        &self.limiter
+    }
+
+    // method to check one request for a key, as RateLimiter::is_allowed
+    pub fn is_allowed(&self, client_id: &T) -> bool {
+        self.charge(client_id, 1).is_ok()
+    }
+
+    // method to charge `cost` units to a key against the calling thread's
+    // buffer, flushing it first if it is due
+    pub fn charge(&self, client_id: &T, cost: u32) -> Result<(), Denied> {
+        if self.limiter.allows_all() {
+            return Ok(());
+        }
+        let now = self
+            .limiter
+            .decision_time()
+            .map_err(|behind| Denied::new(Some(behind)))?;
+        let result = self.charge_buffered(client_id, cost, now);
+        match self.limiter.is_shadow() {
+            true => Ok(()),
+            false => result,
+        }
+    }
+
+    // charge against the calling thread's buffer, deciding as if enforced
+    fn charge_buffered(&self, client_id: &T, cost: u32, now: u64) -> Result<(), Denied> {
+        let slot = BUFFER_SLOT.with(|slot| *slot) % self.buffers.len();
+        let mut guard = self.buffers[slot].lock().unwrap();
+        let buffer = &mut *guard;
+        if buffer
+            .oldest
+            .is_some_and(|oldest| now.saturating_sub(oldest) >= self.max_age_nanos)
+        {
+            self.flush_buffer(buffer);
+        }
+
+        let previous = buffer.keys.get(client_id).copied();
+        let pending = match previous {
+            Some(pending) if self.limiter.is_current(&pending.resolved, now) => {
+                buffer.keys.get_mut(client_id).unwrap()
+            }
+            _ => {
+                // first sight since the last flush, or an override changed
+                let pending = match previous {
+                    Some(pending) => Pending {
+                        resolved: self.limiter.resolve(client_id, now),
+                        ..pending
+                    },
+                    None => {
+                        let base = self
+                            .limiter
+                            .client_state()
+                            .get(client_id)
+                            .map_or(now, |state| *state);
+                        Pending {
+                            base,
+                            state: base,
+                            units: 0,
+                            resolved: self.limiter.resolve(client_id, now),
+                        }
+                    }
+                };
+                buffer
+                    .keys
+                    .entry(client_id.clone())
+                    .insert_entry(pending)
+                    .into_mut()
+            }
+        };
+
+        let resolved = pending.resolved;
+        match resolved.listing {
+            Some(Listing::Allow) => return Ok(()),
+            Some(Listing::Deny) => return Err(Denied::new(None)),
+            None => {}
+        }
+        if let Some(until) = resolved.ban_until.filter(|until| *until > now) {
+            return Err(Denied::new(Some(until - now)));
+        }
+        let params = (resolved.increment, resolved.tolerance);
+        let algorithm = self.limiter.algorithm();
+        match algorithm.conform(now, pending.state, params, cost) {
+            Some(state) => {
+                pending.state = state;
+                pending.units = pending.units.saturating_add(cost as u64);
+                buffer.units = buffer.units.saturating_add(cost as u64);
+                buffer.oldest.get_or_insert(now);
+                if buffer.units >= self.max_updates {
+                    self.flush_buffer(buffer);
+                }
+                Ok(())
+            }
+            None => {
+                let retry_at = algorithm.retry_at(now, pending.state, params, cost);
+                Err(Denied::new(retry_at.map(|at| at.saturating_sub(now))))
+            }
+        }
+    }
+
+    // method to flush every buffer, e.g. before a snapshot or shutdown;
+    // a buffer no thread charges is otherwise only flushed on its next charge
+    pub fn flush(&self) {
+        for buffer in self.buffers.iter() {
+            self.flush_buffer(&mut buffer.lock().unwrap());
+        }
+    }
+
+    // fold a buffer's charges into the shared map and forget its views, so
+    // its next charges start from the shared states again
+    fn flush_buffer(&self, buffer: &mut Buffer<T>) {
+        for (key, pending) in buffer.keys.drain() {
+            if pending.units > 0 {
+                let (base, units) = (pending.base, pending.units);
+                self.limiter.absorb(key, base, units, pending.state);
+            }
+        }
+        buffer.units = 0;
+        buffer.oldest = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestClock;
+    use crate::algorithm::{FixedWindow, LimitAlgorithm, SlidingWindow, TokenBucket};
+    use std::sync::Arc;
+    use std::thread;
+
+    fn policy(max_updates: u32, buffers: usize) -> BufferPolicy {
+        BufferPolicy {
+            max_updates,
+            max_age: Duration::from_secs(60),
+            buffers,
+        }
+    }
+
+    #[test]
+    fn single_buffer_matches_the_unbuffered_limiter() {
+        let clock = TestClock::new(0.0);
+        let exact = RateLimiter::new(10.0, 4.0, clock.clone()).unwrap();
+        let buffered = BufferedLimiter::new(
+            RateLimiter::new(10.0, 4.0, clock.clone()).unwrap(),
+            policy(3, 1),
+        );
+
+        for step in 0..200 {
+            clock.advance(if step % 7 == 0 { 0.25 } else { 0.01 });
+            assert_eq!(buffered.is_allowed(&"k"), exact.is_allowed("k").unwrap());
+        }
+        buffered.flush();
+        assert_eq!(
+            buffered.limiter().iter().collect::<Vec<_>>(),
+            exact.iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn single_buffer_matches_the_unbuffered_window_limiters() {
+        let windows: [Arc<dyn Fn() -> Arc<dyn LimitAlgorithm>>; 2] = [
+            Arc::new(|| Arc::new(SlidingWindow::new(Duration::from_secs(10)))),
+            Arc::new(|| Arc::new(FixedWindow::new(Duration::from_secs(10)))),
+        ];
+        for algorithm in windows {
+            let clock = TestClock::new(0.0);
+            let limiter = || {
+                RateLimiter::new(1.0, 4.0, clock.clone())
+                    .unwrap()
+                    .with_shared_algorithm(algorithm())
+            };
+            let exact = limiter();
+            let buffered = BufferedLimiter::new(limiter(), policy(2, 1));
+
+            for step in 0..60 {
+                clock.advance(if step % 9 == 0 { 3.0 } else { 0.1 });
+                assert_eq!(
+                    buffered.is_allowed(&"k"),
+                    exact.is_allowed("k").unwrap(),
+                    "{:?} at step {step}",
+                    exact.algorithm()
+                );
+            }
+            buffered.flush();
+            assert_eq!(
+                buffered.limiter().iter().collect::<Vec<_>>(),
+                exact.iter().collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn flushes_on_age_and_honours_bans() {
+        let clock = TestClock::new(0.0);
+        let buffered = BufferedLimiter::new(
+            RateLimiter::new(1.0, 9.0, clock.clone()).unwrap(),
+            BufferPolicy {
+                max_updates: 100,
+                max_age: Duration::from_millis(10),
+                buffers: 1,
+            },
+        );
+        assert!(buffered.is_allowed(&"k"));
+        assert!(buffered.limiter().is_empty());
+        clock.advance(0.01);
+        assert!(buffered.is_allowed(&"k"));
+        assert_eq!(buffered.limiter().len(), 1);
+
+        buffered.limiter().ban_for("k", Duration::from_secs(5));
+        let denied = buffered.charge(&"k", 1).unwrap_err();
+        assert_eq!(denied.retry_after(), Some(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn follows_the_limiters_modes_and_algorithm() {
+        let clock = TestClock::new(0.5);
+        let limiter = RateLimiter::new(1.0, 1.0, clock.clone())
+            .unwrap()
+            .with_algorithm(TokenBucket::new(Duration::from_secs(10)));
+        let buffered = BufferedLimiter::new(limiter, policy(100, 1));
+
+        // the bucket holds two tokens and refills only at t = 10s
+        assert!(buffered.is_allowed(&"k"));
+        assert!(buffered.is_allowed(&"k"));
+        let denied = buffered.charge(&"k", 1).unwrap_err();
+        assert_eq!(denied.retry_after(), Some(Duration::from_millis(9500)));
+
+        buffered.limiter().set_shadow(true);
+        assert!(buffered.is_allowed(&"k"));
+        buffered.limiter().set_shadow(false);
+        buffered.limiter().set_allow_all(true);
+        assert!(buffered.is_allowed(&"k"));
+        buffered.limiter().set_allow_all(false);
+        assert!(!buffered.is_allowed(&"k"));
+    }
+
+    // charges `cost` units from each of `threads` threads on a frozen clock,
+    // returning how many units were admitted
+    fn admitted_units(threads: usize, max_updates: u32, cost: u32) -> u64 {
+        let clock = TestClock::new(0.0);
+        let buffered = Arc::new(BufferedLimiter::new(
+            RateLimiter::new(1.0, 10.0, clock.clone()).unwrap(),
+            policy(max_updates, threads),
+        ));
+
+        let admitted: u64 = (0..threads)
+            .map(|_| {
+                let buffered = Arc::clone(&buffered);
+                thread::spawn(move || {
+                    (0..100)
+                        .filter(|_| buffered.charge(&"k", cost).is_ok())
+                        .count() as u64
+                })
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .sum::<u64>()
+            * cost as u64;
+
+        buffered.flush();
+        let tat = buffered.limiter().iter().next().unwrap().1;
+        assert_eq!(tat, admitted * 1_000_000_000);
+        admitted
+    }
+
+    #[test]
+    fn overshoot_stays_within_the_documented_bound() {
+        let (threads, max_updates) = (4, 5);
+        for cost in [1, 3] {
+            let admitted = admitted_units(threads, max_updates, cost);
+            // the clock is frozen, so the exact answer is the burst of 11
+            let slack = (threads as u64 - 1) * (max_updates + cost - 1) as u64;
+            assert!(admitted >= 11 - (cost as u64 - 1));
+            assert!(admitted <= 11 + slack);
+        }
+    }
+}
@@ -0,0 +1,292 @@
+// src/lib/builder.rs
+
+// dependencies
+use crate::SystemClock;
+use crate::algorithm::{Gcra, LimitAlgorithm};
+use crate::clock::{Clock, ClockRegression};
+use crate::quota::Quota;
+use crate::rate_limiter::{RateLimiter, RateLimiterError, Rounding};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::sync::Arc;
+use std::time::Duration;
+
+// struct type to represent a RateLimiter builder
+// only the rate (or a quota) is required; everything else defaults to what
+// `RateLimiter::new` gives (no burst, floor rounding, the system clock, no
+// eviction beyond explicit GC), and the options can be set in any order
+#[derive(Debug, Clone)]
+pub struct RateLimiterBuilder<C = SystemClock, S = RandomState> {
+    rate_per_second: Option<f64>,
+    burst_capacity: f64,
+    quota: Option<Quota>,
+    rounding: Rounding,
+    algorithm: Arc<dyn LimitAlgorithm>,
+    clock: C,
+    hasher: S,
+    shards: Option<usize>,
+    capacity: usize,
+    min_interval: Duration,
+    pace_window: Duration,
+    idle_ttl: Option<Duration>,
+    max_keys: Option<usize>,
+    tat_horizon: Option<Duration>,
+    clock_regression: ClockRegression,
+}
+
+// methods for the RateLimiterBuilder struct
+impl RateLimiterBuilder {
+    // method to start a builder with every option at its default
+    pub fn new() -> Self {
+        Self {
+            rate_per_second: None,
+            burst_capacity: 0.0,
+            quota: None,
+            rounding: Rounding::Floor,
+            algorithm: Arc::new(Gcra),
+            clock: SystemClock,
+            hasher: RandomState::new(),
+            shards: None,
+            capacity: 0,
+            min_interval: Duration::ZERO,
+            pace_window: Duration::ZERO,
+            idle_ttl: None,
+            max_keys: None,
+            tat_horizon: None,
+            clock_regression: ClockRegression::Allow,
+        }
+    }
+}
+
+// implement the Default trait for an empty RateLimiterBuilder
+impl Default for RateLimiterBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C, S> RateLimiterBuilder<C, S> {
+    // method to set the sustained rate in requests per second
+    pub fn rate(mut self, rate_per_second: f64) -> Self {
+        self.rate_per_second = Some(rate_per_second);
+        self
+    }
+
+    // method to set how many extra requests may arrive at once
+    pub fn burst(mut self, burst_capacity: f64) -> Self {
+        self.burst_capacity = burst_capacity;
+        self
+    }
+
+    // method to set the rate and burst from an integer quota instead; it
+    // takes precedence over `rate`, `burst` and `rounding`
+    pub fn quota(mut self, quota: Quota) -> Self {
+        self.quota = Some(quota);
+        self
+    }
+
+    // method to choose how the emission interval is rounded to nanoseconds
+    pub fn rounding(mut self, rounding: Rounding) -> Self {
+        self.rounding = rounding;
+        self
+    }
+
+    // see RateLimiter::with_algorithm
+    pub fn algorithm(mut self, algorithm: impl LimitAlgorithm + 'static) -> Self {
+        self.algorithm = Arc::new(algorithm);
+        self
+    }
+
+    // method to use another clock than the system clock
+    pub fn clock<C2: Clock>(self, clock: C2) -> RateLimiterBuilder<C2, S> {
+        RateLimiterBuilder {
+            rate_per_second: self.rate_per_second,
+            burst_capacity: self.burst_capacity,
+            quota: self.quota,
+            rounding: self.rounding,
+            algorithm: self.algorithm,
+            clock,
+            hasher: self.hasher,
+            shards: self.shards,
+            capacity: self.capacity,
+            min_interval: self.min_interval,
+            pace_window: self.pace_window,
+            idle_ttl: self.idle_ttl,
+            max_keys: self.max_keys,
+            tat_horizon: self.tat_horizon,
+            clock_regression: self.clock_regression,
+        }
+    }
+
+    // method to hash keys with another BuildHasher, see RateLimiter::with_hasher
+    pub fn hasher<S2: BuildHasher + Clone>(self, hasher: S2) -> RateLimiterBuilder<C, S2> {
+        RateLimiterBuilder {
+            rate_per_second: self.rate_per_second,
+            burst_capacity: self.burst_capacity,
+            quota: self.quota,
+            rounding: self.rounding,
+            algorithm: self.algorithm,
+            clock: self.clock,
+            hasher,
+            shards: self.shards,
+            capacity: self.capacity,
+            min_interval: self.min_interval,
+            pace_window: self.pace_window,
+            idle_ttl: self.idle_ttl,
+            max_keys: self.max_keys,
+            tat_horizon: self.tat_horizon,
+            clock_regression: self.clock_regression,
+        }
+    }
+
+    // see RateLimiter::with_shards
+    pub fn shards(mut self, shards: usize) -> Self {
+        self.shards = Some(shards);
+        self
+    }
+
+    // see RateLimiter::with_clock_regression
+    pub fn clock_regression(mut self, policy: ClockRegression) -> Self {
+        self.clock_regression = policy;
+        self
+    }
+
+    // method to size the state map up front, see RateLimiter::with_capacity
+    pub fn initial_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    // see RateLimiter::with_min_interval
+    pub fn min_interval(mut self, gap: Duration) -> Self {
+        self.min_interval = gap;
+        self
+    }
+
+    // see RateLimiter::with_pace_tracking
+    pub fn pace_tracking(mut self, window: Duration) -> Self {
+        self.pace_window = window;
+        self
+    }
+
+    // eviction policy: see RateLimiter::with_idle_ttl
+    pub fn idle_ttl(mut self, ttl: Duration) -> Self {
+        self.idle_ttl = Some(ttl);
+        self
+    }
+
+    // eviction policy: see RateLimiter::with_max_keys
+    pub fn max_keys(mut self, max_keys: usize) -> Self {
+        self.max_keys = Some(max_keys);
+        self
+    }
+
+    // see RateLimiter::with_tat_horizon
+    pub fn tat_horizon(mut self, horizon: Duration) -> Self {
+        self.tat_horizon = Some(horizon);
+        self
+    }
+}
+
+impl<C, S> RateLimiterBuilder<C, S>
+where
+    C: Clock,
+    S: BuildHasher + Clone,
+{
+    // method to create the limiter; without a quota, a missing or invalid
+    // rate and an invalid burst are the only ways to fail
+    pub fn build<T>(self) -> Result<RateLimiter<T, C, S>, RateLimiterError>
+    where
+        T: Hash + Eq + Clone,
+    {
+        let limiter = match self.quota {
+            Some(quota) => RateLimiter::with_quota(quota, self.clock),
+            None => {
+                let rate = self.rate_per_second.ok_or(RateLimiterError::InvalidRate)?;
+                RateLimiter::with_rounding(rate, self.burst_capacity, self.rounding, self.clock)?
+            }
+        };
+        let mut limiter = limiter
+            .with_shared_algorithm(self.algorithm)
+            .with_hasher(self.hasher);
+        if let Some(shards) = self.shards {
+            limiter = limiter.with_shards(shards);
+        }
+        let mut limiter = limiter
+            .with_capacity(self.capacity)
+            .with_min_interval(self.min_interval)
+            .with_pace_tracking(self.pace_window)
+            .with_clock_regression(self.clock_regression);
+        if let Some(ttl) = self.idle_ttl {
+            limiter = limiter.with_idle_ttl(ttl);
+        }
+        if let Some(max_keys) = self.max_keys {
+            limiter = limiter.with_max_keys(max_keys);
+        }
+        if let Some(horizon) = self.tat_horizon {
+            limiter = limiter.with_tat_horizon(horizon);
+        }
+        Ok(limiter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestClock;
+
+    #[test]
+    fn builds_with_defaults_and_options_in_any_order() {
+        let clock = TestClock::new(0.0);
+        let limiter = RateLimiterBuilder::new()
+            .max_keys(100)
+            .clock(clock.clone())
+            .burst(1.0)
+            .rate(2.0)
+            .min_interval(Duration::from_millis(100))
+            .initial_capacity(64)
+            .build()
+            .unwrap();
+
+        assert!(limiter.is_allowed("client").unwrap());
+        // burst credit is left, but the minimum spacing applies
+        assert!(!limiter.is_allowed("client").unwrap());
+        clock.advance(0.1);
+        assert!(limiter.is_allowed("client").unwrap());
+        assert!(limiter.client_state().capacity() >= 64);
+    }
+
+    #[test]
+    fn missing_or_invalid_quota_fails_to_build() {
+        let missing = RateLimiterBuilder::new().burst(1.0).build::<u32>();
+        assert!(matches!(missing, Err(RateLimiterError::InvalidRate)));
+        let negative = RateLimiterBuilder::new()
+            .rate(1.0)
+            .burst(-1.0)
+            .build::<u32>();
+        assert!(matches!(negative, Err(RateLimiterError::InvalidBurst)));
+        let quota = Quota::per_second(std::num::NonZeroU32::new(4).unwrap());
+        let limiter = RateLimiterBuilder::new()
+            .quota(quota)
+            .build::<u32>()
+            .unwrap();
+        assert_eq!(limiter.increment_nanos(), 250_000_000);
+    }
+
+    #[test]
+    fn builds_with_a_custom_hasher_and_shard_count() {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::BuildHasherDefault;
+
+        let limiter = RateLimiterBuilder::new()
+            .rate(1.0)
+            .shards(6)
+            .hasher(BuildHasherDefault::<DefaultHasher>::default())
+            .clock(TestClock::new(0.0))
+            .build()
+            .unwrap();
+        assert_eq!(limiter.shard_count(), 8);
+        assert!(limiter.is_allowed("client").unwrap());
+        assert!(!limiter.is_allowed("client").unwrap());
+    }
+}
@@ -0,0 +1,365 @@
+// src/lib/canary.rs
+
+// dependencies
+use crate::clock::Clock;
+use crate::rate_limiter::RateLimiter;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::hash::Hash;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+// struct type to represent the canary section of a configuration file
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CanaryConfig {
+    #[cfg_attr(feature = "serde", serde(default = "default_interval"))]
+    pub interval_secs: f64,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub canaries: Vec<CanarySpec>,
+}
+
+#[cfg(feature = "serde")]
+fn default_interval() -> f64 {
+    60.0
+}
+
+// struct type to represent one canary key and the decisions it should get
+// `expect` is a string of `A` (allow) and `D` (deny) for a burst of probes
+// against fresh state; by default the limiter's burst is allowed, then one denial
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CanarySpec {
+    pub key: String,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub expect: Option<String>,
+}
+
+// methods for the CanaryConfig struct
+impl CanaryConfig {
+    // method to parse a canary configuration from TOML text
+    #[cfg(feature = "config")]
+    pub fn from_toml(text: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(text)
+    }
+}
+
+// enum type to represent a canary configuration that cannot be used
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CanaryError {
+    InvalidKey(String),
+    InvalidPattern(String),
+}
+
+// implement the Display trait for the CanaryError type
+impl fmt::Display for CanaryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CanaryError::InvalidKey(key) => write!(f, "invalid canary key: {}", key),
+            CanaryError::InvalidPattern(pattern) => {
+                write!(f, "canary pattern must be A/D characters: {}", pattern)
+            }
+        }
+    }
+}
+
+// implement the Error trait for the CanaryError type
+impl Error for CanaryError {}
+
+// enum type to represent something a canary round found wrong
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CanaryAnomaly {
+    // the clock read earlier than on a previous probe
+    ClockWentBackwards {
+        previous: u64,
+        now: u64,
+    },
+    // a probe got a different decision than the expected pattern
+    UnexpectedDecision {
+        key: String,
+        probe: usize,
+        expected: bool,
+    },
+    // the stored TAT does not match the admissions just made
+    StateMismatch {
+        key: String,
+        expected_min: u64,
+        expected_max: u64,
+        found: Option<u64>,
+    },
+}
+
+// implement the Display trait for the CanaryAnomaly type
+impl fmt::Display for CanaryAnomaly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CanaryAnomaly::ClockWentBackwards { previous, now } => {
+                write!(f, "clock went backwards from {} to {}", previous, now)
+            }
+            CanaryAnomaly::UnexpectedDecision {
+                key,
+                probe,
+                expected,
+            } => write!(
+                f,
+                "canary {} probe {} was {} but expected {}",
+                key,
+                probe,
+                if *expected { "denied" } else { "allowed" },
+                if *expected { "allow" } else { "deny" }
+            ),
+            CanaryAnomaly::StateMismatch {
+                key,
+                expected_min,
+                expected_max,
+                found,
+            } => write!(
+                f,
+                "canary {} stored TAT {:?}, expected {}..={}",
+                key, found, expected_min, expected_max
+            ),
+        }
+    }
+}
+
+// struct type to represent one parsed canary
+#[derive(Debug, Clone)]
+struct Canary<T> {
+    key: T,
+    label: String,
+    expect: Option<Vec<bool>>,
+}
+
+// struct type to represent a set of canary keys that probe a limiter
+// each round resets the canary keys, replays their expected pattern and checks
+// the decisions, the stored state and the clock; canary keys should be reserved
+// values that real traffic never uses, since their state is reset every round
+#[derive(Debug)]
+pub struct Canaries<T> {
+    canaries: Vec<Canary<T>>,
+    interval: Duration,
+    last_now: AtomicU64,
+}
+
+// methods for the Canaries struct
+impl<T> Canaries<T>
+where
+    T: Hash + Eq + Clone + FromStr,
+{
+    // method to build the canaries from configuration, parsing each key
+    pub fn from_config(config: &CanaryConfig) -> Result<Self, CanaryError> {
+        let canaries = config
+            .canaries
+            .iter()
+            .map(|spec| {
+                let key = spec
+                    .key
+                    .parse()
+                    .map_err(|_| CanaryError::InvalidKey(spec.key.clone()))?;
+                let expect = match &spec.expect {
+                    Some(pattern) => Some(parse_pattern(pattern)?),
+                    None => None,
+                };
+                Ok(Canary {
+                    key,
+                    label: spec.key.clone(),
+                    expect,
+                })
+            })
+            .collect::<Result<_, CanaryError>>()?;
+
+        Ok(Self {
+            canaries,
+            interval: Duration::from_secs_f64(config.interval_secs.max(0.001)),
+            last_now: AtomicU64::new(0),
+        })
+    }
+
+    // method to run one round against the limiter, returning what went wrong
+    pub fn run_once<C: Clock>(&self, limiter: &RateLimiter<T, C>) -> Vec<CanaryAnomaly> {
+        let mut anomalies = Vec::new();
+        for canary in &self.canaries {
+            let state = limiter.client_state();
+            state.remove(&canary.key);
+
+            let expect = canary.expect.clone().unwrap_or_else(|| {
+                let burst = (limiter.tolerance_nanos() / limiter.increment_nanos()) as usize;
+                let mut pattern = vec![true; burst + 1];
+                pattern.push(false);
+                pattern
+            });
+
+            let start = limiter.now();
+            let previous = self.last_now.fetch_max(start, Ordering::Relaxed);
+            if start < previous {
+                anomalies.push(CanaryAnomaly::ClockWentBackwards {
+                    previous,
+                    now: start,
+                });
+            }
+
+            let mut admitted = 0;
+            for (probe, expected) in expect.iter().enumerate() {
+                let allowed = limiter.is_allowed(canary.key.clone()).unwrap_or(false);
+                admitted += u64::from(allowed);
+                if allowed != *expected {
+                    anomalies.push(CanaryAnomaly::UnexpectedDecision {
+                        key: canary.label.clone(),
+                        probe,
+                        expected: *expected,
+                    });
+                }
+            }
+            let end = limiter.now();
+
+            // from fresh state, each admission moves the TAT one increment past
+            // the time of the first probe
+            if admitted > 0 {
+                let charged = admitted * limiter.increment_nanos();
+                let found = state.get(&canary.key).map(|tat| *tat);
+                let (expected_min, expected_max) = (start + charged, end + charged);
+                if !found.is_some_and(|tat| tat >= expected_min && tat <= expected_max) {
+                    anomalies.push(CanaryAnomaly::StateMismatch {
+                        key: canary.label.clone(),
+                        expected_min,
+                        expected_max,
+                        found,
+                    });
+                }
+            }
+            state.remove(&canary.key);
+        }
+        anomalies
+    }
+
+    // method to probe the limiter every configured interval on a background
+    // thread, passing each round's anomalies to `on_anomaly`
+    pub fn spawn<C, F>(self, limiter: Arc<RateLimiter<T, C>>, on_anomaly: F) -> CanaryMonitor
+    where
+        T: Send + Sync + 'static,
+        C: Clock + 'static,
+        F: Fn(&CanaryAnomaly) + Send + 'static,
+    {
+        let shutdown = Arc::new((Mutex::new(false), Condvar::new()));
+        let signal = Arc::clone(&shutdown);
+        let worker = thread::spawn(move || {
+            let (lock, condvar) = &*signal;
+            let mut stopped = lock.lock().unwrap();
+            while !*stopped {
+                let (guard, _) = condvar.wait_timeout(stopped, self.interval).unwrap();
+                stopped = guard;
+                if !*stopped {
+                    self.run_once(&limiter).iter().for_each(&on_anomaly);
+                }
+            }
+        });
+
+        CanaryMonitor {
+            shutdown,
+            worker: Some(worker),
+        }
+    }
+}
+
+// parse an `A`/`D` pattern into expected decisions
+fn parse_pattern(pattern: &str) -> Result<Vec<bool>, CanaryError> {
+    pattern
+        .chars()
+        .map(|c| match c.to_ascii_uppercase() {
+            'A' => Ok(true),
+            'D' => Ok(false),
+            _ => Err(CanaryError::InvalidPattern(pattern.to_string())),
+        })
+        .collect()
+}
+
+// struct type to represent a running canary thread, stopped when dropped
+#[derive(Debug)]
+pub struct CanaryMonitor {
+    shutdown: Arc<(Mutex<bool>, Condvar)>,
+    worker: Option<JoinHandle<()>>,
+}
+
+// implement the Drop trait to stop the canary thread
+impl Drop for CanaryMonitor {
+    fn drop(&mut self) {
+        let (lock, signal) = &*self.shutdown;
+        *lock.lock().unwrap() = true;
+        signal.notify_all();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestClock;
+
+    fn config(expect: Option<&str>) -> CanaryConfig {
+        CanaryConfig {
+            interval_secs: 1.0,
+            canaries: vec![CanarySpec {
+                key: String::from("canary"),
+                expect: expect.map(String::from),
+            }],
+        }
+    }
+
+    #[test]
+    fn healthy_limiter_has_no_anomalies() {
+        let clock = TestClock::new(10.0);
+        let limiter = RateLimiter::<String, _>::new(1.0, 2.0, clock.clone()).unwrap();
+        let canaries = Canaries::from_config(&config(None)).unwrap();
+
+        assert!(canaries.run_once(&limiter).is_empty());
+        clock.advance(1.0);
+        assert!(canaries.run_once(&limiter).is_empty());
+        // canary state does not linger between rounds
+        assert!(limiter.client_state().is_empty());
+    }
+
+    #[test]
+    fn reports_wrong_decisions_and_clock_steps() {
+        let clock = TestClock::new(10.0);
+        let limiter = RateLimiter::<String, _>::new(1.0, 0.0, clock.clone()).unwrap();
+        let canaries = Canaries::from_config(&config(Some("AAD"))).unwrap();
+
+        assert_eq!(
+            canaries.run_once(&limiter),
+            vec![CanaryAnomaly::UnexpectedDecision {
+                key: String::from("canary"),
+                probe: 1,
+                expected: true,
+            }]
+        );
+
+        // a clock that jumps back is caught on the next round
+        let rewound = RateLimiter::<String, _>::new(1.0, 0.0, TestClock::new(5.0)).unwrap();
+        assert!(matches!(
+            canaries.run_once(&rewound)[0],
+            CanaryAnomaly::ClockWentBackwards {
+                previous: 10_000_000_000,
+                now: 5_000_000_000
+            }
+        ));
+    }
+
+    #[test]
+    fn rejects_bad_config() {
+        assert_eq!(
+            Canaries::<std::net::IpAddr>::from_config(&config(None)).unwrap_err(),
+            CanaryError::InvalidKey(String::from("canary"))
+        );
+        assert_eq!(
+            Canaries::<String>::from_config(&config(Some("AX"))).unwrap_err(),
+            CanaryError::InvalidPattern(String::from("AX"))
+        );
+    }
+}
@@ -0,0 +1,315 @@
+// src/lib/checkpoint.rs
+
+// dependencies
+use crate::SystemClock;
+use crate::clock::Clock;
+use crate::persistence::StoreKey;
+use crate::rate_limiter::RateLimiter;
+use crate::snapshot::{Snapshot, SnapshotError};
+use std::collections::hash_map::RandomState;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::hash::{BuildHasher, Hash};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+// enum type to represent errors reading or writing a checkpoint file
+#[derive(Debug)]
+pub enum PersistenceError {
+    Io(io::Error),           // the file could not be read, written or replaced
+    Snapshot(SnapshotError), // the file is not a snapshot this crate can read
+}
+
+// implement the Display trait for the PersistenceError type
+impl fmt::Display for PersistenceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PersistenceError::Io(e) => write!(f, "Checkpoint file error: {}", e),
+            PersistenceError::Snapshot(e) => write!(f, "Checkpoint file is unreadable: {}", e),
+        }
+    }
+}
+
+// implement the Error trait for the PersistenceError type
+impl Error for PersistenceError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PersistenceError::Io(e) => Some(e),
+            PersistenceError::Snapshot(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for PersistenceError {
+    fn from(e: io::Error) -> Self {
+        PersistenceError::Io(e)
+    }
+}
+
+impl From<SnapshotError> for PersistenceError {
+    fn from(e: SnapshotError) -> Self {
+        PersistenceError::Snapshot(e)
+    }
+}
+
+// struct type to represent a limiter's state checkpointed to a single file
+// each checkpoint is an encoded Snapshot written to a temporary file next to
+// the target and renamed over it, so a crash mid-write leaves the previous
+// checkpoint intact; for single-node deployments that restart often
+pub struct PersistenceManager<T, C = SystemClock, S = RandomState>
+where
+    T: StoreKey + Hash + Eq + Clone + Send + Sync + 'static,
+    C: Clock + 'static,
+    S: BuildHasher + Clone + Send + Sync + 'static,
+{
+    path: PathBuf,
+    epoch_nanos: u64,
+    limiter: Arc<RateLimiter<T, C, S>>,
+    restored: usize,
+    shutdown: Arc<(Mutex<bool>, Condvar)>,
+    checkpointer: Option<JoinHandle<()>>,
+    checkpoint_failed: Arc<AtomicBool>,
+}
+
+// methods for the PersistenceManager struct
+impl<T, C, S> PersistenceManager<T, C, S>
+where
+    T: StoreKey + Hash + Eq + Clone + Send + Sync + 'static,
+    C: Clock + 'static,
+    S: BuildHasher + Clone + Send + Sync + 'static,
+{
+    // method to warm-start the limiter from the checkpoint at `path`, if
+    // there is one, on a clock counting from the Unix epoch (SystemClock)
+    pub fn open(
+        path: impl AsRef<Path>,
+        limiter: Arc<RateLimiter<T, C, S>>,
+    ) -> Result<Self, PersistenceError> {
+        Self::open_with_epoch(path, limiter, 0)
+    }
+
+    // method to warm-start the limiter on a clock that read 0 at Unix time
+    // `epoch_nanos`; TATs are rebased as Snapshot::decode does
+    pub fn open_with_epoch(
+        path: impl AsRef<Path>,
+        limiter: Arc<RateLimiter<T, C, S>>,
+        epoch_nanos: u64,
+    ) -> Result<Self, PersistenceError> {
+        let path = path.as_ref().to_path_buf();
+        let restored = match fs::read(&path) {
+            Ok(bytes) => {
+                let snapshot = Snapshot::decode(&bytes, epoch_nanos)?;
+                limiter.merge(&snapshot);
+                snapshot.len()
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Self {
+            path,
+            epoch_nanos,
+            limiter,
+            restored,
+            shutdown: Arc::new((Mutex::new(false), Condvar::new())),
+            checkpointer: None,
+            checkpoint_failed: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    // accessor method to return the checkpoint file's path
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // accessor method to return how many keys the checkpoint read at open
+    // held; keys already idle were not restored into the limiter
+    pub fn restored(&self) -> usize {
+        self.restored
+    }
+
+    // method to checkpoint every key still holding state now, returning how
+    // many keys were written
+    pub fn checkpoint(&self) -> Result<usize, PersistenceError> {
+        write_checkpoint(&self.path, &self.limiter, self.epoch_nanos)
+    }
+
+    // method to start a background thread that checkpoints at the given
+    // interval; calling it again replaces the previous thread
+    pub fn spawn_checkpointer(&mut self, interval: Duration) {
+        self.stop_checkpointer();
+
+        let path = self.path.clone();
+        let epoch_nanos = self.epoch_nanos;
+        let limiter = Arc::clone(&self.limiter);
+        let shutdown = Arc::clone(&self.shutdown);
+        let checkpoint_failed = Arc::clone(&self.checkpoint_failed);
+
+        self.checkpointer = Some(thread::spawn(move || {
+            let (lock, signal) = &*shutdown;
+            let mut stopped = lock.lock().unwrap();
+            while !*stopped {
+                let (guard, _) = signal.wait_timeout(stopped, interval).unwrap();
+                stopped = guard;
+                if !*stopped && write_checkpoint(&path, &limiter, epoch_nanos).is_err() {
+                    checkpoint_failed.store(true, Ordering::Relaxed);
+                }
+            }
+        }));
+    }
+
+    // accessor method reporting whether a background checkpoint has failed
+    pub fn checkpoint_failed(&self) -> bool {
+        self.checkpoint_failed.load(Ordering::Relaxed)
+    }
+
+    // internal method to stop the background thread, if one is running
+    fn stop_checkpointer(&mut self) {
+        if let Some(handle) = self.checkpointer.take() {
+            let (lock, signal) = &*self.shutdown;
+            *lock.lock().unwrap() = true;
+            signal.notify_all();
+            let _ = handle.join();
+            *lock.lock().unwrap() = false;
+        }
+    }
+}
+
+// stop the checkpoint thread and write a final checkpoint when dropped, so a
+// clean shutdown loses nothing since the last interval
+impl<T, C, S> Drop for PersistenceManager<T, C, S>
+where
+    T: StoreKey + Hash + Eq + Clone + Send + Sync + 'static,
+    C: Clock + 'static,
+    S: BuildHasher + Clone + Send + Sync + 'static,
+{
+    fn drop(&mut self) {
+        self.stop_checkpointer();
+        let _ = self.checkpoint();
+    }
+}
+
+// implement the Debug trait for the PersistenceManager type
+impl<T, C, S> fmt::Debug for PersistenceManager<T, C, S>
+where
+    T: StoreKey + Hash + Eq + Clone + Send + Sync + 'static,
+    C: Clock + 'static,
+    S: BuildHasher + Clone + Send + Sync + 'static,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PersistenceManager")
+            .field("path", &self.path)
+            .field("epoch_nanos", &self.epoch_nanos)
+            .field("restored", &self.restored)
+            .finish_non_exhaustive()
+    }
+}
+
+// helper to write a checkpoint to a temporary file, flush it to disk and
+// rename it over `path`; the rename is atomic, so readers see either the old
+// checkpoint or the new one
+fn write_checkpoint<T, C, S>(
+    path: &Path,
+    limiter: &RateLimiter<T, C, S>,
+    epoch_nanos: u64,
+) -> Result<usize, PersistenceError>
+where
+    T: StoreKey + Hash + Eq + Clone,
+    C: Clock,
+    S: BuildHasher + Clone,
+{
+    let snapshot = limiter.snapshot();
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let temporary = PathBuf::from(temporary);
+
+    let mut file = File::create(&temporary)?;
+    file.write_all(&snapshot.encode(epoch_nanos))?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&temporary, path)?;
+    Ok(snapshot.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestClock;
+
+    fn checkpoint_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "gcra-checkpoint-{}-{}.bin",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn limiter(clock: &TestClock) -> Arc<RateLimiter<String, TestClock>> {
+        Arc::new(RateLimiter::new(1.0, 1.0, clock.clone()).unwrap())
+    }
+
+    #[test]
+    fn warm_starts_from_the_last_checkpoint() {
+        let path = checkpoint_path("warm");
+        let clock = TestClock::new(0.0);
+
+        let before = limiter(&clock);
+        let manager = PersistenceManager::open(&path, Arc::clone(&before)).unwrap();
+        assert_eq!(manager.restored(), 0);
+        assert!(before.is_allowed(String::from("spent")).unwrap());
+        assert!(before.is_allowed(String::from("spent")).unwrap());
+        assert_eq!(manager.checkpoint().unwrap(), 1);
+        drop(manager);
+
+        // the restarted limiter still holds the spent burst
+        let after = limiter(&clock);
+        let manager = PersistenceManager::open(&path, Arc::clone(&after)).unwrap();
+        assert_eq!(manager.restored(), 1);
+        assert!(!after.is_allowed(String::from("spent")).unwrap());
+        assert!(manager.path().with_extension("bin.tmp").metadata().is_err());
+
+        drop(manager);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn checkpoints_in_the_background_and_on_drop() {
+        let path = checkpoint_path("background");
+        let clock = TestClock::new(0.0);
+        let before = limiter(&clock);
+        let mut manager = PersistenceManager::open(&path, Arc::clone(&before)).unwrap();
+        manager.spawn_checkpointer(Duration::from_millis(5));
+
+        assert!(before.is_allowed(String::from("a")).unwrap());
+        thread::sleep(Duration::from_millis(50));
+        let written = Snapshot::<String>::decode(&fs::read(&path).unwrap(), 0).unwrap();
+        assert_eq!(written.len(), 1);
+        assert!(!manager.checkpoint_failed());
+
+        // keys charged after the last interval are written when dropped
+        assert!(before.is_allowed(String::from("b")).unwrap());
+        drop(manager);
+        let written = Snapshot::<String>::decode(&fs::read(&path).unwrap(), 0).unwrap();
+        assert_eq!(written.len(), 2);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn refuses_a_file_that_is_not_a_checkpoint() {
+        let path = checkpoint_path("foreign");
+        fs::write(&path, b"not a snapshot").unwrap();
+        let clock = TestClock::new(0.0);
+        let error = PersistenceManager::open(&path, limiter(&clock)).unwrap_err();
+        assert!(matches!(
+            error,
+            PersistenceError::Snapshot(SnapshotError::BadMagic)
+        ));
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -1,24 +1,235 @@
 // src/lib/clock.rs
 
 // dependencies
+#[cfg(feature = "async")]
+use async_trait::async_trait;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+// Source of the current time in nanoseconds for a limiter
+// the limiter expects readings never to go backwards (see ClockRegression
+// for when they do); the epoch is otherwise arbitrary, and only matters when TATs leave the process (shared
+// stores, snapshots merged between instances), where clocks must agree
 pub trait Clock: Send + Sync {
     fn now(&self) -> u64;
 }
 
+// Clocks that can also wait until a deadline (nanoseconds on the clock's own
+// timeline), for async waiters; a wake-up may come early if the clock is
+// stepped, so callers re-check the time after waking
+#[cfg(feature = "async")]
+#[async_trait]
+pub trait AsyncClock: Clock {
+    async fn sleep_until(&self, deadline: u64);
+}
+
+// What a limiter does when its clock reads earlier than a reading it has
+// already used, e.g. after an NTP step or a VM resume
+// Allow takes readings as they come, at no cost; Clamp holds time at the
+// latest reading until the clock catches up, so nothing moves backwards;
+// Error also clamps, but refuses every decision until the clock catches up.
+// Clamp and Error keep the latest reading in one shared atomic
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ClockRegression {
+    #[default]
+    Allow,
+    Clamp,
+    Error,
+}
+
+// Strategy for blocking the calling thread while a limiter waits for a slot
+// closures work as sleepers, e.g. to advance a TestClock in tests
+pub trait Sleeper {
+    fn sleep(&self, wait: Duration);
+}
+
+// Default sleeper: park the thread with std::thread::sleep
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ThreadSleeper;
+
+impl Sleeper for ThreadSleeper {
+    fn sleep(&self, wait: Duration) {
+        std::thread::sleep(wait)
+    }
+}
+
+impl<F: Fn(Duration)> Sleeper for F {
+    fn sleep(&self, wait: Duration) {
+        self(wait)
+    }
+}
+
+// sleep on the tokio timer for however long the clock says is left
+#[cfg(feature = "tokio")]
+async fn tokio_sleep_until<C: Clock>(clock: &C, deadline: u64) {
+    let wait = deadline.saturating_sub(clock.now());
+    if wait > 0 {
+        tokio::time::sleep(Duration::from_nanos(wait)).await;
+    }
+}
+
 // Default implementation using SystemTime
 #[derive(Debug, Clone)]
 pub struct SystemClock;
 
 impl Clock for SystemClock {
     fn now(&self) -> u64 {
+        // a wall clock set before the epoch reads 0, leaving the limiter's
+        // ClockRegression policy to deal with it
         SystemTime::now()
             .duration_since(UNIX_EPOCH)
-            .expect("System clock went backwards before Unix epoch")
-            .as_nanos() as u64
+            .map_or(0, |since_epoch| since_epoch.as_nanos() as u64)
+    }
+}
+
+#[cfg(feature = "tokio")]
+#[async_trait]
+impl AsyncClock for SystemClock {
+    async fn sleep_until(&self, deadline: u64) {
+        tokio_sleep_until(self, deadline).await
+    }
+}
+
+// Hybrid clock: monotonic at runtime, anchored to the wall clock at creation
+// readings are the Unix time of the anchor plus the monotonic time elapsed
+// since, so NTP steps cannot move them backwards while snapshots and shared
+// stores still see nanoseconds since the Unix epoch
+#[derive(Debug, Clone, Copy)]
+pub struct AnchoredClock {
+    anchor_nanos: u64,
+    started: Instant,
+}
+
+impl AnchoredClock {
+    pub fn new() -> Self {
+        Self {
+            anchor_nanos: SystemClock.now(),
+            started: Instant::now(),
+        }
+    }
+
+    // wall-clock time (nanoseconds since the Unix epoch) the clock was anchored at
+    pub fn anchor(&self) -> u64 {
+        self.anchor_nanos
+    }
+}
+
+impl Default for AnchoredClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for AnchoredClock {
+    fn now(&self) -> u64 {
+        self.anchor_nanos
+            .saturating_add(self.started.elapsed().as_nanos() as u64)
+    }
+}
+
+#[cfg(feature = "tokio")]
+#[async_trait]
+impl AsyncClock for AnchoredClock {
+    async fn sleep_until(&self, deadline: u64) {
+        tokio_sleep_until(self, deadline).await
+    }
+}
+
+// Monotonic clock with a process-local epoch: readings are the nanoseconds
+// elapsed since the clock (or the Instant it was given) was created
+// reading it is one Instant::now() with no wall-clock conversion, so it suits
+// hot loops such as game server ticks; TATs from it mean nothing to other
+// processes, so it is not for shared stores or snapshot exchange
+#[derive(Debug, Clone, Copy)]
+pub struct InstantClock {
+    epoch: Instant,
+}
+
+impl InstantClock {
+    pub fn new() -> Self {
+        Self::with_epoch(Instant::now())
+    }
+
+    // count from an existing Instant, so several clocks share one timeline
+    pub fn with_epoch(epoch: Instant) -> Self {
+        Self { epoch }
+    }
+
+    // the Instant readings count from
+    pub fn epoch(&self) -> Instant {
+        self.epoch
+    }
+}
+
+impl Default for InstantClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for InstantClock {
+    fn now(&self) -> u64 {
+        self.epoch.elapsed().as_nanos() as u64
+    }
+}
+
+#[cfg(feature = "tokio")]
+#[async_trait]
+impl AsyncClock for InstantClock {
+    async fn sleep_until(&self, deadline: u64) {
+        tokio::time::sleep_until((self.epoch + Duration::from_nanos(deadline)).into()).await;
+    }
+}
+
+// Clock driven by tokio's timer: readings are the Unix time at creation plus
+// the tokio time elapsed since, so under `tokio::time::pause()` the limiter
+// moves in lockstep with tokio's virtual time (`advance` and auto-advance)
+#[cfg(feature = "tokio")]
+#[derive(Debug, Clone, Copy)]
+pub struct TokioClock {
+    anchor_nanos: u64,
+    started: tokio::time::Instant,
+}
+
+#[cfg(feature = "tokio")]
+impl TokioClock {
+    // must be created inside a tokio runtime if time is paused, so that it
+    // reads the runtime's virtual clock
+    pub fn new() -> Self {
+        Self::starting_at(SystemClock.now())
+    }
+
+    // start the clock at a fixed reading, for reproducible tests
+    pub fn starting_at(anchor_nanos: u64) -> Self {
+        Self {
+            anchor_nanos,
+            started: tokio::time::Instant::now(),
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Default for TokioClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Clock for TokioClock {
+    fn now(&self) -> u64 {
+        self.anchor_nanos
+            .saturating_add(self.started.elapsed().as_nanos() as u64)
+    }
+}
+
+#[cfg(feature = "tokio")]
+#[async_trait]
+impl AsyncClock for TokioClock {
+    async fn sleep_until(&self, deadline: u64) {
+        let offset = deadline.saturating_sub(self.anchor_nanos);
+        tokio::time::sleep_until(self.started + Duration::from_nanos(offset)).await;
     }
 }
 
@@ -35,10 +246,57 @@ impl TestClock {
         }
     }
 
+    // start at an exact reading in nanoseconds, without float rounding
+    pub fn from_nanos(nanos: u64) -> Self {
+        Self {
+            time: Arc::new(AtomicU64::new(nanos)),
+        }
+    }
+
+    // start at a wall-clock time, in the same frame as SystemClock
+    pub fn from_system_time(at: SystemTime) -> Self {
+        let since_epoch = at
+            .duration_since(UNIX_EPOCH)
+            .expect("test time is before the Unix epoch");
+        Self::from_nanos(since_epoch.as_nanos() as u64)
+    }
+
     pub fn advance(&self, seconds: f64) {
         let nanos = (seconds * 1_000_000_000.0) as u64;
         self.time.fetch_add(nanos, Ordering::Relaxed);
     }
+
+    // move forward by an exact duration
+    pub fn advance_by(&self, by: Duration) -> &Self {
+        self.time.fetch_add(by.as_nanos() as u64, Ordering::Relaxed);
+        self
+    }
+
+    // move backward by an exact duration (stopping at 0), e.g. to simulate
+    // an NTP step or a VM resume
+    pub fn rewind_by(&self, by: Duration) -> &Self {
+        let by = by.as_nanos() as u64;
+        let _ = self
+            .time
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |time| {
+                Some(time.saturating_sub(by))
+            });
+        self
+    }
+
+    // move forward to an exact reading; earlier readings leave the clock alone
+    pub fn advance_to_nanos(&self, nanos: u64) -> &Self {
+        self.time.fetch_max(nanos, Ordering::Relaxed);
+        self
+    }
+
+    // move forward to a wall-clock time; earlier times leave the clock alone
+    pub fn advance_to(&self, at: SystemTime) -> &Self {
+        let since_epoch = at
+            .duration_since(UNIX_EPOCH)
+            .expect("test time is before the Unix epoch");
+        self.advance_to_nanos(since_epoch.as_nanos() as u64)
+    }
 }
 
 impl Clock for TestClock {
@@ -46,3 +304,122 @@ impl Clock for TestClock {
         self.time.load(Ordering::Relaxed)
     }
 }
+
+// Virtual time: sleeping jumps the clock straight to the deadline, so async
+// waiters complete immediately and deterministically
+#[cfg(feature = "async")]
+#[async_trait]
+impl AsyncClock for TestClock {
+    async fn sleep_until(&self, deadline: u64) {
+        self.time.fetch_max(deadline, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn anchored_clock_starts_at_wall_time_and_never_goes_back() {
+        let before = SystemClock.now();
+        let clock = AnchoredClock::new();
+        let after = SystemClock.now();
+
+        assert!(clock.anchor() >= before && clock.anchor() <= after);
+
+        let first = clock.now();
+        let second = clock.now();
+        assert!(first >= clock.anchor());
+        assert!(second >= first);
+
+        // copies share the same anchor
+        let copy = clock;
+        assert_eq!(copy.anchor(), clock.anchor());
+    }
+
+    #[test]
+    fn instant_clock_counts_from_its_epoch() {
+        let epoch = Instant::now();
+        let clock = InstantClock::with_epoch(epoch);
+        let first = clock.now();
+        let second = clock.now();
+        assert!(second >= first);
+        assert!(first <= epoch.elapsed().as_nanos() as u64);
+
+        // clocks sharing an epoch read the same timeline
+        let other = InstantClock::with_epoch(clock.epoch());
+        assert!(other.now() >= second);
+
+        let limiter = crate::RateLimiter::new(1.0, 0.0, clock).unwrap();
+        assert!(limiter.is_allowed("player").unwrap());
+        assert!(!limiter.is_allowed("player").unwrap());
+    }
+
+    #[test]
+    fn test_clock_advances_by_exact_durations() {
+        let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let clock = TestClock::from_system_time(start);
+        assert_eq!(clock.now(), 1_700_000_000_000_000_000);
+
+        // f64 seconds cannot represent this step exactly; Duration can
+        clock
+            .advance_by(Duration::from_nanos(1))
+            .advance_by(Duration::from_millis(100));
+        assert_eq!(clock.now(), 1_700_000_000_100_000_001);
+
+        clock.advance_to(start + Duration::from_secs(2));
+        assert_eq!(clock.now(), 1_700_000_002_000_000_000);
+        // advancing to the past does nothing
+        clock.advance_to_nanos(0);
+        assert_eq!(clock.now(), 1_700_000_002_000_000_000);
+        // rewinding does go back
+        clock.rewind_by(Duration::from_secs(1));
+        assert_eq!(clock.now(), 1_700_000_001_000_000_000);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_clock_sleep_advances_virtual_time() {
+        use std::task::{Context, Poll, Waker};
+
+        let clock = TestClock::new(1.0);
+        let mut sleep = clock.sleep_until(3_000_000_000);
+        let mut cx = Context::from_waker(Waker::noop());
+        assert_eq!(sleep.as_mut().poll(&mut cx), Poll::Ready(()));
+        assert_eq!(clock.now(), 3_000_000_000);
+
+        // deadlines in the past never move the clock backwards
+        let mut sleep = clock.sleep_until(2_000_000_000);
+        assert_eq!(sleep.as_mut().poll(&mut cx), Poll::Ready(()));
+        assert_eq!(clock.now(), 3_000_000_000);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn system_clock_sleeps_until_deadline() {
+        let deadline = SystemClock.now() + 5_000_000;
+        SystemClock.sleep_until(deadline).await;
+        assert!(SystemClock.now() >= deadline);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test(start_paused = true)]
+    async fn tokio_clock_follows_paused_time() {
+        let clock = TokioClock::starting_at(0);
+        assert_eq!(clock.now(), 0);
+
+        tokio::time::advance(Duration::from_secs(2)).await;
+        assert_eq!(clock.now(), 2_000_000_000);
+
+        // sleeping auto-advances virtual time straight to the deadline
+        clock.sleep_until(5_000_000_000).await;
+        assert_eq!(clock.now(), 5_000_000_000);
+
+        // a limiter on this clock refills as virtual time passes
+        let limiter = crate::RateLimiter::new(1.0, 0.0, clock).unwrap();
+        assert!(limiter.is_allowed("client1").unwrap());
+        assert!(!limiter.is_allowed("client1").unwrap());
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert!(limiter.is_allowed("client1").unwrap());
+    }
+}
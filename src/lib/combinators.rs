@@ -0,0 +1,248 @@
+// src/lib/combinators.rs
+
+// dependencies
+use crate::clock::Clock;
+use crate::concurrency::{ConcurrencyLimiter, ConcurrencyPermit};
+use crate::rate_limiter::RateLimiter;
+use std::hash::{BuildHasher, Hash};
+
+// trait for anything that can admit a request for a key and later undo it
+// `admit` returns a receipt when quota was charged; handing the receipt back
+// to `rollback` refunds exactly what that admission charged
+pub trait Policy<K> {
+    type Receipt;
+
+    // method to admit a request, charging quota only if it is allowed
+    fn admit(&self, key: &K) -> Option<Self::Receipt>;
+
+    // method to refund the quota charged by an earlier admission
+    fn rollback(&self, key: &K, receipt: Self::Receipt);
+
+    // method to admit a request without keeping the receipt
+    fn is_allowed(&self, key: &K) -> bool {
+        self.admit(key).is_some()
+    }
+
+    // method to combine two policies so a request must pass both
+    fn and<B: Policy<K>>(self, other: B) -> And<Self, B>
+    where
+        Self: Sized,
+    {
+        And {
+            first: self,
+            second: other,
+        }
+    }
+
+    // method to combine two policies so a request may pass either
+    fn or<B: Policy<K>>(self, other: B) -> Or<Self, B>
+    where
+        Self: Sized,
+    {
+        Or {
+            first: self,
+            second: other,
+        }
+    }
+}
+
+// the receipt records whether the admission charged the key's state, so
+// admissions by allow-all, the allowlist or shadow mode are not refunded
+impl<K, C, S> Policy<K> for RateLimiter<K, C, S>
+where
+    K: Hash + Eq + Clone,
+    C: Clock,
+    S: BuildHasher + Clone,
+{
+    type Receipt = bool;
+
+    fn admit(&self, key: &K) -> Option<bool> {
+        self.charge_applied(key.clone(), 1).ok()
+    }
+
+    fn rollback(&self, key: &K, charged: bool) {
+        if charged {
+            self.refund(key, 1);
+        }
+    }
+}
+
+// the receipt is the permit itself, so work admitted through a combination
+// stays in flight until its receipt is dropped or rolled back
+impl<K> Policy<K> for ConcurrencyLimiter<K>
+where
+    K: Hash + Eq + Clone,
+{
+    type Receipt = ConcurrencyPermit<K>;
+
+    fn admit(&self, key: &K) -> Option<ConcurrencyPermit<K>> {
+        self.try_acquire(key.clone())
+    }
+
+    fn rollback(&self, _key: &K, receipt: ConcurrencyPermit<K>) {
+        drop(receipt);
+    }
+}
+
+// references delegate, so limiters can be combined without giving them up
+impl<K, P: Policy<K>> Policy<K> for &P {
+    type Receipt = P::Receipt;
+
+    fn admit(&self, key: &K) -> Option<Self::Receipt> {
+        (**self).admit(key)
+    }
+
+    fn rollback(&self, key: &K, receipt: Self::Receipt) {
+        (**self).rollback(key, receipt)
+    }
+}
+
+// struct type to represent two policies that must both admit a request
+// if the second rejects, the first one's charge is rolled back
+#[derive(Debug, Clone)]
+pub struct And<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<K, A: Policy<K>, B: Policy<K>> Policy<K> for And<A, B> {
+    type Receipt = (A::Receipt, B::Receipt);
+
+    fn admit(&self, key: &K) -> Option<Self::Receipt> {
+        let first = self.first.admit(key)?;
+        match self.second.admit(key) {
+            Some(second) => Some((first, second)),
+            None => {
+                self.first.rollback(key, first);
+                None
+            }
+        }
+    }
+
+    fn rollback(&self, key: &K, (first, second): Self::Receipt) {
+        self.second.rollback(key, second);
+        self.first.rollback(key, first);
+    }
+}
+
+// struct type to represent two policies where either may admit a request
+// the first is tried before the second, and only the one that admitted is charged
+#[derive(Debug, Clone)]
+pub struct Or<A, B> {
+    first: A,
+    second: B,
+}
+
+// enum type to represent which side of an Or admitted a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrReceipt<RA, RB> {
+    First(RA),
+    Second(RB),
+}
+
+impl<K, A: Policy<K>, B: Policy<K>> Policy<K> for Or<A, B> {
+    type Receipt = OrReceipt<A::Receipt, B::Receipt>;
+
+    fn admit(&self, key: &K) -> Option<Self::Receipt> {
+        if let Some(receipt) = self.first.admit(key) {
+            return Some(OrReceipt::First(receipt));
+        }
+        self.second.admit(key).map(OrReceipt::Second)
+    }
+
+    fn rollback(&self, key: &K, receipt: Self::Receipt) {
+        match receipt {
+            OrReceipt::First(receipt) => self.first.rollback(key, receipt),
+            OrReceipt::Second(receipt) => self.second.rollback(key, receipt),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestClock;
+
+    #[test]
+    fn and_requires_both_and_rolls_back() {
+        let clock = TestClock::new(0.0);
+        let per_second = RateLimiter::new(1.0, 2.0, clock.clone()).unwrap(); // 3 at once
+        let strict = RateLimiter::new(1.0, 0.0, clock.clone()).unwrap(); // 1 at once
+
+        let policy = (&per_second).and(&strict);
+        assert!(policy.is_allowed(&"client1"));
+        assert!(!policy.is_allowed(&"client1"));
+
+        // the rejected call did not consume the looser limiter's quota
+        assert!(per_second.is_allowed("client1").unwrap());
+        assert!(per_second.is_allowed("client1").unwrap());
+        assert!(!per_second.is_allowed("client1").unwrap());
+    }
+
+    #[test]
+    fn or_charges_only_the_admitting_side() {
+        let clock = TestClock::new(0.0);
+        let primary = RateLimiter::new(1.0, 0.0, clock.clone()).unwrap();
+        let overflow = RateLimiter::new(1.0, 1.0, clock.clone()).unwrap();
+
+        let policy = (&primary).or(&overflow);
+        assert_eq!(policy.admit(&"client1"), Some(OrReceipt::First(true)));
+        assert_eq!(policy.admit(&"client1"), Some(OrReceipt::Second(true)));
+        assert_eq!(policy.admit(&"client1"), Some(OrReceipt::Second(true)));
+        assert_eq!(policy.admit(&"client1"), None);
+    }
+
+    #[test]
+    fn rollback_refunds_composite_receipts() {
+        let clock = TestClock::new(0.0);
+        let a = RateLimiter::new(1.0, 0.0, clock.clone()).unwrap();
+        let b = RateLimiter::new(1.0, 0.0, clock.clone()).unwrap();
+        let c = RateLimiter::new(1.0, 0.0, clock.clone()).unwrap();
+
+        let policy = (&a).and(&b).or(&c);
+        let receipt = policy.admit(&"client1").unwrap();
+        policy.rollback(&"client1", receipt);
+
+        // everything was refunded, so each limiter admits again
+        assert!(a.is_allowed("client1").unwrap());
+        assert!(b.is_allowed("client1").unwrap());
+        assert!(c.is_allowed("client1").unwrap());
+    }
+
+    #[test]
+    fn rollback_skips_admissions_that_charged_nothing() {
+        let clock = TestClock::new(0.0);
+        let shadowed = RateLimiter::new(1.0, 0.0, clock.clone()).unwrap();
+        let strict = RateLimiter::new(1.0, 0.0, clock.clone()).unwrap();
+        assert!(shadowed.is_allowed("client1").unwrap());
+        assert!(strict.is_allowed("client1").unwrap());
+        let tat = shadowed.peek(&"client1").tat_nanos();
+
+        // the shadowed denial is admitted uncharged, so the second limiter's
+        // denial must not roll it back into credit
+        shadowed.set_shadow(true);
+        assert_eq!((&shadowed).and(&strict).admit(&"client1"), None);
+        assert_eq!(shadowed.peek(&"client1").tat_nanos(), tat);
+    }
+
+    #[test]
+    fn rate_and_concurrency_hold_the_permit_in_the_receipt() {
+        let clock = TestClock::new(0.0);
+        let rate = RateLimiter::new(1.0, 2.0, clock.clone()).unwrap(); // 3 at once
+        let concurrency = ConcurrencyLimiter::new(1);
+
+        let policy = (&rate).and(&concurrency);
+        let (_, permit) = policy.admit(&"client1").unwrap();
+        assert_eq!(permit.key(), &"client1");
+
+        // the second request has rate to spare but nothing left in flight,
+        // and its rate charge is refunded
+        assert!(policy.admit(&"client1").is_none());
+        assert_eq!(rate.peek(&"client1").remaining_burst(), 1);
+
+        drop(permit);
+        assert!(policy.is_allowed(&"client1"));
+        assert_eq!(concurrency.in_flight(&"client1"), 0);
+        assert_eq!(rate.peek(&"client1").remaining_burst(), 0);
+    }
+}
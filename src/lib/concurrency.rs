@@ -0,0 +1,133 @@
+// src/lib/concurrency.rs
+
+// dependencies
+use dashmap::DashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::sync::Arc;
+
+// struct type to represent a per-key cap on work in flight
+// where the rate limiter bounds how often a key may start work, this bounds
+// how much of it may run at once; a permit is held for the duration of the
+// work and released when dropped
+#[derive(Debug, Clone)]
+pub struct ConcurrencyLimiter<T>
+where
+    T: Hash + Eq + Clone,
+{
+    max_in_flight: usize,
+    in_flight: Arc<DashMap<T, usize>>,
+}
+
+// methods for the ConcurrencyLimiter struct
+impl<T> ConcurrencyLimiter<T>
+where
+    T: Hash + Eq + Clone,
+{
+    // method to create a limiter allowing `max_in_flight` permits per key
+    pub fn new(max_in_flight: usize) -> Self {
+        Self {
+            max_in_flight,
+            in_flight: Arc::new(DashMap::new()),
+        }
+    }
+
+    // accessor method to return the per-key cap
+    pub fn max_in_flight(&self) -> usize {
+        self.max_in_flight
+    }
+
+    // method to take a permit for a key, or None if the key is at its cap
+    pub fn try_acquire(&self, key: T) -> Option<ConcurrencyPermit<T>> {
+        if self.max_in_flight == 0 {
+            return None;
+        }
+        let mut count = self.in_flight.entry(key.clone()).or_insert(0);
+        if *count >= self.max_in_flight {
+            return None;
+        }
+        *count += 1;
+        drop(count);
+        Some(ConcurrencyPermit {
+            key,
+            in_flight: Arc::clone(&self.in_flight),
+        })
+    }
+
+    // accessor method to return how many permits a key holds
+    pub fn in_flight(&self, key: &T) -> usize {
+        self.in_flight.get(key).map_or(0, |count| *count)
+    }
+}
+
+// struct type to represent one unit of in-flight work for a key
+// keys are forgotten once their last permit is released, so the map only
+// holds keys with work in flight
+pub struct ConcurrencyPermit<T>
+where
+    T: Hash + Eq + Clone,
+{
+    key: T,
+    in_flight: Arc<DashMap<T, usize>>,
+}
+
+// methods for the ConcurrencyPermit struct
+impl<T> ConcurrencyPermit<T>
+where
+    T: Hash + Eq + Clone,
+{
+    // accessor method to return the key the permit was taken for
+    pub fn key(&self) -> &T {
+        &self.key
+    }
+}
+
+// implement the Drop trait to release the permit
+impl<T> Drop for ConcurrencyPermit<T>
+where
+    T: Hash + Eq + Clone,
+{
+    fn drop(&mut self) {
+        if let Some(mut count) = self.in_flight.get_mut(&self.key) {
+            *count = count.saturating_sub(1);
+        }
+        self.in_flight.remove_if(&self.key, |_, count| *count == 0);
+    }
+}
+
+// implement the Debug trait for the ConcurrencyPermit type
+impl<T> fmt::Debug for ConcurrencyPermit<T>
+where
+    T: Hash + Eq + Clone + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ConcurrencyPermit")
+            .field("key", &self.key)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn caps_permits_per_key_and_releases_on_drop() {
+        let limiter = ConcurrencyLimiter::new(2);
+        let first = limiter.try_acquire("a").unwrap();
+        let second = limiter.try_acquire("a").unwrap();
+        assert!(limiter.try_acquire("a").is_none());
+        assert!(limiter.try_acquire("b").is_some());
+        assert_eq!(limiter.in_flight(&"a"), 2);
+
+        drop(first);
+        assert_eq!(limiter.in_flight(&"a"), 1);
+        let third = limiter.try_acquire("a").unwrap();
+        assert_eq!(third.key(), &"a");
+
+        drop(second);
+        drop(third);
+        assert_eq!(limiter.in_flight(&"a"), 0);
+        assert!(limiter.in_flight.is_empty());
+    }
+}
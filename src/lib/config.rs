@@ -0,0 +1,135 @@
+// src/lib/config.rs
+
+// dependencies
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use std::error::Error;
+use std::fmt;
+
+// struct type to represent one invalid field in a configuration
+// `path` uses serde's field path notation, e.g. `routes[3].quota.rate`, so
+// tools can point at the exact value that was rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct FieldError {
+    pub path: String,
+    pub value: String,
+    pub reason: &'static str,
+}
+
+// methods for the FieldError struct
+impl FieldError {
+    pub(crate) fn new(path: String, value: impl fmt::Debug, reason: &'static str) -> Self {
+        Self {
+            path,
+            value: format!("{:?}", value),
+            reason,
+        }
+    }
+}
+
+// implement the Display trait for the FieldError type
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {} (got {})", self.path, self.reason, self.value)
+    }
+}
+
+// enum type to represent a configuration that could not be loaded
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", serde(tag = "kind", rename_all = "snake_case"))]
+pub enum ConfigError {
+    // the text is not valid TOML or does not fit the expected shape;
+    // line and column are 1-based
+    Parse {
+        message: String,
+        line: Option<usize>,
+        column: Option<usize>,
+    },
+    // the text parsed, but these fields hold values the limiter cannot use
+    Invalid {
+        fields: Vec<FieldError>,
+    },
+}
+
+// methods for the ConfigError enum
+impl ConfigError {
+    // method to convert a TOML error, locating its span in the source text
+    #[cfg(feature = "config")]
+    pub(crate) fn from_toml(error: toml::de::Error, text: &str) -> Self {
+        let position = error.span().map(|span| {
+            let before = &text[..span.start.min(text.len())];
+            let line = before.matches('\n').count() + 1;
+            let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+            (line, column)
+        });
+        ConfigError::Parse {
+            message: error.message().to_string(),
+            line: position.map(|(line, _)| line),
+            column: position.map(|(_, column)| column),
+        }
+    }
+
+    // method to turn collected field errors into a result
+    pub(crate) fn check(fields: Vec<FieldError>) -> Result<(), Self> {
+        if fields.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Invalid { fields })
+        }
+    }
+}
+
+// implement the Display trait for the ConfigError type
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Parse {
+                message,
+                line: Some(line),
+                column: Some(column),
+            } => write!(f, "line {}, column {}: {}", line, column, message),
+            ConfigError::Parse { message, .. } => write!(f, "{}", message),
+            ConfigError::Invalid { fields } => {
+                let lines: Vec<String> = fields.iter().map(FieldError::to_string).collect();
+                write!(f, "{}", lines.join("\n"))
+            }
+        }
+    }
+}
+
+// implement the Error trait for the ConfigError type
+impl Error for ConfigError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn field_errors_name_path_and_value() {
+        let error = ConfigError::check(vec![
+            FieldError::new(String::from("routes[3].quota.rate"), 0.0, "must be > 0"),
+            FieldError::new(String::from("routes[4].path"), "api", "must start with /"),
+        ])
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "routes[3].quota.rate: must be > 0 (got 0.0)\nroutes[4].path: must start with / (got \"api\")"
+        );
+        assert_eq!(ConfigError::check(Vec::new()), Ok(()));
+    }
+
+    #[cfg(feature = "config")]
+    #[test]
+    fn parse_errors_report_line_and_column() {
+        let text = "[[routes]]\npath = \"/\"\nquota = { rate = \"fast\" }\n";
+        match crate::RouteConfig::load(text).unwrap_err() {
+            ConfigError::Parse { line, column, .. } => {
+                assert_eq!(line, Some(3));
+                assert_eq!(column, Some(18));
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,179 @@
+// src/lib/cost_guard.rs
+
+// dependencies
+use crate::clock::Clock;
+use crate::rate_limiter::RateLimiter;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+
+// struct type to represent quota charged for an operation that has not finished
+// dropping the guard without calling commit() refunds the charged cost, if
+// the admission charged any
+pub struct CostGuard<'a, T, C, S = RandomState>
+where
+    T: Hash + Eq + Clone,
+    C: Clock,
+    S: BuildHasher + Clone,
+{
+    limiter: &'a RateLimiter<T, C, S>,
+    client_id: T,
+    cost: u32,
+    charged: bool,
+    committed: bool,
+}
+
+// a single request's tentative charge, as returned by RateLimiter::reserve,
+// e.g. held by a job waiting in a queue until it runs or is shed
+pub type Reservation<'a, T, C, S = RandomState> = CostGuard<'a, T, C, S>;
+
+// methods for the CostGuard struct
+impl<'a, T, C, S> CostGuard<'a, T, C, S>
+where
+    T: Hash + Eq + Clone,
+    C: Clock,
+    S: BuildHasher + Clone,
+{
+    pub(crate) fn new(
+        limiter: &'a RateLimiter<T, C, S>,
+        client_id: T,
+        cost: u32,
+        charged: bool,
+    ) -> Self {
+        Self {
+            limiter,
+            client_id,
+            cost,
+            charged,
+            committed: false,
+        }
+    }
+
+    // accessor method to return the key the cost was charged to
+    pub fn key(&self) -> &T {
+        &self.client_id
+    }
+
+    // accessor method to return the charged cost
+    pub fn cost(&self) -> u32 {
+        self.cost
+    }
+
+    // method to keep the charge once the operation has done real work
+    pub fn commit(mut self) {
+        self.committed = true;
+    }
+
+    // method to refund the charge now, e.g. when a queued job is shed;
+    // the same as dropping the guard, but explicit at the call site
+    pub fn cancel(self) {}
+}
+
+// refund the cost if the guard goes away without being committed
+impl<T, C, S> Drop for CostGuard<'_, T, C, S>
+where
+    T: Hash + Eq + Clone,
+    C: Clock,
+    S: BuildHasher + Clone,
+{
+    fn drop(&mut self) {
+        if self.charged && !self.committed {
+            self.limiter.refund(&self.client_id, self.cost);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{RateLimiter, TestClock};
+    use std::time::Duration;
+
+    #[test]
+    fn committed_cost_stays_charged() {
+        let clock = TestClock::new(0.0);
+        let limiter = RateLimiter::new(1.0, 2.0, clock).unwrap(); // 3 units of capacity
+
+        let guard = limiter.try_begin("client1", 3).unwrap();
+        assert_eq!(guard.cost(), 3);
+        guard.commit();
+
+        assert!(!limiter.is_allowed("client1").unwrap());
+    }
+
+    #[test]
+    fn dropped_guard_refunds_cost() {
+        let clock = TestClock::new(0.0);
+        let limiter = RateLimiter::new(1.0, 2.0, clock).unwrap();
+
+        {
+            let _guard = limiter.try_begin("client1", 3).unwrap();
+            assert!(!limiter.is_allowed("client1").unwrap());
+        }
+
+        // the full burst is available again after the refund
+        let guard = limiter.try_begin("client1", 3).unwrap();
+        guard.commit();
+    }
+
+    #[test]
+    fn denied_reports_retry_after() {
+        let clock = TestClock::new(0.0);
+        let limiter = RateLimiter::new(1.0, 0.0, clock).unwrap();
+
+        limiter.try_begin("client1", 1).unwrap().commit();
+        let denied = limiter.try_begin("client1", 1).err().unwrap();
+        assert_eq!(denied.retry_after(), Some(Duration::from_secs(1)));
+
+        // a cost larger than the bucket can never be admitted
+        let denied = limiter.try_begin("client2", 2).err().unwrap();
+        assert_eq!(denied.retry_after(), None);
+    }
+
+    #[test]
+    fn reservations_hold_quota_until_cancelled() {
+        let clock = TestClock::new(0.0);
+        let limiter = RateLimiter::new(1.0, 1.0, clock).unwrap();
+
+        let first = limiter.reserve("client1").unwrap();
+        let second = limiter.reserve("client1").unwrap();
+        assert_eq!(second.key(), &"client1");
+        assert!(limiter.reserve("client1").is_err());
+
+        // a shed job gives its slot to the next one
+        second.cancel();
+        let third = limiter.reserve("client1").unwrap();
+        first.commit();
+        third.commit();
+        assert!(limiter.reserve("client1").is_err());
+    }
+
+    #[test]
+    fn shadow_admissions_are_not_refunded() {
+        let clock = TestClock::new(0.0);
+        let limiter = RateLimiter::new(1.0, 0.0, clock).unwrap();
+        limiter.reserve("k").unwrap().commit();
+        let tat = limiter.peek(&"k").tat_nanos();
+
+        // shadow mode admits the exhausted key without charging it
+        limiter.set_shadow(true);
+        drop(limiter.reserve("k").unwrap());
+        assert_eq!(limiter.peek(&"k").tat_nanos(), tat);
+    }
+
+    #[test]
+    fn allow_all_and_allowlisted_guards_are_not_refunded() {
+        let clock = TestClock::new(0.0);
+        let limiter = RateLimiter::new(1.0, 0.0, clock).unwrap();
+        limiter.reserve("k").unwrap().commit();
+        let tat = limiter.peek(&"k").tat_nanos();
+
+        // neither fast path charged the key, so neither may lower its TAT
+        limiter.set_allow_all(true);
+        limiter.try_begin("k", 1).unwrap().cancel();
+        limiter.set_allow_all(false);
+        limiter.allowlist("k");
+        limiter.reserve("k").unwrap().cancel();
+        limiter.unlist(&"k");
+        assert_eq!(limiter.peek(&"k").tat_nanos(), tat);
+        assert!(limiter.reserve("k").is_err());
+    }
+}
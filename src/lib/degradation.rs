@@ -0,0 +1,424 @@
+// src/lib/degradation.rs
+
+// dependencies
+use crate::SystemClock;
+use crate::clock::Clock;
+use crate::config::{ConfigError, FieldError};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Mutex;
+#[cfg(feature = "tokio")]
+use std::sync::{
+    Arc,
+    atomic::{AtomicU64, Ordering},
+};
+#[cfg(feature = "tokio")]
+use std::time::Duration;
+
+// enum type to represent how important a request is when shedding load
+// ordered from least to most important
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    Critical, // never shed
+}
+
+// struct type to represent one rung of the ladder: once utilization reaches
+// `utilization`, requests of priority `shed` and below are denied
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Rung {
+    pub utilization: f64, // fraction of capacity, e.g. 0.8
+    pub shed: Priority,
+}
+
+// struct type to represent a declarative overload playbook
+// `capacity` is the admit rate per second that counts as full utilization,
+// measured over a sliding window of `window_secs`
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LadderConfig {
+    pub capacity: f64,
+    #[cfg_attr(feature = "serde", serde(default = "default_window"))]
+    pub window_secs: f64,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub rungs: Vec<Rung>,
+}
+
+#[cfg(feature = "serde")]
+fn default_window() -> f64 {
+    1.0
+}
+
+// methods for the LadderConfig struct
+impl LadderConfig {
+    // method to parse and validate a ladder from TOML text
+    #[cfg(feature = "config")]
+    pub fn load(text: &str) -> Result<Self, ConfigError> {
+        let config: Self = toml::from_str(text).map_err(|e| ConfigError::from_toml(e, text))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    // method to check the capacity, window and every rung
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut fields = Vec::new();
+        if !(self.capacity > 0.0 && self.capacity.is_finite()) {
+            fields.push(FieldError::new(
+                String::from("capacity"),
+                self.capacity,
+                "must be > 0",
+            ));
+        }
+        if !(self.window_secs > 0.0 && self.window_secs.is_finite()) {
+            fields.push(FieldError::new(
+                String::from("window_secs"),
+                self.window_secs,
+                "must be > 0",
+            ));
+        }
+        for (i, rung) in self.rungs.iter().enumerate() {
+            if !(rung.utilization >= 0.0 && rung.utilization.is_finite()) {
+                fields.push(FieldError::new(
+                    format!("rungs[{}].utilization", i),
+                    rung.utilization,
+                    "must be >= 0",
+                ));
+            }
+            if rung.shed == Priority::Critical {
+                fields.push(FieldError::new(
+                    format!("rungs[{}].shed", i),
+                    rung.shed,
+                    "critical traffic is never shed",
+                ));
+            }
+        }
+        ConfigError::check(fields)
+    }
+}
+
+// trait for host load signals a ladder sheds on alongside its admit rate,
+// e.g. how saturated the async runtime serving the requests is
+pub trait LoadSignal: Send + Sync {
+    // the current load as a fraction of what the host can take
+    fn load(&self) -> f64;
+}
+
+// closures can be used directly as signals, e.g. to feed in the mean poll
+// duration reported by tokio-metrics
+impl<F> LoadSignal for F
+where
+    F: Fn() -> f64 + Send + Sync,
+{
+    fn load(&self) -> f64 {
+        self()
+    }
+}
+
+// struct type to represent the load signals attached to a ladder
+struct Signals(Vec<Box<dyn LoadSignal>>);
+
+// implement the Debug trait for the Signals type
+impl fmt::Debug for Signals {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Signals({})", self.0.len())
+    }
+}
+
+// struct type to represent admissions counted in the current and previous window
+#[derive(Debug)]
+struct Window {
+    start: u64,
+    current: u64,
+    previous: u64,
+}
+
+// struct type to represent a degradation ladder evaluated against the
+// observed admit rate
+// the rate is a sliding-window estimate: the previous window's count,
+// weighted by how much of it still overlaps, plus the current window's count
+#[derive(Debug)]
+pub struct DegradationLadder<C: Clock = SystemClock> {
+    rungs: Vec<Rung>,
+    capacity: f64,
+    window_nanos: u64,
+    window: Mutex<Window>,
+    signals: Signals,
+    clock: C,
+}
+
+// methods for the DegradationLadder struct
+impl<C: Clock> DegradationLadder<C> {
+    // method to build a ladder from a validated configuration
+    pub fn new(config: &LadderConfig, clock: C) -> Result<Self, ConfigError> {
+        config.validate()?;
+        let mut rungs = config.rungs.clone();
+        rungs.sort_by(|a, b| a.utilization.total_cmp(&b.utilization));
+        let now = clock.now();
+        Ok(Self {
+            rungs,
+            capacity: config.capacity,
+            window_nanos: ((config.window_secs * 1_000_000_000.0) as u64).max(1),
+            window: Mutex::new(Window {
+                start: now,
+                current: 0,
+                previous: 0,
+            }),
+            signals: Signals(Vec::new()),
+            clock,
+        })
+    }
+
+    // method to also shed on a host load signal; the ladder then climbs on
+    // whichever of its admit rate and its signals is highest, so it tightens
+    // when the runtime saturates even if the admit rate looks healthy
+    pub fn with_signal(mut self, signal: impl LoadSignal + 'static) -> Self {
+        self.signals.0.push(Box::new(signal));
+        self
+    }
+
+    // internal method to roll the window forward to `now`
+    fn roll(&self, window: &mut Window, now: u64) {
+        let elapsed = now.saturating_sub(window.start);
+        if elapsed >= self.window_nanos {
+            let windows = elapsed / self.window_nanos;
+            window.previous = if windows == 1 { window.current } else { 0 };
+            window.current = 0;
+            window.start += windows * self.window_nanos;
+        }
+    }
+
+    // method to return the observed admit rate as a fraction of capacity,
+    // or the highest load signal if that is higher
+    pub fn utilization(&self) -> f64 {
+        let load = self.signals.0.iter().map(|signal| signal.load());
+        load.fold(self.admit_utilization(), f64::max)
+    }
+
+    // method to return the observed admit rate alone as a fraction of capacity
+    pub fn admit_utilization(&self) -> f64 {
+        let now = self.clock.now();
+        let mut window = self.window.lock().unwrap();
+        self.roll(&mut window, now);
+        let overlap = 1.0 - now.saturating_sub(window.start) as f64 / self.window_nanos as f64;
+        let admitted = window.previous as f64 * overlap + window.current as f64;
+        let window_secs = self.window_nanos as f64 / 1_000_000_000.0;
+        admitted / window_secs / self.capacity
+    }
+
+    // method to return the highest rung currently reached, if any
+    pub fn active_rung(&self) -> Option<Rung> {
+        let utilization = self.utilization();
+        self.rungs
+            .iter()
+            .rev()
+            .find(|rung| utilization >= rung.utilization)
+            .copied()
+    }
+
+    // method to check whether the ladder lets a request of this priority through
+    pub fn allows(&self, priority: Priority) -> bool {
+        priority == Priority::Critical || self.active_rung().is_none_or(|rung| priority > rung.shed)
+    }
+
+    // method to count an admission made by the limiter towards utilization
+    pub fn record_admit(&self) {
+        let now = self.clock.now();
+        let mut window = self.window.lock().unwrap();
+        self.roll(&mut window, now);
+        window.current += 1;
+    }
+
+    // method to check a request and, if the ladder allows it, count it
+    pub fn admit(&self, priority: Priority) -> bool {
+        let allowed = self.allows(priority);
+        if allowed {
+            self.record_admit();
+        }
+        allowed
+    }
+}
+
+// struct type to represent the saturation of the tokio runtime it runs on
+// a probe task sleeps for `interval` in a loop and records how late it wakes
+// up, which grows when workers are busy or blocked; the load is the larger of
+// that lag over `max_lag` and the global queue depth over `max_queue_depth`
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+pub struct RuntimeLoad {
+    handle: tokio::runtime::Handle,
+    max_queue_depth: usize,
+    max_lag: Duration,
+    lag_nanos: Arc<AtomicU64>,
+    probe: tokio::task::JoinHandle<()>,
+}
+
+// methods for the RuntimeLoad struct
+#[cfg(feature = "tokio")]
+impl RuntimeLoad {
+    // method to start probing the current runtime; panics outside a runtime
+    pub fn spawn(max_queue_depth: usize, max_lag: Duration, interval: Duration) -> Self {
+        let handle = tokio::runtime::Handle::current();
+        let lag_nanos = Arc::new(AtomicU64::new(0));
+        let probe = handle.spawn({
+            let lag_nanos = Arc::clone(&lag_nanos);
+            async move {
+                loop {
+                    let due = tokio::time::Instant::now() + interval;
+                    tokio::time::sleep_until(due).await;
+                    let lag = tokio::time::Instant::now().saturating_duration_since(due);
+                    lag_nanos.store(lag.as_nanos() as u64, Ordering::Relaxed);
+                }
+            }
+        });
+        Self {
+            handle,
+            max_queue_depth: max_queue_depth.max(1),
+            max_lag,
+            lag_nanos,
+            probe,
+        }
+    }
+
+    // accessor method to return how late the probe last woke up
+    pub fn lag(&self) -> Duration {
+        Duration::from_nanos(self.lag_nanos.load(Ordering::Relaxed))
+    }
+
+    // accessor method to return how many tasks wait in the global queue
+    pub fn queue_depth(&self) -> usize {
+        self.handle.metrics().global_queue_depth()
+    }
+}
+
+// implement the LoadSignal trait for the runtime's saturation
+#[cfg(feature = "tokio")]
+impl LoadSignal for RuntimeLoad {
+    fn load(&self) -> f64 {
+        let lag = self.lag().as_secs_f64() / self.max_lag.as_secs_f64();
+        let queue = self.queue_depth() as f64 / self.max_queue_depth as f64;
+        lag.max(queue)
+    }
+}
+
+// implement the Drop trait to stop the probe task
+#[cfg(feature = "tokio")]
+impl Drop for RuntimeLoad {
+    fn drop(&mut self) {
+        self.probe.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestClock;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    fn config() -> LadderConfig {
+        LadderConfig {
+            capacity: 10.0,
+            window_secs: 1.0,
+            rungs: vec![
+                Rung {
+                    utilization: 0.95,
+                    shed: Priority::Normal,
+                },
+                Rung {
+                    utilization: 0.8,
+                    shed: Priority::Low,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn sheds_lower_priorities_as_utilization_climbs() {
+        let clock = TestClock::new(0.0);
+        let ladder = DegradationLadder::new(&config(), clock.clone()).unwrap();
+
+        (0..8).for_each(|_| assert!(ladder.admit(Priority::Normal)));
+        assert_eq!(ladder.active_rung().unwrap().shed, Priority::Low);
+        assert!(!ladder.admit(Priority::Low));
+
+        (0..2).for_each(|_| assert!(ladder.admit(Priority::Normal)));
+        assert!(!ladder.allows(Priority::Normal));
+        assert!(ladder.admit(Priority::Critical));
+
+        // half a window later, half of the earlier admissions still count
+        clock.advance(1.5);
+        assert!((ladder.utilization() - 0.55).abs() < 1e-9);
+        assert!(ladder.allows(Priority::Low));
+        clock.advance(1.0);
+        assert_eq!(ladder.utilization(), 0.0);
+    }
+
+    #[test]
+    fn load_signals_raise_utilization() {
+        let clock = TestClock::new(0.0);
+        let saturation = Arc::new(AtomicU64::new(0));
+        let signal = Arc::clone(&saturation);
+        let ladder = DegradationLadder::new(&config(), clock.clone())
+            .unwrap()
+            .with_signal(move || signal.load(Ordering::Relaxed) as f64 / 100.0);
+
+        (0..5).for_each(|_| assert!(ladder.admit(Priority::Low)));
+        assert_eq!(ladder.utilization(), 0.5);
+        // the runtime saturates while the admit rate stays the same
+        saturation.store(96, Ordering::Relaxed);
+        assert_eq!(ladder.admit_utilization(), 0.5);
+        assert!(!ladder.allows(Priority::Normal));
+        assert!(ladder.allows(Priority::Critical));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn runtime_load_sees_a_blocked_runtime() {
+        let load = RuntimeLoad::spawn(64, Duration::from_millis(50), Duration::from_millis(5));
+        // let the probe start sleeping, then block the only worker past its
+        // wake-up time and give it a turn to notice
+        tokio::task::yield_now().await;
+        std::thread::sleep(Duration::from_millis(100));
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        tokio::task::yield_now().await;
+
+        assert!(load.lag() >= Duration::from_millis(50));
+        assert!(load.load() >= 1.0);
+        assert_eq!(load.queue_depth(), 0);
+    }
+
+    #[test]
+    fn rejects_ladders_that_shed_critical_traffic() {
+        let mut config = config();
+        config.rungs[1].shed = Priority::Critical;
+        config.capacity = 0.0;
+        let Err(ConfigError::Invalid { fields }) = config.validate() else {
+            panic!("expected field errors");
+        };
+        let paths: Vec<&str> = fields.iter().map(|field| field.path.as_str()).collect();
+        assert_eq!(paths, vec!["capacity", "rungs[1].shed"]);
+    }
+
+    #[cfg(feature = "config")]
+    #[test]
+    fn parses_toml_ladder() {
+        let config = LadderConfig::load(
+            r#"
+            capacity = 500.0
+            rungs = [
+                { utilization = 0.8, shed = "low" },
+                { utilization = 0.95, shed = "normal" },
+            ]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.window_secs, 1.0);
+        assert_eq!(config.rungs[1].shed, Priority::Normal);
+    }
+}
@@ -0,0 +1,169 @@
+// src/lib/direct.rs
+
+// dependencies
+use crate::SystemClock;
+use crate::clock::Clock;
+use crate::gcra;
+use crate::quota::Quota;
+use crate::rate_limiter::{Denied, RateLimiter, RateLimiterError, Rounding, quota_nanos};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// struct type to represent a rate limiter with a single bucket and no keys,
+// e.g. for one outbound API; the TAT lives in one atomic updated by CAS, so a
+// check takes no lock and does no hashing
+#[derive(Debug)]
+pub struct DirectLimiter<C = SystemClock>
+where
+    C: Clock,
+{
+    increment_nanos: u64,
+    tolerance_nanos: u64,
+    tat: AtomicU64,
+    clock: C,
+}
+
+// methods for the DirectLimiter struct
+impl<C> DirectLimiter<C>
+where
+    C: Clock,
+{
+    // method to create a keyless limiter given a rate and burst, as
+    // RateLimiter::new
+    pub fn new(
+        rate_per_second: f64,
+        burst_capacity: f64,
+        clock: C,
+    ) -> Result<Self, RateLimiterError> {
+        let (increment_nanos, tolerance_nanos) =
+            quota_nanos(rate_per_second, burst_capacity, Rounding::Floor)?;
+        Ok(Self::from_nanos(increment_nanos, tolerance_nanos, clock))
+    }
+
+    // method to create a keyless limiter from an integer quota
+    pub fn with_quota(quota: Quota, clock: C) -> Self {
+        let (increment_nanos, tolerance_nanos) = quota.nanos();
+        Self::from_nanos(increment_nanos, tolerance_nanos, clock)
+    }
+
+    fn from_nanos(increment_nanos: u64, tolerance_nanos: u64, clock: C) -> Self {
+        Self {
+            increment_nanos,
+            tolerance_nanos,
+            tat: AtomicU64::new(0),
+            clock,
+        }
+    }
+
+    // accessor method to return the rate in requests per second
+    pub fn rate(&self) -> f64 {
+        1_000_000_000.0 / self.increment_nanos as f64
+    }
+
+    // accessor method to return the burst (extra requests beyond the first)
+    pub fn burst(&self) -> f64 {
+        self.tolerance_nanos as f64 / self.increment_nanos as f64
+    }
+
+    // method to check one request
+    pub fn is_allowed(&self) -> bool {
+        self.check_with_cost(1).is_ok()
+    }
+
+    // method to charge `cost` units at once, e.g. a batch of calls
+    // the CAS retries only when another thread charged in between, so the
+    // check is lock-free and never admits more than the quota allows
+    pub fn check_with_cost(&self, cost: u32) -> Result<(), Denied> {
+        let now = self.clock.now();
+        let (increment, tolerance) = (self.increment_nanos, self.tolerance_nanos);
+        let mut tat = self.tat.load(Ordering::Acquire);
+        loop {
+            let Some(new_tat) = gcra::conform(now, tat, increment, tolerance, cost) else {
+                let retry_at = gcra::retry_at(tat, increment, tolerance, cost);
+                return Err(Denied::new(retry_at.map(|at| at.saturating_sub(now))));
+            };
+            match self
+                .tat
+                .compare_exchange_weak(tat, new_tat, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => return Ok(()),
+                Err(current) => tat = current,
+            }
+        }
+    }
+
+    // method to return how many unit requests would be admitted right now
+    pub fn remaining(&self) -> u64 {
+        let now = self.clock.now();
+        let tat = self.tat.load(Ordering::Acquire);
+        gcra::remaining(now, tat, self.increment_nanos, self.tolerance_nanos)
+    }
+
+    // method to forget every charge, giving back the whole burst
+    pub fn reset(&self) {
+        self.tat.store(0, Ordering::Release);
+    }
+}
+
+// the keyless limiter is reached from RateLimiter for discoverability
+impl RateLimiter<()> {
+    // method to create a keyless limiter on the system clock, see DirectLimiter
+    pub fn direct(
+        rate_per_second: f64,
+        burst_capacity: f64,
+    ) -> Result<DirectLimiter, RateLimiterError> {
+        DirectLimiter::new(rate_per_second, burst_capacity, SystemClock)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestClock;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn matches_a_single_keyed_bucket() {
+        let clock = TestClock::new(0.0);
+        let direct = DirectLimiter::new(10.0, 4.0, clock.clone()).unwrap();
+        let keyed = RateLimiter::new(10.0, 4.0, clock.clone()).unwrap();
+        for step in 0..200 {
+            clock.advance(if step % 9 == 0 { 0.3 } else { 0.01 });
+            assert_eq!(direct.is_allowed(), keyed.is_allowed(()).unwrap());
+        }
+
+        let denied = direct.check_with_cost(50).unwrap_err();
+        assert_eq!(denied.retry_after(), None);
+        direct.reset();
+        assert_eq!(direct.remaining(), 5);
+        assert!(direct.check_with_cost(5).is_ok());
+        let denied = direct.check_with_cost(1).unwrap_err();
+        assert_eq!(denied.retry_after(), Some(Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn concurrent_checks_never_exceed_the_burst() {
+        let clock = TestClock::new(0.0);
+        let direct = Arc::new(DirectLimiter::new(1.0, 99.0, clock).unwrap());
+        let admitted: usize = (0..8)
+            .map(|_| {
+                let direct = Arc::clone(&direct);
+                thread::spawn(move || (0..50).filter(|_| direct.is_allowed()).count())
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .sum();
+        // the clock is frozen, so exactly the burst of 100 gets through
+        assert_eq!(admitted, 100);
+    }
+
+    #[test]
+    fn direct_uses_the_system_clock() {
+        let direct = RateLimiter::direct(5.0, 1.0).unwrap();
+        assert_eq!(direct.rate(), 5.0);
+        assert_eq!(direct.burst(), 1.0);
+        assert!(direct.is_allowed());
+    }
+}
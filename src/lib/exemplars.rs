@@ -0,0 +1,151 @@
+// src/lib/exemplars.rs
+
+// dependencies
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// struct type to represent one example decision attached to a counter sample
+// `trace_id` links to the request's trace, `decision_id` to the response body
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exemplar {
+    pub trace_id: Option<String>,
+    pub decision_id: u64,
+    pub unix_secs: f64,
+}
+
+// methods for the Exemplar struct
+impl Exemplar {
+    // method to create an exemplar stamped with the current wall-clock time
+    pub fn new(trace_id: Option<&str>, decision_id: u64) -> Self {
+        let unix_secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_secs_f64())
+            .unwrap_or(0.0);
+        Self {
+            trace_id: trace_id.map(String::from),
+            decision_id,
+            unix_secs,
+        }
+    }
+}
+
+// struct type to represent one labelled series: its count and latest exemplar
+#[derive(Debug, Default)]
+struct Series {
+    count: u64,
+    exemplar: Option<Exemplar>,
+}
+
+// struct type to represent a denial counter that keeps the most recent
+// exemplar per reason and renders in the OpenMetrics text format, so a
+// dashboard can jump from a spike in 429s to an example trace
+// it is counted by the HTTP layer rather than next to the `metrics` feature's
+// gcra_decisions_total, for three reasons: the `metrics` facade has no way to
+// attach an exemplar to a counter increment; a limiter decides on a key and
+// never sees the request whose trace ID the exemplar carries; and it counts
+// responses (including access-list 403s no limiter takes part in), where
+// gcra_decisions_total counts every limiter decision, shadowed ones and
+// non-HTTP callers included. Serve one or the other per denial, not both;
+// the server binary serves this one and does not enable `with_metrics`
+#[derive(Debug)]
+pub struct DenialCounter {
+    name: String,
+    series: Mutex<BTreeMap<String, Series>>,
+}
+
+// methods for the DenialCounter struct
+impl DenialCounter {
+    // content type to serve the rendered counter with
+    pub const CONTENT_TYPE: &'static str =
+        "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+    // method to create a counter; `name` is the metric family without `_total`
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            series: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    // method to count a denial for `reason`, keeping its exemplar
+    pub fn record(&self, reason: &str, exemplar: Exemplar) {
+        let mut series = self.series.lock().unwrap();
+        let entry = series.entry(reason.to_string()).or_default();
+        entry.count += 1;
+        entry.exemplar = Some(exemplar);
+    }
+
+    // accessor method to return the count for one reason
+    pub fn count(&self, reason: &str) -> u64 {
+        let series = self.series.lock().unwrap();
+        series.get(reason).map_or(0, |entry| entry.count)
+    }
+
+    // method to render the counter as an OpenMetrics exposition
+    pub fn render(&self) -> String {
+        let mut text = String::new();
+        let _ = writeln!(text, "# TYPE {} counter", self.name);
+        for (reason, entry) in self.series.lock().unwrap().iter() {
+            let _ = write!(
+                text,
+                "{}_total{{reason=\"{}\"}} {}",
+                self.name,
+                escape(reason),
+                entry.count
+            );
+            if let Some(exemplar) = &entry.exemplar {
+                let mut labels = Vec::new();
+                if let Some(trace_id) = &exemplar.trace_id {
+                    labels.push(format!("trace_id=\"{}\"", escape(trace_id)));
+                }
+                labels.push(format!("decision_id=\"{}\"", exemplar.decision_id));
+                let _ = write!(
+                    text,
+                    " # {{{}}} 1 {:.3}",
+                    labels.join(","),
+                    exemplar.unix_secs
+                );
+            }
+            text.push('\n');
+        }
+        text.push_str("# EOF\n");
+        text
+    }
+}
+
+// escape a label value for the text exposition format
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_counts_with_latest_exemplar() {
+        let counter = DenialCounter::new("gcra_denied");
+        let exemplar = |trace_id: Option<&str>, decision_id| Exemplar {
+            trace_id: trace_id.map(String::from),
+            decision_id,
+            unix_secs: 1_700_000_000.5,
+        };
+        counter.record("rate_limited", exemplar(Some("aaa"), 1));
+        counter.record("rate_limited", exemplar(Some("4bf92f3577b34da6"), 2));
+        counter.record("forbidden", exemplar(None, 3));
+
+        assert_eq!(counter.count("rate_limited"), 2);
+        assert_eq!(
+            counter.render(),
+            "# TYPE gcra_denied counter\n\
+             gcra_denied_total{reason=\"forbidden\"} 1 # {decision_id=\"3\"} 1 1700000000.500\n\
+             gcra_denied_total{reason=\"rate_limited\"} 2 # {trace_id=\"4bf92f3577b34da6\",decision_id=\"2\"} 1 1700000000.500\n\
+             # EOF\n"
+        );
+    }
+}
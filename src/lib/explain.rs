@@ -0,0 +1,136 @@
+// src/lib/explain.rs
+
+// dependencies
+use crate::rate_limiter::Decision;
+use std::fmt;
+use std::time::Duration;
+
+// struct type to represent why a key gets the decision it gets right now
+// times are nanoseconds on the limiter's clock; `tat` is None for a key that
+// holds no state, which is treated as a TAT of `now`. A unit request
+// conforms once `now >= tat - tolerance`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Explanation<T> {
+    pub key: T,
+    pub now: u64,
+    pub tat: Option<u64>,
+    pub increment: u64,
+    pub tolerance: u64,
+    pub overridden: bool, // the quota comes from a per-key override
+    pub banned_for: Option<Duration>,
+    pub min_interval: Duration,
+    pub allow_all: bool,
+    pub decision: Decision, // what a unit request would get, as RateLimiter::peek
+}
+
+// implement the Display trait for a support-ticket-ready explanation
+impl<T: fmt::Debug> fmt::Display for Explanation<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let increment = Duration::from_nanos(self.increment);
+        let tolerance = Duration::from_nanos(self.tolerance);
+        writeln!(
+            f,
+            "key {:?}: quota of {} requests/s (one every {:?}) with {} extra at once \
+             (tolerance {:?}){}",
+            self.key,
+            1_000_000_000.0 / self.increment as f64,
+            increment,
+            self.tolerance / self.increment.max(1),
+            tolerance,
+            if self.overridden {
+                ", from a per-key override"
+            } else {
+                ""
+            }
+        )?;
+        if self.allow_all {
+            return write!(f, "allowed: the limiter is in allow-all mode");
+        }
+        if let Some(ban) = self.banned_for {
+            return write!(f, "denied: the key is banned for another {:?}", ban);
+        }
+
+        let tat = self.tat.unwrap_or(self.now);
+        match self.tat {
+            Some(_) if tat > self.now => writeln!(
+                f,
+                "TAT {}ns is {:?} ahead of now {}ns",
+                tat,
+                Duration::from_nanos(tat - self.now),
+                self.now
+            )?,
+            Some(_) => writeln!(f, "TAT {}ns has passed (now {}ns)", tat, self.now)?,
+            None => writeln!(f, "no state: the key has not been seen or has been idle")?,
+        }
+        let allow_at = tat.saturating_sub(self.tolerance);
+        let conforms = self.now >= allow_at;
+        writeln!(
+            f,
+            "a request conforms once now >= TAT - tolerance: {} {} {} - {} = {}",
+            self.now,
+            if conforms { ">=" } else { "<" },
+            tat,
+            self.tolerance,
+            allow_at
+        )?;
+        match (self.decision.is_allowed(), self.decision.retry_after()) {
+            (true, _) => write!(
+                f,
+                "allowed, with {} more requests left in the burst after it",
+                self.decision.remaining_burst()
+            ),
+            (false, Some(wait)) if conforms => write!(
+                f,
+                "denied: requests must be at least {:?} apart, retry after {:?}",
+                self.min_interval, wait
+            ),
+            (false, Some(wait)) => write!(f, "denied: retry after {:?}", wait),
+            (false, None) => write!(f, "denied: the request can never conform"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{RateLimiter, TestClock};
+    use std::time::Duration;
+
+    #[test]
+    fn explains_the_arithmetic_behind_a_denial() {
+        let clock = TestClock::new(1.0);
+        let limiter = RateLimiter::new(10.0, 2.0, clock.clone()).unwrap();
+        (0..3).for_each(|_| assert!(limiter.is_allowed("client").unwrap()));
+
+        let explanation = limiter.explain(&"client");
+        assert_eq!(explanation.tat, Some(1_300_000_000));
+        assert!(!explanation.decision.is_allowed());
+        assert_eq!(
+            explanation.to_string(),
+            "key \"client\": quota of 10 requests/s (one every 100ms) with 2 extra at once \
+             (tolerance 200ms)\n\
+             TAT 1300000000ns is 300ms ahead of now 1000000000ns\n\
+             a request conforms once now >= TAT - tolerance: 1000000000 < 1300000000 - \
+             200000000 = 1100000000\n\
+             denied: retry after 100ms"
+        );
+
+        clock.advance(0.1);
+        let explanation = limiter.explain(&"client").to_string();
+        assert!(explanation.ends_with("allowed, with 0 more requests left in the burst after it"));
+    }
+
+    #[test]
+    fn explains_bans_overrides_and_unseen_keys() {
+        let clock = TestClock::new(0.0);
+        let limiter = RateLimiter::new(1.0, 0.0, clock.clone()).unwrap();
+        limiter.set_override("vip", 5.0, 0.0).unwrap();
+        let vip = limiter.explain(&"vip").to_string();
+        assert!(vip.contains("from a per-key override"));
+        assert!(vip.contains("no state"));
+        assert!(vip.ends_with("allowed, with 0 more requests left in the burst after it"));
+
+        limiter.ban_for("abuser", Duration::from_secs(30));
+        let abuser = limiter.explain(&"abuser").to_string();
+        assert!(abuser.ends_with("denied: the key is banned for another 30s"));
+    }
+}
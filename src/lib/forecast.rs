@@ -0,0 +1,153 @@
+// src/lib/forecast.rs
+
+// dependencies
+use dashmap::DashMap;
+use std::hash::Hash;
+use std::time::Duration;
+
+// struct type to represent one key's request counts in fixed windows
+#[derive(Debug, Clone, Copy)]
+struct PaceWindow {
+    start: u64,
+    current: u64,
+    previous: u64,
+}
+
+// struct type to represent the per-key request pace a limiter observes
+// the pace is a sliding-window estimate: the previous window's units,
+// weighted by how much of it still overlaps, plus the current window's;
+// a zero window means tracking is off and nothing is stored
+#[derive(Debug)]
+pub(crate) struct PaceTracker<T>
+where
+    T: Hash + Eq,
+{
+    window_nanos: u64,
+    windows: DashMap<T, PaceWindow>,
+}
+
+// methods for the PaceTracker struct
+impl<T> PaceTracker<T>
+where
+    T: Hash + Eq + Clone,
+{
+    pub(crate) fn new(window: Duration) -> Self {
+        Self {
+            window_nanos: window.as_nanos() as u64,
+            windows: DashMap::new(),
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.window_nanos > 0
+    }
+
+    // roll a window forward to `now`
+    fn roll(&self, window: &mut PaceWindow, now: u64) {
+        let elapsed = now.saturating_sub(window.start);
+        if elapsed >= self.window_nanos {
+            let windows = elapsed / self.window_nanos;
+            window.previous = if windows == 1 { window.current } else { 0 };
+            window.current = 0;
+            window.start += windows * self.window_nanos;
+        }
+    }
+
+    // count `cost` units requested by a key at `now`
+    pub(crate) fn record(&self, key: &T, now: u64, cost: u32) {
+        if !self.is_enabled() {
+            return;
+        }
+        let mut window = match self.windows.get_mut(key) {
+            Some(window) => window,
+            None => self.windows.entry(key.clone()).or_insert(PaceWindow {
+                start: now,
+                current: 0,
+                previous: 0,
+            }),
+        };
+        self.roll(&mut window, now);
+        window.current += cost as u64;
+    }
+
+    // the key's observed pace in units per second, if it has been seen
+    pub(crate) fn rate(&self, key: &T, now: u64) -> Option<f64> {
+        let mut window = *self.windows.get(key)?;
+        self.roll(&mut window, now);
+        let overlap = 1.0 - now.saturating_sub(window.start) as f64 / self.window_nanos as f64;
+        let units = window.previous as f64 * overlap + window.current as f64;
+        Some(units / (self.window_nanos as f64 / 1_000_000_000.0))
+    }
+
+    // drop every key's counts
+    pub(crate) fn clear(&self) {
+        self.windows.clear();
+    }
+
+    // drop a key's counts, e.g. when its rate state is evicted
+    pub(crate) fn remove(&self, key: &T) {
+        if self.is_enabled() {
+            self.windows.remove(key);
+        }
+    }
+}
+
+// estimate how long until a key at `pace` units per second is first denied
+// the key's backlog (TAT minus now) grows by `pace * increment` and drains by
+// one second every second, and requests are denied once it exceeds the
+// tolerance; None if the pace does not outrun the quota
+pub(crate) fn time_to_exhaustion(
+    now: u64,
+    tat: u64,
+    increment: u64,
+    tolerance: u64,
+    pace: f64,
+) -> Option<Duration> {
+    let growth = pace * increment as f64 - 1_000_000_000.0; // nanos per second
+    if growth <= 0.0 {
+        return None;
+    }
+    let headroom = tolerance.saturating_sub(tat.saturating_sub(now));
+    Some(Duration::from_secs_f64(headroom as f64 / growth))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pace_is_a_sliding_window_estimate() {
+        let tracker = PaceTracker::new(Duration::from_secs(1));
+        assert_eq!(tracker.rate(&"k", 0), None);
+        for _ in 0..10 {
+            tracker.record(&"k", 0, 1);
+        }
+        assert_eq!(tracker.rate(&"k", 0), Some(10.0));
+        // half of the previous window still overlaps
+        assert_eq!(tracker.rate(&"k", 1_500_000_000), Some(5.0));
+        assert_eq!(tracker.rate(&"k", 3_000_000_000), Some(0.0));
+
+        let off = PaceTracker::new(Duration::ZERO);
+        off.record(&"k", 0, 1);
+        assert_eq!(off.rate(&"k", 0), None);
+    }
+
+    #[test]
+    fn exhaustion_needs_a_pace_above_the_quota() {
+        let second = 1_000_000_000;
+        // 1/s with 4s of tolerance and 2s of backlog, visited at 3/s: the
+        // backlog grows 2s per second and passes the tolerance after 1s
+        assert_eq!(
+            time_to_exhaustion(0, 2 * second, second, 4 * second, 3.0),
+            Some(Duration::from_secs(1))
+        );
+        assert_eq!(
+            time_to_exhaustion(0, 2 * second, second, 4 * second, 1.0),
+            None
+        );
+        assert_eq!(
+            time_to_exhaustion(0, 9 * second, second, 4 * second, 2.0),
+            Some(Duration::ZERO)
+        );
+    }
+}
@@ -0,0 +1,437 @@
+// src/lib/gcra.rs
+
+// core GCRA conformance test shared by the limiter types
+// all times are in nanoseconds; a request of `cost` units arriving at `now`
+// conforms if charging it keeps the TAT within one increment plus the
+// tolerance of `now`. returns the new TAT, or None if it must be rejected
+pub(crate) fn conform(
+    now: u64,
+    tat: u64,
+    increment: u64,
+    tolerance: u64,
+    cost: u32,
+) -> Option<u64> {
+    // Update TAT: max(current_time, previous_tat) + increment * cost
+    // a TAT that would overflow can never conform, so reject instead of
+    // saturating, which would understate the charge
+    let charge = increment.checked_mul(cost as u64)?;
+    let new_tat = now.max(tat).checked_add(charge)?;
+
+    if new_tat - now <= increment.saturating_add(tolerance) {
+        Some(new_tat)
+    } else {
+        None
+    }
+}
+
+// earliest time at which a request of `cost` units would conform against `tat`
+// returns None if the cost exceeds the burst capacity, or the TAT would
+// overflow, and so can never conform
+pub(crate) fn retry_at(tat: u64, increment: u64, tolerance: u64, cost: u32) -> Option<u64> {
+    let charge = increment.checked_mul(cost as u64)?;
+    let allowance = increment.saturating_add(tolerance);
+    if charge > allowance {
+        return None;
+    }
+    Some(tat.checked_add(charge)?.saturating_sub(allowance))
+}
+
+// number of unit requests that would still conform at `now` against `tat`
+pub(crate) fn remaining(now: u64, tat: u64, increment: u64, tolerance: u64) -> u64 {
+    let allowance = increment.saturating_add(tolerance);
+    let backlog = tat.saturating_sub(now);
+    allowance.saturating_sub(backlog) / increment.max(1)
+}
+
+// invariant checks shared by the unit tests and the cargo-fuzz targets in fuzz/
+// each function panics with a description when an invariant does not hold
+#[cfg(any(test, feature = "fuzzing"))]
+pub mod invariants {
+    use super::{conform, retry_at};
+
+    // check one conformance test against arbitrary inputs, including ones
+    // near u64::MAX where the arithmetic saturates
+    pub fn check_step(now: u64, tat: u64, increment: u64, tolerance: u64, cost: u32) {
+        let allowance = increment.saturating_add(tolerance);
+        let retry = retry_at(tat, increment, tolerance, cost);
+
+        match conform(now, tat, increment, tolerance, cost) {
+            Some(new_tat) => {
+                assert!(new_tat >= now && new_tat >= tat, "TAT moved backwards");
+                assert!(new_tat - now <= allowance, "admitted past the allowance");
+                let retry = retry.expect("conforming request reported as never conforming");
+                assert!(retry <= now, "conforming request told to retry later");
+            }
+            None => {
+                // near u64::MAX the new TAT cannot be represented at all, and
+                // the request is rejected outright
+                let charge = increment.checked_mul(cost as u64);
+                if charge.and_then(|c| now.max(tat).checked_add(c)).is_none() {
+                    return;
+                }
+                let Some(retry) = retry else {
+                    return;
+                };
+                assert!(retry > now, "rejected request told it could go now");
+                assert!(
+                    conform(retry, tat, increment, tolerance, cost).is_some(),
+                    "request at the retry time was rejected"
+                );
+                assert!(
+                    conform(retry - 1, tat, increment, tolerance, cost).is_none(),
+                    "retry time is not the earliest conforming time"
+                );
+            }
+        }
+    }
+
+    // check a sequence of requests against one key: times are offsets from
+    // `start` and are sorted first; across the whole run the admitted units
+    // may not exceed the burst plus what the elapsed time earns
+    pub fn check_sequence(start: u64, increment: u64, tolerance: u64, requests: &mut [(u64, u32)]) {
+        if increment == 0 {
+            return;
+        }
+        requests.sort_by_key(|(offset, _)| *offset);
+
+        let mut tat = None;
+        let mut admitted: u128 = 0;
+        let mut first = None;
+        let mut last = start;
+        for (offset, cost) in requests.iter() {
+            let now = start.saturating_add(*offset);
+            last = now;
+            let current = *tat.get_or_insert(now);
+            first.get_or_insert(now);
+            check_step(now, current, increment, tolerance, *cost);
+            if let Some(new_tat) = conform(now, current, increment, tolerance, *cost) {
+                tat = Some(new_tat);
+                admitted += *cost as u128;
+            }
+        }
+
+        if let (Some(first), Some(tat)) = (first, tat)
+            && tat < u64::MAX
+        {
+            let earned = (last - first) as u128 + increment as u128 + tolerance as u128;
+            assert!(
+                admitted * increment as u128 <= earned,
+                "admitted {} units in {}ns",
+                admitted,
+                last - first
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_cost_matches_classic_test() {
+        // increment 10, tolerance 20: conforming while now >= tat - 20
+        assert_eq!(conform(100, 120, 10, 20, 1), Some(130));
+        assert_eq!(conform(99, 120, 10, 20, 1), None);
+        assert_eq!(conform(200, 120, 10, 20, 1), Some(210));
+    }
+
+    #[test]
+    fn cost_scales_the_increment() {
+        // capacity is tolerance / increment + 1 = 3 units
+        assert_eq!(conform(0, 0, 10, 20, 3), Some(30));
+        assert_eq!(conform(0, 0, 10, 20, 4), None);
+        assert_eq!(conform(0, 10, 10, 20, 2), Some(30));
+        assert_eq!(conform(0, 10, 10, 20, 3), None);
+    }
+
+    #[test]
+    fn retry_at_is_first_conforming_time() {
+        let at = retry_at(130, 10, 20, 1).unwrap();
+        assert_eq!(at, 110);
+        assert!(conform(at, 130, 10, 20, 1).is_some());
+        assert!(conform(at - 1, 130, 10, 20, 1).is_none());
+
+        // a cost larger than the bucket never conforms
+        assert_eq!(retry_at(0, 10, 20, 4), None);
+    }
+
+    // xorshift generator so the seeded inputs are the same on every run
+    pub(super) struct Rng(pub(super) u64);
+
+    impl Rng {
+        pub(super) fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        // mostly small values, sometimes full-width or edge values
+        fn pick(&mut self, wide: bool) -> u64 {
+            const EDGES: [u64; 7] = [0, 1, 2, 1_000_000_000, u64::MAX / 2, u64::MAX - 1, u64::MAX];
+            match self.next() % 4 {
+                0 => EDGES[(self.next() % EDGES.len() as u64) as usize],
+                _ if wide => self.next(),
+                _ => self.next() % 10_000,
+            }
+        }
+    }
+
+    // deterministic stand-in for the fuzz targets so the invariants run in CI
+    #[test]
+    fn invariants_hold_for_seeded_inputs() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+
+        for _ in 0..20_000 {
+            let wide = rng.next().is_multiple_of(2);
+            let (now, tat) = (rng.pick(wide), rng.pick(wide));
+            let (increment, tolerance) = (rng.pick(wide), rng.pick(wide));
+            let cost = (rng.next() % 8) as u32;
+            invariants::check_step(now, tat, increment, tolerance, cost);
+        }
+
+        for _ in 0..500 {
+            let increment = 1 + rng.next() % 1_000;
+            let tolerance = rng.next() % 5_000;
+            let mut requests: Vec<(u64, u32)> = (0..50)
+                .map(|_| (rng.next() % 20_000, (rng.next() % 4) as u32))
+                .collect();
+            let start = rng.pick(true);
+            invariants::check_sequence(start, increment, tolerance, &mut requests);
+        }
+    }
+}
+
+// canonical test vectors: the virtual scheduling algorithm of ITU-T I.371,
+// a governor-style quota of n cells per period, multi-unit costs and idle
+// resets; each step gives the TAT a conforming arrival must leave behind or
+// the earliest time a rejected one may retry, so the exact TAT evolution
+// matches other GCRA implementations
+#[cfg(test)]
+mod vectors {
+    use super::*;
+    use crate::{RateLimiter, TestClock};
+
+    // enum type to represent the expected result of one arrival
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum Outcome {
+        Conform(u64),        // the new TAT
+        Reject(Option<u64>), // the earliest conforming time, if any
+    }
+    use Outcome::{Conform, Reject};
+
+    // struct type to represent one vector: T (increment), L (tolerance) and
+    // a sequence of (arrival, cost, outcome) steps
+    struct Vector {
+        name: &'static str,
+        increment: u64,
+        tolerance: u64,
+        steps: &'static [(u64, u32, Outcome)],
+    }
+
+    const VECTORS: &[Vector] = &[
+        // I.371 virtual scheduling with T = 10, L = 15: a cell conforms unless
+        // it arrives before TAT - L, and conforming cells set TAT = max(t, TAT) + T
+        Vector {
+            name: "i371-virtual-scheduling",
+            increment: 10,
+            tolerance: 15,
+            steps: &[
+                (0, 1, Conform(10)),
+                (1, 1, Conform(20)),
+                (2, 1, Reject(Some(5))),
+                (3, 1, Reject(Some(5))),
+                (15, 1, Conform(30)),
+                (20, 1, Conform(40)),
+                (40, 1, Conform(50)),
+                (41, 1, Conform(60)),
+                (42, 1, Reject(Some(45))),
+                (70, 1, Conform(80)),
+            ],
+        },
+        // governor-style quota of 5 cells per second: T = 1s, L = 4s
+        Vector {
+            name: "five-per-second-burst",
+            increment: 1_000_000_000,
+            tolerance: 4_000_000_000,
+            steps: &[
+                (0, 1, Conform(1_000_000_000)),
+                (0, 1, Conform(2_000_000_000)),
+                (0, 1, Conform(3_000_000_000)),
+                (0, 1, Conform(4_000_000_000)),
+                (0, 1, Conform(5_000_000_000)),
+                (0, 1, Reject(Some(1_000_000_000))),
+                (999_999_999, 1, Reject(Some(1_000_000_000))),
+                (1_000_000_000, 1, Conform(6_000_000_000)),
+                (10_000_000_000, 1, Conform(11_000_000_000)),
+            ],
+        },
+        // multi-unit cells charge cost * T against an allowance of T + L
+        Vector {
+            name: "weighted-cells",
+            increment: 100,
+            tolerance: 200,
+            steps: &[
+                (0, 3, Conform(300)),
+                (0, 1, Reject(Some(100))),
+                (100, 1, Conform(400)),
+                (150, 4, Reject(None)),
+                (150, 2, Reject(Some(300))),
+                (300, 2, Conform(600)),
+            ],
+        },
+        // with no tolerance cells must be spaced by T, and an idle gap resets
+        // the TAT to the arrival time rather than banking credit
+        Vector {
+            name: "strict-spacing-and-idle-reset",
+            increment: 10,
+            tolerance: 0,
+            steps: &[
+                (0, 1, Conform(10)),
+                (9, 1, Reject(Some(10))),
+                (10, 1, Conform(20)),
+                (100, 1, Conform(110)),
+                (101, 1, Reject(Some(110))),
+            ],
+        },
+    ];
+
+    #[test]
+    fn core_matches_reference_tat_evolution() {
+        for vector in VECTORS {
+            let mut tat = vector.steps[0].0;
+            for (i, &(now, cost, expected)) in vector.steps.iter().enumerate() {
+                let outcome = match conform(now, tat, vector.increment, vector.tolerance, cost) {
+                    Some(new_tat) => {
+                        tat = new_tat;
+                        Conform(new_tat)
+                    }
+                    None => Reject(retry_at(tat, vector.increment, vector.tolerance, cost)),
+                };
+                assert_eq!(outcome, expected, "{} step {}", vector.name, i);
+            }
+        }
+    }
+
+    #[test]
+    fn limiter_matches_reference_tat_evolution() {
+        for vector in VECTORS {
+            let rate = 1_000_000_000.0 / vector.increment as f64;
+            let burst = vector.tolerance as f64 / vector.increment as f64;
+            let clock = TestClock::from_nanos(vector.steps[0].0);
+            let limiter = RateLimiter::new(rate, burst, clock.clone()).unwrap();
+
+            for (i, &(now, cost, expected)) in vector.steps.iter().enumerate() {
+                clock.advance_to_nanos(now);
+                let result = limiter.charge("key", cost);
+                match expected {
+                    Conform(new_tat) => {
+                        assert!(result.is_ok(), "{} step {}", vector.name, i);
+                        assert_eq!(
+                            limiter.client_state().get("key").map(|tat| *tat),
+                            Some(new_tat),
+                            "{} step {}",
+                            vector.name,
+                            i
+                        );
+                    }
+                    Reject(retry) => {
+                        let wait = result.unwrap_err().retry_after();
+                        let expected = retry.map(|at| std::time::Duration::from_nanos(at - now));
+                        assert_eq!(wait, expected, "{} step {}", vector.name, i);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// differential tests against a slow reference: the continuous-state leaky
+// bucket of ITU-T I.371, kept in f64 nanoseconds (exact for whole numbers
+// below 2^53, which every time here is). It shares no code with the integer
+// TAT implementation, so a rewrite of the hot path that changes any decision
+// or retry time shows up as a mismatch against the step that exposed it
+#[cfg(test)]
+mod differential {
+    use super::tests::Rng;
+    use crate::clock::Clock;
+    use crate::{RateLimiter, TestClock};
+    use std::collections::HashMap;
+
+    // struct type to represent the reference limiter: per key, the bucket
+    // level X and the last time it was filled; the bucket drains one
+    // nanosecond per nanosecond and each admitted unit pours in T
+    struct Reference {
+        increment: f64,
+        tolerance: f64,
+        buckets: HashMap<u64, (f64, f64)>,
+    }
+
+    impl Reference {
+        // the quota the limiter documents: T = 1e9 / rate truncated to
+        // whole nanoseconds, L = burst * T truncated
+        fn new(rate: f64, burst: f64) -> Self {
+            let increment = (1e9 / rate).floor();
+            Self {
+                increment,
+                tolerance: (burst * increment).floor(),
+                buckets: HashMap::new(),
+            }
+        }
+
+        // admit `cost` units at `now`, or return the wait in nanoseconds
+        // until they would fit (None if they never can)
+        fn charge(&mut self, key: u64, now: f64, cost: u32) -> Result<(), Option<f64>> {
+            let (level, filled_at) = self.buckets.get(&key).copied().unwrap_or((0.0, now));
+            let drained = (level - (now - filled_at)).max(0.0);
+            let needed = cost as f64 * self.increment;
+            let overflow = drained + needed - self.increment - self.tolerance;
+            if overflow <= 0.0 {
+                self.buckets.insert(key, (drained + needed, now));
+                Ok(())
+            } else if needed > self.increment + self.tolerance {
+                Err(None)
+            } else {
+                Err(Some(overflow))
+            }
+        }
+    }
+
+    #[test]
+    fn limiter_agrees_with_reference_on_random_traffic() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        let rates = [0.5, 1.0, 3.0, 7.0, 10.0, 1_000.0, 333_333.0];
+        let bursts = [0.0, 1.0, 2.5, 5.0, 100.0];
+
+        for run in 0..200 {
+            let rate = rates[run % rates.len()];
+            let burst = bursts[(run / rates.len()) % bursts.len()];
+            let clock = TestClock::from_nanos(rng.next() % 1_000_000_000_000);
+            let limiter = RateLimiter::new(rate, burst, clock.clone()).unwrap();
+            let mut reference = Reference::new(rate, burst);
+            let span = reference.increment as u64 * 3;
+
+            for step in 0..500 {
+                // mostly bursts of back-to-back requests, sometimes a pause
+                if rng.next().is_multiple_of(3) {
+                    let now = clock.now() + rng.next() % span;
+                    clock.advance_to_nanos(now);
+                }
+                let key = rng.next() % 4;
+                let cost = (rng.next() % 4) as u32;
+
+                let now = clock.now();
+                let expected = reference.charge(key, now as f64, cost);
+                let actual = limiter
+                    .charge(key, cost)
+                    .map_err(|denied| denied.retry_after().map(|wait| wait.as_nanos() as f64));
+                assert_eq!(
+                    actual, expected,
+                    "rate {} burst {} step {} key {} cost {}",
+                    rate, burst, step, key, cost
+                );
+            }
+        }
+    }
+}
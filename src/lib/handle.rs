@@ -0,0 +1,133 @@
+// src/lib/handle.rs
+
+// dependencies
+use crate::clock::Clock;
+use crate::rate_limiter::{Denied, Listing, RateLimiter};
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+
+// struct type to represent a key's ban and quota as resolved at one time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Resolved {
+    pub(crate) generation: u64, // the overrides' change counter when resolved
+    pub(crate) ban_until: Option<u64>, // when a ban in force ends
+    pub(crate) listing: Option<Listing>, // the list the key is on, if any
+    pub(crate) increment: u64,
+    pub(crate) tolerance: u64,
+    pub(crate) valid_until: u64, // when a ban or quota override lapses
+}
+
+// struct type to represent a limiter handle for one key, obtained once per
+// connection and reused for every request on it
+// the handle keeps the key, so checks borrow it instead of cloning it, and
+// keeps the key's resolved ban and quota override, so checks skip those
+// lookups until an override changes or lapses; the rate state itself is
+// always read from the shared map, so a handle never holds a lock between
+// checks, and if the key is evicted its next check simply starts it afresh
+#[derive(Debug)]
+pub struct KeyHandle<'a, T, C, S = RandomState>
+where
+    T: Hash + Eq + Clone,
+    C: Clock,
+    S: BuildHasher + Clone,
+{
+    limiter: &'a RateLimiter<T, C, S>,
+    key: T,
+    resolved: Cell<Option<Resolved>>,
+}
+
+// methods for the KeyHandle struct
+impl<'a, T, C, S> KeyHandle<'a, T, C, S>
+where
+    T: Hash + Eq + Clone,
+    C: Clock,
+    S: BuildHasher + Clone,
+{
+    pub(crate) fn new(limiter: &'a RateLimiter<T, C, S>, key: T) -> Self {
+        Self {
+            limiter,
+            key,
+            resolved: Cell::new(None),
+        }
+    }
+
+    // accessor method to return the key this handle checks
+    pub fn key(&self) -> &T {
+        &self.key
+    }
+
+    // method to check one request for the key, as RateLimiter::is_allowed
+    pub fn is_allowed(&self) -> bool {
+        self.charge(1).is_ok()
+    }
+
+    // method to charge `cost` units to the key, reporting a denial's wait
+    pub fn charge(&self, cost: u32) -> Result<(), Denied> {
+        if self.limiter.allows_all() {
+            return Ok(());
+        }
+        let now = self
+            .limiter
+            .decision_time()
+            .map_err(|behind| Denied::new(Some(behind)))?;
+        let resolved = match self.resolved.get() {
+            Some(resolved) if self.limiter.is_current(&resolved, now) => resolved,
+            _ => {
+                let resolved = self.limiter.resolve(&self.key, now);
+                self.resolved.set(Some(resolved));
+                resolved
+            }
+        };
+        self.limiter
+            .charge_resolved(&self.key, now, &resolved, cost)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{RateLimiter, TestClock};
+    use std::time::Duration;
+
+    #[test]
+    fn handle_shares_state_with_the_limiter() {
+        let clock = TestClock::new(0.0);
+        let limiter = RateLimiter::new(1.0, 1.0, clock.clone()).unwrap();
+        let handle = limiter.handle(String::from("conn"));
+
+        assert!(handle.is_allowed());
+        assert!(limiter.is_allowed(String::from("conn")).unwrap());
+        assert!(!handle.is_allowed());
+
+        // an evicted key starts afresh on the handle's next check
+        clock.advance(5.0);
+        assert_eq!(limiter.gc_step(10).evicted, 1);
+        assert!(handle.is_allowed());
+        assert_eq!(limiter.client_state().len(), 1);
+    }
+
+    #[test]
+    fn handle_sees_override_changes_and_expiry() {
+        let clock = TestClock::new(0.0);
+        let limiter = RateLimiter::new(1.0, 0.0, clock.clone()).unwrap();
+        let handle = limiter.handle("conn");
+        assert!(handle.is_allowed());
+
+        limiter.ban_for("conn", Duration::from_secs(2));
+        clock.advance(1.0);
+        assert_eq!(
+            handle.charge(1).unwrap_err().retry_after(),
+            Some(Duration::from_secs(1))
+        );
+        // the cached ban lapses on its own
+        clock.advance(1.0);
+        assert!(handle.is_allowed());
+
+        limiter
+            .set_override_for("conn", 1.0, 2.0, Duration::from_secs(60))
+            .unwrap();
+        assert!(handle.is_allowed());
+        assert!(handle.is_allowed());
+        assert!(!handle.is_allowed());
+    }
+}
@@ -0,0 +1,174 @@
+// src/lib/hierarchy.rs
+
+// dependencies
+use crate::SystemClock;
+use crate::clock::Clock;
+use crate::gcra;
+use crate::quota::Quota;
+use crate::rate_limiter::Denied;
+use dashmap::DashMap;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// enum type to represent which level of a hierarchical limit denied a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Key,
+    Global,
+}
+
+// struct type to represent a per-key limit under a shared global limit,
+// e.g. each IP at most 5/s and the whole service at most 2000/s
+// a request is charged to both or to neither: the key's entry lock is held
+// while the global TAT is updated with compare-and-swap, so the key is only
+// charged once the global limit has admitted it, and a global denial leaves
+// the key's quota untouched
+#[derive(Debug)]
+pub struct HierarchicalLimiter<T, C = SystemClock>
+where
+    T: Hash + Eq + Clone,
+    C: Clock,
+{
+    per_key: Quota,
+    global: Quota,
+    client_state: DashMap<T, u64>,
+    global_tat: AtomicU64,
+    clock: C,
+}
+
+// methods for the HierarchicalLimiter struct
+impl<T, C> HierarchicalLimiter<T, C>
+where
+    T: Hash + Eq + Clone,
+    C: Clock,
+{
+    // method to create a limiter with a per-key and a global quota
+    pub fn new(per_key: Quota, global: Quota, clock: C) -> Self {
+        Self {
+            per_key,
+            global,
+            client_state: DashMap::new(),
+            global_tat: AtomicU64::new(0),
+            clock,
+        }
+    }
+
+    // method to check one request for a key against both limits
+    pub fn is_allowed(&self, client_id: T) -> bool {
+        self.charge(client_id, 1).is_ok()
+    }
+
+    // method to charge `cost` units to a key and to the global limit, or to
+    // neither; the denial names the level that refused
+    pub fn charge(&self, client_id: T, cost: u32) -> Result<(), (Level, Denied)> {
+        let now = self.clock.now();
+        let mut tat = self.client_state.entry(client_id).or_insert(now);
+
+        let (increment, tolerance) = self.per_key.nanos();
+        let Some(key_tat) = gcra::conform(now, *tat, increment, tolerance, cost) else {
+            let retry_at = gcra::retry_at(*tat, increment, tolerance, cost);
+            let denied = Denied::new(retry_at.map(|at| at.saturating_sub(now)));
+            return Err((Level::Key, denied));
+        };
+
+        let (increment, tolerance) = self.global.nanos();
+        let mut global = self.global_tat.load(Ordering::Acquire);
+        loop {
+            let Some(global_tat) = gcra::conform(now, global, increment, tolerance, cost) else {
+                let retry_at = gcra::retry_at(global, increment, tolerance, cost);
+                let denied = Denied::new(retry_at.map(|at| at.saturating_sub(now)));
+                return Err((Level::Global, denied));
+            };
+            match self.global_tat.compare_exchange_weak(
+                global,
+                global_tat,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => break,
+                Err(current) => global = current,
+            }
+        }
+
+        *tat = key_tat;
+        Ok(())
+    }
+
+    // method to drop keys whose TAT has passed, as RateLimiter::evict_idle
+    // returns how many keys were removed
+    pub fn evict_idle(&self) -> usize {
+        let now = self.clock.now();
+        let before = self.client_state.len();
+        self.client_state.retain(|_, tat| *tat > now);
+        before - self.client_state.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestClock;
+    use std::num::NonZeroU32;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    fn n(value: u32) -> NonZeroU32 {
+        NonZeroU32::new(value).unwrap()
+    }
+
+    #[test]
+    fn global_denial_leaves_the_key_uncharged() {
+        let clock = TestClock::new(0.0);
+        // each key 2/s, everyone together 3/s
+        let limiter = HierarchicalLimiter::new(
+            Quota::per_second(n(2)),
+            Quota::per_second(n(3)),
+            clock.clone(),
+        );
+
+        assert!(limiter.is_allowed("a"));
+        assert!(limiter.is_allowed("a"));
+        let (level, _) = limiter.charge("a", 1).unwrap_err();
+        assert_eq!(level, Level::Key);
+
+        assert!(limiter.is_allowed("b"));
+        let (level, denied) = limiter.charge("b", 1).unwrap_err();
+        assert_eq!(level, Level::Global);
+        assert_eq!(
+            denied.retry_after(),
+            Some(Duration::from_nanos(333_333_333))
+        );
+
+        // "b" still has its second unit once the global limit frees up
+        clock.advance(0.34);
+        assert!(limiter.is_allowed("b"));
+        assert_eq!(limiter.charge("b", 1).unwrap_err().0, Level::Key);
+
+        clock.advance(10.0);
+        assert_eq!(limiter.evict_idle(), 2);
+    }
+
+    #[test]
+    fn concurrent_keys_never_exceed_the_global_limit() {
+        let limiter = Arc::new(HierarchicalLimiter::new(
+            Quota::per_second(n(5)),
+            Quota::per_second(n(20)),
+            TestClock::new(0.0),
+        ));
+        let admitted: usize = (0..8u32)
+            .map(|thread_id| {
+                let limiter = Arc::clone(&limiter);
+                thread::spawn(move || {
+                    (0..50)
+                        .filter(|i| limiter.is_allowed(thread_id * 100 + i % 10))
+                        .count()
+                })
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .sum();
+        assert_eq!(admitted, 20);
+    }
+}
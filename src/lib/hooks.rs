@@ -0,0 +1,124 @@
+// src/lib/hooks.rs
+
+// dependencies
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+// enum type to represent why a key's state was removed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvictionReason {
+    Idle,     // the key's TAT had passed, so it held no state worth keeping
+    Expired,  // the key had been idle for longer than the limiter's idle TTL
+    Capacity, // the limiter was at its key cap and this key was charged longest ago
+    Manual,   // an operator reset or removed the key, or cleared the limiter
+}
+
+// implement the Display trait for the EvictionReason type
+impl fmt::Display for EvictionReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvictionReason::Idle => write!(f, "idle"),
+            EvictionReason::Expired => write!(f, "expired"),
+            EvictionReason::Capacity => write!(f, "capacity"),
+            EvictionReason::Manual => write!(f, "manual"),
+        }
+    }
+}
+
+// type alias for a callback told about each evicted key
+type EvictionHook<T> = Arc<dyn Fn(&T, EvictionReason) + Send + Sync>;
+
+// struct type to represent the eviction callbacks attached to a limiter
+pub(crate) struct EvictionHooks<T>(Vec<EvictionHook<T>>);
+
+// methods for the EvictionHooks struct
+impl<T> EvictionHooks<T> {
+    pub(crate) fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    pub(crate) fn add(&mut self, hook: EvictionHook<T>) {
+        self.0.push(hook);
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub(crate) fn notify(&self, key: &T, reason: EvictionReason) {
+        for hook in &self.0 {
+            hook(key, reason);
+        }
+    }
+}
+
+// implement the Debug trait for the EvictionHooks type
+impl<T> fmt::Debug for EvictionHooks<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "EvictionHooks({})", self.0.len())
+    }
+}
+
+// trait for callbacks told about every decision a limiter makes, e.g. for
+// logging or abuse detection; both methods default to doing nothing, so an
+// observer only implements the outcomes it cares about
+pub trait DecisionObserver<T>: Send + Sync {
+    fn on_allowed(&self, _key: &T) {}
+
+    // `retry_after` is None when the request can never be admitted
+    fn on_denied(&self, _key: &T, _retry_after: Option<Duration>) {}
+}
+
+// struct type to represent an observer built from a single closure, see
+// RateLimiter::on_allowed and RateLimiter::on_denied
+pub(crate) struct OnAllowed<F>(pub(crate) F);
+pub(crate) struct OnDenied<F>(pub(crate) F);
+
+impl<T, F> DecisionObserver<T> for OnAllowed<F>
+where
+    F: Fn(&T) + Send + Sync,
+{
+    fn on_allowed(&self, key: &T) {
+        (self.0)(key)
+    }
+}
+
+impl<T, F> DecisionObserver<T> for OnDenied<F>
+where
+    F: Fn(&T, Option<Duration>) + Send + Sync,
+{
+    fn on_denied(&self, key: &T, retry_after: Option<Duration>) {
+        (self.0)(key, retry_after)
+    }
+}
+
+// struct type to represent the decision observers attached to a limiter
+pub(crate) struct DecisionObservers<T>(Vec<Arc<dyn DecisionObserver<T>>>);
+
+// methods for the DecisionObservers struct
+impl<T> DecisionObservers<T> {
+    pub(crate) fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    pub(crate) fn add(&mut self, observer: Arc<dyn DecisionObserver<T>>) {
+        self.0.push(observer);
+    }
+
+    pub(crate) fn notify(&self, key: &T, retry_after: Result<(), Option<Duration>>) {
+        for observer in &self.0 {
+            match retry_after {
+                Ok(()) => observer.on_allowed(key),
+                Err(retry_after) => observer.on_denied(key, retry_after),
+            }
+        }
+    }
+}
+
+// implement the Debug trait for the DecisionObservers type
+impl<T> fmt::Debug for DecisionObservers<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DecisionObservers({})", self.0.len())
+    }
+}
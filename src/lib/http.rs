@@ -0,0 +1,237 @@
+// src/lib/http.rs
+
+// dependencies
+use std::net::IpAddr;
+use std::time::Duration;
+
+// struct type to represent the parts of an HTTP request the limiter cares about
+// kept framework-agnostic so every integration can build one from its own types
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpRequest {
+    method: String,
+    path: String,
+    peer: IpAddr,
+    headers: Vec<(String, String)>,
+}
+
+// function to map an address to the form clients are keyed by
+// a dual-stack listener sees IPv4 clients as IPv4-mapped IPv6 addresses
+// (`::ffff:1.2.3.4`), so those collapse to the plain IPv4 address; otherwise
+// one client would get two separate limits
+pub fn normalize_ip(ip: IpAddr) -> IpAddr {
+    ip.to_canonical()
+}
+
+// function to return the length of a raw request's head, including the blank
+// line that ends it, or None if the head has not been read in full yet
+pub fn head_len(raw: &[u8]) -> Option<usize> {
+    raw.windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .map(|start| start + 4)
+}
+
+// methods for the HttpRequest struct
+impl HttpRequest {
+    // method to create a request from its parts; the peer is normalized
+    pub fn new(method: &str, path: &str, peer: IpAddr) -> Self {
+        Self {
+            method: method.to_string(),
+            path: path.to_string(),
+            peer: normalize_ip(peer),
+            headers: Vec::new(),
+        }
+    }
+
+    // builder-style method to attach a header
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    // method to parse the request line and headers of a raw HTTP/1.x request
+    // returns None if the head is incomplete (no blank line ends it yet) or
+    // malformed; anything after the head, such as a body, is ignored
+    pub fn parse(raw: &[u8], peer: IpAddr) -> Option<Self> {
+        let end = head_len(raw)?;
+        let head = std::str::from_utf8(&raw[..end - 4]).ok()?;
+        let mut lines = head.split("\r\n");
+
+        let mut request_line = lines.next()?.split_whitespace();
+        let method = request_line.next()?;
+        let target = request_line.next()?;
+        request_line.next()?; // HTTP version
+
+        let mut request = Self::new(method, target, peer);
+        for line in lines {
+            let (name, value) = line.split_once(':')?;
+            request
+                .headers
+                .push((name.trim().to_string(), value.trim().to_string()));
+        }
+        Some(request)
+    }
+
+    // accessor method to return the request method
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    // accessor method to return the request path without the query string
+    pub fn path(&self) -> &str {
+        self.path.split('?').next().unwrap_or("")
+    }
+
+    // accessor method to return the address of the connected peer
+    pub fn peer(&self) -> IpAddr {
+        self.peer
+    }
+
+    // method to look up a header value, ignoring the case of the name
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    // method to check whether the request asks to upgrade to a WebSocket
+    pub fn is_websocket_upgrade(&self) -> bool {
+        let upgrade = self
+            .header("upgrade")
+            .is_some_and(|value| value.trim().eq_ignore_ascii_case("websocket"));
+        let connection = self.header("connection").is_some_and(|value| {
+            value
+                .split(',')
+                .any(|token| token.trim().eq_ignore_ascii_case("upgrade"))
+        });
+        upgrade && connection
+    }
+
+    // method to return the trace ID from a W3C `traceparent` header, if the
+    // header is well formed and the ID is not the all-zero invalid value
+    pub fn trace_id(&self) -> Option<&str> {
+        let mut parts = self.header("traceparent")?.split('-');
+        let (_version, trace_id) = (parts.next()?, parts.next()?);
+        let valid = trace_id.len() == 32
+            && trace_id.bytes().all(|b| b.is_ascii_hexdigit())
+            && trace_id.bytes().any(|b| b != b'0');
+        valid.then_some(trace_id)
+    }
+
+    // method to return how long the client is willing to wait, from an
+    // `X-Request-Deadline` header holding the budget in milliseconds
+    pub fn deadline(&self) -> Option<Duration> {
+        let millis = self.header("x-request-deadline")?.trim().parse().ok()?;
+        Some(Duration::from_millis(millis))
+    }
+
+    // method to check whether the client asked for JSON in its Accept header
+    pub fn accepts_json(&self) -> bool {
+        self.header("accept").is_some_and(|accept| {
+            accept.split(',').any(|media| {
+                let media = media.split(';').next().unwrap_or("").trim();
+                media.eq_ignore_ascii_case("application/json")
+                    || media.to_ascii_lowercase().ends_with("+json")
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_request_head() {
+        let raw = b"GET /api/items?page=2 HTTP/1.1\r\nHost: localhost\r\nX-Api-Key: abc\r\n\r\n";
+        let peer: IpAddr = "127.0.0.1".parse().unwrap();
+        let request = HttpRequest::parse(raw, peer).unwrap();
+
+        assert_eq!(request.method(), "GET");
+        assert_eq!(request.path(), "/api/items");
+        assert_eq!(request.peer(), peer);
+        assert_eq!(request.header("x-api-key"), Some("abc"));
+        assert_eq!(request.header("missing"), None);
+    }
+
+    #[test]
+    fn rejects_malformed_head() {
+        let peer: IpAddr = "127.0.0.1".parse().unwrap();
+        assert!(HttpRequest::parse(b"GET\r\n\r\n", peer).is_none());
+        assert!(HttpRequest::parse(b"GET / HTTP/1.1\r\nbad header\r\n\r\n", peer).is_none());
+    }
+
+    #[test]
+    fn waits_for_the_whole_head() {
+        let peer: IpAddr = "127.0.0.1".parse().unwrap();
+        let raw = b"GET / HTTP/1.1\r\nHost: localhost\r\nX-Api-Key: abc\r\n\r\nbody";
+
+        // a head cut anywhere before its blank line is incomplete, even where
+        // the part read so far would parse
+        for cut in 0..raw.len() - 4 {
+            assert!(HttpRequest::parse(&raw[..cut], peer).is_none(), "{cut}");
+        }
+        assert_eq!(head_len(raw), Some(raw.len() - 4));
+        let request = HttpRequest::parse(raw, peer).unwrap();
+        assert_eq!(request.header("x-api-key"), Some("abc"));
+    }
+
+    #[test]
+    fn detects_json_accept_header() {
+        let peer: IpAddr = "127.0.0.1".parse().unwrap();
+        let request =
+            |accept: &str| HttpRequest::new("GET", "/", peer).with_header("Accept", accept);
+
+        assert!(request("application/json").accepts_json());
+        assert!(request("text/html, application/problem+json;q=0.9").accepts_json());
+        assert!(!request("text/html").accepts_json());
+        assert!(!HttpRequest::new("GET", "/", peer).accepts_json());
+    }
+
+    #[test]
+    fn parses_deadline_budget() {
+        let peer: IpAddr = "127.0.0.1".parse().unwrap();
+        let request = |value: &str| {
+            HttpRequest::new("GET", "/", peer).with_header("X-Request-Deadline", value)
+        };
+
+        assert_eq!(request("250").deadline(), Some(Duration::from_millis(250)));
+        assert_eq!(request("soon").deadline(), None);
+        assert_eq!(HttpRequest::new("GET", "/", peer).deadline(), None);
+    }
+
+    #[test]
+    fn extracts_trace_id_from_traceparent() {
+        let peer: IpAddr = "127.0.0.1".parse().unwrap();
+        let request = |traceparent: &str| {
+            HttpRequest::new("GET", "/", peer).with_header("traceparent", traceparent)
+        };
+
+        assert_eq!(
+            request("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").trace_id(),
+            Some("4bf92f3577b34da6a3ce929d0e0e4736")
+        );
+        assert_eq!(
+            request("00-00000000000000000000000000000000-00f067aa0ba902b7-01").trace_id(),
+            None
+        );
+        assert_eq!(request("garbage").trace_id(), None);
+    }
+
+    #[test]
+    fn ipv4_mapped_peers_collapse_to_ipv4() {
+        let mapped: IpAddr = "::ffff:1.2.3.4".parse().unwrap();
+        let v4: IpAddr = "1.2.3.4".parse().unwrap();
+        let v6: IpAddr = "2001:db8::1".parse().unwrap();
+
+        assert_eq!(normalize_ip(mapped), v4);
+        assert_eq!(normalize_ip(v6), v6);
+        assert_eq!(HttpRequest::new("GET", "/", mapped).peer(), v4);
+        assert_eq!(
+            HttpRequest::parse(b"GET / HTTP/1.1\r\n\r\n", mapped)
+                .unwrap()
+                .peer(),
+            v4
+        );
+    }
+}
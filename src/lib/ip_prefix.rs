@@ -0,0 +1,196 @@
+// src/lib/ip_prefix.rs
+
+// dependencies
+use crate::http::{HttpRequest, normalize_ip};
+use crate::key_extractor::KeyExtractor;
+use crate::persistence::StoreKey;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+// struct type to represent how many leading bits of an address form its prefix
+// the default /24 for IPv4 and /64 for IPv6 match what a single customer is
+// usually assigned, so rotating addresses within it does not escape the limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PrefixLengths {
+    pub v4: u8,
+    pub v6: u8,
+}
+
+// implement the Default trait for /24 and /64
+impl Default for PrefixLengths {
+    fn default() -> Self {
+        Self { v4: 24, v6: 64 }
+    }
+}
+
+// struct type to represent a rate-limit key for a whole subnet
+// the address is masked down to its prefix, so every address in the subnet
+// is the same key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct IpPrefixKey {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+// methods for the IpPrefixKey struct
+impl IpPrefixKey {
+    // method to key an address by its prefix; lengths beyond the address
+    // width are clamped to it, and IPv4-mapped IPv6 addresses count as IPv4
+    pub fn new(ip: IpAddr, lengths: PrefixLengths) -> Self {
+        match normalize_ip(ip) {
+            IpAddr::V4(ip) => {
+                let prefix_len = lengths.v4.min(32);
+                let mask = u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0);
+                Self {
+                    network: IpAddr::V4(Ipv4Addr::from(u32::from(ip) & mask)),
+                    prefix_len,
+                }
+            }
+            IpAddr::V6(ip) => {
+                let prefix_len = lengths.v6.min(128);
+                let mask = u128::MAX.checked_shl(128 - prefix_len as u32).unwrap_or(0);
+                Self {
+                    network: IpAddr::V6(Ipv6Addr::from(u128::from(ip) & mask)),
+                    prefix_len,
+                }
+            }
+        }
+    }
+
+    // accessor method to return the first address of the prefix
+    pub fn network(&self) -> IpAddr {
+        self.network
+    }
+
+    // accessor method to return the prefix length in bits
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+}
+
+// implement the From trait to key an address by the default prefix lengths
+impl From<IpAddr> for IpPrefixKey {
+    fn from(ip: IpAddr) -> Self {
+        Self::new(ip, PrefixLengths::default())
+    }
+}
+
+// implement the Display trait in CIDR notation, e.g. 203.0.113.0/24
+impl fmt::Display for IpPrefixKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+// prefixes are stored as the network's address bytes plus the length
+impl StoreKey for IpPrefixKey {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.network.to_bytes();
+        bytes.push(self.prefix_len);
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let (prefix_len, network) = bytes.split_last()?;
+        let network = IpAddr::from_bytes(network)?;
+        let lengths = PrefixLengths {
+            v4: *prefix_len,
+            v6: *prefix_len,
+        };
+        // reject host bits or lengths the address cannot have
+        let key = Self::new(network, lengths);
+        (key.network == network && key.prefix_len == *prefix_len).then_some(key)
+    }
+}
+
+// struct type to represent an extractor that keys the address another
+// extractor finds by its prefix, e.g. `IpPrefix::new(PeerIp)`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpPrefix<E> {
+    inner: E,
+    lengths: PrefixLengths,
+}
+
+// methods for the IpPrefix struct
+impl<E> IpPrefix<E> {
+    // method to aggregate by the default /24 and /64 prefixes
+    pub fn new(inner: E) -> Self {
+        Self::with_lengths(inner, PrefixLengths::default())
+    }
+
+    // method to aggregate by other prefix lengths
+    pub fn with_lengths(inner: E, lengths: PrefixLengths) -> Self {
+        Self { inner, lengths }
+    }
+}
+
+impl<E> KeyExtractor for IpPrefix<E>
+where
+    E: KeyExtractor<Key = IpAddr>,
+{
+    type Key = IpPrefixKey;
+
+    fn extract(&self, request: &HttpRequest) -> Option<IpPrefixKey> {
+        let ip = self.inner.extract(request)?;
+        Some(IpPrefixKey::new(ip, self.lengths))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_extractor::PeerIp;
+    use crate::{RateLimiter, TestClock};
+
+    fn ip(text: &str) -> IpAddr {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn addresses_in_one_prefix_share_a_key() {
+        let key = IpPrefixKey::from(ip("203.0.113.77"));
+        assert_eq!(key, IpPrefixKey::from(ip("203.0.113.200")));
+        assert_ne!(key, IpPrefixKey::from(ip("203.0.114.77")));
+        assert_eq!(key.to_string(), "203.0.113.0/24");
+
+        let v6 = IpPrefixKey::from(ip("2001:db8:1:2:aaaa::1"));
+        assert_eq!(v6, IpPrefixKey::from(ip("2001:db8:1:2:ffff::9")));
+        assert_eq!(v6.to_string(), "2001:db8:1:2::/64");
+
+        // mapped addresses aggregate with their IPv4 form
+        assert_eq!(IpPrefixKey::from(ip("::ffff:203.0.113.5")), key);
+
+        assert_eq!(IpPrefixKey::from_bytes(&key.to_bytes()), Some(key));
+        assert_eq!(IpPrefixKey::from_bytes(&v6.to_bytes()), Some(v6));
+        assert_eq!(IpPrefixKey::from_bytes(&[203, 0, 113, 77, 24]), None);
+    }
+
+    #[test]
+    fn prefix_lengths_are_configurable_and_clamped() {
+        let lengths = PrefixLengths { v4: 16, v6: 200 };
+        let key = IpPrefixKey::new(ip("10.20.30.40"), lengths);
+        assert_eq!(key.to_string(), "10.20.0.0/16");
+        let v6 = IpPrefixKey::new(ip("2001:db8::1"), lengths);
+        assert_eq!(v6.to_string(), "2001:db8::1/128");
+
+        let everything = PrefixLengths { v4: 0, v6: 0 };
+        assert_eq!(
+            IpPrefixKey::new(ip("10.20.30.40"), everything).network(),
+            ip("0.0.0.0")
+        );
+    }
+
+    #[test]
+    fn rotating_within_a_subnet_shares_one_limit() {
+        let limiter = RateLimiter::new(1.0, 1.0, TestClock::new(0.0)).unwrap();
+        let extractor = IpPrefix::new(PeerIp);
+        let admitted = (1..=10)
+            .map(|host| HttpRequest::new("GET", "/", IpAddr::from([198, 51, 100, host])))
+            .filter(|request| {
+                let key = extractor.extract(request).unwrap();
+                limiter.is_allowed(key).unwrap()
+            })
+            .count();
+        assert_eq!(admitted, 2);
+    }
+}
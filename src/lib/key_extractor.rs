@@ -0,0 +1,421 @@
+// src/lib/key_extractor.rs
+
+// dependencies
+use crate::access_list::{in_prefix, parse_network};
+use crate::http::{HttpRequest, normalize_ip};
+use std::net::IpAddr;
+
+// trait for strategies that map a request to a rate-limit key
+// None means the request carries no key of this kind (a missing header, a
+// malformed token), so strategies can be chained with `or`
+pub trait KeyExtractor: Send + Sync {
+    type Key;
+
+    fn extract(&self, request: &HttpRequest) -> Option<Self::Key>;
+
+    // method to fall back to another strategy when this one finds no key,
+    // e.g. `ApiKeyHeader::new("x-api-key").or(...)`
+    fn or<E>(self, fallback: E) -> Fallback<Self, E>
+    where
+        Self: Sized,
+        E: KeyExtractor<Key = Self::Key>,
+    {
+        Fallback {
+            first: self,
+            fallback,
+        }
+    }
+}
+
+// closures can be used directly as extractors
+impl<F, K> KeyExtractor for F
+where
+    F: Fn(&HttpRequest) -> Option<K> + Send + Sync,
+{
+    type Key = K;
+
+    fn extract(&self, request: &HttpRequest) -> Option<K> {
+        self(request)
+    }
+}
+
+// struct type to represent keying by the connected peer's address
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PeerIp;
+
+impl KeyExtractor for PeerIp {
+    type Key = IpAddr;
+
+    fn extract(&self, request: &HttpRequest) -> Option<IpAddr> {
+        Some(request.peer())
+    }
+}
+
+// struct type to represent keying by the client address in X-Forwarded-For
+// each proxy appends the address it received the request from, so behind
+// `trusted_proxies` proxies the client is that many entries from the right;
+// anything further left was written by the client and can be forged
+// the header is only read from peers in the trusted proxy set; any other
+// client could set it to a fresh address on every request, so it is keyed
+// by its own peer address
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForwardedFor {
+    trusted_proxies: usize,
+    proxies: Vec<(IpAddr, u8)>, // networks whose header is believed
+}
+
+// methods for the ForwardedFor struct
+impl ForwardedFor {
+    // method to trust the last `trusted_proxies` hops (at least one); no
+    // peer is a trusted proxy until `trust` names one
+    pub fn new(trusted_proxies: usize) -> Self {
+        Self {
+            trusted_proxies: trusted_proxies.max(1),
+            proxies: Vec::new(),
+        }
+    }
+
+    // method to read the header from peers at `proxy`, an address or CIDR
+    // prefix such as "10.0.0.0/8"; None if it is neither
+    pub fn trust(mut self, proxy: &str) -> Option<Self> {
+        let (network, len) = parse_network(proxy.trim())?;
+        self.proxies.push((normalize_ip(network), len));
+        Some(self)
+    }
+
+    // internal method to check whether the peer is a trusted proxy
+    fn is_trusted(&self, peer: IpAddr) -> bool {
+        let peer = normalize_ip(peer);
+        self.proxies
+            .iter()
+            .any(|(network, len)| in_prefix(peer, *network, *len))
+    }
+}
+
+impl KeyExtractor for ForwardedFor {
+    type Key = IpAddr;
+
+    // the peer address when the peer is not a trusted proxy; from a trusted
+    // proxy, None when the header is missing, too short to have passed
+    // through every trusted proxy, or holds something other than an address
+    fn extract(&self, request: &HttpRequest) -> Option<IpAddr> {
+        if !self.is_trusted(request.peer()) {
+            return Some(normalize_ip(request.peer()));
+        }
+        let hops = request.header("x-forwarded-for")?.split(',');
+        let client = hops.rev().nth(self.trusted_proxies - 1)?;
+        client.trim().parse().ok().map(normalize_ip)
+    }
+}
+
+// struct type to represent keying by the value of an API key header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiKeyHeader {
+    name: String,
+}
+
+// methods for the ApiKeyHeader struct
+impl ApiKeyHeader {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+        }
+    }
+}
+
+impl KeyExtractor for ApiKeyHeader {
+    type Key = String;
+
+    fn extract(&self, request: &HttpRequest) -> Option<String> {
+        let value = request.header(&self.name)?.trim();
+        (!value.is_empty()).then(|| value.to_string())
+    }
+}
+
+// struct type to represent keying by the `sub` claim of a bearer JWT
+// the signature is NOT checked: anyone can mint a token with any subject,
+// so use this only behind a gateway that has already verified the token
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JwtSubject {
+    header: String,
+}
+
+// methods for the JwtSubject struct
+impl JwtSubject {
+    // method to read the token from `Authorization: Bearer <token>`
+    pub fn new() -> Self {
+        Self::from_header("authorization")
+    }
+
+    // method to read the token from another header, with or without the
+    // `Bearer ` prefix
+    pub fn from_header(name: &str) -> Self {
+        Self {
+            header: name.to_string(),
+        }
+    }
+}
+
+// implement the Default trait to read the Authorization header
+impl Default for JwtSubject {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KeyExtractor for JwtSubject {
+    type Key = String;
+
+    fn extract(&self, request: &HttpRequest) -> Option<String> {
+        let value = request.header(&self.header)?.trim();
+        let token = match value.split_once(' ') {
+            Some((scheme, token)) if scheme.eq_ignore_ascii_case("bearer") => token.trim(),
+            Some(_) => return None,
+            None => value,
+        };
+        let mut parts = token.split('.');
+        let (_header, payload, _signature) = (parts.next()?, parts.next()?, parts.next()?);
+        let claims = String::from_utf8(base64url_decode(payload)?).ok()?;
+        string_claim(&claims, "sub")
+    }
+}
+
+// struct type to represent keying by two strategies at once, e.g. an API
+// key per client IP; both must find a key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Composite<A, B>(pub A, pub B);
+
+impl<A, B> KeyExtractor for Composite<A, B>
+where
+    A: KeyExtractor,
+    B: KeyExtractor,
+{
+    type Key = (A::Key, B::Key);
+
+    fn extract(&self, request: &HttpRequest) -> Option<Self::Key> {
+        Some((self.0.extract(request)?, self.1.extract(request)?))
+    }
+}
+
+// struct type to represent a strategy with a fallback, see KeyExtractor::or
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fallback<A, B> {
+    first: A,
+    fallback: B,
+}
+
+impl<A, B> KeyExtractor for Fallback<A, B>
+where
+    A: KeyExtractor,
+    B: KeyExtractor<Key = A::Key>,
+{
+    type Key = A::Key;
+
+    fn extract(&self, request: &HttpRequest) -> Option<A::Key> {
+        self.first
+            .extract(request)
+            .or_else(|| self.fallback.extract(request))
+    }
+}
+
+// decode unpadded (or padded) base64url, as used in JWT segments
+fn base64url_decode(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len() * 3 / 4);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in text.trim_end_matches('=').bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'-' => 62,
+            b'_' => 63,
+            _ => return None,
+        };
+        buffer = (buffer << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+// find a top-level string claim in a JSON object, skipping nested values
+// only the escapes a subject plausibly holds are decoded; a value using
+// `\u` escapes is treated as missing rather than guessed at
+fn string_claim(json: &str, name: &str) -> Option<String> {
+    let mut chars = json.trim().strip_prefix('{')?.chars().peekable();
+    loop {
+        skip_whitespace(&mut chars);
+        if chars.next()? != '"' {
+            return None;
+        }
+        let key = read_string(&mut chars)?;
+        skip_whitespace(&mut chars);
+        if chars.next()? != ':' {
+            return None;
+        }
+        skip_whitespace(&mut chars);
+        if key == name {
+            return match chars.next()? {
+                '"' => read_string(&mut chars),
+                _ => None,
+            };
+        }
+        skip_value(&mut chars)?;
+        skip_whitespace(&mut chars);
+        if chars.next()? != ',' {
+            return None;
+        }
+    }
+}
+
+type Chars<'a> = std::iter::Peekable<std::str::Chars<'a>>;
+
+fn skip_whitespace(chars: &mut Chars<'_>) {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+}
+
+// read the rest of a string whose opening quote was consumed
+fn read_string(chars: &mut Chars<'_>) -> Option<String> {
+    let mut text = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(text),
+            '\\' => text.push(match chars.next()? {
+                '"' => '"',
+                '\\' => '\\',
+                '/' => '/',
+                _ => return None,
+            }),
+            c => text.push(c),
+        }
+    }
+}
+
+// skip one value: a string, or anything up to the next top-level , or }
+fn skip_value(chars: &mut Chars<'_>) -> Option<()> {
+    let mut depth = 0usize;
+    while let Some(&c) = chars.peek() {
+        match c {
+            '"' => {
+                chars.next();
+                skip_string(chars)?;
+                continue;
+            }
+            '{' | '[' => depth += 1,
+            '}' | ']' if depth == 0 => return Some(()),
+            '}' | ']' => depth -= 1,
+            ',' if depth == 0 => return Some(()),
+            _ => {}
+        }
+        chars.next();
+    }
+    None
+}
+
+// skip the rest of a string whose opening quote was consumed, whatever its escapes
+fn skip_string(chars: &mut Chars<'_>) -> Option<()> {
+    loop {
+        match chars.next()? {
+            '"' => return Some(()),
+            '\\' => {
+                chars.next()?;
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> HttpRequest {
+        HttpRequest::new("GET", "/", IpAddr::from([10, 0, 0, 1]))
+    }
+
+    #[test]
+    fn forwarded_for_trusts_only_the_proxy_hops() {
+        let behind = |hops| ForwardedFor::new(hops).trust("10.0.0.0/8").unwrap();
+        let forwarded = request().with_header("X-Forwarded-For", "6.6.6.6, 1.2.3.4, 10.0.0.2");
+        assert_eq!(
+            behind(1).extract(&forwarded),
+            Some(IpAddr::from([10, 0, 0, 2]))
+        );
+        assert_eq!(
+            behind(2).extract(&forwarded),
+            Some(IpAddr::from([1, 2, 3, 4]))
+        );
+        assert_eq!(behind(4).extract(&forwarded), None);
+        assert_eq!(behind(1).extract(&request()), None);
+
+        // falling back to the peer when the proxy sent no header
+        let keyed = behind(1).or(PeerIp);
+        assert_eq!(keyed.extract(&request()), Some(IpAddr::from([10, 0, 0, 1])));
+        assert!(ForwardedFor::new(1).trust("10.0.0.0/33").is_none());
+    }
+
+    #[test]
+    fn forwarded_for_ignores_the_header_from_untrusted_peers() {
+        // a client connecting directly cannot pick its own key
+        let direct = HttpRequest::new("GET", "/", IpAddr::from([203, 0, 113, 7]))
+            .with_header("X-Forwarded-For", "1.2.3.4");
+        let forwarded = ForwardedFor::new(1).trust("10.0.0.1").unwrap();
+        assert_eq!(
+            forwarded.extract(&direct),
+            Some(IpAddr::from([203, 0, 113, 7]))
+        );
+        assert_eq!(
+            ForwardedFor::new(1).extract(&request().with_header("X-Forwarded-For", "1.2.3.4")),
+            Some(IpAddr::from([10, 0, 0, 1]))
+        );
+    }
+
+    #[test]
+    fn jwt_subject_reads_the_sub_claim() {
+        // {"iss":"x","aud":{"sub":"nested"},"sub":"user-42"}
+        let payload = "eyJpc3MiOiJ4IiwiYXVkIjp7InN1YiI6Im5lc3RlZCJ9LCJzdWIiOiJ1c2VyLTQyIn0";
+        let bearer = format!("Bearer eyJhbGciOiJIUzI1NiJ9.{}.c2ln", payload);
+        let authorized = request().with_header("Authorization", &bearer);
+        assert_eq!(
+            JwtSubject::new().extract(&authorized),
+            Some(String::from("user-42"))
+        );
+
+        let basic = request().with_header("Authorization", "Basic dXNlcjpwYXNz");
+        assert_eq!(JwtSubject::new().extract(&basic), None);
+        let garbage = request().with_header("Authorization", "Bearer not-a-token");
+        assert_eq!(JwtSubject::new().extract(&garbage), None);
+    }
+
+    #[test]
+    fn string_claims_skip_nested_values_and_escapes() {
+        let json = r#"{"a": [1, {"sub": "no"}], "b": "x\"}", "sub" : "a\/b"}"#;
+        assert_eq!(string_claim(json, "sub"), Some(String::from("a/b")));
+        assert_eq!(string_claim(r#"{"sub": 42}"#, "sub"), None);
+        assert_eq!(string_claim(r#"{"sub": "\u0041"}"#, "sub"), None);
+        assert_eq!(string_claim(r#"{"iss": "x"}"#, "sub"), None);
+        assert_eq!(base64url_decode("aGk_"), Some(vec![b'h', b'i', 0x3f]));
+    }
+
+    #[test]
+    fn composite_and_closure_keys() {
+        let tenant = |request: &HttpRequest| request.header("x-tenant").map(str::to_string);
+        let keyed = Composite(ApiKeyHeader::new("x-api-key"), tenant);
+        let both = request()
+            .with_header("X-Api-Key", "k1")
+            .with_header("X-Tenant", "acme");
+        assert_eq!(
+            keyed.extract(&both),
+            Some((String::from("k1"), String::from("acme")))
+        );
+        let only_key = request().with_header("X-Api-Key", "k1");
+        assert_eq!(keyed.extract(&only_key), None);
+        assert_eq!(
+            ApiKeyHeader::new("x-api-key").extract(&request().with_header("X-Api-Key", " ")),
+            None
+        );
+    }
+}
@@ -0,0 +1,173 @@
+// src/lib/labels.rs
+
+// dependencies
+use dashmap::DashMap;
+use std::cell::RefCell;
+use std::fmt::Write;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+// trait for keys that can render themselves as a metrics label
+// writing into a caller-owned buffer lets exporters reuse one allocation
+pub trait KeyLabel {
+    fn write_label(&self, buf: &mut String);
+}
+
+impl KeyLabel for str {
+    fn write_label(&self, buf: &mut String) {
+        buf.push_str(self);
+    }
+}
+
+impl KeyLabel for String {
+    fn write_label(&self, buf: &mut String) {
+        buf.push_str(self);
+    }
+}
+
+impl KeyLabel for IpAddr {
+    fn write_label(&self, buf: &mut String) {
+        let _ = write!(buf, "{}", self);
+    }
+}
+
+impl KeyLabel for u64 {
+    fn write_label(&self, buf: &mut String) {
+        let _ = write!(buf, "{}", self);
+    }
+}
+
+impl KeyLabel for u32 {
+    fn write_label(&self, buf: &mut String) {
+        let _ = write!(buf, "{}", self);
+    }
+}
+
+impl KeyLabel for usize {
+    fn write_label(&self, buf: &mut String) {
+        let _ = write!(buf, "{}", self);
+    }
+}
+
+impl<T: KeyLabel + ?Sized> KeyLabel for &T {
+    fn write_label(&self, buf: &mut String) {
+        (**self).write_label(buf)
+    }
+}
+
+thread_local! {
+    // scratch buffer for labels that do not fit in the cache
+    static SCRATCH: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+// struct type to represent a bounded cache of rendered labels for stable keys
+// once `capacity` labels are cached, further keys are rendered into a reused
+// per-thread buffer instead, so large keyspaces cannot grow the cache unbounded
+#[derive(Debug)]
+pub struct LabelCache<T>
+where
+    T: KeyLabel + Hash + Eq + Clone,
+{
+    labels: DashMap<T, Arc<str>>,
+    capacity: usize,
+}
+
+// methods for the LabelCache struct
+impl<T> LabelCache<T>
+where
+    T: KeyLabel + Hash + Eq + Clone,
+{
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            labels: DashMap::new(),
+            capacity,
+        }
+    }
+
+    // method to return the shared label for a key, caching it if there is room
+    pub fn label(&self, key: &T) -> Arc<str> {
+        if let Some(label) = self.labels.get(key) {
+            return Arc::clone(label.value());
+        }
+
+        let mut buf = String::new();
+        key.write_label(&mut buf);
+        let label: Arc<str> = Arc::from(buf);
+        if self.labels.len() < self.capacity {
+            self.labels.insert(key.clone(), Arc::clone(&label));
+        }
+        label
+    }
+
+    // method to run `f` with the key's label without allocating for keys
+    // that are already cached or that do not fit in the cache
+    pub fn with_label<R>(&self, key: &T, f: impl FnOnce(&str) -> R) -> R {
+        if let Some(label) = self.labels.get(key) {
+            return f(label.value());
+        }
+        if self.labels.len() < self.capacity {
+            return f(&self.label(key));
+        }
+
+        SCRATCH.with(|scratch| {
+            let mut buf = scratch.borrow_mut();
+            buf.clear();
+            key.write_label(&mut buf);
+            f(&buf)
+        })
+    }
+
+    // method to drop a key's cached label, e.g. when the key is evicted
+    pub fn forget(&self, key: &T) {
+        self.labels.remove(key);
+    }
+
+    // accessor method to return the number of cached labels
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    // method to check whether no labels are cached
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_write_labels_into_buffer() {
+        let mut buf = String::new();
+        "client1".write_label(&mut buf);
+        buf.push(',');
+        "10.0.0.1".parse::<IpAddr>().unwrap().write_label(&mut buf);
+        buf.push(',');
+        42u64.write_label(&mut buf);
+        assert_eq!(buf, "client1,10.0.0.1,42");
+    }
+
+    #[test]
+    fn cached_labels_are_shared() {
+        let cache = LabelCache::new(10);
+        let first = cache.label(&String::from("client1"));
+        let second = cache.label(&String::from("client1"));
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(cache.len(), 1);
+
+        cache.forget(&String::from("client1"));
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn cache_is_bounded() {
+        let cache = LabelCache::new(2);
+        for key in 0u64..5 {
+            let rendered = cache.with_label(&key, |label| label.to_string());
+            assert_eq!(rendered, key.to_string());
+        }
+        assert_eq!(cache.len(), 2);
+    }
+}
@@ -1,9 +1,102 @@
 // src/lib/lib.rs
 
 // modules
+pub mod access_list;
+pub mod algorithm;
+pub mod audit;
+pub mod buffered;
+pub mod builder;
+pub mod canary;
+pub mod checkpoint;
 pub mod clock;
+pub mod combinators;
+pub mod concurrency;
+pub mod config;
+pub mod cost_guard;
+pub mod degradation;
+pub mod direct;
+pub mod exemplars;
+pub mod explain;
+mod forecast;
+mod gcra;
+pub mod handle;
+pub mod hierarchy;
+pub mod hooks;
+pub mod http;
+pub mod ip_prefix;
+pub mod key_extractor;
+pub mod labels;
+#[cfg(feature = "metrics")]
+mod limiter_metrics;
+pub mod middleware;
+pub mod multi;
+mod overrides;
+pub mod persistence;
+pub mod problem;
+pub mod quota;
 pub mod rate_limiter;
+#[cfg(feature = "redis")]
+pub mod redis_store;
+pub mod registry;
+pub mod retry_budget;
+pub mod routes;
+pub mod sampling;
+pub mod shaper;
+#[cfg(feature = "sled")]
+pub mod sled_store;
+pub mod snapshot;
+pub mod static_limiter;
+pub mod store;
+#[cfg(feature = "futures")]
+pub mod stream;
+pub mod templates;
+pub mod view;
 
 // re-exports
+pub use access_list::*;
+pub use algorithm::*;
+pub use audit::*;
+pub use buffered::*;
+pub use builder::*;
+pub use canary::*;
+pub use checkpoint::*;
 pub use clock::*;
+pub use combinators::*;
+pub use concurrency::*;
+pub use config::*;
+pub use cost_guard::*;
+pub use degradation::*;
+pub use direct::*;
+pub use exemplars::*;
+pub use explain::*;
+#[cfg(feature = "fuzzing")]
+pub use gcra::invariants as gcra_invariants;
+pub use handle::*;
+pub use hierarchy::*;
+pub use hooks::*;
+pub use http::*;
+pub use ip_prefix::*;
+pub use key_extractor::*;
+pub use labels::*;
+pub use middleware::*;
+pub use multi::*;
+pub use persistence::*;
+pub use problem::*;
+pub use quota::*;
 pub use rate_limiter::*;
+#[cfg(feature = "redis")]
+pub use redis_store::*;
+pub use registry::*;
+pub use retry_budget::*;
+pub use routes::*;
+pub use sampling::*;
+pub use shaper::*;
+#[cfg(feature = "sled")]
+pub use sled_store::*;
+pub use snapshot::*;
+pub use static_limiter::*;
+pub use store::*;
+#[cfg(feature = "futures")]
+pub use stream::*;
+pub use templates::*;
+pub use view::*;
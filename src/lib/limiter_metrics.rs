@@ -0,0 +1,183 @@
+// src/lib/limiter_metrics.rs
+
+// dependencies
+use crate::hooks::EvictionReason;
+use metrics::{Counter, Gauge, counter, gauge};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// decisions between refreshes of the tracked-keys gauge; counting the keys
+// visits every shard, so it is not done on every check
+const KEY_COUNT_REFRESH: usize = 256;
+
+// struct type to represent the metrics of one limiter, registered with the
+// `metrics` recorder installed when the limiter was configured
+// every series carries a `limiter` label with the limiter's name:
+//   - gcra_decisions_total{outcome="allowed"|"denied"}
+//   - gcra_tracked_keys
+//   - gcra_evictions_total{reason="idle"|"expired"|"capacity"|"manual"}
+#[derive(Debug)]
+pub(crate) struct LimiterMetrics {
+    allowed: Counter,
+    denied: Counter,
+    tracked_keys: Gauge,
+    evictions: [Counter; 4],
+    since_refresh: AtomicUsize,
+}
+
+// methods for the LimiterMetrics struct
+impl LimiterMetrics {
+    pub(crate) fn new(name: &str) -> Self {
+        let decisions = |outcome: &'static str| {
+            counter!(
+                "gcra_decisions_total",
+                "limiter" => name.to_string(),
+                "outcome" => outcome
+            )
+        };
+        let evictions = |reason: EvictionReason| {
+            counter!(
+                "gcra_evictions_total",
+                "limiter" => name.to_string(),
+                "reason" => reason.to_string()
+            )
+        };
+        Self {
+            allowed: decisions("allowed"),
+            denied: decisions("denied"),
+            tracked_keys: gauge!("gcra_tracked_keys", "limiter" => name.to_string()),
+            evictions: [
+                evictions(EvictionReason::Idle),
+                evictions(EvictionReason::Expired),
+                evictions(EvictionReason::Capacity),
+                evictions(EvictionReason::Manual),
+            ],
+            since_refresh: AtomicUsize::new(0),
+        }
+    }
+
+    pub(crate) fn decided(&self, allowed: bool) {
+        match allowed {
+            true => self.allowed.increment(1),
+            false => self.denied.increment(1),
+        }
+    }
+
+    pub(crate) fn evicted(&self, reason: EvictionReason) {
+        let index = match reason {
+            EvictionReason::Idle => 0,
+            EvictionReason::Expired => 1,
+            EvictionReason::Capacity => 2,
+            EvictionReason::Manual => 3,
+        };
+        self.evictions[index].increment(1);
+    }
+
+    // count a check and refresh the tracked-keys gauge if it is due, starting
+    // with the first check; must not be called while holding an entry lock
+    pub(crate) fn refresh_keys(&self, count: impl FnOnce() -> usize) {
+        let seen = self.since_refresh.fetch_add(1, Ordering::Relaxed);
+        if seen.is_multiple_of(KEY_COUNT_REFRESH) {
+            self.set_keys(count());
+        }
+    }
+
+    pub(crate) fn set_keys(&self, keys: usize) {
+        self.tracked_keys.set(keys as f64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{RateLimiter, TestClock};
+    use metrics::{
+        Counter, Gauge, Histogram, Key, KeyName, Metadata, Recorder, SharedString, Unit,
+    };
+    use std::collections::BTreeMap;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    // a recorder keeping every series as an atomic, keyed by name and labels
+    #[derive(Default)]
+    struct TestRecorder(Mutex<BTreeMap<String, Arc<AtomicU64>>>);
+
+    impl TestRecorder {
+        fn series(&self, key: &Key) -> Arc<AtomicU64> {
+            let labels: Vec<String> = key
+                .labels()
+                .map(|label| format!("{}={}", label.key(), label.value()))
+                .collect();
+            let name = format!("{}{{{}}}", key.name(), labels.join(","));
+            Arc::clone(self.0.lock().unwrap().entry(name).or_default())
+        }
+
+        fn value(&self, name: &str) -> u64 {
+            self.0.lock().unwrap()[name].load(Ordering::Relaxed)
+        }
+    }
+
+    impl Recorder for TestRecorder {
+        fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+        fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+            Counter::from_arc(self.series(key))
+        }
+
+        fn register_gauge(&self, key: &Key, _: &Metadata<'_>) -> Gauge {
+            Gauge::from_arc(self.series(key))
+        }
+
+        fn register_histogram(&self, _: &Key, _: &Metadata<'_>) -> Histogram {
+            Histogram::noop()
+        }
+    }
+
+    #[test]
+    fn counts_decisions_keys_and_evictions() {
+        let recorder = TestRecorder::default();
+        let clock = TestClock::new(0.0);
+        let limiter = metrics::with_local_recorder(&recorder, || {
+            RateLimiter::new(1.0, 1.0, clock.clone())
+                .unwrap()
+                .with_max_keys(2)
+                .with_metrics("api")
+        });
+
+        for _ in 0..3 {
+            let _ = limiter.is_allowed("a");
+        }
+        assert_eq!(
+            recorder.value("gcra_decisions_total{limiter=api,outcome=allowed}"),
+            2
+        );
+        assert_eq!(
+            recorder.value("gcra_decisions_total{limiter=api,outcome=denied}"),
+            1
+        );
+        let keys = recorder.value("gcra_tracked_keys{limiter=api}");
+        assert_eq!(f64::from_bits(keys), 1.0);
+
+        // a third key at the cap evicts the least recently charged one
+        clock.advance(1.0);
+        let _ = limiter.is_allowed("b");
+        let _ = limiter.is_allowed("c");
+        limiter.reset(&"c");
+        assert_eq!(
+            recorder.value("gcra_evictions_total{limiter=api,reason=capacity}"),
+            1
+        );
+        assert_eq!(
+            recorder.value("gcra_evictions_total{limiter=api,reason=manual}"),
+            1
+        );
+
+        limiter.gc_step(usize::MAX);
+        let keys = recorder.value("gcra_tracked_keys{limiter=api}");
+        assert_eq!(f64::from_bits(keys), 1.0);
+        clock.advance(10.0);
+        limiter.gc_step(usize::MAX);
+        let keys = recorder.value("gcra_tracked_keys{limiter=api}");
+        assert_eq!(f64::from_bits(keys), 0.0);
+    }
+}
@@ -0,0 +1,720 @@
+// src/lib/middleware.rs
+
+// dependencies
+use crate::SystemClock;
+use crate::clock::Clock;
+use crate::http::HttpRequest;
+use crate::key_extractor::KeyExtractor;
+use crate::problem::Problem;
+use crate::rate_limiter::{Denied, RateLimiter, RateLimiterError};
+use crate::routes::{RouteOutcome, RouteTable};
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+// type aliases for the pieces a middleware is assembled from
+type KeyFn<T> = Box<dyn Fn(&HttpRequest) -> T + Send + Sync>;
+type CostFn = Box<dyn Fn(&HttpRequest) -> u32 + Send + Sync>;
+type DeniedFn<R> = Box<dyn Fn(&HttpRequest, Denied) -> R + Send + Sync>;
+type RoutesFn = Box<dyn Fn(&HttpRequest) -> RouteOutcome + Send + Sync>;
+
+// struct type to represent a required builder piece that has not been given yet
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Missing;
+
+// struct type to represent the key extractor once it has been given
+pub struct WithKey<T>(KeyFn<T>);
+
+// struct type to represent the quota once it has been given
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WithQuota {
+    rate_per_second: f64,
+    burst_capacity: f64,
+}
+
+// struct type to represent a cost table keyed by HTTP method
+// the defaults charge reads 1 and writes 5; methods not in the table cost
+// `fallback`, which starts at 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MethodCosts {
+    costs: HashMap<String, u32>,
+    fallback: u32,
+}
+
+// methods for the MethodCosts struct
+impl MethodCosts {
+    // method to create a table with no entries, charging every method `fallback`
+    pub fn uniform(fallback: u32) -> Self {
+        Self {
+            costs: HashMap::new(),
+            fallback,
+        }
+    }
+
+    // builder-style method to override the cost of one method
+    pub fn with(mut self, method: &str, cost: u32) -> Self {
+        self.costs.insert(method.to_ascii_uppercase(), cost);
+        self
+    }
+
+    // builder-style method to set the cost of methods not in the table
+    pub fn with_fallback(mut self, fallback: u32) -> Self {
+        self.fallback = fallback;
+        self
+    }
+
+    // method to look up the cost of a method, ignoring its case
+    pub fn cost(&self, method: &str) -> u32 {
+        match self.costs.get(method) {
+            Some(cost) => *cost,
+            None => self
+                .costs
+                .get(&method.to_ascii_uppercase())
+                .copied()
+                .unwrap_or(self.fallback),
+        }
+    }
+}
+
+// implement the Default trait with reads at 1 and writes at 5
+impl Default for MethodCosts {
+    fn default() -> Self {
+        let reads = ["GET", "HEAD", "OPTIONS"].map(|method| (method, 1));
+        let writes = ["POST", "PUT", "PATCH", "DELETE"].map(|method| (method, 5));
+        reads
+            .into_iter()
+            .chain(writes)
+            .fold(Self::uniform(1), |costs, (method, cost)| {
+                costs.with(method, cost)
+            })
+    }
+}
+
+// struct type to represent the denied response a middleware returns unless
+// `on_denied` says otherwise: the denial, with an RFC 7807 problem body for
+// clients that sent `Accept: application/json`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rejection {
+    denied: Denied,
+    problem: Option<Problem>,
+}
+
+// methods for the Rejection struct
+impl Rejection {
+    // method to build the response for a denial, numbering the problem body
+    // with `decision_id` if the client wants JSON
+    pub fn new(request: &HttpRequest, denied: Denied, decision_id: u64) -> Self {
+        let problem = request.accepts_json().then(|| {
+            let retry_after = denied.retry_after().map_or(1, |wait| {
+                wait.as_secs() + u64::from(wait.subsec_nanos() > 0)
+            });
+            Problem::rate_limited(retry_after.max(1), decision_id)
+        });
+        Self { denied, problem }
+    }
+
+    // accessor method to return the limiter's denial
+    pub fn denied(&self) -> Denied {
+        self.denied
+    }
+
+    // accessor method to return how long the client should wait
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.denied.retry_after()
+    }
+
+    // accessor method to return the problem body to send with a 429, if the
+    // client asked for JSON; send it as `Problem::CONTENT_TYPE`
+    pub fn problem(&self) -> Option<&Problem> {
+        self.problem.as_ref()
+    }
+}
+
+// struct type to represent a middleware builder
+// the key extractor and the quota are tracked in the type, so `build` only
+// exists once both have been given and a missing piece is a compile error;
+// the cost function, denied-response builder and clock stay optional
+pub struct MiddlewareBuilder<K, Q, C = SystemClock, R = Rejection> {
+    key: K,
+    quota: Q,
+    clock: C,
+    cost: Option<CostFn>,
+    max_delay: Option<Duration>,
+    messages: Option<WithQuota>,
+    routes: Option<RoutesFn>,
+    on_denied: DeniedFn<R>,
+}
+
+// methods for the MiddlewareBuilder struct
+impl MiddlewareBuilder<Missing, Missing> {
+    // method to start a builder with no key extractor and no quota
+    pub fn new() -> Self {
+        Self {
+            key: Missing,
+            quota: Missing,
+            clock: SystemClock,
+            cost: None,
+            max_delay: None,
+            messages: None,
+            routes: None,
+            on_denied: {
+                let next_decision_id = AtomicU64::new(1);
+                Box::new(move |request, denied| {
+                    let decision_id = next_decision_id.fetch_add(1, Ordering::Relaxed);
+                    Rejection::new(request, denied, decision_id)
+                })
+            },
+        }
+    }
+}
+
+// implement the Default trait for an empty MiddlewareBuilder
+impl Default for MiddlewareBuilder<Missing, Missing> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, Q, C, R> MiddlewareBuilder<K, Q, C, R> {
+    // method to set how a request maps to a rate-limit key
+    pub fn key<T>(
+        self,
+        extract: impl Fn(&HttpRequest) -> T + Send + Sync + 'static,
+    ) -> MiddlewareBuilder<WithKey<T>, Q, C, R> {
+        MiddlewareBuilder {
+            key: WithKey(Box::new(extract)),
+            quota: self.quota,
+            clock: self.clock,
+            cost: self.cost,
+            max_delay: self.max_delay,
+            messages: self.messages,
+            routes: self.routes,
+            on_denied: self.on_denied,
+        }
+    }
+
+    // method to key requests with a KeyExtractor; requests it finds no key
+    // for share one limit under `None`, so chain a fallback with `or` to
+    // keep them apart
+    pub fn key_extractor<E>(
+        self,
+        extractor: E,
+    ) -> MiddlewareBuilder<WithKey<Option<E::Key>>, Q, C, R>
+    where
+        E: KeyExtractor + 'static,
+    {
+        self.key(move |request: &HttpRequest| extractor.extract(request))
+    }
+
+    // method to set the rate and burst every key is limited to
+    pub fn quota(
+        self,
+        rate_per_second: f64,
+        burst_capacity: f64,
+    ) -> MiddlewareBuilder<K, WithQuota, C, R> {
+        MiddlewareBuilder {
+            key: self.key,
+            quota: WithQuota {
+                rate_per_second,
+                burst_capacity,
+            },
+            clock: self.clock,
+            cost: self.cost,
+            max_delay: self.max_delay,
+            messages: self.messages,
+            routes: self.routes,
+            on_denied: self.on_denied,
+        }
+    }
+
+    // method to use another clock than the system clock
+    pub fn clock<C2: Clock>(self, clock: C2) -> MiddlewareBuilder<K, Q, C2, R> {
+        MiddlewareBuilder {
+            key: self.key,
+            quota: self.quota,
+            clock,
+            cost: self.cost,
+            max_delay: self.max_delay,
+            messages: self.messages,
+            routes: self.routes,
+            on_denied: self.on_denied,
+        }
+    }
+
+    // method to charge requests a cost other than 1
+    pub fn cost(mut self, cost: impl Fn(&HttpRequest) -> u32 + Send + Sync + 'static) -> Self {
+        self.cost = Some(Box::new(cost));
+        self
+    }
+
+    // method to charge requests by their method, e.g.
+    // `.method_costs(MethodCosts::default().with("POST", 10))`; replaces any
+    // cost function set before
+    pub fn method_costs(self, costs: MethodCosts) -> Self {
+        self.cost(move |request: &HttpRequest| costs.cost(request.method()))
+    }
+
+    // method to let clients that send an `X-Request-Deadline` wait for a slot
+    // instead of being denied, for no longer than their deadline or `max_delay`
+    pub fn honor_deadlines(mut self, max_delay: Duration) -> Self {
+        self.max_delay = Some(max_delay);
+        self
+    }
+
+    // method to limit the messages of WebSocket connections, which a single
+    // upgrade request would otherwise let through unchecked; messages are
+    // keyed by the client that made the upgrade, across all its connections
+    pub fn websocket_messages(mut self, rate_per_second: f64, burst_capacity: f64) -> Self {
+        self.messages = Some(WithQuota {
+            rate_per_second,
+            burst_capacity,
+        });
+        self
+    }
+
+    // method to check requests against a route table first, e.g. one built
+    // from the `RouteConfig` the server binary loads; the first matching
+    // rule's quota, cost and key strategy decide the request, and requests
+    // matching no rule fall through to the builder's key and quota
+    pub fn routes<C2>(mut self, table: RouteTable<C2>) -> Self
+    where
+        C2: Clock + Send + Sync + 'static,
+    {
+        self.routes = Some(Box::new(move |request: &HttpRequest| table.check(request)));
+        self
+    }
+
+    // method to turn a denial into the caller's response type; without it the
+    // middleware returns a Rejection, with a problem body for JSON clients
+    pub fn on_denied<R2>(
+        self,
+        respond: impl Fn(&HttpRequest, Denied) -> R2 + Send + Sync + 'static,
+    ) -> MiddlewareBuilder<K, Q, C, R2> {
+        MiddlewareBuilder {
+            key: self.key,
+            quota: self.quota,
+            clock: self.clock,
+            cost: self.cost,
+            max_delay: self.max_delay,
+            messages: self.messages,
+            routes: self.routes,
+            on_denied: Box::new(respond),
+        }
+    }
+}
+
+impl<T, C, R> MiddlewareBuilder<WithKey<T>, WithQuota, C, R>
+where
+    T: Hash + Eq + Clone,
+    C: Clock + Clone,
+{
+    // method to create the middleware; only the quota values can still be
+    // rejected here, since every required piece is known to be present
+    pub fn build(self) -> Result<RateLimitMiddleware<T, C, R>, RateLimiterError> {
+        let messages = match self.messages {
+            Some(quota) => Some(Arc::new(RateLimiter::new(
+                quota.rate_per_second,
+                quota.burst_capacity,
+                self.clock.clone(),
+            )?)),
+            None => None,
+        };
+        let limiter = RateLimiter::new(
+            self.quota.rate_per_second,
+            self.quota.burst_capacity,
+            self.clock,
+        )?;
+        Ok(RateLimitMiddleware {
+            limiter,
+            key: self.key.0,
+            cost: self.cost,
+            max_delay: self.max_delay,
+            messages,
+            routes: self.routes,
+            on_denied: self.on_denied,
+        })
+    }
+}
+
+// struct type to represent a rate-limiting middleware for HttpRequests
+pub struct RateLimitMiddleware<T, C = SystemClock, R = Rejection>
+where
+    T: Hash + Eq + Clone,
+    C: Clock,
+{
+    limiter: RateLimiter<T, C>,
+    key: KeyFn<T>,
+    cost: Option<CostFn>,
+    max_delay: Option<Duration>,
+    messages: Option<Arc<RateLimiter<T, C>>>,
+    routes: Option<RoutesFn>,
+    on_denied: DeniedFn<R>,
+}
+
+// enum type to represent an admitted request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    Now,             // handle the request right away
+    After(Duration), // a slot is reserved; handle the request after this wait
+}
+
+// methods for the RateLimitMiddleware struct
+impl<T, C, R> RateLimitMiddleware<T, C, R>
+where
+    T: Hash + Eq + Clone,
+    C: Clock,
+{
+    // method to charge a request, returning the denied response if it is limited
+    // with deadlines honored, a request that would conform within both its
+    // deadline and the max delay has its slot reserved and is told to wait;
+    // a request matching a route rule is decided by that rule alone
+    pub fn check(&self, request: &HttpRequest) -> Result<Admission, R> {
+        if let Some(routes) = &self.routes {
+            match routes(request) {
+                RouteOutcome::Allowed => return Ok(Admission::Now),
+                RouteOutcome::Denied(denied) => return Err((self.on_denied)(request, denied)),
+                RouteOutcome::NoMatch => {}
+            }
+        }
+        let cost = self.cost.as_ref().map_or(1, |cost| cost(request));
+        let key = (self.key)(request);
+        let denied = match self.limiter.charge(key.clone(), cost) {
+            Ok(()) => return Ok(Admission::Now),
+            Err(denied) => denied,
+        };
+
+        let budget = self.max_delay.zip(request.deadline());
+        let wait = denied.retry_after().filter(|wait| {
+            budget.is_some_and(|(max_delay, deadline)| *wait <= max_delay.min(deadline))
+        });
+        match wait {
+            Some(wait) => {
+                let at = self.limiter.now() + wait.as_nanos() as u64;
+                self.limiter
+                    .charge_at(key, cost, at)
+                    .map(|()| Admission::After(wait))
+                    .map_err(|denied| (self.on_denied)(request, denied))
+            }
+            None => Err((self.on_denied)(request, denied)),
+        }
+    }
+
+    // method to return the message gate for an admitted WebSocket upgrade,
+    // keyed by the client that made it; None for other requests, or when
+    // WebSocket messages are not limited
+    pub fn websocket(&self, request: &HttpRequest) -> Option<MessageGate<T, C>> {
+        let limiter = self.messages.as_ref()?;
+        request.is_websocket_upgrade().then(|| MessageGate {
+            limiter: Arc::clone(limiter),
+            key: (self.key)(request),
+        })
+    }
+
+    // accessor method to return the underlying limiter, e.g. to set overrides
+    pub fn limiter(&self) -> &RateLimiter<T, C> {
+        &self.limiter
+    }
+}
+
+// struct type to represent the message limit of one WebSocket connection
+// the gate owns what it needs, so it can move into the connection's task
+#[derive(Debug)]
+pub struct MessageGate<T, C = SystemClock>
+where
+    T: Hash + Eq + Clone,
+    C: Clock,
+{
+    limiter: Arc<RateLimiter<T, C>>,
+    key: T,
+}
+
+// methods for the MessageGate struct
+impl<T, C> MessageGate<T, C>
+where
+    T: Hash + Eq + Clone,
+    C: Clock,
+{
+    // method to charge one message, reporting a denial's wait
+    pub fn admit(&self) -> Result<(), Denied> {
+        self.limiter.charge(self.key.clone(), 1)
+    }
+
+    // method to wrap a connection's incoming messages so that each one is
+    // charged as it is read; a limited message comes out as the denial, and
+    // the caller decides whether to drop it or close the connection
+    pub fn wrap<I: IntoIterator>(self, messages: I) -> GatedMessages<I::IntoIter, T, C> {
+        GatedMessages {
+            messages: messages.into_iter(),
+            gate: self,
+        }
+    }
+}
+
+// struct type to represent a message stream with each message charged to a gate
+#[derive(Debug)]
+pub struct GatedMessages<I, T, C = SystemClock>
+where
+    T: Hash + Eq + Clone,
+    C: Clock,
+{
+    messages: I,
+    gate: MessageGate<T, C>,
+}
+
+// implement the Iterator trait to charge each message as it is read
+impl<I, T, C> Iterator for GatedMessages<I, T, C>
+where
+    I: Iterator,
+    T: Hash + Eq + Clone,
+    C: Clock,
+{
+    type Item = Result<I::Item, Denied>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let message = self.messages.next()?;
+        Some(self.gate.admit().map(|()| message))
+    }
+}
+
+// implement the Debug trait for the RateLimitMiddleware type
+impl<T, C, R> fmt::Debug for RateLimitMiddleware<T, C, R>
+where
+    T: Hash + Eq + Clone + fmt::Debug,
+    C: Clock + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RateLimitMiddleware")
+            .field("limiter", &self.limiter)
+            .field("custom_cost", &self.cost.is_some())
+            .field("routes", &self.routes.is_some())
+            .field("websocket_messages", &self.messages.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestClock;
+    use crate::problem::Problem;
+    use std::net::IpAddr;
+
+    fn request(method: &str, peer: &str) -> HttpRequest {
+        let peer: IpAddr = peer.parse().unwrap();
+        HttpRequest::new(method, "/", peer)
+    }
+
+    #[test]
+    fn builds_with_required_pieces_and_defaults() {
+        let middleware = MiddlewareBuilder::new()
+            .key(|request: &HttpRequest| request.peer())
+            .quota(1.0, 0.0)
+            .clock(TestClock::new(0.0))
+            .build()
+            .unwrap();
+
+        assert!(middleware.check(&request("GET", "10.0.0.1")).is_ok());
+        let denied = middleware.check(&request("GET", "10.0.0.1")).unwrap_err();
+        assert_eq!(
+            denied.retry_after(),
+            Some(std::time::Duration::from_secs(1))
+        );
+        assert!(middleware.check(&request("GET", "10.0.0.2")).is_ok());
+    }
+
+    #[test]
+    fn keys_with_an_extractor() {
+        use crate::key_extractor::{ForwardedFor, PeerIp};
+
+        // behind one proxy, clients are told apart by X-Forwarded-For
+        let middleware = MiddlewareBuilder::new()
+            .key_extractor(ForwardedFor::new(1).trust("10.0.0.9").unwrap().or(PeerIp))
+            .quota(1.0, 0.0)
+            .clock(TestClock::new(0.0))
+            .build()
+            .unwrap();
+        let via_proxy =
+            |client: &str| request("GET", "10.0.0.9").with_header("X-Forwarded-For", client);
+
+        assert!(middleware.check(&via_proxy("1.2.3.4")).is_ok());
+        assert!(middleware.check(&via_proxy("1.2.3.4")).is_err());
+        assert!(middleware.check(&via_proxy("5.6.7.8")).is_ok());
+        assert!(middleware.check(&request("GET", "10.0.0.9")).is_ok());
+    }
+
+    #[test]
+    fn optional_cost_and_denied_response() {
+        // the optional pieces can come in any order around the required ones
+        let middleware = MiddlewareBuilder::new()
+            .on_denied(|_: &HttpRequest, denied: Denied| {
+                Problem::rate_limited(denied.retry_after().map_or(0, |d| d.as_secs()), 7)
+            })
+            .quota(1.0, 4.0)
+            .cost(|request: &HttpRequest| if request.method() == "POST" { 5 } else { 1 })
+            .key(|_: &HttpRequest| "global")
+            .clock(TestClock::new(0.0))
+            .build()
+            .unwrap();
+
+        assert!(middleware.check(&request("POST", "10.0.0.1")).is_ok());
+        let problem = middleware.check(&request("GET", "10.0.0.2")).unwrap_err();
+        assert_eq!(problem.status, 429);
+        assert_eq!(problem.retry_after, Some(1));
+    }
+
+    #[test]
+    fn json_clients_get_a_problem_body_by_default() {
+        let middleware = MiddlewareBuilder::new()
+            .key(|_: &HttpRequest| "global")
+            .quota(1.0, 0.0)
+            .clock(TestClock::new(0.5))
+            .build()
+            .unwrap();
+        let json = |peer| request("GET", peer).with_header("Accept", "application/json");
+
+        assert!(middleware.check(&json("10.0.0.1")).is_ok());
+        let rejection = middleware.check(&json("10.0.0.1")).unwrap_err();
+        let problem = rejection.problem().unwrap();
+        assert_eq!(problem.status, 429);
+        assert_eq!(problem.retry_after, Some(1));
+        assert_eq!(problem.decision_id, Some(1));
+        assert_eq!(
+            rejection.retry_after(),
+            Some(std::time::Duration::from_secs(1))
+        );
+
+        // other clients get the bare denial
+        let rejection = middleware.check(&request("GET", "10.0.0.1")).unwrap_err();
+        assert_eq!(rejection.problem(), None);
+    }
+
+    #[test]
+    fn route_tables_decide_matching_requests() {
+        use crate::routes::{KeyStrategy, QuotaConfig, RouteConfig, RouteRule};
+
+        let config = RouteConfig {
+            routes: vec![RouteRule {
+                method: Some(String::from("POST")),
+                path: String::from("/login"),
+                quota: QuotaConfig::new(1.0, 0.0),
+                cost: 1,
+                key: KeyStrategy::Global,
+            }],
+            ..RouteConfig::default()
+        };
+        let clock = TestClock::new(0.0);
+        let middleware = MiddlewareBuilder::new()
+            .routes(RouteTable::new(&config, clock.clone()).unwrap())
+            .key(|request: &HttpRequest| request.peer())
+            .quota(10.0, 10.0)
+            .clock(clock)
+            .build()
+            .unwrap();
+        let login = |peer| HttpRequest::new("POST", "/login", peer);
+
+        // the login rule is shared by every client, whatever the default quota
+        assert!(middleware.check(&login([10, 0, 0, 1].into())).is_ok());
+        assert!(middleware.check(&login([10, 0, 0, 2].into())).is_err());
+        assert!(middleware.check(&request("GET", "10.0.0.2")).is_ok());
+    }
+
+    #[test]
+    fn method_costs_charge_writes_more_than_reads() {
+        let costs = MethodCosts::default().with("delete", 20);
+        assert_eq!(costs.cost("GET"), 1);
+        assert_eq!(costs.cost("post"), 5);
+        assert_eq!(costs.cost("DELETE"), 20);
+        assert_eq!(costs.cost("PROPFIND"), 1);
+        assert_eq!(costs.with_fallback(3).cost("PROPFIND"), 3);
+
+        let middleware = MiddlewareBuilder::new()
+            .key(|_: &HttpRequest| "global")
+            .quota(1.0, 5.0)
+            .method_costs(MethodCosts::default())
+            .clock(TestClock::new(0.0))
+            .build()
+            .unwrap();
+        assert!(middleware.check(&request("POST", "10.0.0.1")).is_ok());
+        assert!(middleware.check(&request("GET", "10.0.0.1")).is_ok());
+        assert!(middleware.check(&request("PUT", "10.0.0.1")).is_err());
+    }
+
+    #[test]
+    fn deadlines_turn_short_waits_into_delays() {
+        let clock = TestClock::new(0.0);
+        let middleware = MiddlewareBuilder::new()
+            .key(|_: &HttpRequest| "global")
+            .quota(1.0, 0.0)
+            .honor_deadlines(std::time::Duration::from_millis(1500))
+            .clock(clock.clone())
+            .build()
+            .unwrap();
+        let patient =
+            |millis: &str| request("GET", "10.0.0.1").with_header("X-Request-Deadline", millis);
+
+        assert_eq!(middleware.check(&patient("5000")), Ok(Admission::Now));
+        // the next slot is 1s away: within this deadline, so it is reserved
+        assert_eq!(
+            middleware.check(&patient("5000")),
+            Ok(Admission::After(std::time::Duration::from_secs(1)))
+        );
+        // two slots away exceeds the max delay, and a short deadline or no
+        // header is denied with the accurate wait
+        let denied = middleware.check(&patient("5000")).unwrap_err();
+        assert_eq!(
+            denied.retry_after(),
+            Some(std::time::Duration::from_secs(2))
+        );
+        assert!(middleware.check(&patient("100")).is_err());
+        assert!(middleware.check(&request("GET", "10.0.0.1")).is_err());
+
+        clock.advance(1.0);
+        assert!(middleware.check(&request("GET", "10.0.0.1")).is_err());
+        clock.advance(1.0);
+        assert_eq!(
+            middleware.check(&request("GET", "10.0.0.1")),
+            Ok(Admission::Now)
+        );
+    }
+
+    #[test]
+    fn websocket_messages_are_limited_per_client() {
+        let middleware = MiddlewareBuilder::new()
+            .key(|request: &HttpRequest| request.peer())
+            .quota(10.0, 10.0)
+            .websocket_messages(1.0, 2.0)
+            .clock(TestClock::new(0.0))
+            .build()
+            .unwrap();
+        let upgrade = || {
+            request("GET", "10.0.0.1")
+                .with_header("Connection", "keep-alive, Upgrade")
+                .with_header("Upgrade", "websocket")
+        };
+
+        assert!(middleware.websocket(&request("GET", "10.0.0.1")).is_none());
+        assert!(middleware.check(&upgrade()).is_ok());
+        let gate = middleware.websocket(&upgrade()).unwrap();
+        let delivered: Vec<bool> = gate
+            .wrap(["a", "b", "c", "d"])
+            .map(|message| message.is_ok())
+            .collect();
+        assert_eq!(delivered, [true, true, true, false]);
+
+        // a second connection from the same client shares its message budget
+        let second = middleware.websocket(&upgrade()).unwrap();
+        assert!(second.admit().is_err());
+    }
+
+    #[test]
+    fn invalid_quota_is_still_a_runtime_error() {
+        let result = MiddlewareBuilder::new()
+            .key(|request: &HttpRequest| request.peer())
+            .quota(0.0, 1.0)
+            .build();
+        assert!(matches!(result, Err(RateLimiterError::InvalidRate)));
+    }
+}
@@ -0,0 +1,157 @@
+// src/lib/multi.rs
+
+// dependencies
+use crate::SystemClock;
+use crate::clock::Clock;
+use crate::gcra;
+use crate::quota::Quota;
+use crate::rate_limiter::{Denied, RateLimiterError};
+use dashmap::DashMap;
+use std::hash::Hash;
+
+// struct type to represent a rate limiter with several quotas per key, e.g.
+// 10/s with a burst of 20 and also 1000/hour
+// each key holds one TAT per stage in a single map entry, so a request is
+// tested against every stage and either charged to all of them or to none
+// under one entry lock
+#[derive(Debug)]
+pub struct MultiLimiter<T, C = SystemClock>
+where
+    T: Hash + Eq + Clone,
+    C: Clock,
+{
+    stages: Vec<Quota>,
+    client_state: DashMap<T, Box<[u64]>>,
+    clock: C,
+}
+
+// methods for the MultiLimiter struct
+impl<T, C> MultiLimiter<T, C>
+where
+    T: Hash + Eq + Clone,
+    C: Clock,
+{
+    // method to create a limiter enforcing every quota in `stages`; with no
+    // stages nothing would be limited, which is rejected as an invalid rate
+    pub fn new(
+        stages: impl IntoIterator<Item = Quota>,
+        clock: C,
+    ) -> Result<Self, RateLimiterError> {
+        let stages: Vec<Quota> = stages.into_iter().collect();
+        if stages.is_empty() {
+            return Err(RateLimiterError::InvalidRate);
+        }
+        Ok(Self {
+            stages,
+            client_state: DashMap::new(),
+            clock,
+        })
+    }
+
+    // accessor method to return the quotas, in the order given
+    pub fn stages(&self) -> &[Quota] {
+        &self.stages
+    }
+
+    // method to check one request for a key against every stage
+    pub fn is_allowed(&self, client_id: T) -> bool {
+        self.charge(client_id, 1).is_ok()
+    }
+
+    // method to charge `cost` units to a key if every stage admits them
+    // a denial waits for the slowest stage, and is None if some stage can
+    // never admit the cost
+    pub fn charge(&self, client_id: T, cost: u32) -> Result<(), Denied> {
+        let now = self.clock.now();
+        let mut entry = self
+            .client_state
+            .entry(client_id)
+            .or_insert_with(|| vec![now; self.stages.len()].into_boxed_slice());
+        let tats = entry.value_mut();
+
+        let mut charged = Vec::with_capacity(self.stages.len());
+        let mut retry_at = Some(0);
+        for (quota, tat) in self.stages.iter().zip(tats.iter()) {
+            let (increment, tolerance) = quota.nanos();
+            match gcra::conform(now, *tat, increment, tolerance, cost) {
+                Some(new_tat) => charged.push(new_tat),
+                None => {
+                    let at = gcra::retry_at(*tat, increment, tolerance, cost);
+                    retry_at = retry_at.zip(at).map(|(slowest, at)| slowest.max(at));
+                }
+            }
+        }
+
+        if charged.len() < self.stages.len() {
+            return Err(Denied::new(retry_at.map(|at| at.saturating_sub(now))));
+        }
+        tats.copy_from_slice(&charged);
+        Ok(())
+    }
+
+    // method to drop keys whose TATs have all passed, as RateLimiter::evict_idle
+    // returns how many keys were removed
+    pub fn evict_idle(&self) -> usize {
+        let now = self.clock.now();
+        let before = self.client_state.len();
+        self.client_state
+            .retain(|_, tats| tats.iter().any(|tat| *tat > now));
+        before - self.client_state.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestClock;
+    use std::num::NonZeroU32;
+    use std::time::Duration;
+
+    fn n(value: u32) -> NonZeroU32 {
+        NonZeroU32::new(value).unwrap()
+    }
+
+    // 2/s with up to 4 at once, and 6/minute
+    fn limiter(clock: &TestClock) -> MultiLimiter<&'static str, TestClock> {
+        let stages = [
+            Quota::per_second(n(2)).allow_burst(n(4)),
+            Quota::per_minute(n(6)),
+        ];
+        MultiLimiter::new(stages, clock.clone()).unwrap()
+    }
+
+    #[test]
+    fn every_stage_must_admit() {
+        let clock = TestClock::new(0.0);
+        let limiter = limiter(&clock);
+
+        // the per-second burst runs out first
+        (0..4).for_each(|_| assert!(limiter.is_allowed("k")));
+        let denied = limiter.charge("k", 1).unwrap_err();
+        assert_eq!(denied.retry_after(), Some(Duration::from_millis(500)));
+
+        // then the per-minute quota, which waits longer
+        clock.advance(1.0);
+        (0..2).for_each(|_| assert!(limiter.is_allowed("k")));
+        let denied = limiter.charge("k", 1).unwrap_err();
+        assert_eq!(denied.retry_after(), Some(Duration::from_secs(9)));
+        assert!(limiter.is_allowed("other"));
+    }
+
+    #[test]
+    fn denial_charges_no_stage() {
+        let clock = TestClock::new(0.0);
+        let limiter = limiter(&clock);
+        (0..4).for_each(|_| assert!(limiter.is_allowed("k")));
+        // denied by the first stage, so the minute stage keeps its 2 units
+        (0..10).for_each(|_| assert!(!limiter.is_allowed("k")));
+        clock.advance(1.0);
+        (0..2).for_each(|_| assert!(limiter.is_allowed("k")));
+
+        assert_eq!(limiter.charge("k", 7).unwrap_err().retry_after(), None);
+        assert!(MultiLimiter::<&str, _>::new([], clock.clone()).is_err());
+
+        clock.advance(60.0);
+        assert_eq!(limiter.evict_idle(), 1);
+    }
+}
@@ -0,0 +1,240 @@
+// src/lib/overrides.rs
+
+// dependencies
+use crate::rate_limiter::Listing;
+use dashmap::DashMap;
+use std::borrow::Borrow;
+use std::hash::Hash;
+use std::sync::Mutex;
+use std::sync::atomic::{self, AtomicBool, AtomicU64, Ordering};
+
+// struct type to represent a per-key quota that replaces the limiter default
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct QuotaOverride {
+    pub(crate) increment: u64,
+    pub(crate) tolerance: u64,
+    pub(crate) expires_at: u64,
+}
+
+// struct type to represent a limiter's default increment and tolerance,
+// which can be swapped at runtime
+// a sequence lock: the writer makes the sequence odd while it stores, and a
+// reader retries until it saw the same even sequence before and after its
+// loads, so the pair is always read consistently without the read path
+// writing to a shared cache line
+#[derive(Debug)]
+pub(crate) struct BaseQuota {
+    sequence: AtomicU64,
+    increment: AtomicU64,
+    tolerance: AtomicU64,
+    writer: Mutex<()>,
+}
+
+// methods for the BaseQuota struct
+impl BaseQuota {
+    pub(crate) fn new(increment: u64, tolerance: u64) -> Self {
+        Self {
+            sequence: AtomicU64::new(0),
+            increment: AtomicU64::new(increment),
+            tolerance: AtomicU64::new(tolerance),
+            writer: Mutex::new(()),
+        }
+    }
+
+    // method to read the increment and tolerance as one pair
+    pub(crate) fn load(&self) -> (u64, u64) {
+        loop {
+            let before = self.sequence.load(Ordering::Acquire);
+            let increment = self.increment.load(Ordering::Relaxed);
+            let tolerance = self.tolerance.load(Ordering::Relaxed);
+            atomic::fence(Ordering::Acquire);
+            if before.is_multiple_of(2) && self.sequence.load(Ordering::Relaxed) == before {
+                return (increment, tolerance);
+            }
+            std::hint::spin_loop();
+        }
+    }
+
+    // method to replace the pair, with `update` given the current one
+    pub(crate) fn update(&self, update: impl FnOnce(u64, u64) -> (u64, u64)) {
+        let _writer = self.writer.lock().unwrap();
+        let (increment, tolerance) = update(
+            self.increment.load(Ordering::Relaxed),
+            self.tolerance.load(Ordering::Relaxed),
+        );
+        let sequence = self.sequence.load(Ordering::Relaxed);
+        self.sequence.store(sequence + 1, Ordering::Relaxed);
+        atomic::fence(Ordering::Release);
+        self.increment.store(increment, Ordering::Relaxed);
+        self.tolerance.store(tolerance, Ordering::Relaxed);
+        self.sequence.store(sequence + 2, Ordering::Release);
+    }
+}
+
+// struct type to represent the per-key overrides, bans and list entries
+// attached to a limiter; overrides and bans expire against the limiter's
+// clock and are dropped lazily when read, so no external scheduler is needed
+// to revert them, while list entries stay until removed
+#[derive(Debug)]
+pub(crate) struct Overrides<T>
+where
+    T: Hash + Eq + Clone,
+{
+    quotas: DashMap<T, QuotaOverride>,
+    bans: DashMap<T, u64>, // key -> ban expiry in nanoseconds
+    lists: DashMap<T, Listing>,
+    // set once anything was ever added, letting the hot path skip the lookups
+    has_quotas: AtomicBool,
+    has_bans: AtomicBool,
+    has_lists: AtomicBool,
+    // bumped on every change, so cached lookups can tell they are stale
+    generation: AtomicU64,
+}
+
+// methods for the Overrides struct
+impl<T> Overrides<T>
+where
+    T: Hash + Eq + Clone,
+{
+    pub(crate) fn new() -> Self {
+        Self {
+            quotas: DashMap::new(),
+            bans: DashMap::new(),
+            lists: DashMap::new(),
+            has_quotas: AtomicBool::new(false),
+            has_bans: AtomicBool::new(false),
+            has_lists: AtomicBool::new(false),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    // method to install or replace a quota override
+    pub(crate) fn set_quota(&self, key: T, quota: QuotaOverride) {
+        self.has_quotas.store(true, Ordering::Release);
+        self.quotas.insert(key, quota);
+        self.generation.fetch_add(1, Ordering::AcqRel);
+    }
+
+    // method to drop a quota override
+    pub(crate) fn remove_quota(&self, key: &T) {
+        self.quotas.remove(key);
+        self.generation.fetch_add(1, Ordering::AcqRel);
+    }
+
+    // method to look up the quota override in force at `now`
+    pub(crate) fn quota<Q>(&self, key: &Q, now: u64) -> Option<QuotaOverride>
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if !self.has_quotas.load(Ordering::Acquire) {
+            return None;
+        }
+        let quota = *self.quotas.get(key)?;
+        if quota.expires_at <= now {
+            self.quotas.remove_if(key, |_, q| q.expires_at <= now);
+            return None;
+        }
+        Some(quota)
+    }
+
+    // method to ban a key until the given time
+    pub(crate) fn ban(&self, key: T, expires_at: u64) {
+        self.has_bans.store(true, Ordering::Release);
+        self.bans.insert(key, expires_at);
+        self.generation.fetch_add(1, Ordering::AcqRel);
+    }
+
+    // method to lift a ban
+    pub(crate) fn unban(&self, key: &T) {
+        self.bans.remove(key);
+        self.generation.fetch_add(1, Ordering::AcqRel);
+    }
+
+    // method to put a key on the allowlist or the denylist, taking it off
+    // the other one
+    pub(crate) fn list(&self, key: T, listing: Listing) {
+        self.has_lists.store(true, Ordering::Release);
+        self.lists.insert(key, listing);
+        self.generation.fetch_add(1, Ordering::AcqRel);
+    }
+
+    // method to take a key off whichever list it is on
+    pub(crate) fn unlist(&self, key: &T) {
+        self.lists.remove(key);
+        self.generation.fetch_add(1, Ordering::AcqRel);
+    }
+
+    // method to return the list a key is on, if any
+    pub(crate) fn listing<Q>(&self, key: &Q) -> Option<Listing>
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if !self.has_lists.load(Ordering::Acquire) {
+            return None;
+        }
+        self.lists.get(key).map(|listing| *listing)
+    }
+
+    // method to list every key on the allowlist or the denylist
+    pub(crate) fn listed(&self) -> Vec<(T, Listing)> {
+        self.lists
+            .iter()
+            .map(|entry| (entry.key().clone(), *entry.value()))
+            .collect()
+    }
+
+    // method to drop every override, ban and list entry
+    pub(crate) fn clear(&self) {
+        self.quotas.clear();
+        self.bans.clear();
+        self.lists.clear();
+        self.generation.fetch_add(1, Ordering::AcqRel);
+    }
+
+    // method to mark cached lookups stale, e.g. after the default quota changed
+    pub(crate) fn touch(&self) {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+    }
+
+    // accessor method to return the change counter
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    // method to return the nanoseconds left on a key's ban at `now`, if banned
+    pub(crate) fn ban_remaining<Q>(&self, key: &Q, now: u64) -> Option<u64>
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if !self.has_bans.load(Ordering::Acquire) {
+            return None;
+        }
+        let expires_at = *self.bans.get(key)?;
+        if expires_at <= now {
+            self.bans.remove_if(key, |_, expiry| *expiry <= now);
+            return None;
+        }
+        Some(expires_at - now)
+    }
+
+    // method to list unexpired quota overrides with the nanoseconds they have left
+    pub(crate) fn active_quotas(&self, now: u64) -> Vec<(T, QuotaOverride, u64)> {
+        self.quotas.retain(|_, quota| quota.expires_at > now);
+        self.quotas
+            .iter()
+            .map(|entry| (entry.key().clone(), *entry.value(), entry.expires_at - now))
+            .collect()
+    }
+
+    // method to list unexpired bans with the nanoseconds they have left
+    pub(crate) fn active_bans(&self, now: u64) -> Vec<(T, u64)> {
+        self.bans.retain(|_, expires_at| *expires_at > now);
+        self.bans
+            .iter()
+            .map(|entry| (entry.key().clone(), *entry.value() - now))
+            .collect()
+    }
+}
@@ -0,0 +1,102 @@
+// src/lib/persistence.rs
+
+// dependencies
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+// trait for keys that can be written to and read back from a persistent store
+pub trait StoreKey: Sized {
+    fn to_bytes(&self) -> Vec<u8>;
+    fn from_bytes(bytes: &[u8]) -> Option<Self>;
+}
+
+impl StoreKey for String {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        String::from_utf8(bytes.to_vec()).ok()
+    }
+}
+
+// IPv4 addresses are stored as 4 bytes, IPv6 addresses as 16 bytes
+impl StoreKey for IpAddr {
+    fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            IpAddr::V4(ip) => ip.octets().to_vec(),
+            IpAddr::V6(ip) => ip.octets().to_vec(),
+        }
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        match bytes.len() {
+            4 => {
+                let octets: [u8; 4] = bytes.try_into().ok()?;
+                Some(IpAddr::V4(Ipv4Addr::from(octets)))
+            }
+            16 => {
+                let octets: [u8; 16] = bytes.try_into().ok()?;
+                Some(IpAddr::V6(Ipv6Addr::from(octets)))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl StoreKey for u64 {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.to_be_bytes().to_vec()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        Some(u64::from_be_bytes(bytes.try_into().ok()?))
+    }
+}
+
+impl StoreKey for u32 {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.to_be_bytes().to_vec()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        Some(u32::from_be_bytes(bytes.try_into().ok()?))
+    }
+}
+
+// helper to encode a TAT value for storage
+pub fn encode_tat(tat_nanos: u64) -> [u8; 8] {
+    tat_nanos.to_be_bytes()
+}
+
+// helper to decode a stored TAT value
+pub fn decode_tat(bytes: &[u8]) -> Option<u64> {
+    Some(u64::from_be_bytes(bytes.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_round_trip() {
+        let key = String::from("client1");
+        assert_eq!(String::from_bytes(&key.to_bytes()), Some(key));
+
+        let v4: IpAddr = "192.168.1.10".parse().unwrap();
+        assert_eq!(IpAddr::from_bytes(&v4.to_bytes()), Some(v4));
+
+        let v6: IpAddr = "2001:db8::1".parse().unwrap();
+        assert_eq!(IpAddr::from_bytes(&v6.to_bytes()), Some(v6));
+
+        assert_eq!(u64::from_bytes(&42u64.to_bytes()), Some(42));
+        assert_eq!(u32::from_bytes(&7u32.to_bytes()), Some(7));
+    }
+
+    #[test]
+    fn malformed_bytes_are_rejected() {
+        assert_eq!(IpAddr::from_bytes(&[1, 2, 3]), None);
+        assert_eq!(u64::from_bytes(&[0; 4]), None);
+        assert_eq!(decode_tat(&[0; 7]), None);
+        assert_eq!(decode_tat(&encode_tat(99)), Some(99));
+    }
+}
@@ -0,0 +1,121 @@
+// src/lib/problem.rs
+
+// dependencies
+use std::fmt::Write;
+
+// struct type to represent an RFC 7807 problem details body
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub type_uri: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: Option<String>,
+    pub retry_after: Option<u64>, // whole seconds
+    pub decision_id: Option<u64>,
+}
+
+// methods for the Problem struct
+impl Problem {
+    // content type to send problem bodies with
+    pub const CONTENT_TYPE: &'static str = "application/problem+json";
+
+    // method to describe a request denied by the rate limiter
+    pub fn rate_limited(retry_after: u64, decision_id: u64) -> Self {
+        Self {
+            type_uri: String::from("https://httpwg.org/specs/rfc6585.html#status-429"),
+            title: String::from("Too Many Requests"),
+            status: 429,
+            detail: Some(format!(
+                "Rate limit exceeded; retry in {} seconds.",
+                retry_after
+            )),
+            retry_after: Some(retry_after),
+            decision_id: Some(decision_id),
+        }
+    }
+
+    // method to describe a request rejected by an access list
+    pub fn forbidden(decision_id: u64) -> Self {
+        Self {
+            type_uri: String::from("about:blank"),
+            title: String::from("Forbidden"),
+            status: 403,
+            detail: None,
+            retry_after: None,
+            decision_id: Some(decision_id),
+        }
+    }
+
+    // method to render the problem as a JSON object
+    // `retry_after` and `decision_id` are extension members
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("{");
+        let _ = write!(
+            json,
+            "\"type\":{},\"title\":{},\"status\":{}",
+            quote(&self.type_uri),
+            quote(&self.title),
+            self.status
+        );
+        if let Some(detail) = &self.detail {
+            let _ = write!(json, ",\"detail\":{}", quote(detail));
+        }
+        if let Some(retry_after) = self.retry_after {
+            let _ = write!(json, ",\"retry_after\":{}", retry_after);
+        }
+        if let Some(decision_id) = self.decision_id {
+            let _ = write!(json, ",\"decision_id\":{}", decision_id);
+        }
+        json.push('}');
+        json
+    }
+}
+
+// quote and escape a string as a JSON string literal
+fn quote(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_rate_limited_problem() {
+        assert_eq!(
+            Problem::rate_limited(3, 42).to_json(),
+            "{\"type\":\"https://httpwg.org/specs/rfc6585.html#status-429\",\
+             \"title\":\"Too Many Requests\",\"status\":429,\
+             \"detail\":\"Rate limit exceeded; retry in 3 seconds.\",\
+             \"retry_after\":3,\"decision_id\":42}"
+        );
+    }
+
+    #[test]
+    fn escapes_strings() {
+        let problem = Problem {
+            detail: Some(String::from("a \"quoted\"\nline\u{1}")),
+            ..Problem::forbidden(1)
+        };
+        assert!(
+            problem
+                .to_json()
+                .contains("\"detail\":\"a \\\"quoted\\\"\\nline\\u0001\"")
+        );
+    }
+}
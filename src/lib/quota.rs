@@ -0,0 +1,132 @@
+// src/lib/quota.rs
+
+// dependencies
+use std::num::NonZeroU32;
+use std::time::Duration;
+
+// struct type to represent a quota in whole requests and nanoseconds
+// one request is replenished every `replenish_interval`, and up to
+// `burst_size` requests may arrive at once; unlike the f64 constructors, the
+// only rounding is the division of a period by a request count, which floors
+// to the nanosecond
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Quota {
+    increment_nanos: u64,
+    max_burst: NonZeroU32,
+}
+
+// methods for the Quota struct
+impl Quota {
+    // method to allow `max_burst` requests per second, all at once if need be
+    pub const fn per_second(max_burst: NonZeroU32) -> Self {
+        Self::per_period(1_000_000_000, max_burst)
+    }
+
+    // method to allow `max_burst` requests per minute, all at once if need be
+    pub const fn per_minute(max_burst: NonZeroU32) -> Self {
+        Self::per_period(60 * 1_000_000_000, max_burst)
+    }
+
+    // method to allow `max_burst` requests per hour, all at once if need be
+    pub const fn per_hour(max_burst: NonZeroU32) -> Self {
+        Self::per_period(3_600 * 1_000_000_000, max_burst)
+    }
+
+    // method to allow one request every `period`, with no burst; None if the
+    // period is zero or does not fit in u64 nanoseconds (about 584 years)
+    pub const fn with_period(period: Duration) -> Option<Self> {
+        let nanos = period.as_nanos();
+        if nanos == 0 || nanos > u64::MAX as u128 {
+            return None;
+        }
+        Some(Self {
+            increment_nanos: nanos as u64,
+            max_burst: NonZeroU32::MIN,
+        })
+    }
+
+    // method to allow up to `max_burst` requests at once, keeping the
+    // replenish interval
+    pub const fn allow_burst(self, max_burst: NonZeroU32) -> Self {
+        Self { max_burst, ..self }
+    }
+
+    // spread `max_burst` requests over a period; more than one request per
+    // nanosecond cannot be expressed, so the interval is at least 1ns
+    const fn per_period(period_nanos: u64, max_burst: NonZeroU32) -> Self {
+        let increment_nanos = period_nanos / max_burst.get() as u64;
+        Self {
+            increment_nanos: if increment_nanos == 0 {
+                1
+            } else {
+                increment_nanos
+            },
+            max_burst,
+        }
+    }
+
+    // accessor method to return the time it takes to replenish one request
+    pub const fn replenish_interval(&self) -> Duration {
+        Duration::from_nanos(self.increment_nanos)
+    }
+
+    // accessor method to return how many requests may arrive at once
+    pub const fn burst_size(&self) -> NonZeroU32 {
+        self.max_burst
+    }
+
+    // internal method to return the emission interval and tolerance in
+    // nanoseconds; the tolerance covers every request of the burst but one
+    pub(crate) fn nanos(&self) -> (u64, u64) {
+        let extra = self.max_burst.get() as u64 - 1;
+        (
+            self.increment_nanos,
+            self.increment_nanos.saturating_mul(extra),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{RateLimiter, TestClock};
+
+    fn n(value: u32) -> NonZeroU32 {
+        NonZeroU32::new(value).unwrap()
+    }
+
+    #[test]
+    fn constructors_use_exact_integer_intervals() {
+        assert_eq!(
+            Quota::per_second(n(3)).replenish_interval(),
+            Duration::from_nanos(333_333_333)
+        );
+        assert_eq!(
+            Quota::per_minute(n(7)).replenish_interval(),
+            Duration::from_nanos(8_571_428_571)
+        );
+        assert_eq!(
+            Quota::per_hour(n(1)).replenish_interval(),
+            Duration::from_secs(3_600)
+        );
+        let quota = Quota::with_period(Duration::from_millis(250)).unwrap();
+        assert_eq!(quota.burst_size(), n(1));
+        assert_eq!(quota.allow_burst(n(4)).nanos(), (250_000_000, 750_000_000));
+        assert_eq!(Quota::with_period(Duration::ZERO), None);
+        assert_eq!(Quota::with_period(Duration::MAX), None);
+        assert_eq!(Quota::per_second(n(u32::MAX)).nanos().0, 1);
+    }
+
+    #[test]
+    fn limiter_admits_the_burst_then_replenishes() {
+        let clock = TestClock::new(0.0);
+        let limiter = RateLimiter::with_quota(Quota::per_minute(n(3)), clock.clone());
+        assert_eq!(limiter.increment_nanos(), 20_000_000_000);
+
+        (0..3).for_each(|_| assert!(limiter.is_allowed("k").unwrap()));
+        assert!(!limiter.is_allowed("k").unwrap());
+        clock.advance(20.0);
+        assert!(limiter.is_allowed("k").unwrap());
+        assert!(!limiter.is_allowed("k").unwrap());
+    }
+}
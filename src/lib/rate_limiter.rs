@@ -1,20 +1,46 @@
 // lib/rate_limiter.rs
 
 // dependencies
-use crate::clock::Clock;
+use crate::algorithm::{Gcra, LimitAlgorithm};
+use crate::audit::{Admin, AuditAction, AuditSink, Journal};
+#[cfg(feature = "async")]
+use crate::clock::AsyncClock;
+use crate::clock::{Clock, ClockRegression, Sleeper, ThreadSleeper};
+use crate::cost_guard::{CostGuard, Reservation};
+use crate::explain::Explanation;
+use crate::forecast::{self, PaceTracker};
+use crate::handle::{KeyHandle, Resolved};
+use crate::hooks::{
+    DecisionObserver, DecisionObservers, EvictionHooks, EvictionReason, OnAllowed, OnDenied,
+};
+#[cfg(feature = "metrics")]
+use crate::limiter_metrics::LimiterMetrics;
+use crate::overrides::{BaseQuota, Overrides, QuotaOverride};
+use crate::quota::Quota;
+use crate::sampling::{DecisionLog, DecisionSink, Sampling};
+use crate::snapshot::{LimiterSnapshot, Snapshot};
+use crate::view::SnapshotView;
 use dashmap::DashMap;
+use std::borrow::Borrow;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
 use std::error::Error;
 use std::fmt;
-use std::hash::Hash;
-use std::sync::Arc;
+use std::hash::{BuildHasher, Hash};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
 
 use crate::SystemClock;
 
 // enum type to represent errors related to the rate limiter type
 #[derive(Debug)]
 pub enum RateLimiterError {
-    InvalidRate,  // for rate <= 0
-    InvalidBurst, // for burst < 0
+    InvalidRate,                  // for rate <= 0
+    InvalidBurst,                 // for burst < 0
+    ClockWentBackwards(Duration), // under ClockRegression::Error, by how much
 }
 
 // implement the Display trait for the RateLimiterError type
@@ -23,6 +49,9 @@ impl fmt::Display for RateLimiterError {
         match self {
             RateLimiterError::InvalidRate => write!(f, "Rate must be positive"),
             RateLimiterError::InvalidBurst => write!(f, "Burst must be non-negative"),
+            RateLimiterError::ClockWentBackwards(by) => {
+                write!(f, "Clock went backwards by {:?}", by)
+            }
         }
     }
 }
@@ -30,16 +59,351 @@ impl fmt::Display for RateLimiterError {
 // implement the Error trait for the RateLimiter type
 impl Error for RateLimiterError {}
 
+// enum type to represent how the emission interval is rounded to nanoseconds
+// Floor admits slightly more than the configured rate over long periods,
+// Ceil slightly less, and Nearest stays within half a nanosecond per request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Rounding {
+    #[default]
+    Floor,
+    Ceil,
+    Nearest,
+}
+
+// methods for the Rounding enum
+impl Rounding {
+    // method to round an interval in (fractional) nanoseconds
+    pub(crate) fn apply(self, nanos: f64) -> u64 {
+        match self {
+            Rounding::Floor => nanos.floor() as u64,
+            Rounding::Ceil => nanos.ceil() as u64,
+            Rounding::Nearest => nanos.round() as u64,
+        }
+    }
+}
+
+// struct type to represent a quota override that is still in force
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActiveOverride<T> {
+    pub client_id: T,
+    pub rate: f64,
+    pub burst: f64,
+    pub remaining: Option<Duration>, // None for an override that never expires
+}
+
+// enum type to represent the list a key has been put on
+// allowlisted keys are never limited, e.g. health checkers; denylisted keys
+// are always rejected, e.g. known-bad scrapers. Both are checked before bans
+// and the key's quota, and stay until removed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Listing {
+    Allow,
+    Deny,
+}
+
+// struct type to represent a rejected request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Denied {
+    retry_after: Option<Duration>,
+}
+
+// methods for the Denied struct
+impl Denied {
+    pub(crate) fn new(retry_after_nanos: Option<u64>) -> Self {
+        Self {
+            retry_after: retry_after_nanos.map(Duration::from_nanos),
+        }
+    }
+
+    // accessor method to return how long to wait before retrying
+    // None if the cost exceeds the burst capacity and can never be admitted
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.retry_after
+    }
+}
+
+// implement the Display trait for the Denied type
+impl fmt::Display for Denied {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.retry_after {
+            Some(wait) => write!(f, "Rate limit exceeded, retry after {:?}", wait),
+            None => write!(f, "Request cost exceeds the burst capacity"),
+        }
+    }
+}
+
+// implement the Error trait for the Denied type
+impl Error for Denied {}
+
+// struct type to represent the outcome of one check, with the key's timing
+// `tat` is None in allow-all mode, which reads no state, and for requests
+// refused because the clock went backwards
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decision {
+    allowed: bool,
+    retry_after: Option<Duration>,
+    remaining_burst: u32,
+    tat: Option<u64>,
+    charged: bool, // whether the cost reached the key's state
+}
+
+// methods for the Decision struct
+impl Decision {
+    // the decision of the allow-all fast path
+    pub(crate) fn allow_all() -> Self {
+        Self {
+            allowed: true,
+            retry_after: None,
+            remaining_burst: u32::MAX,
+            tat: None,
+            charged: false,
+        }
+    }
+
+    // the decision for a request refused under ClockRegression::Error,
+    // retried once the clock has caught up
+    pub(crate) fn clock_regressed(behind_nanos: u64) -> Self {
+        Self {
+            allowed: false,
+            retry_after: Some(Duration::from_nanos(behind_nanos)),
+            remaining_burst: 0,
+            tat: None,
+            charged: false,
+        }
+    }
+
+    // the decision for a denylisted key, which is never admitted
+    pub(crate) fn denylisted(tat: u64) -> Self {
+        Self {
+            allowed: false,
+            retry_after: None,
+            remaining_burst: 0,
+            tat: Some(tat),
+            charged: false,
+        }
+    }
+
+    // the decision for a banned key, whose quota state is left untouched
+    pub(crate) fn banned(remaining_nanos: u64, tat: u64) -> Self {
+        Self {
+            allowed: false,
+            retry_after: Some(Duration::from_nanos(remaining_nanos)),
+            remaining_burst: 0,
+            tat: Some(tat),
+            charged: false,
+        }
+    }
+
+    // the decision as shadow mode hands it to the caller: admitted, with the
+    // key's timing left as the real decision found it; a denied request
+    // stays uncharged
+    pub(crate) fn shadowed(self) -> Self {
+        Self {
+            allowed: true,
+            retry_after: None,
+            ..self
+        }
+    }
+
+    // the decision for a charge against a key's quota, given its TAT after
+    // and how many unit requests that TAT still admits
+    pub(crate) fn charged(result: Result<(), Denied>, tat: u64, remaining: u64) -> Self {
+        Self {
+            allowed: result.is_ok(),
+            retry_after: result.err().and_then(|denied| denied.retry_after),
+            remaining_burst: u32::try_from(remaining).unwrap_or(u32::MAX),
+            tat: Some(tat),
+            charged: result.is_ok(),
+        }
+    }
+
+    // accessor method to return whether the request was admitted
+    pub fn is_allowed(&self) -> bool {
+        self.allowed
+    }
+
+    // accessor method to return how long to wait before retrying a denied
+    // request; None when allowed or when the cost exceeds the burst capacity
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.retry_after
+    }
+
+    // accessor method to return how many more unit requests the key's quota
+    // would admit right now, after this one
+    pub fn remaining_burst(&self) -> u32 {
+        self.remaining_burst
+    }
+
+    // accessor method to return the key's theoretical arrival time after
+    // this check, in nanoseconds on the limiter's clock
+    pub fn tat_nanos(&self) -> Option<u64> {
+        self.tat
+    }
+
+    // internal accessor reporting whether the cost was applied to the key's
+    // state; false for admissions by allow-all mode, the allowlist or shadow
+    // mode, which must not be refunded
+    pub(crate) fn was_charged(&self) -> bool {
+        self.charged
+    }
+}
+
+// struct type to represent the outcome of check_n: how many of the requested
+// units were admitted and charged, and when the next one could be
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchDecision {
+    requested: u32,
+    admitted: u32,
+    retry_after: Option<Duration>,
+    remaining_burst: u32,
+}
+
+// methods for the BatchDecision struct
+impl BatchDecision {
+    // the batch outcome given the decision for its last unit checked
+    pub(crate) fn new(requested: u32, admitted: u32, last: Decision) -> Self {
+        Self {
+            requested,
+            admitted,
+            retry_after: last.retry_after,
+            remaining_burst: last.remaining_burst,
+        }
+    }
+
+    // accessor method to return how many units were asked for
+    pub fn requested(&self) -> u32 {
+        self.requested
+    }
+
+    // accessor method to return how many units were admitted and charged
+    pub fn admitted(&self) -> u32 {
+        self.admitted
+    }
+
+    // accessor method to return how many units were not admitted
+    pub fn denied(&self) -> u32 {
+        self.requested - self.admitted
+    }
+
+    // accessor method to report whether every requested unit was admitted
+    pub fn is_complete(&self) -> bool {
+        self.admitted == self.requested
+    }
+
+    // accessor method to return how long until the first unit not admitted
+    // would be; None when all were admitted or it never would be
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.retry_after
+    }
+
+    // accessor method to return how many more unit requests the key's quota
+    // would admit right now, after this batch
+    pub fn remaining_burst(&self) -> u32 {
+        self.remaining_burst
+    }
+}
+
+// implement the From trait to turn a Decision into a charge result
+impl From<Decision> for Result<(), Denied> {
+    fn from(decision: Decision) -> Self {
+        if decision.allowed {
+            Ok(())
+        } else {
+            Err(Denied {
+                retry_after: decision.retry_after,
+            })
+        }
+    }
+}
+
+// struct type to represent the result of one incremental GC step
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GcStep {
+    pub scanned: usize,      // keys examined in this step
+    pub evicted: usize,      // idle keys removed in this step
+    pub pass_complete: bool, // the scan reached the end and starts over next step
+}
+
+// struct type to represent a running idle-key sweeper thread, stopped when dropped
+#[derive(Debug)]
+pub struct Sweeper {
+    shutdown: Arc<(Mutex<bool>, Condvar)>,
+    worker: Option<JoinHandle<()>>,
+}
+
+// implement the Drop trait to stop the sweeper thread
+impl Drop for Sweeper {
+    fn drop(&mut self) {
+        let (lock, signal) = &*self.shutdown;
+        *lock.lock().unwrap() = true;
+        signal.notify_all();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+// with an idle TTL, a full GC pass runs on the request path once the number
+// of charges since the last pass reaches the number of keys, checked every
+// this many charges; the pass then costs O(1) per charge on average
+const LAZY_SWEEP_CHECK: usize = 1024;
+
+// keys examined per step by the background sweeper
+const SWEEP_BATCH: usize = 4096;
+
+// convert a rate and burst into an emission interval and tolerance in nanoseconds
+pub(crate) fn quota_nanos(
+    rate_per_second: f64,
+    burst_capacity: f64,
+    rounding: Rounding,
+) -> Result<(u64, u64), RateLimiterError> {
+    // rate must be non-negative and not zero
+    if rate_per_second <= 0.0 {
+        return Err(RateLimiterError::InvalidRate);
+    }
+    // burst parameter must be positive
+    if burst_capacity < 0.0 {
+        return Err(RateLimiterError::InvalidBurst);
+    }
+
+    // Convert to nanoseconds
+    let rate_nanos = rounding.apply(1_000_000_000.0 / rate_per_second);
+    let tolerance_nanos = (burst_capacity * rate_nanos as f64) as u64;
+    Ok((rate_nanos, tolerance_nanos))
+}
+
 // struct type to represent a rate limiter
+// `S` hashes keys in the state map; see `with_hasher`
 #[derive(Debug)]
-pub struct RateLimiter<T, C = SystemClock>
+pub struct RateLimiter<T, C = SystemClock, S = RandomState>
 where
     T: Hash + Eq + Clone,
     C: Clock,
+    S: BuildHasher + Clone,
 {
-    rate_nanos: u64,
-    tolerance_nanos: u64,
-    client_state: Arc<DashMap<T, u64>>,
+    base: BaseQuota, // the default increment and tolerance in nanoseconds
+    rounding: Rounding,
+    algorithm: Arc<dyn LimitAlgorithm>,
+    client_state: Arc<DashMap<T, u64, S>>,
+    overrides: Overrides<T>,
+    journal: Journal<T>,
+    decisions: DecisionLog<T>,
+    min_interval_nanos: u64,        // 0 when no minimum spacing is enforced
+    last_admitted: DashMap<T, u64>, // only used with a minimum spacing
+    pace: PaceTracker<T>,           // only stores anything with pace tracking
+    gc_cursor: AtomicUsize,         // where the next incremental GC step starts
+    idle_ttl_nanos: Option<u64>,    // evict keys idle this long, sweeping lazily
+    max_keys: Option<usize>,        // evict the least recently charged keys past this
+    tat_horizon_nanos: Option<u64>, // how far ahead of now a TAT may be held
+    charges_since_sweep: AtomicUsize,
+    eviction_hooks: EvictionHooks<T>,
+    observers: DecisionObservers<T>,
+    allow_all: AtomicBool, // bypass: admit everything without touching state
+    shadow: AtomicBool,    // dry run: decide and report as usual, but admit everything
+    clock_regression: ClockRegression,
+    latest_reading: AtomicU64, // only kept when clamping clock regressions
+    #[cfg(feature = "metrics")]
+    metrics: Option<Arc<LimiterMetrics>>,
     clock: C,
 }
 
@@ -55,25 +419,101 @@ where
         burst_capacity: f64,
         clock: C,
     ) -> Result<Self, RateLimiterError> {
-        // rate must be non-negative and not zero
-        if rate_per_second <= 0.0 {
-            return Err(RateLimiterError::InvalidRate);
-        }
-        // burst parameter must be positive
-        if burst_capacity < 0.0 {
-            return Err(RateLimiterError::InvalidBurst);
-        }
-
-        // Convert to nanoseconds
-        let rate_nanos = (1_000_000_000.0 / rate_per_second) as u64;
-        let tolerance_nanos = (burst_capacity * rate_nanos as f64) as u64;
+        Self::with_rounding(rate_per_second, burst_capacity, Rounding::Floor, clock)
+    }
 
-        Ok(Self {
+    // method to create a new rate limiter, choosing how the emission interval
+    // is rounded to whole nanoseconds
+    pub fn with_rounding(
+        rate_per_second: f64,
+        burst_capacity: f64,
+        rounding: Rounding,
+        clock: C,
+    ) -> Result<Self, RateLimiterError> {
+        let (rate_nanos, tolerance_nanos) = quota_nanos(rate_per_second, burst_capacity, rounding)?;
+        Ok(Self::from_nanos(
             rate_nanos,
             tolerance_nanos,
+            rounding,
+            clock,
+        ))
+    }
+
+    // method to create a new rate limiter from an integer quota, with no
+    // floating-point conversion
+    pub fn with_quota(quota: Quota, clock: C) -> Self {
+        let (rate_nanos, tolerance_nanos) = quota.nanos();
+        Self::from_nanos(rate_nanos, tolerance_nanos, Rounding::Floor, clock)
+    }
+
+    // method to resume a limiter from a full snapshot, e.g. one saved before
+    // a restart; keys whose TAT has passed on `clock` are skipped, and a
+    // snapshot with a zero increment is refused as an invalid rate
+    pub fn from_snapshot(snapshot: LimiterSnapshot<T>, clock: C) -> Result<Self, RateLimiterError> {
+        if snapshot.increment_nanos == 0 {
+            return Err(RateLimiterError::InvalidRate);
+        }
+        let limiter = Self::from_nanos(
+            snapshot.increment_nanos,
+            snapshot.tolerance_nanos,
+            Rounding::Floor,
+            clock,
+        );
+        limiter.merge(&snapshot.tats);
+        Ok(limiter)
+    }
+
+    // internal constructor from an emission interval and tolerance in nanoseconds
+    fn from_nanos(rate_nanos: u64, tolerance_nanos: u64, rounding: Rounding, clock: C) -> Self {
+        Self {
+            base: BaseQuota::new(rate_nanos, tolerance_nanos),
+            rounding,
+            algorithm: Arc::new(Gcra),
             client_state: Arc::new(DashMap::new()),
+            overrides: Overrides::new(),
+            journal: Journal::new(),
+            decisions: DecisionLog::new(),
+            min_interval_nanos: 0,
+            last_admitted: DashMap::new(),
+            pace: PaceTracker::new(Duration::ZERO),
+            gc_cursor: AtomicUsize::new(0),
+            idle_ttl_nanos: None,
+            max_keys: None,
+            tat_horizon_nanos: None,
+            charges_since_sweep: AtomicUsize::new(0),
+            eviction_hooks: EvictionHooks::new(),
+            observers: DecisionObservers::new(),
+            allow_all: AtomicBool::new(false),
+            shadow: AtomicBool::new(false),
+            clock_regression: ClockRegression::Allow,
+            latest_reading: AtomicU64::new(0),
+            #[cfg(feature = "metrics")]
+            metrics: None,
             clock,
-        })
+        }
+    }
+
+    // method to create a limiter that allows `extra` requests on top of the
+    // first one in a burst, so up to extra + 1 requests may arrive at once
+    pub fn with_extra_burst(
+        rate_per_second: f64,
+        extra: u32,
+        clock: C,
+    ) -> Result<Self, RateLimiterError> {
+        Self::new(rate_per_second, extra as f64, clock)
+    }
+
+    // method to create a limiter that allows at most `total` requests to
+    // arrive at once; a total of zero would admit nothing and is rejected
+    pub fn with_max_burst_total(
+        rate_per_second: f64,
+        total: u32,
+        clock: C,
+    ) -> Result<Self, RateLimiterError> {
+        if total == 0 {
+            return Err(RateLimiterError::InvalidBurst);
+        }
+        Self::new(rate_per_second, (total - 1) as f64, clock)
     }
 
     // Convenience constructor with default system clock
@@ -84,284 +524,3062 @@ where
         Self::new(rate, burst, C::default())
     }
 
-    // accessor method to return the rate field (convert back to requests per second)
-    pub fn rate(&self) -> f64 {
-        1_000_000_000.0 / self.rate_nanos as f64
+    // method to hash keys with `hasher` instead of the default SipHash, e.g.
+    // a faster non-keyed hash when keys are not attacker-chosen; the state
+    // map is rebuilt, so it panics once a store holds the old map
+    pub fn with_hasher<S>(self, hasher: S) -> RateLimiter<T, C, S>
+    where
+        S: BuildHasher + Clone,
+    {
+        let shards = self.client_state.shards().len();
+        let state = DashMap::with_capacity_and_hasher_and_shard_amount(
+            self.client_state.len(),
+            hasher,
+            shards,
+        );
+        self.with_state_map(state)
+    }
+}
+
+// methods for the RateLimiter struct, whatever hashes its keys
+impl<T, C, S> RateLimiter<T, C, S>
+where
+    T: Hash + Eq + Clone,
+    C: Clock,
+    S: BuildHasher + Clone,
+{
+    // method to split the state map into `shards` independently locked
+    // shards (rounded up to a power of two, at least 2), trading memory for
+    // less contention on many cores; the state map is rebuilt, so it panics
+    // once a store holds the old map
+    pub fn with_shards(self, shards: usize) -> Self {
+        let hasher = self.client_state.hasher().clone();
+        let state = DashMap::with_capacity_and_hasher_and_shard_amount(
+            self.client_state.len(),
+            hasher,
+            shards.max(2).next_power_of_two(),
+        );
+        self.with_state_map(state)
+    }
+
+    // accessor method to return how many shards the state map is split into
+    pub fn shard_count(&self) -> usize {
+        self.client_state.shards().len()
+    }
+
+    // move every key into `state` and swap it in for the state map
+    fn with_state_map<S2>(self, state: DashMap<T, u64, S2>) -> RateLimiter<T, C, S2>
+    where
+        S2: BuildHasher + Clone,
+    {
+        // a store attached earlier would keep persisting the old map
+        assert!(
+            Arc::strong_count(&self.client_state) == 1,
+            "the state map must be rebuilt before attaching a store"
+        );
+        for entry in self.client_state.iter() {
+            state.insert(entry.key().clone(), *entry.value());
+        }
+        RateLimiter {
+            base: self.base,
+            rounding: self.rounding,
+            algorithm: self.algorithm,
+            client_state: Arc::new(state),
+            overrides: self.overrides,
+            journal: self.journal,
+            decisions: self.decisions,
+            min_interval_nanos: self.min_interval_nanos,
+            last_admitted: self.last_admitted,
+            pace: self.pace,
+            gc_cursor: self.gc_cursor,
+            idle_ttl_nanos: self.idle_ttl_nanos,
+            max_keys: self.max_keys,
+            tat_horizon_nanos: self.tat_horizon_nanos,
+            charges_since_sweep: self.charges_since_sweep,
+            eviction_hooks: self.eviction_hooks,
+            observers: self.observers,
+            allow_all: self.allow_all,
+            shadow: self.shadow,
+            clock_regression: self.clock_regression,
+            latest_reading: self.latest_reading,
+            #[cfg(feature = "metrics")]
+            metrics: self.metrics,
+            clock: self.clock,
+        }
+    }
+
+    // method to size the state map for `capacity` keys up front, so it does
+    // not rehash while the first clients arrive
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        // the map is only shared once a store attaches, so it is still ours here
+        if let Some(state) = Arc::get_mut(&mut self.client_state) {
+            let _ = state.try_reserve(capacity);
+        }
+        self
+    }
+
+    // method to size the state map for a manifest of expected keys (e.g. the
+    // known tenants) so the first surge of traffic does not stall on rehashing;
+    // with `prefill`, entries are created up front with full burst credit
+    pub fn with_key_manifest(mut self, keys: impl IntoIterator<Item = T>, prefill: bool) -> Self {
+        let keys: Vec<T> = keys.into_iter().collect();
+        // the map is only shared once a store attaches, so it is still ours here
+        if let Some(state) = Arc::get_mut(&mut self.client_state) {
+            let _ = state.try_reserve(keys.len());
+        }
+        if prefill {
+            let now = self.now();
+            for key in keys {
+                self.client_state.entry(key).or_insert(now);
+            }
+        }
+        self
+    }
+
+    // method to choose the conformance test applied to each charge, e.g.
+    // TokenBucket to compare it with GCRA on the same traffic; every key is
+    // judged by the same algorithm, so choose it before the first check
+    pub fn with_algorithm(self, algorithm: impl LimitAlgorithm + 'static) -> Self {
+        self.with_shared_algorithm(Arc::new(algorithm))
+    }
+
+    pub(crate) fn with_shared_algorithm(mut self, algorithm: Arc<dyn LimitAlgorithm>) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    // method to choose what happens when the clock goes backwards; by
+    // default readings are taken as they come (see ClockRegression)
+    pub fn with_clock_regression(mut self, policy: ClockRegression) -> Self {
+        self.clock_regression = policy;
+        self
+    }
+
+    // method to also require at least `gap` between any two admitted requests
+    // for a key, whatever burst credit the key has; a zero gap turns this off
+    pub fn with_min_interval(mut self, gap: Duration) -> Self {
+        self.min_interval_nanos = gap.as_nanos() as u64;
+        self
+    }
+
+    // method to track each key's request pace over a sliding `window`, which
+    // `forecast_exhaustion` needs; a zero window turns tracking off
+    pub fn with_pace_tracking(mut self, window: Duration) -> Self {
+        self.pace = PaceTracker::new(window);
+        self
+    }
+
+    // method to evict keys once their TAT is at least `ttl` in the past, so
+    // the state map stays bounded by the keys seen recently; GC then keeps
+    // keys idle for less than `ttl` (saving churn for clients that come back
+    // soon) and sweeps lazily from the request path, see `spawn_sweeper` to
+    // move the sweeping to a thread instead
+    pub fn with_idle_ttl(mut self, ttl: Duration) -> Self {
+        self.idle_ttl_nanos = Some(ttl.as_nanos() as u64);
+        self
+    }
+
+    // method to cap the number of keys holding state, e.g. so spoofed keys
+    // cannot exhaust memory; a new key arriving at the cap first evicts the
+    // least recently charged tenth of the keys, so the scan is paid once per
+    // many new keys. Recency is read from the TAT: a key's TAT moves forward
+    // every time it is charged, and evicting the lowest TATs hands back the
+    // least credit, while a client being denied keeps its far-ahead TAT and
+    // cannot shed its limit by spraying keys. The cap can be overshot by the
+    // number of keys being created concurrently
+    pub fn with_max_keys(mut self, max_keys: usize) -> Self {
+        self.max_keys = Some(max_keys.max(1));
+        self
+    }
+
+    // method to cap how far ahead of now a key's TAT may be held, so a client
+    // whose TAT was pushed far out (by reservations, merged peer state, or a
+    // quota that shrank under it) is denied for at most `horizon` once it
+    // stops sending; a horizon shorter than a key's burst window is raised to
+    // that window, so the cap never takes away burst
+    pub fn with_tat_horizon(mut self, horizon: Duration) -> Self {
+        self.tat_horizon_nanos = Some(horizon.as_nanos() as u64);
+        self
+    }
+
+    // accessor method to return the rate field (convert back to requests per second)
+    pub fn rate(&self) -> f64 {
+        1_000_000_000.0 / self.increment_nanos() as f64
+    }
+
+    // accessor method to return the burst field (convert back to burst capacity)
+    pub fn burst(&self) -> f64 {
+        let (increment, tolerance) = self.base.load();
+        tolerance as f64 / increment as f64
+    }
+
+    // method to change the default rate at runtime, keeping the burst in
+    // requests; every key keeps its state, so a client already ahead of the
+    // new rate is paced by it from its current TAT on
+    pub fn set_rate(&self, rate_per_second: f64) -> Result<(), RateLimiterError> {
+        self.set_rate_by(None, rate_per_second)
+    }
+
+    pub(crate) fn set_rate_by(
+        &self,
+        actor: Option<&str>,
+        rate_per_second: f64,
+    ) -> Result<(), RateLimiterError> {
+        let (increment, _) = quota_nanos(rate_per_second, 0.0, self.rounding)?;
+        self.reconfigure_nanos(actor, |old_increment, old_tolerance| {
+            let burst = old_tolerance as f64 / old_increment as f64;
+            (increment, (burst * increment as f64) as u64)
+        });
+        Ok(())
+    }
+
+    // method to change the default burst at runtime, keeping the rate
+    pub fn set_burst(&self, burst_capacity: f64) -> Result<(), RateLimiterError> {
+        self.set_burst_by(None, burst_capacity)
+    }
+
+    pub(crate) fn set_burst_by(
+        &self,
+        actor: Option<&str>,
+        burst_capacity: f64,
+    ) -> Result<(), RateLimiterError> {
+        if burst_capacity < 0.0 {
+            return Err(RateLimiterError::InvalidBurst);
+        }
+        self.reconfigure_nanos(actor, |increment, _| {
+            (increment, (burst_capacity * increment as f64) as u64)
+        });
+        Ok(())
+    }
+
+    // method to swap the default rate and burst together at runtime
+    pub fn reconfigure(&self, quota: Quota) {
+        self.reconfigure_by(None, quota)
+    }
+
+    pub(crate) fn reconfigure_by(&self, actor: Option<&str>, quota: Quota) {
+        self.reconfigure_nanos(actor, |_, _| quota.nanos());
+    }
+
+    // internal method to swap the default quota, journal the quota it ends
+    // up as, and invalidate the quotas cached by key handles
+    fn reconfigure_nanos(&self, actor: Option<&str>, update: impl FnOnce(u64, u64) -> (u64, u64)) {
+        self.base.update(update);
+        self.overrides.touch();
+        let action = AuditAction::SetDefaultQuota {
+            rate: self.rate(),
+            burst: self.burst(),
+        };
+        self.journal.record(self.now(), actor, None, action);
+    }
+
+    // method to attach an audit sink that records every administrative change
+    pub fn set_audit_sink(&mut self, sink: impl AuditSink<T> + 'static) {
+        self.journal.set(Arc::new(sink));
+    }
+
+    // method to attach a sink fed a sample of the limiter's decisions, e.g.
+    // every deny but only a small share of allows at high request rates; the
+    // sink runs while the key's entry is locked, so it should only hand the
+    // event off (e.g. to a channel), and allow-all mode records nothing
+    pub fn set_decision_sink(&mut self, sink: impl DecisionSink<T> + 'static, sampling: Sampling) {
+        self.decisions.set(Arc::new(sink), sampling);
+    }
+
+    // method to switch allow-all mode on or off; while on, every check is
+    // admitted on a fast path that reads no clock and no state and writes
+    // nothing, so keys keep the state they had when the mode was switched on
+    pub fn set_allow_all(&self, allow_all: bool) {
+        self.allow_all.store(allow_all, Ordering::Relaxed);
+    }
+
+    // accessor method to report whether allow-all mode is on
+    pub fn allows_all(&self) -> bool {
+        self.allow_all.load(Ordering::Relaxed)
+    }
+
+    // method to switch shadow mode on or off, e.g. to dry-run a new limit in
+    // production; while on, every decision is made, charged and reported to
+    // sinks, observers and metrics as if enforced, but callers are told the
+    // request was admitted. Denied requests charge nothing, so the reported
+    // denials are the ones enforcing the limit would have produced
+    pub fn set_shadow(&self, shadow: bool) {
+        self.shadow.store(shadow, Ordering::Relaxed);
+    }
+
+    // accessor method to report whether shadow mode is on
+    pub fn is_shadow(&self) -> bool {
+        self.shadow.load(Ordering::Relaxed)
+    }
+
+    // method to register a callback told about every key whose state is
+    // evicted, so caches kept alongside the limiter can drop the key too;
+    // overrides and bans are not touched by eviction and expire on their own
+    pub fn on_evict(&mut self, hook: impl Fn(&T, EvictionReason) + Send + Sync + 'static) {
+        self.eviction_hooks.add(Arc::new(hook));
+    }
+
+    // method to attach an observer told about every decision, allowed or
+    // denied, with the key and how long a denied client should wait; unlike a
+    // decision sink nothing is sampled and any number can be attached. The
+    // observer runs while the key's entry is locked, so it should be quick,
+    // and allow-all mode tells it nothing
+    pub fn add_observer(&mut self, observer: impl DecisionObserver<T> + 'static) {
+        self.observers.add(Arc::new(observer));
+    }
+
+    // method to attach a callback told about every admitted request
+    pub fn on_allowed(&mut self, callback: impl Fn(&T) + Send + Sync + 'static) {
+        self.add_observer(OnAllowed(callback));
+    }
+
+    // method to attach a callback told about every denied request and its
+    // retry-after, None if it can never be admitted
+    pub fn on_denied(&mut self, callback: impl Fn(&T, Option<Duration>) + Send + Sync + 'static) {
+        self.add_observer(OnDenied(callback));
+    }
+
+    // method to report decisions, the number of tracked keys and evictions
+    // through the `metrics` facade, labelled `limiter = name`; the recorder
+    // (e.g. a Prometheus exporter) must be installed before this is called.
+    // Allow-all mode records nothing, like decision sinks
+    #[cfg(feature = "metrics")]
+    pub fn with_metrics(mut self, name: &str) -> Self {
+        let metrics = Arc::new(LimiterMetrics::new(name));
+        let evictions = Arc::clone(&metrics);
+        self.on_evict(move |_, reason| evictions.evicted(reason));
+        self.metrics = Some(metrics);
+        self
+    }
+
+    // method to make administrative changes on behalf of `actor`, who is
+    // named in the audit journal
+    pub fn as_actor<'a>(&'a self, actor: &'a str) -> Admin<'a, T, C, S> {
+        Admin::new(self, actor)
+    }
+
+    // method to give a key its own rate and burst until the override is
+    // removed, e.g. a higher limit for a premium customer; replaces any
+    // earlier override for the key
+    pub fn set_override(
+        &self,
+        client_id: T,
+        rate_per_second: f64,
+        burst_capacity: f64,
+    ) -> Result<(), RateLimiterError> {
+        self.set_override_by(None, client_id, rate_per_second, burst_capacity, None)
+    }
+
+    // method to give a key its own rate and burst for `ttl`, after which it
+    // reverts to the limiter default; replaces any earlier override for the key
+    pub fn set_override_for(
+        &self,
+        client_id: T,
+        rate_per_second: f64,
+        burst_capacity: f64,
+        ttl: Duration,
+    ) -> Result<(), RateLimiterError> {
+        self.set_override_by(None, client_id, rate_per_second, burst_capacity, Some(ttl))
+    }
+
+    pub(crate) fn set_override_by(
+        &self,
+        actor: Option<&str>,
+        client_id: T,
+        rate_per_second: f64,
+        burst_capacity: f64,
+        ttl: Option<Duration>,
+    ) -> Result<(), RateLimiterError> {
+        let (increment, tolerance) = quota_nanos(rate_per_second, burst_capacity, self.rounding)?;
+        let now = self.now();
+        let action = AuditAction::SetOverride {
+            rate: rate_per_second,
+            burst: burst_capacity,
+            ttl,
+        };
+        self.journal.record(now, actor, Some(&client_id), action);
+        self.overrides.set_quota(
+            client_id,
+            QuotaOverride {
+                increment,
+                tolerance,
+                // an override without a ttl never expires
+                expires_at: ttl.map_or(u64::MAX, |ttl| {
+                    now.saturating_add(u64::try_from(ttl.as_nanos()).unwrap_or(u64::MAX))
+                }),
+            },
+        );
+        Ok(())
+    }
+
+    // method to drop a key's quota override before it expires
+    pub fn remove_override(&self, client_id: &T) {
+        self.remove_override_by(None, client_id)
+    }
+
+    pub(crate) fn remove_override_by(&self, actor: Option<&str>, client_id: &T) {
+        let now = self.now();
+        self.journal
+            .record(now, actor, Some(client_id), AuditAction::RemoveOverride);
+        self.overrides.remove_quota(client_id);
+    }
+
+    // method to reject every request from a key for `ttl`
+    pub fn ban_for(&self, client_id: T, ttl: Duration) {
+        self.ban_by(None, client_id, ttl)
+    }
+
+    pub(crate) fn ban_by(&self, actor: Option<&str>, client_id: T, ttl: Duration) {
+        let now = self.now();
+        self.journal
+            .record(now, actor, Some(&client_id), AuditAction::Ban { ttl });
+        self.overrides
+            .ban(client_id, now.saturating_add(ttl.as_nanos() as u64));
+    }
+
+    // method to lift a ban before it expires
+    pub fn unban(&self, client_id: &T) {
+        self.unban_by(None, client_id)
+    }
+
+    pub(crate) fn unban_by(&self, actor: Option<&str>, client_id: &T) {
+        let now = self.now();
+        self.journal
+            .record(now, actor, Some(client_id), AuditAction::Unban);
+        self.overrides.unban(client_id);
+    }
+
+    // method to clear a key's rate state, giving it a full burst again, e.g.
+    // after a support escalation; overrides and bans stay in force
+    pub fn reset(&self, client_id: &T) {
+        self.reset_by(None, client_id)
+    }
+
+    pub(crate) fn reset_by(&self, actor: Option<&str>, client_id: &T) {
+        let now = self.now();
+        self.journal
+            .record(now, actor, Some(client_id), AuditAction::Reset);
+        self.forget(client_id);
+    }
+
+    // method to forget everything about a key: its rate state, its override
+    // and its ban
+    pub fn remove(&self, client_id: &T) {
+        self.remove_by(None, client_id)
+    }
+
+    pub(crate) fn remove_by(&self, actor: Option<&str>, client_id: &T) {
+        let now = self.now();
+        self.journal
+            .record(now, actor, Some(client_id), AuditAction::Remove);
+        self.overrides.remove_quota(client_id);
+        self.overrides.unban(client_id);
+        self.forget(client_id);
+    }
+
+    // method to drop all state: every key's rate state, overrides and bans
+    // the clear is journaled as a change to the whole limiter
+    pub fn clear(&self) {
+        self.clear_by(None)
+    }
+
+    pub(crate) fn clear_by(&self, actor: Option<&str>) {
+        self.journal
+            .record(self.now(), actor, None, AuditAction::Clear);
+        if !self.eviction_hooks.is_empty() {
+            let keys: Vec<T> = self
+                .client_state
+                .iter()
+                .map(|entry| entry.key().clone())
+                .collect();
+            for key in &keys {
+                self.forget(key);
+            }
+        }
+        self.client_state.clear();
+        self.last_admitted.clear();
+        self.pace.clear();
+        self.overrides.clear();
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = &self.metrics {
+            metrics.set_keys(0);
+        }
+    }
+
+    // internal method to drop a key's rate state, telling the eviction hooks
+    fn forget(&self, client_id: &T) {
+        let removed = self.client_state.remove(client_id).is_some();
+        self.last_admitted.remove(client_id);
+        self.pace.remove(client_id);
+        if removed {
+            self.eviction_hooks
+                .notify(client_id, EvictionReason::Manual);
+        }
+    }
+
+    // method to list the overrides still in force with their rate, burst and
+    // remaining duration
+    pub fn active_overrides(&self) -> Vec<ActiveOverride<T>> {
+        self.overrides
+            .active_quotas(self.now())
+            .into_iter()
+            .map(|(client_id, quota, remaining)| ActiveOverride {
+                client_id,
+                rate: 1_000_000_000.0 / quota.increment as f64,
+                burst: quota.tolerance as f64 / quota.increment as f64,
+                remaining: (quota.expires_at != u64::MAX).then(|| Duration::from_nanos(remaining)),
+            })
+            .collect()
+    }
+
+    // method to exempt a key from limiting until it is unlisted; its checks
+    // are admitted without reading or writing its state, and, as in
+    // allow-all mode, are not reported to sinks, observers or metrics
+    pub fn allowlist(&self, client_id: T) {
+        self.list_by(None, client_id, Listing::Allow)
+    }
+
+    // method to reject every request from a key until it is unlisted; unlike
+    // a ban there is no expiry, and denials carry no retry-after
+    pub fn denylist(&self, client_id: T) {
+        self.list_by(None, client_id, Listing::Deny)
+    }
+
+    // method to take a key off the allowlist or the denylist
+    pub fn unlist(&self, client_id: &T) {
+        self.unlist_by(None, client_id)
+    }
+
+    pub(crate) fn list_by(&self, actor: Option<&str>, client_id: T, listing: Listing) {
+        let action = match listing {
+            Listing::Allow => AuditAction::Allowlist,
+            Listing::Deny => AuditAction::Denylist,
+        };
+        self.journal
+            .record(self.now(), actor, Some(&client_id), action);
+        self.overrides.list(client_id, listing);
+    }
+
+    pub(crate) fn unlist_by(&self, actor: Option<&str>, client_id: &T) {
+        self.journal
+            .record(self.now(), actor, Some(client_id), AuditAction::Unlist);
+        self.overrides.unlist(client_id);
+    }
+
+    // accessor method to return the list a key is on, if any
+    pub fn listing(&self, client_id: &T) -> Option<Listing> {
+        self.overrides.listing(client_id)
+    }
+
+    // method to list every allowlisted and denylisted key
+    pub fn listed(&self) -> Vec<(T, Listing)> {
+        self.overrides.listed()
+    }
+
+    // method to list the bans still in force with their remaining duration
+    pub fn active_bans(&self) -> Vec<(T, Duration)> {
+        self.overrides
+            .active_bans(self.now())
+            .into_iter()
+            .map(|(client_id, remaining)| (client_id, Duration::from_nanos(remaining)))
+            .collect()
+    }
+
+    // internal method to return the increment and tolerance in force for a key
+    fn params_for(&self, client_id: &T, now: u64) -> (u64, u64) {
+        match self.overrides.quota(client_id, now) {
+            Some(quota) => (quota.increment, quota.tolerance),
+            None => self.base.load(),
+        }
+    }
+
+    // accessor method to return the number of keys holding rate state,
+    // including idle keys that GC has not removed yet
+    pub fn len(&self) -> usize {
+        self.client_state.len()
+    }
+
+    // method to check whether no key holds rate state
+    pub fn is_empty(&self) -> bool {
+        self.client_state.is_empty()
+    }
+
+    // method to iterate over the tracked keys and their TATs in nanoseconds,
+    // e.g. for an admin view; a TAT further past `clock().now()` means a more
+    // throttled client. Each shard of the map stays read-locked while the
+    // iterator is inside it, so collect first rather than charging the
+    // limiter from inside the loop
+    pub fn iter(&self) -> impl Iterator<Item = (T, u64)> + '_ {
+        self.client_state
+            .iter()
+            .map(|entry| (entry.key().clone(), *entry.value()))
+    }
+
+    // internal accessor for the shared client state map, used by persistent stores
+    pub(crate) fn client_state(&self) -> &Arc<DashMap<T, u64, S>> {
+        &self.client_state
+    }
+
+    // internal method to read the clock under the regression policy,
+    // returning the time to use and how far the reading is behind the
+    // latest one seen (always 0 when regressions are allowed)
+    fn read_clock(&self) -> (u64, u64) {
+        let reading = self.clock.now();
+        if self.clock_regression == ClockRegression::Allow {
+            return (reading, 0);
+        }
+        // only a new latest reading writes, so steady readers share the line
+        let mut latest = self.latest_reading.load(Ordering::Relaxed);
+        if reading > latest {
+            latest = self.latest_reading.fetch_max(reading, Ordering::Relaxed);
+        }
+        (reading.max(latest), latest.saturating_sub(reading))
+    }
+
+    // internal method to return the current time under the regression policy
+    pub(crate) fn now(&self) -> u64 {
+        self.read_clock().0
+    }
+
+    // internal method to return the time to decide a request at, or, when
+    // the clock went backwards under ClockRegression::Error, how far behind
+    // it is in nanoseconds
+    pub(crate) fn decision_time(&self) -> Result<u64, u64> {
+        match self.read_clock() {
+            (_, behind) if behind > 0 && self.clock_regression == ClockRegression::Error => {
+                Err(behind)
+            }
+            (now, _) => Ok(now),
+        }
+    }
+
+    // internal method to get the algorithm every charge is judged by
+    pub(crate) fn algorithm(&self) -> &dyn LimitAlgorithm {
+        &*self.algorithm
+    }
+
+    // internal method to get the increment in nanoseconds
+    pub(crate) fn increment_nanos(&self) -> u64 {
+        self.base.load().0
+    }
+
+    // internal method to get the tolerance in nanoseconds
+    pub(crate) fn tolerance_nanos(&self) -> u64 {
+        self.base.load().1
+    }
+
+    // Optional: keep the old method names for backwards compatibility
+    #[allow(dead_code)]
+    fn increment(&self) -> f64 {
+        self.increment_nanos() as f64 / 1_000_000_000.0
+    }
+
+    #[allow(dead_code)]
+    fn tolerance(&self) -> f64 {
+        self.tolerance_nanos() as f64 / 1_000_000_000.0
+    }
+
+    // method that implements the GCRA algorithm
+    pub fn is_allowed(&self, client_id: T) -> Result<bool, RateLimiterError> {
+        self.is_allowed_with_cost(client_id, 1)
+    }
+
+    // method to block until a request for the key conforms, then record it,
+    // e.g. to pace outbound calls; fails only if the key could never be
+    // admitted (see `wait_with`)
+    pub fn wait(&self, client_id: T) -> Result<(), Denied> {
+        self.wait_with(client_id, 1, &ThreadSleeper)
+    }
+
+    // method to wait for `cost` units with a chosen sleeper
+    // each round sleeps for the denial's retry_after and tries again, so a
+    // slot taken by another caller in the meantime just means another round;
+    // a cost that can never fit the burst is returned as the denial at once
+    pub fn wait_with(&self, client_id: T, cost: u32, sleeper: &impl Sleeper) -> Result<(), Denied> {
+        loop {
+            match self.charge(client_id.clone(), cost) {
+                Ok(()) => return Ok(()),
+                Err(denied) => match denied.retry_after() {
+                    Some(wait) => sleeper.sleep(wait),
+                    None => return Err(denied),
+                },
+            }
+        }
+    }
+
+    // method to wait without blocking until a request for the key conforms,
+    // then record it; the async counterpart of `wait`, sleeping on the
+    // clock's timer (the tokio timer for the clocks of the `tokio` feature)
+    #[cfg(feature = "async")]
+    pub async fn until_key_ready(&self, client_id: T) -> Result<(), Denied>
+    where
+        C: AsyncClock,
+    {
+        loop {
+            match self.charge(client_id.clone(), 1) {
+                Ok(()) => return Ok(()),
+                Err(denied) => match denied.retry_after() {
+                    Some(wait) => {
+                        let deadline = self.clock.now().saturating_add(wait.as_nanos() as u64);
+                        self.clock.sleep_until(deadline).await;
+                    }
+                    None => return Err(denied),
+                },
+            }
+        }
+    }
+
+    // method to check a request that consumes `cost` units of quota at once,
+    // e.g. a bulk endpoint; the TAT advances by `cost` increments only if the
+    // whole cost conforms, so a denied request consumes nothing
+    // under ClockRegression::Error, a clock behind its latest reading fails
+    pub fn is_allowed_with_cost(&self, client_id: T, cost: u32) -> Result<bool, RateLimiterError> {
+        if self.allows_all() {
+            return Ok(true);
+        }
+        let now = self
+            .decision_time()
+            .map_err(|behind| RateLimiterError::ClockWentBackwards(Duration::from_nanos(behind)))?;
+        Ok(self.charge_at(client_id, cost, now).is_ok())
+    }
+
+    // method to check a request for a borrowed key, e.g. a `&str` for a
+    // limiter keyed by String; the key is only cloned into the map the first
+    // time it is seen (or after its state was evicted), so known keys cost
+    // no allocation
+    pub fn is_allowed_ref<Q>(&self, client_id: &Q) -> Result<bool, RateLimiterError>
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = T> + ?Sized,
+    {
+        self.is_allowed_with_cost_ref(client_id, 1)
+    }
+
+    // method to check a request of `cost` units for a borrowed key
+    pub fn is_allowed_with_cost_ref<Q>(
+        &self,
+        client_id: &Q,
+        cost: u32,
+    ) -> Result<bool, RateLimiterError>
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = T> + ?Sized,
+    {
+        if self.allows_all() {
+            return Ok(true);
+        }
+        let now = self
+            .decision_time()
+            .map_err(|behind| RateLimiterError::ClockWentBackwards(Duration::from_nanos(behind)))?;
+        let resolved = self.resolve(client_id, now);
+        Ok(self
+            .charge_resolved(client_id, now, &resolved, cost)
+            .is_ok())
+    }
+
+    // method to answer whether a request arriving at `at_nanos` (in the clock's
+    // time frame) would be allowed given the current state, without recording it
+    // the answer is the one `peek` would give at that time
+    pub fn simulate(&self, client_id: &T, at_nanos: u64) -> bool {
+        if self.allows_all() {
+            return true;
+        }
+        if let Some(listing) = self.overrides.listing(client_id) {
+            return listing == Listing::Allow;
+        }
+        if self.overrides.ban_remaining(client_id, at_nanos).is_some() {
+            return false;
+        }
+
+        let mut tat = self
+            .client_state
+            .get(client_id)
+            .map_or(at_nanos, |tat| *tat);
+        let params = self.params_for(client_id, at_nanos);
+        self.charge_one(client_id, &mut tat, at_nanos, params, 1, false)
+            .is_ok()
+    }
+
+    // method to charge `cost` units up front, returning a guard that refunds
+    // them when dropped unless the caller commits; an admission that charged
+    // nothing (allow-all, allowlist or shadow mode) has nothing to refund
+    pub fn try_begin(&self, client_id: T, cost: u32) -> Result<CostGuard<'_, T, C, S>, Denied> {
+        let charged = self.charge_applied(client_id.clone(), cost)?;
+        Ok(CostGuard::new(self, client_id, cost, charged))
+    }
+
+    // method to tentatively charge one request, returning a reservation that
+    // refunds it when cancelled or dropped unless the caller commits
+    pub fn reserve(&self, client_id: T) -> Result<Reservation<'_, T, C, S>, Denied> {
+        self.try_begin(client_id, 1)
+    }
+
+    // method to check a batch of (key, cost) requests in one pass, e.g. all
+    // the connections a proxy accepted in one event-loop wakeup
+    // requests are grouped by key so each key's entry is locked once, and the
+    // whole batch is judged at a single clock reading; requests for the same
+    // key are charged in the order given, and decisions come back in that order
+    pub fn check_many(&self, requests: impl IntoIterator<Item = (T, u32)>) -> Vec<Decision> {
+        if self.allows_all() {
+            return requests
+                .into_iter()
+                .map(|_| Decision::allow_all())
+                .collect();
+        }
+        let now = match self.decision_time() {
+            Ok(now) => now,
+            Err(behind) => {
+                return requests
+                    .into_iter()
+                    .map(|_| Decision::clock_regressed(behind))
+                    .collect();
+            }
+        };
+        let mut groups: HashMap<T, Vec<(usize, u32)>> = HashMap::new();
+        let mut count = 0;
+        for (index, (client_id, cost)) in requests.into_iter().enumerate() {
+            groups.entry(client_id).or_default().push((index, cost));
+            count = index + 1;
+        }
+
+        let mut decisions = vec![Decision::allow_all(); count];
+        for (client_id, checks) in groups {
+            let mut slots = checks.iter().map(|(index, _)| *index);
+            let costs = checks.iter().map(|(_, cost)| *cost);
+            self.charge_each(client_id, now, costs, |decision| {
+                if let Some(index) = slots.next() {
+                    decisions[index] = decision;
+                }
+            });
+        }
+        decisions
+    }
+
+    // method to check one request for a key, returning the decision with its
+    // timing: when to retry, how much burst is left and the key's TAT
+    pub fn check(&self, client_id: T) -> Decision {
+        if self.allows_all() {
+            return Decision::allow_all();
+        }
+        let mut outcome = Decision::allow_all();
+        let now = match self.decision_time() {
+            Ok(now) => now,
+            Err(behind) => return Decision::clock_regressed(behind),
+        };
+        self.charge_each(client_id, now, [1], |decision| outcome = decision);
+        outcome
+    }
+
+    // method to admit as many of `n` unit requests as conform right now,
+    // e.g. for a queue consumer pulling a batch, charging exactly those
+    // under one entry lock; units are checked and reported one at a time
+    // and the first denied one ends the batch; an empty batch charges
+    // nothing and reports what `peek` sees
+    pub fn check_n(&self, client_id: T, n: u32) -> BatchDecision {
+        if n == 0 {
+            return BatchDecision::new(0, 0, self.peek(&client_id));
+        }
+        if self.allows_all() {
+            return BatchDecision::new(n, n, Decision::allow_all());
+        }
+        let now = match self.decision_time() {
+            Ok(now) => now,
+            Err(behind) => return BatchDecision::new(n, 0, Decision::clock_regressed(behind)),
+        };
+        let (mut admitted, mut last) = (0, Decision::allow_all());
+        let stopped = Cell::new(false);
+        let units = (0..n).map(|_| 1).take_while(|_| !stopped.get());
+        self.charge_each(client_id, now, units, |decision| {
+            match decision.is_allowed() {
+                true => admitted += 1,
+                false => stopped.set(true),
+            }
+            last = decision;
+        });
+        BatchDecision::new(n, admitted, last)
+    }
+
+    // method to return the decision `check` would make for a key right now,
+    // without charging it: neither the TAT nor the decision stream is touched
+    pub fn peek(&self, client_id: &T) -> Decision {
+        if self.allows_all() {
+            return Decision::allow_all();
+        }
+        let now = self.now();
+        let mut tat = self.client_state.get(client_id).map_or(now, |tat| *tat);
+        match self.overrides.listing(client_id) {
+            Some(Listing::Allow) => return Decision::allow_all(),
+            Some(Listing::Deny) => return Decision::denylisted(tat),
+            None => {}
+        }
+        if let Some(remaining) = self.overrides.ban_remaining(client_id, now) {
+            return Decision::banned(remaining, tat);
+        }
+        let params = self.params_for(client_id, now);
+        let result = self.charge_one(client_id, &mut tat, now, params, 1, false);
+        Decision::charged(result, tat, self.algorithm.remaining(now, tat, params))
+    }
+
+    // method to return the earliest time, in nanoseconds on the limiter's
+    // clock, at which a unit request for the key would be admitted, without
+    // charging it, e.g. to plan send times for paced email or SMS; u64::MAX
+    // if it never would be. Another caller may take the slot first
+    pub fn next_available(&self, client_id: &T) -> u64 {
+        self.next_available_slots(client_id, 1)
+            .next()
+            .unwrap_or(u64::MAX)
+    }
+
+    // method to return the earliest times at which each of `count` unit
+    // requests in a row would be admitted, as if each were charged at its
+    // slot; nothing is charged. Slots past the last reachable one are u64::MAX
+    // the key's state is read once, up front, and each slot is planned only
+    // when the iterator reaches it, so a huge `count` costs nothing unused
+    pub fn next_available_slots(&self, client_id: &T, count: usize) -> impl Iterator<Item = u64> {
+        let now = self.now();
+        let fixed = match self.overrides.listing(client_id) {
+            _ if self.allows_all() => Some(now),
+            Some(Listing::Allow) => Some(now),
+            Some(Listing::Deny) => Some(u64::MAX),
+            None => None,
+        };
+        let params = self.params_for(client_id, now);
+        let banned_for = self.overrides.ban_remaining(client_id, now).unwrap_or(0);
+        let mut at = now.saturating_add(banned_for);
+        let tat = self.client_state.get(client_id).map_or(now, |tat| *tat);
+        let mut tat = self.within_horizon(tat, now, params);
+        let mut last = match self.min_interval_nanos {
+            0 => None,
+            _ => self.last_admitted.get(client_id).map(|last| *last),
+        };
+        let mut reachable = true;
+
+        let plan = move || {
+            if let Some(fixed) = fixed {
+                return Some(fixed);
+            }
+            if !reachable {
+                return Some(u64::MAX);
+            }
+            if let Some(last) = last {
+                at = at.max(last.saturating_add(self.min_interval_nanos));
+            }
+            let slot = match self.algorithm.conform(at, tat, params, 1) {
+                Some(_) => Some(at),
+                None => self.algorithm.retry_at(at, tat, params, 1),
+            };
+            let next = slot.and_then(|slot| {
+                let slot = slot.max(at);
+                Some((slot, self.algorithm.conform(slot, tat, params, 1)?))
+            });
+            let Some((slot, new_tat)) = next else {
+                reachable = false;
+                return Some(u64::MAX);
+            };
+            (at, tat) = (slot, new_tat);
+            if self.min_interval_nanos > 0 {
+                last = Some(slot);
+            }
+            Some(slot)
+        };
+        std::iter::from_fn(plan).take(count)
+    }
+
+    // method to explain the decision a unit request for a key would get
+    // right now, with the quota, TAT and arithmetic behind it, e.g. for a
+    // support ticket; nothing is charged. Its Display is the prose version
+    pub fn explain(&self, client_id: &T) -> Explanation<T> {
+        let now = self.now();
+        let params = self.params_for(client_id, now);
+        let tat = self.client_state.get(client_id).map(|tat| *tat);
+        Explanation {
+            key: client_id.clone(),
+            now,
+            tat: tat.map(|tat| self.within_horizon(tat, now, params)),
+            increment: params.0,
+            tolerance: params.1,
+            overridden: self.overrides.quota(client_id, now).is_some(),
+            banned_for: self
+                .overrides
+                .ban_remaining(client_id, now)
+                .map(Duration::from_nanos),
+            min_interval: Duration::from_nanos(self.min_interval_nanos),
+            allow_all: self.allows_all(),
+            decision: self.peek(client_id),
+        }
+    }
+
+    // method to estimate how long until a key is first denied if it keeps
+    // its recent pace, e.g. to warn a client before it hits its limit
+    // Some(ZERO) means it is denied already; None means its pace does not
+    // outrun its quota, or its pace is unknown because the key has not been
+    // seen or pace tracking is off (see `with_pace_tracking`)
+    pub fn forecast_exhaustion(&self, client_id: &T) -> Option<Duration> {
+        let now = self.now();
+        let pace = self.pace.rate(client_id, now)?;
+        match self.overrides.listing(client_id) {
+            Some(Listing::Allow) => return None,
+            Some(Listing::Deny) => return Some(Duration::ZERO),
+            None => {}
+        }
+        if self.overrides.ban_remaining(client_id, now).is_some() {
+            return Some(Duration::ZERO);
+        }
+        let tat = self.client_state.get(client_id).map_or(now, |tat| *tat);
+        let (increment, tolerance) = self.params_for(client_id, now);
+        forecast::time_to_exhaustion(now, tat, increment, tolerance, pace)
+    }
+
+    // internal method to run the GCRA test and record the new TAT
+    pub(crate) fn charge(&self, client_id: T, cost: u32) -> Result<(), Denied> {
+        self.charge_applied(client_id, cost).map(drop)
+    }
+
+    // internal method to charge as `charge` does, reporting whether an
+    // admission reached the key's state, so only those are ever refunded
+    pub(crate) fn charge_applied(&self, client_id: T, cost: u32) -> Result<bool, Denied> {
+        if self.allows_all() {
+            return Ok(false);
+        }
+        let now = self
+            .decision_time()
+            .map_err(|behind| Denied::new(Some(behind)))?;
+        let mut outcome = Ok(false);
+        self.charge_each(client_id, now, [cost], |decision| {
+            outcome = Result::from(decision).map(|()| decision.was_charged())
+        });
+        outcome
+    }
+
+    // internal method to charge a request as if it arrived at `at_nanos`;
+    // charging a future time reserves a slot for a request that will wait
+    pub(crate) fn charge_at(&self, client_id: T, cost: u32, at_nanos: u64) -> Result<(), Denied> {
+        let mut outcome = Ok(());
+        self.charge_each(client_id, at_nanos, [cost], |decision| {
+            outcome = decision.into()
+        });
+        outcome
+    }
+
+    // internal method to charge one key for each cost in turn at `now`,
+    // passing every outcome to `decide`
+    // the entry lock is held from the read to the last write, so concurrent
+    // requests for the same key cannot both consume the same slot
+    fn charge_each(
+        &self,
+        client_id: T,
+        current_time_nanos: u64,
+        costs: impl IntoIterator<Item = u32>,
+        mut decide: impl FnMut(Decision),
+    ) {
+        // listed keys are settled before bans or quotas are looked at
+        if let Some(listing) = self.overrides.listing(&client_id) {
+            let tat = self.client_state.get(&client_id).map(|tat| *tat);
+            for cost in costs {
+                decide(match listing {
+                    Listing::Allow => Decision::allow_all(),
+                    Listing::Deny => {
+                        let decision = Decision::denylisted(tat.unwrap_or(current_time_nanos));
+                        self.record_decision(current_time_nanos, &client_id, cost, decision)
+                    }
+                });
+            }
+            return;
+        }
+
+        // banned keys are rejected before any quota is looked at
+        if let Some(remaining) = self.overrides.ban_remaining(&client_id, current_time_nanos) {
+            let tat = self.client_state.get(&client_id).map(|tat| *tat);
+            for cost in costs {
+                let decision = Decision::banned(remaining, tat.unwrap_or(current_time_nanos));
+                decide(self.record_decision(current_time_nanos, &client_id, cost, decision));
+            }
+            return;
+        }
+        let params = self.params_for(&client_id, current_time_nanos);
+        self.make_room(&client_id);
+
+        // new clients start with a TAT of the current time; the insert happens
+        // under the same entry lock, so when several first requests for a key
+        // race, exactly one initializes it and the others see its update
+        let mut entry = self
+            .client_state
+            .entry(client_id)
+            .or_insert(current_time_nanos);
+        let (key, tat) = entry.pair_mut();
+        self.charge_locked(key, tat, current_time_nanos, params, costs, decide);
+        drop(entry);
+        self.sweep_lazily();
+    }
+
+    // internal method to evict the least recently charged keys when a new key
+    // would go past the key cap; must not be called while holding an entry lock
+    fn make_room<Q>(&self, client_id: &Q)
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let Some(max_keys) = self.max_keys else {
+            return;
+        };
+        if self.client_state.len() >= max_keys && !self.client_state.contains_key(client_id) {
+            self.evict_to_fit(max_keys);
+        }
+    }
+
+    // internal method to evict the least recently charged tenth of the keys
+    fn evict_to_fit(&self, max_keys: usize) {
+        let keep = max_keys - max_keys.div_ceil(10);
+        let mut by_tat: Vec<(u64, T)> = self
+            .client_state
+            .iter()
+            .map(|entry| (*entry.value(), entry.key().clone()))
+            .collect();
+        let evict = by_tat.len().saturating_sub(keep);
+        if evict == 0 {
+            return;
+        }
+        by_tat.select_nth_unstable_by_key(evict - 1, |(tat, _)| *tat);
+        for (tat, key) in &by_tat[..evict] {
+            // skip keys charged since the scan; they are recent again
+            if self
+                .client_state
+                .remove_if(key, |_, current| *current == *tat)
+                .is_some()
+            {
+                self.last_admitted.remove(key);
+                self.pace.remove(key);
+                self.eviction_hooks.notify(key, EvictionReason::Capacity);
+            }
+        }
+    }
+
+    // internal method to pass a decision to the decision sink, observers
+    // and metrics, returning the decision the caller gets, which shadow mode
+    // turns into an admission
+    fn record_decision(&self, now: u64, key: &T, cost: u32, decision: Decision) -> Decision {
+        self.decisions.record(now, key, cost, &decision);
+        let outcome = match decision.is_allowed() {
+            true => Ok(()),
+            false => Err(decision.retry_after()),
+        };
+        self.observers.notify(key, outcome);
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = &self.metrics {
+            metrics.decided(decision.is_allowed());
+        }
+        match self.is_shadow() {
+            true => decision.shadowed(),
+            false => decision,
+        }
+    }
+
+    // internal method to run a full GC pass once enough charges have passed
+    // with an idle TTL set; must not be called while holding an entry lock
+    fn sweep_lazily(&self) {
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = &self.metrics {
+            metrics.refresh_keys(|| self.client_state.len());
+        }
+        if self.idle_ttl_nanos.is_none() {
+            return;
+        }
+        let charges = self.charges_since_sweep.fetch_add(1, Ordering::Relaxed) + 1;
+        if charges.is_multiple_of(LAZY_SWEEP_CHECK) && charges >= self.client_state.len() {
+            self.charges_since_sweep.store(0, Ordering::Relaxed);
+            self.gc_step(usize::MAX);
+        }
+    }
+
+    // internal method to charge a key whose entry is already held, passing
+    // every outcome to `decide`; the entry lock is held from the read to the
+    // last write, so concurrent requests cannot both consume the same slot
+    fn charge_locked(
+        &self,
+        key: &T,
+        tat: &mut u64,
+        current_time_nanos: u64,
+        (increment, tolerance): (u64, u64),
+        costs: impl IntoIterator<Item = u32>,
+        mut decide: impl FnMut(Decision),
+    ) {
+        let params = (increment, tolerance);
+        for cost in costs {
+            self.pace.record(key, current_time_nanos, cost);
+            let result = self.charge_one(key, tat, current_time_nanos, params, cost, true);
+            let remaining = self.algorithm.remaining(current_time_nanos, *tat, params);
+            let decision = Decision::charged(result, *tat, remaining);
+            decide(self.record_decision(current_time_nanos, key, cost, decision));
+        }
+    }
+
+    // internal method to clamp a TAT to the horizon, if one is set
+    fn within_horizon(&self, tat: u64, now: u64, (increment, tolerance): (u64, u64)) -> u64 {
+        match self.tat_horizon_nanos {
+            Some(horizon) => {
+                let ahead = horizon.max(self.algorithm.max_ahead((increment, tolerance)));
+                tat.min(now.saturating_add(ahead))
+            }
+            None => tat,
+        }
+    }
+
+    // internal method to run the GCRA test for one charge under the entry lock
+    // without `commit`, `tat` is a scratch copy and no admission is recorded
+    fn charge_one(
+        &self,
+        key: &T,
+        tat: &mut u64,
+        current_time_nanos: u64,
+        (increment, tolerance): (u64, u64),
+        cost: u32,
+        commit: bool,
+    ) -> Result<(), Denied> {
+        *tat = self.within_horizon(*tat, current_time_nanos, (increment, tolerance));
+
+        // the minimum spacing is checked and recorded under the same lock
+        let spaced = self.min_interval_nanos > 0;
+        if spaced && let Some(last) = self.last_admitted.get(key).map(|last| *last) {
+            let ready_at = last.saturating_add(self.min_interval_nanos);
+            if current_time_nanos < ready_at {
+                return Err(Denied::new(Some(ready_at - current_time_nanos)));
+            }
+        }
+
+        // conformance test of the limiter's algorithm, GCRA by default
+        let params = (increment, tolerance);
+        match self
+            .algorithm
+            .conform(current_time_nanos, *tat, params, cost)
+        {
+            Some(new_tat_nanos) => {
+                *tat = new_tat_nanos;
+                if spaced && commit {
+                    self.last_admitted.insert(key.clone(), current_time_nanos);
+                }
+                Ok(())
+            }
+            None => {
+                let retry_at = self
+                    .algorithm
+                    .retry_at(current_time_nanos, *tat, params, cost);
+                Err(Denied::new(
+                    retry_at.map(|at| at.saturating_sub(current_time_nanos)),
+                ))
+            }
+        }
+    }
+
+    // internal method to resolve the ban and quota in force for a key at
+    // `now`, with the time until which that answer holds if nothing changes
+    pub(crate) fn resolve<Q>(&self, client_id: &Q, now: u64) -> Resolved
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        // read first, so a change racing with the lookups marks them stale
+        let generation = self.overrides.generation();
+        let listing = self.overrides.listing(client_id);
+        let ban_until = self
+            .overrides
+            .ban_remaining(client_id, now)
+            .map(|remaining| now + remaining);
+        let quota = self.overrides.quota(client_id, now);
+        let (increment, tolerance) = match quota {
+            Some(quota) => (quota.increment, quota.tolerance),
+            None => self.base.load(),
+        };
+        let expires_at = quota.map_or(u64::MAX, |quota| quota.expires_at);
+        Resolved {
+            generation,
+            ban_until,
+            listing,
+            increment,
+            tolerance,
+            valid_until: ban_until.unwrap_or(u64::MAX).min(expires_at),
+        }
+    }
+
+    // internal method to report whether a resolved answer can still be used
+    pub(crate) fn is_current(&self, resolved: &Resolved, now: u64) -> bool {
+        now < resolved.valid_until && resolved.generation == self.overrides.generation()
+    }
+
+    // internal method to charge a borrowed key with already-resolved params
+    // an existing entry is found by reference, so the key is only cloned when
+    // its state was never created or has been evicted in the meantime, or
+    // when a banned key's decision is recorded
+    pub(crate) fn charge_resolved<Q>(
+        &self,
+        client_id: &Q,
+        now: u64,
+        resolved: &Resolved,
+        cost: u32,
+    ) -> Result<(), Denied>
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = T> + ?Sized,
+    {
+        match resolved.listing {
+            Some(Listing::Allow) => return Ok(()),
+            Some(Listing::Deny) => {
+                let tat = self.client_state.get(client_id).map(|tat| *tat);
+                let decision = Decision::denylisted(tat.unwrap_or(now));
+                return self
+                    .record_decision(now, &client_id.to_owned(), cost, decision)
+                    .into();
+            }
+            None => {}
+        }
+        if let Some(until) = resolved.ban_until.filter(|until| *until > now) {
+            let tat = self.client_state.get(client_id).map(|tat| *tat);
+            let decision = Decision::banned(until - now, tat.unwrap_or(now));
+            return self
+                .record_decision(now, &client_id.to_owned(), cost, decision)
+                .into();
+        }
+        let params = (resolved.increment, resolved.tolerance);
+        let mut outcome = Ok(());
+        let decide = |decision: Decision| outcome = decision.into();
+        match self.client_state.get_mut(client_id) {
+            Some(mut entry) => {
+                let (key, tat) = entry.pair_mut();
+                self.charge_locked(key, tat, now, params, [cost], decide)
+            }
+            None => {
+                self.make_room(client_id);
+                let mut entry = self.client_state.entry(client_id.to_owned()).or_insert(now);
+                let (key, tat) = entry.pair_mut();
+                self.charge_locked(key, tat, now, params, [cost], decide)
+            }
+        }
+        self.sweep_lazily();
+        outcome
+    }
+
+    // internal method to fold charges admitted against a local view of a
+    // key back into the shared state: `units` units charged to a view that
+    // read the shared state as `base` and now ends at `state`. If nothing
+    // else changed the key since, the view is the exact answer; otherwise
+    // the algorithm records the units on top of whatever is there now
+    pub(crate) fn absorb(&self, client_id: T, base: u64, units: u64, state: u64) {
+        let now = self.now();
+        let params = self.params_for(&client_id, now);
+        let cost = u32::try_from(units).unwrap_or(u32::MAX);
+        if !self.client_state.contains_key(&client_id) {
+            self.make_room(&client_id);
+        }
+        self.client_state
+            .entry(client_id)
+            .and_modify(|held| {
+                *held = match *held == base {
+                    true => state,
+                    false => self.algorithm.absorb(now, *held, params, cost).max(state),
+                }
+            })
+            .or_insert(state);
+    }
+
+    // method to get a handle for repeated checks of one key, e.g. on a
+    // keep-alive connection
+    pub fn handle(&self, client_id: T) -> KeyHandle<'_, T, C, S> {
+        KeyHandle::new(self, client_id)
+    }
+
+    // method to push a key's next admission `penalty` further into the
+    // future, e.g. after a failed login or a malformed payload, so the client
+    // waits that much longer on top of what it consumed; a key without state
+    // is penalized from the current time. Each algorithm applies the penalty
+    // to its own state (window counters charge the requests that much time
+    // is worth), and a TAT horizon caps it like any other charge. Allow-all
+    // mode and allowlisted keys are not rate limited, so they are left alone
+    pub fn penalize(&self, client_id: T, penalty: Duration) {
+        if self.allows_all() || self.overrides.listing(&client_id) == Some(Listing::Allow) {
+            return;
+        }
+        let now = self.now();
+        let params = self.params_for(&client_id, now);
+        let penalty = u64::try_from(penalty.as_nanos()).unwrap_or(u64::MAX);
+        self.make_room(&client_id);
+        let mut state = self.client_state.entry(client_id).or_insert(now);
+        *state = self.algorithm.penalize(now, *state, params, penalty);
+    }
+
+    // method to hand back `cost` units charged earlier, e.g. for a request
+    // that failed authorization or validation before doing any work
+    // the TAT never moves behind the current time, so refunds cannot create
+    // more credit than an idle client already has; a key without state is
+    // left alone
+    pub fn refund(&self, client_id: &T, cost: u32) {
+        let current_time_nanos = self.now();
+        let params = self.params_for(client_id, current_time_nanos);
+        if let Some(mut tat) = self.client_state.get_mut(client_id) {
+            *tat = self
+                .algorithm
+                .refund(current_time_nanos, *tat, params, cost);
+        }
+    }
+
+    // method to copy the TATs of every key still holding state
+    // idle keys are left out, since they are indistinguishable from unseen ones
+    pub fn snapshot(&self) -> Snapshot<T> {
+        let now = self.now();
+        self.client_state
+            .iter()
+            .filter(|entry| *entry.value() > now)
+            .map(|entry| (entry.key().clone(), *entry.value()))
+            .collect()
+    }
+
+    // method to copy the default quota along with every key's TAT, so the
+    // limiter can be rebuilt with from_snapshot
+    pub fn full_snapshot(&self) -> LimiterSnapshot<T> {
+        let (increment_nanos, tolerance_nanos) = self.base.load();
+        LimiterSnapshot {
+            increment_nanos,
+            tolerance_nanos,
+            tats: self.snapshot(),
+        }
+    }
+
+    // method to fold a peer's snapshot into this limiter, keeping the later
+    // TAT per key, so a client cannot get a fresh burst from each instance
+    // entries already idle here are skipped rather than inserted, and new
+    // keys respect the key cap as charged ones do
+    pub fn merge(&self, other: &Snapshot<T>) {
+        let now = self.now();
+        for (key, tat) in other.iter().filter(|(_, tat)| *tat > now) {
+            if !self.client_state.contains_key(key) {
+                self.make_room(key);
+            }
+            self.client_state
+                .entry(key.clone())
+                .and_modify(|held| *held = (*held).max(tat))
+                .or_insert(tat);
+        }
+    }
+
+    // method to drop idle keys, looking at no more than `max_entries` of them
+    // a key whose TAT has passed is indistinguishable from one never seen, so
+    // removing it changes no decision; with an idle TTL only keys idle for at
+    // least the TTL count; returns how many keys were removed
+    pub fn evict_idle(&self, max_entries: usize) -> usize {
+        self.gc_step(max_entries).evicted
+    }
+
+    // method to run one step of an incremental idle-key scan, examining at
+    // most `max_entries` keys and resuming where the previous step stopped
+    // no lock is held between steps and each key is removed under its own
+    // entry lock, so steps can be driven from the request path or a timer
+    // without pausing other requests; keys inserted or rehashed mid-pass may
+    // be skipped until the next pass
+    pub fn gc_step(&self, max_entries: usize) -> GcStep {
+        self.gc_step_with(max_entries, |_| {})
+    }
+
+    // internal method to run a GC step, passing each evicted key to `evicted`
+    pub(crate) fn gc_step_with(&self, max_entries: usize, mut evicted: impl FnMut(&T)) -> GcStep {
+        let now = self.now();
+        let (cutoff, reason) = match self.idle_ttl_nanos {
+            Some(ttl) => (now.saturating_sub(ttl), EvictionReason::Expired),
+            None => (now, EvictionReason::Idle),
+        };
+        let start = self.gc_cursor.load(Ordering::Relaxed);
+        let mut scanned = 0;
+        let idle: Vec<T> = self
+            .client_state
+            .iter()
+            .skip(start)
+            .take(max_entries)
+            .inspect(|_| scanned += 1)
+            .filter(|entry| *entry.value() <= cutoff)
+            .map(|entry| entry.key().clone())
+            .collect();
+
+        // re-check under the entry lock in case a request touched the key since
+        let evicted_count = idle
+            .iter()
+            .filter(|key| {
+                let removed = self
+                    .client_state
+                    .remove_if(key, |_, tat| *tat <= cutoff)
+                    .is_some();
+                if removed && self.min_interval_nanos > 0 {
+                    self.last_admitted.remove_if(key, |_, last| {
+                        last.saturating_add(self.min_interval_nanos) <= now
+                    });
+                }
+                if removed {
+                    self.pace.remove(key);
+                }
+                if removed && !self.eviction_hooks.is_empty() {
+                    self.eviction_hooks.notify(key, reason);
+                }
+                if removed {
+                    evicted(key);
+                }
+                removed
+            })
+            .count();
+
+        // removing a key moves every later key one place earlier in the scan
+        let pass_complete = scanned < max_entries || max_entries == 0;
+        let next = if pass_complete {
+            0
+        } else {
+            start + scanned - evicted_count
+        };
+        self.gc_cursor.store(next, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = &self.metrics {
+            metrics.set_keys(self.client_state.len());
+        }
+        GcStep {
+            scanned,
+            evicted: evicted_count,
+            pass_complete,
+        }
+    }
+
+    // method to run a full GC pass every `interval` on a background thread,
+    // in steps so that no pass holds up requests; the thread stops when the
+    // returned sweeper is dropped
+    pub fn spawn_sweeper(limiter: Arc<Self>, interval: Duration) -> Sweeper
+    where
+        T: Send + Sync + 'static,
+        C: 'static,
+        S: Send + Sync + 'static,
+    {
+        let shutdown = Arc::new((Mutex::new(false), Condvar::new()));
+        let signal = Arc::clone(&shutdown);
+        let worker = thread::spawn(move || {
+            let (lock, condvar) = &*signal;
+            let mut stopped = lock.lock().unwrap();
+            while !*stopped {
+                let (guard, _) = condvar.wait_timeout(stopped, interval).unwrap();
+                stopped = guard;
+                if !*stopped {
+                    while !limiter.gc_step(SWEEP_BATCH).pass_complete {}
+                }
+            }
+        });
+
+        Sweeper {
+            shutdown,
+            worker: Some(worker),
+        }
+    }
+
+    // method to publish the limiter's aggregate stats and `top_n` most
+    // throttled keys every `interval`, for dashboards that must not touch
+    // the map; see SnapshotView
+    pub fn snapshot_view(limiter: Arc<Self>, interval: Duration, top_n: usize) -> SnapshotView<T>
+    where
+        T: Send + Sync + 'static,
+        C: Send + Sync + 'static,
+        S: Send + Sync + 'static,
+    {
+        SnapshotView::spawn(limiter, interval, top_n)
+    }
+}
+
+// Make SystemClock the default
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    // Test clock implementation
+    #[derive(Debug, Clone)]
+    struct TestClock {
+        time: Arc<AtomicU64>, // Store as nanos
+    }
+
+    impl TestClock {
+        fn new(initial_time: f64) -> Self {
+            Self {
+                time: Arc::new(AtomicU64::new((initial_time * 1_000_000_000.0) as u64)),
+            }
+        }
+
+        fn advance(&self, seconds: f64) {
+            let nanos = (seconds * 1_000_000_000.0) as u64;
+            self.time.fetch_add(nanos, Ordering::Relaxed);
+        }
+
+        fn set_time(&self, seconds: f64) {
+            let nanos = (seconds * 1_000_000_000.0) as u64;
+            self.time.store(nanos, Ordering::Relaxed);
+        }
+
+        // Helper to get time as f64 for test assertions
+        fn time_as_f64(&self) -> f64 {
+            self.time.load(Ordering::Relaxed) as f64 / 1_000_000_000.0
+        }
+    }
+
+    impl Clock for TestClock {
+        fn now(&self) -> u64 {
+            self.time.load(Ordering::Relaxed)
+        }
+    }
+
+    #[test]
+    fn constructor_rejects_zero_rate() {
+        let clock = TestClock::new(0.0);
+        let result = RateLimiter::<String, _>::new(0.0, 1.0, clock);
+        assert!(result.is_err());
+        assert!(matches!(result.unwrap_err(), RateLimiterError::InvalidRate));
+    }
+
+    #[test]
+    fn constructor_rejects_negative_rate() {
+        let clock = TestClock::new(0.0);
+        let result = RateLimiter::<String, _>::new(-1.0, 1.0, clock);
+        assert!(result.is_err());
+        assert!(matches!(result.unwrap_err(), RateLimiterError::InvalidRate));
+    }
+
+    #[test]
+    fn constructor_rejects_negative_burst() {
+        let clock = TestClock::new(0.0);
+        let result = RateLimiter::<String, _>::new(1.0, -1.0, clock);
+        assert!(result.is_err());
+        assert!(matches!(
+            result.unwrap_err(),
+            RateLimiterError::InvalidBurst
+        ));
+    }
+
+    #[test]
+    fn constructor_accepts_valid_parameters() {
+        let clock = TestClock::new(0.0);
+        let result = RateLimiter::<String, _>::new(10.0, 5.0, clock);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn constructor_accepts_zero_burst() {
+        let clock = TestClock::new(0.0);
+        let result = RateLimiter::<String, _>::new(1.0, 0.0, clock);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn first_request_always_allowed() {
+        let clock = TestClock::new(0.0);
+        let limiter = RateLimiter::new(1.0, 1.0, clock).unwrap();
+        let result = limiter.is_allowed("client1");
+        assert!(result.unwrap());
+    }
+
+    #[test]
+    fn rate_limiting_blocks_rapid_requests() {
+        let clock = TestClock::new(0.0);
+        let limiter = RateLimiter::new(1.0, 0.0, clock.clone()).unwrap(); // 1 req/sec, no burst
+        let client = "client1";
+
+        // First request at time 0.0 should be allowed
+        assert!(limiter.is_allowed(client).unwrap());
+
+        // Second request immediately after should be blocked
+        assert!(!limiter.is_allowed(client).unwrap());
+
+        // Request at 0.5 seconds should still be blocked
+        clock.set_time(0.5);
+        assert!(!limiter.is_allowed(client).unwrap());
+
+        // Request at 1.0 seconds should be allowed (exactly 1 second later)
+        clock.set_time(1.0);
+        assert!(limiter.is_allowed(client).unwrap());
+
+        // Another immediate request should be blocked again
+        assert!(!limiter.is_allowed(client).unwrap());
+    }
+
+    #[test]
+    fn burst_allowance_works() {
+        let clock = TestClock::new(0.0);
+        let limiter = RateLimiter::new(1.0, 3.0, clock.clone()).unwrap(); // 1 req/sec, burst of 3
+        let client = "client1";
+
+        // First 4 requests should all be allowed (burst capacity)
+        assert!(limiter.is_allowed(client).unwrap());
+        assert!(limiter.is_allowed(client).unwrap());
+        assert!(limiter.is_allowed(client).unwrap());
+        assert!(limiter.is_allowed(client).unwrap());
+
+        // 5th request at same time should be blocked (burst exhausted)
+        assert!(!limiter.is_allowed(client).unwrap());
+
+        // After 1 second, 1 more request should be allowed
+        clock.set_time(1.0);
+        assert!(limiter.is_allowed(client).unwrap());
+
+        // But immediate follow-up should be blocked
+        assert!(!limiter.is_allowed(client).unwrap());
+    }
+
+    #[test]
+    fn burst_constructors_match_their_names() {
+        let clock = TestClock::new(0.0);
+        let count_immediate = |limiter: &RateLimiter<&str, TestClock>| {
+            (0..10)
+                .filter(|_| limiter.is_allowed("client1").unwrap())
+                .count()
+        };
+
+        let extra = RateLimiter::with_extra_burst(1.0, 3, clock.clone()).unwrap();
+        assert_eq!(count_immediate(&extra), 4);
+        assert_eq!(extra.burst(), 3.0);
+
+        let total = RateLimiter::with_max_burst_total(1.0, 3, clock.clone()).unwrap();
+        assert_eq!(count_immediate(&total), 3);
+        assert_eq!(total.burst(), 2.0);
+
+        let single = RateLimiter::with_max_burst_total(1.0, 1, clock.clone()).unwrap();
+        assert_eq!(count_immediate(&single), 1);
+
+        let result = RateLimiter::<&str, _>::with_max_burst_total(1.0, 0, clock);
+        assert!(matches!(
+            result.unwrap_err(),
+            RateLimiterError::InvalidBurst
+        ));
+    }
+
+    #[test]
+    fn multiple_clients_independent() {
+        let clock = TestClock::new(0.0);
+        let limiter = RateLimiter::new(1.0, 0.0, clock.clone()).unwrap(); // 1 req/sec, no burst
+
+        // Both clients' first requests should be allowed
+        assert!(limiter.is_allowed("client1").unwrap());
+        assert!(limiter.is_allowed("client2").unwrap());
+
+        // Both clients' immediate second requests should be blocked
+        assert!(!limiter.is_allowed("client1").unwrap());
+        assert!(!limiter.is_allowed("client2").unwrap());
+
+        // After 1 second, both should be allowed again
+        clock.set_time(1.0);
+        assert!(limiter.is_allowed("client1").unwrap());
+        assert!(limiter.is_allowed("client2").unwrap());
+
+        // Client1 exhausts their allowance, but client2 should still work
+        assert!(!limiter.is_allowed("client1").unwrap());
+
+        // Client3 (new client) should be allowed even though others are blocked
+        assert!(limiter.is_allowed("client3").unwrap());
+    }
+
+    #[test]
+    fn time_progression_allows_requests() {
+        let clock = TestClock::new(0.0);
+        let limiter = RateLimiter::new(2.0, 0.0, clock.clone()).unwrap(); // 2 req/sec, no burst
+        let client = "client1";
+
+        // First request at t=0 should be allowed
+        assert!(limiter.is_allowed(client).unwrap());
+
+        // Immediate second request should be blocked
+        assert!(!limiter.is_allowed(client).unwrap());
+
+        // Request at 0.25 seconds should still be blocked (need 0.5s interval for 2 req/sec)
+        clock.set_time(0.25);
+        assert!(!limiter.is_allowed(client).unwrap());
+
+        // Request at exactly 0.5 seconds should be allowed
+        clock.set_time(0.5);
+        assert!(limiter.is_allowed(client).unwrap());
+
+        // Immediate follow-up should be blocked again
+        assert!(!limiter.is_allowed(client).unwrap());
+
+        // Another 0.5 seconds later (t=1.0) should be allowed
+        clock.set_time(1.0);
+        assert!(limiter.is_allowed(client).unwrap());
+
+        // Long idle period - request at t=10.0 should definitely be allowed
+        clock.set_time(10.0);
+        assert!(limiter.is_allowed(client).unwrap());
+    }
+
+    #[test]
+    fn test_clock_advances_time() {
+        let clock = TestClock::new(5.0);
+        assert_eq!(clock.time_as_f64(), 5.0);
+
+        clock.advance(2.5);
+        assert_eq!(clock.time_as_f64(), 7.5);
+
+        clock.set_time(0.0);
+        assert_eq!(clock.time_as_f64(), 0.0);
+    }
+
+    #[test]
+    fn accessor_methods_work() {
+        let clock = TestClock::new(0.0);
+        let limiter = RateLimiter::<String, _>::new(10.0, 5.0, clock).unwrap();
+
+        // Test that accessors return the original user-provided values
+        assert_eq!(limiter.rate(), 10.0);
+        assert_eq!(limiter.burst(), 5.0);
+    }
+
+    #[test]
+    fn min_interval_spaces_requests_despite_burst() {
+        let clock = TestClock::new(0.0);
+        let limiter = RateLimiter::new(10.0, 5.0, clock.clone())
+            .unwrap()
+            .with_min_interval(Duration::from_millis(50));
+
+        assert!(limiter.is_allowed("device").unwrap());
+        // burst credit remains, but the gap has not passed
+        let denied = limiter.charge("device", 1).unwrap_err();
+        assert_eq!(denied.retry_after(), Some(Duration::from_millis(50)));
+        assert!(limiter.is_allowed("other").unwrap());
+
+        clock.advance(0.05);
+        assert!(limiter.simulate(&"device", clock.now()));
+        assert!(limiter.is_allowed("device").unwrap());
+        clock.advance(0.049);
+        assert!(!limiter.is_allowed("device").unwrap());
+    }
+
+    #[test]
+    fn key_manifest_preallocates_and_prefills() {
+        let clock = TestClock::new(0.0);
+        let tenants: Vec<String> = (0..1000).map(|i| format!("tenant-{}", i)).collect();
+
+        let limiter = RateLimiter::new(1.0, 2.0, clock.clone())
+            .unwrap()
+            .with_key_manifest(tenants.clone(), false);
+        assert!(limiter.client_state().capacity() >= 1000);
+        assert!(limiter.client_state().is_empty());
+
+        let limiter = RateLimiter::new(1.0, 2.0, clock.clone())
+            .unwrap()
+            .with_key_manifest(tenants, true);
+        assert_eq!(limiter.client_state().len(), 1000);
+        // prefilled keys still get their whole burst
+        clock.advance(10.0);
+        for _ in 0..3 {
+            assert!(limiter.is_allowed(String::from("tenant-7")).unwrap());
+        }
+        assert!(!limiter.is_allowed(String::from("tenant-7")).unwrap());
+    }
+
+    #[test]
+    fn rehashing_and_resharding_keep_every_key() {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::BuildHasherDefault;
+
+        let clock = TestClock::new(0.0);
+        let limiter = RateLimiter::new(1.0, 0.0, clock.clone()).unwrap();
+        for key in 0..100u32 {
+            assert!(limiter.is_allowed(key).unwrap());
+        }
+        let limiter = limiter
+            .with_hasher(BuildHasherDefault::<DefaultHasher>::default())
+            .with_shards(3);
+        assert_eq!(limiter.shard_count(), 4);
+        assert_eq!(limiter.len(), 100);
+        assert!(!limiter.is_allowed(42).unwrap());
+        clock.advance(1.0);
+        assert!(limiter.is_allowed(42).unwrap());
+        assert_eq!(limiter.with_shards(0).shard_count(), 2);
+    }
+
+    #[test]
+    fn check_many_charges_each_key_in_order() {
+        let clock = TestClock::new(0.0);
+        let limiter = RateLimiter::new(1.0, 1.0, clock.clone()).unwrap();
+        limiter.ban_for("banned", Duration::from_secs(5));
+
+        let decisions = limiter.check_many([
+            ("client1", 1),
+            ("client2", 3),
+            ("client1", 1),
+            ("banned", 1),
+            ("client1", 1),
+            ("client2", 1),
+        ]);
+        let allowed: Vec<bool> = decisions.iter().map(Decision::is_allowed).collect();
+        assert_eq!(allowed, vec![true, false, true, false, false, true]);
+
+        // too costly ever to fit, banned, and out of burst credit respectively
+        assert_eq!(decisions[1].retry_after(), None);
+        assert_eq!(decisions[3].retry_after(), Some(Duration::from_secs(5)));
+        assert_eq!(decisions[4].retry_after(), Some(Duration::from_secs(1)));
+
+        // the batch leaves the same state as individual checks would
+        assert!(!limiter.is_allowed("client1").unwrap());
+        clock.advance(1.0);
+        assert!(limiter.is_allowed("client1").unwrap());
+        assert!(limiter.check_many(Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn check_reports_timing_metadata() {
+        let clock = TestClock::new(10.0);
+        let limiter = RateLimiter::new(2.0, 2.0, clock.clone()).unwrap();
+
+        let first = limiter.check("client1");
+        assert!(first.is_allowed());
+        assert_eq!(first.retry_after(), None);
+        assert_eq!(first.remaining_burst(), 2);
+        assert_eq!(first.tat_nanos(), Some(10_500_000_000));
+
+        limiter.check("client1");
+        let third = limiter.check("client1");
+        assert_eq!(third.remaining_burst(), 0);
+        let denied = limiter.check("client1");
+        assert!(!denied.is_allowed());
+        assert_eq!(denied.retry_after(), Some(Duration::from_millis(500)));
+        assert_eq!(denied.tat_nanos(), Some(11_500_000_000));
+
+        clock.advance(0.5);
+        assert_eq!(limiter.check("client1").remaining_burst(), 0);
+        clock.advance(1.0);
+        assert_eq!(limiter.check("client1").remaining_burst(), 1);
+
+        limiter.set_allow_all(true);
+        assert_eq!(limiter.check("client1").tat_nanos(), None);
+    }
+
+    #[test]
+    fn peek_predicts_check_without_charging() {
+        let clock = TestClock::new(0.0);
+        let limiter = RateLimiter::new(1.0, 1.0, clock.clone())
+            .unwrap()
+            .with_min_interval(Duration::from_millis(100));
+
+        // peeking repeatedly neither consumes quota nor creates state
+        for _ in 0..5 {
+            assert!(limiter.peek(&"client1").is_allowed());
+        }
+        assert!(limiter.client_state().is_empty());
+
+        let peeked = limiter.peek(&"client1");
+        assert_eq!(limiter.check("client1"), peeked);
+        // the minimum spacing applies to a peek, but is not recorded by one
+        assert_eq!(
+            limiter.peek(&"client1").retry_after(),
+            Some(Duration::from_millis(100))
+        );
+        clock.advance(0.1);
+        let peeked = limiter.peek(&"client1");
+        assert!(peeked.is_allowed());
+        assert_eq!(limiter.check("client1"), peeked);
+        assert_eq!(limiter.peek(&"client1"), limiter.check("client1"));
+        assert!(!limiter.peek(&"client1").is_allowed());
+
+        limiter.ban_for("client2", Duration::from_secs(3));
+        assert_eq!(
+            limiter.peek(&"client2").retry_after(),
+            Some(Duration::from_secs(3))
+        );
     }
 
-    // accessor method to return the burst field (convert back to burst capacity)
-    pub fn burst(&self) -> f64 {
-        self.tolerance_nanos as f64 / self.rate_nanos as f64
+    #[test]
+    fn forecasts_exhaustion_from_recent_pace() {
+        let clock = TestClock::new(0.0);
+        let limiter = RateLimiter::new(1.0, 4.0, clock.clone())
+            .unwrap()
+            .with_pace_tracking(Duration::from_secs(1));
+        assert_eq!(limiter.forecast_exhaustion(&"client1"), None);
+
+        // three requests a second against a quota of one: the backlog of 3s
+        // grows by 2s a second, passing the 4s tolerance in half a second
+        for _ in 0..3 {
+            limiter.is_allowed("client1").unwrap();
+        }
+        assert_eq!(
+            limiter.forecast_exhaustion(&"client1"),
+            Some(Duration::from_millis(500))
+        );
+
+        // a pace within the quota never runs out
+        for _ in 0..3 {
+            clock.advance(2.0);
+            limiter.is_allowed("client1").unwrap();
+        }
+        assert_eq!(limiter.forecast_exhaustion(&"client1"), None);
+
+        let untracked = RateLimiter::new(1.0, 4.0, clock.clone()).unwrap();
+        untracked.is_allowed("client1").unwrap();
+        assert_eq!(untracked.forecast_exhaustion(&"client1"), None);
     }
 
-    // internal method to get the increment in nanoseconds
-    #[allow(dead_code)]
-    fn increment_nanos(&self) -> u64 {
-        self.rate_nanos
+    #[test]
+    fn cost_is_charged_atomically() {
+        let clock = TestClock::new(0.0);
+        let limiter = RateLimiter::new(1.0, 9.0, clock.clone()).unwrap();
+
+        // the burst holds ten units: a bulk request of ten fits exactly once
+        assert!(limiter.is_allowed_with_cost("client1", 10).unwrap());
+        assert!(!limiter.is_allowed("client1").unwrap());
+
+        // a partial refill is not enough for the whole cost, and the denied
+        // request does not eat into what has refilled
+        clock.advance(5.0);
+        assert!(!limiter.is_allowed_with_cost("client1", 6).unwrap());
+        assert!(limiter.is_allowed_with_cost("client1", 5).unwrap());
+
+        // a cost larger than the burst can never be admitted
+        assert!(!limiter.is_allowed_with_cost("client2", 11).unwrap());
+        assert!(limiter.is_allowed_with_cost("client2", 10).unwrap());
     }
 
-    // internal method to get the tolerance in nanoseconds
-    #[allow(dead_code)]
-    fn tolerance_nanos(&self) -> u64 {
-        self.tolerance_nanos
+    #[test]
+    fn wait_sleeps_until_the_request_conforms() {
+        let clock = TestClock::new(0.0);
+        let limiter = RateLimiter::new(2.0, 0.0, clock.clone()).unwrap();
+        let slept = std::sync::Mutex::new(Vec::new());
+        let sleeper = |wait: Duration| {
+            slept.lock().unwrap().push(wait);
+            clock.advance(wait.as_secs_f64());
+        };
+
+        for _ in 0..3 {
+            limiter.wait_with("api", 1, &sleeper).unwrap();
+        }
+        assert_eq!(
+            *slept.lock().unwrap(),
+            [Duration::from_millis(500), Duration::from_millis(500)]
+        );
+        assert_eq!(clock.time_as_f64(), 1.0);
+
+        // a cost beyond the burst would wait forever, so it fails instead
+        let denied = limiter.wait_with("api", 2, &sleeper).unwrap_err();
+        assert_eq!(denied.retry_after(), None);
+
+        // the default sleeper really sleeps
+        let limiter = RateLimiter::new(100.0, 0.0, SystemClock).unwrap();
+        limiter.wait("api").unwrap();
+        let started = std::time::Instant::now();
+        limiter.wait("api").unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(5));
     }
 
-    // Optional: keep the old method names for backwards compatibility
-    #[allow(dead_code)]
-    fn increment(&self) -> f64 {
-        self.rate_nanos as f64 / 1_000_000_000.0
+    #[cfg(feature = "tokio")]
+    #[tokio::test(start_paused = true)]
+    async fn until_key_ready_sleeps_on_the_tokio_timer() {
+        let clock = crate::TokioClock::starting_at(0);
+        let limiter = RateLimiter::new(2.0, 1.0, clock).unwrap();
+
+        // the burst goes through at once, then each request waits 500ms
+        for _ in 0..4 {
+            limiter.until_key_ready("api").await.unwrap();
+        }
+        assert_eq!(clock.now(), 1_000_000_000);
+        assert!(!limiter.is_allowed("api").unwrap());
     }
 
-    #[allow(dead_code)]
-    fn tolerance(&self) -> f64 {
-        self.tolerance_nanos as f64 / 1_000_000_000.0
+    #[test]
+    fn reconfiguring_keeps_client_state() {
+        let clock = TestClock::new(0.0);
+        let limiter = RateLimiter::new(10.0, 4.0, clock.clone()).unwrap();
+        let handle = limiter.handle("cached");
+        (0..5).for_each(|_| assert!(limiter.is_allowed("a").unwrap()));
+        assert!(handle.is_allowed());
+
+        // tighten during an incident: "a" keeps its backlog of 0.5s, which
+        // the new 1/s rate takes much longer to work off
+        limiter.set_rate(1.0).unwrap();
+        assert_eq!(limiter.rate(), 1.0);
+        assert_eq!(limiter.burst(), 4.0);
+        clock.advance(0.1);
+        assert!(limiter.is_allowed("a").unwrap());
+        assert_eq!(limiter.increment_nanos(), 1_000_000_000);
+
+        limiter.set_burst(0.0).unwrap();
+        assert!(handle.is_allowed());
+        assert!(!handle.is_allowed());
+        assert!(limiter.set_burst(-1.0).is_err());
+        assert!(limiter.set_rate(0.0).is_err());
+
+        let quota = Quota::per_second(std::num::NonZeroU32::new(100).unwrap());
+        limiter.reconfigure(quota);
+        assert_eq!(
+            (limiter.increment_nanos(), limiter.tolerance_nanos()),
+            (10_000_000, 990_000_000)
+        );
     }
 
-    // method that implements the GCRA algorithm
-    pub fn is_allowed(&self, client_id: T) -> Result<bool, RateLimiterError> {
-        let current_time_nanos = self.clock.now(); // Get nanoseconds
+    #[test]
+    fn reconfiguring_swaps_rate_and_burst_together() {
+        let limiter = Arc::new(RateLimiter::<u32, _>::new(1.0, 1.0, TestClock::new(0.0)).unwrap());
+        let writer = {
+            let limiter = Arc::clone(&limiter);
+            thread::spawn(move || {
+                for i in 1..=2_000u32 {
+                    let quota = Quota::per_second(std::num::NonZeroU32::new(i).unwrap());
+                    limiter.reconfigure(quota.allow_burst(std::num::NonZeroU32::new(3).unwrap()));
+                }
+            })
+        };
+        // every pair read is one a writer stored: the tolerance is always
+        // exactly two increments
+        while !writer.is_finished() {
+            let (increment, tolerance) = limiter.base.load();
+            assert!(
+                tolerance == 2 * increment
+                    || (increment, tolerance) == (1_000_000_000, 1_000_000_000)
+            );
+        }
+        writer.join().unwrap();
+    }
 
-        // Get previous TAT in nanoseconds, default to current time for new clients
-        let previous_tat_nanos = self
-            .client_state
-            .get(&client_id)
-            .map(|entry| *entry.value())
-            .unwrap_or(current_time_nanos);
+    #[test]
+    fn gc_step_resumes_where_it_stopped() {
+        let clock = TestClock::new(0.0);
+        let limiter = RateLimiter::new(1.0, 0.0, clock.clone()).unwrap();
+        for key in 0..10 {
+            limiter.is_allowed(key).unwrap();
+        }
+        clock.advance(1.0);
+        // a key that is still busy survives every pass
+        limiter.is_allowed(3).unwrap();
 
-        // Core GCRA test using integer arithmetic
-        let is_conforming =
-            current_time_nanos >= previous_tat_nanos.saturating_sub(self.tolerance_nanos);
+        let first = limiter.gc_step(4);
+        assert_eq!(first.scanned, 4);
+        assert!(!first.pass_complete);
 
-        if is_conforming {
-            // Update TAT: max(current_time, previous_tat) + increment
-            let new_tat_nanos = current_time_nanos.max(previous_tat_nanos) + self.rate_nanos;
-            self.client_state.insert(client_id, new_tat_nanos);
+        let mut steps = vec![first];
+        while !steps.last().unwrap().pass_complete {
+            steps.push(limiter.gc_step(4));
         }
+        let scanned: usize = steps.iter().map(|step| step.scanned).sum();
+        let evicted: usize = steps.iter().map(|step| step.evicted).sum();
+        assert_eq!(scanned, 10);
+        assert_eq!(evicted, 9);
+        assert_eq!(limiter.client_state().len(), 1);
+        assert!(limiter.client_state().contains_key(&3));
+
+        // the next pass starts from the beginning again
+        assert_eq!(limiter.gc_step(4).scanned, 1);
+    }
 
-        Ok(is_conforming)
+    #[test]
+    fn reset_remove_and_clear_drop_state() {
+        let clock = TestClock::new(0.0);
+        let mut limiter = RateLimiter::new(1.0, 0.0, clock.clone()).unwrap();
+        let journal = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = Arc::clone(&journal);
+        limiter.set_audit_sink(move |entry: &crate::AuditEntry<'_, &str>| {
+            sink.lock()
+                .unwrap()
+                .push(format!("{} {}", entry.action, entry.key.unwrap_or(&"-")));
+        });
+        let evicted = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = Arc::clone(&evicted);
+        limiter.on_evict(move |key: &&str, reason| seen.lock().unwrap().push((*key, reason)));
+
+        // a reset gives back the burst but leaves a ban in force
+        limiter.is_allowed("a").unwrap();
+        assert!(!limiter.is_allowed("a").unwrap());
+        limiter.as_actor("support").reset(&"a");
+        assert!(limiter.is_allowed("a").unwrap());
+        limiter.ban_for("a", Duration::from_secs(60));
+        limiter.reset(&"a");
+        assert!(!limiter.is_allowed("a").unwrap());
+
+        // a removal forgets the ban as well
+        limiter.remove(&"a");
+        assert!(limiter.is_allowed("a").unwrap());
+
+        limiter.is_allowed("b").unwrap();
+        limiter.ban_for("c", Duration::from_secs(60));
+        limiter.clear();
+        assert!(limiter.client_state().is_empty());
+        assert!(limiter.is_allowed("b").unwrap());
+        assert!(limiter.is_allowed("c").unwrap());
+
+        assert_eq!(
+            *journal.lock().unwrap(),
+            [
+                "reset a",
+                "ban ttl=60s a",
+                "reset a",
+                "remove a",
+                "ban ttl=60s c",
+                "clear -"
+            ]
+        );
+        // the two resets, then the two keys holding state at the clear
+        let mut evicted = evicted.lock().unwrap().clone();
+        evicted[2..].sort_by_key(|(key, _)| *key);
+        let manual = EvictionReason::Manual;
+        assert_eq!(
+            evicted,
+            [("a", manual), ("a", manual), ("a", manual), ("b", manual)]
+        );
     }
-}
 
-// Make SystemClock the default
-impl Default for SystemClock {
-    fn default() -> Self {
-        Self
+    #[test]
+    fn introspection_lists_tracked_keys() {
+        let clock = TestClock::new(0.0);
+        let limiter = RateLimiter::new(1.0, 2.0, clock.clone()).unwrap();
+        assert!(limiter.is_empty());
+
+        limiter.is_allowed("light").unwrap();
+        for _ in 0..3 {
+            limiter.is_allowed("heavy").unwrap();
+        }
+        assert_eq!(limiter.len(), 2);
+        let mut states: Vec<(&str, u64)> = limiter.iter().collect();
+        states.sort();
+        assert_eq!(states, [("heavy", 3_000_000_000), ("light", 1_000_000_000)]);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::sync::atomic::{AtomicU64, Ordering};
+    #[test]
+    fn eviction_hooks_hear_about_removed_keys() {
+        let clock = TestClock::new(0.0);
+        let mut limiter = RateLimiter::new(1.0, 0.0, clock.clone()).unwrap();
+        let evicted = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = Arc::clone(&evicted);
+        limiter.on_evict(move |key: &&str, reason| {
+            seen.lock().unwrap().push((key.to_string(), reason));
+        });
+
+        limiter.is_allowed("idle").unwrap();
+        limiter.ban_for("banned", Duration::from_secs(60));
+        clock.advance(1.0);
+        limiter.is_allowed("busy").unwrap();
+        limiter.is_allowed("banned").unwrap();
+        limiter.gc_step(10);
+
+        assert_eq!(
+            *evicted.lock().unwrap(),
+            vec![(String::from("idle"), EvictionReason::Idle)]
+        );
+        // eviction leaves bans in force
+        assert!(!limiter.is_allowed("banned").unwrap());
+    }
 
-    // Test clock implementation
-    #[derive(Debug, Clone)]
-    struct TestClock {
-        time: Arc<AtomicU64>, // Store as nanos
+    #[test]
+    fn token_bucket_refills_on_aligned_ticks() {
+        let clock = TestClock::new(0.5);
+        let gcra = RateLimiter::new(1.0, 1.0, clock.clone()).unwrap();
+        let bucket = RateLimiter::new(1.0, 1.0, clock.clone())
+            .unwrap()
+            .with_algorithm(crate::TokenBucket::new(Duration::from_secs(1)));
+
+        for limiter in [&gcra, &bucket] {
+            assert!(limiter.is_allowed("k").unwrap());
+            assert!(limiter.is_allowed("k").unwrap());
+            assert!(!limiter.is_allowed("k").unwrap());
+        }
+        let denied = bucket.check("k");
+        assert_eq!(denied.retry_after(), Some(Duration::from_millis(500)));
+        assert_eq!(denied.remaining_burst(), 0);
+
+        // the bucket gains its token at 1s, GCRA half a second later
+        clock.advance(0.5);
+        assert!(bucket.is_allowed("k").unwrap());
+        assert!(!gcra.is_allowed("k").unwrap());
+
+        // full again at 3s, and evictable one refill interval later
+        clock.advance(2.0);
+        assert_eq!(bucket.peek(&"k").remaining_burst(), 1);
+        assert_eq!(bucket.evict_idle(10), 0);
+        clock.advance(1.0);
+        assert_eq!(bucket.evict_idle(10), 1);
     }
 
-    impl TestClock {
-        fn new(initial_time: f64) -> Self {
-            Self {
-                time: Arc::new(AtomicU64::new((initial_time * 1_000_000_000.0) as u64)),
+    #[test]
+    fn observers_hear_every_decision() {
+        let clock = TestClock::new(0.0);
+        let mut limiter = RateLimiter::new(2.0, 0.0, clock.clone()).unwrap();
+        let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (allowed, denied) = (Arc::clone(&log), Arc::clone(&log));
+        limiter.on_allowed(move |key: &&str| allowed.lock().unwrap().push(format!("+{}", key)));
+        limiter.on_denied(move |key: &&str, retry_after| {
+            denied
+                .lock()
+                .unwrap()
+                .push(format!("-{} {:?}", key, retry_after));
+        });
+
+        // an observer that only cares about denials counts them
+        struct Denials(AtomicUsize);
+        impl DecisionObserver<&str> for Arc<Denials> {
+            fn on_denied(&self, _key: &&str, _retry_after: Option<Duration>) {
+                self.0.fetch_add(1, Ordering::Relaxed);
             }
         }
+        let denials = Arc::new(Denials(AtomicUsize::new(0)));
+        limiter.add_observer(Arc::clone(&denials));
 
-        fn advance(&self, seconds: f64) {
-            let nanos = (seconds * 1_000_000_000.0) as u64;
-            self.time.fetch_add(nanos, Ordering::Relaxed);
-        }
+        limiter.is_allowed("a").unwrap();
+        limiter.is_allowed("a").unwrap();
+        let _ = limiter.is_allowed_with_cost("a", 5);
+        limiter.set_allow_all(true);
+        limiter.is_allowed("a").unwrap();
 
-        fn set_time(&self, seconds: f64) {
-            let nanos = (seconds * 1_000_000_000.0) as u64;
-            self.time.store(nanos, Ordering::Relaxed);
-        }
+        assert_eq!(*log.lock().unwrap(), ["+a", "-a Some(500ms)", "-a None"]);
+        assert_eq!(denials.0.load(Ordering::Relaxed), 2);
+    }
 
-        // Helper to get time as f64 for test assertions
-        fn time_as_f64(&self) -> f64 {
-            self.time.load(Ordering::Relaxed) as f64 / 1_000_000_000.0
+    #[test]
+    fn idle_ttl_keeps_recent_keys_and_sweeps_lazily() {
+        let clock = TestClock::new(0.0);
+        let mut limiter = RateLimiter::new(1.0, 0.0, clock.clone())
+            .unwrap()
+            .with_idle_ttl(Duration::from_secs(10));
+        let evicted = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = Arc::clone(&evicted);
+        limiter.on_evict(move |key: &String, reason| {
+            seen.lock().unwrap().push((key.clone(), reason));
+        });
+
+        limiter.is_allowed(String::from("old")).unwrap();
+        clock.advance(6.0);
+        limiter.is_allowed(String::from("recent")).unwrap();
+        clock.advance(6.0);
+        // "old" went idle 11s ago, "recent" only 5s ago
+        assert_eq!(limiter.gc_step(10).evicted, 1);
+        assert_eq!(
+            *evicted.lock().unwrap(),
+            vec![(String::from("old"), EvictionReason::Expired)]
+        );
+
+        // with no explicit GC, the request path sweeps once enough charges
+        // have gone by
+        clock.advance(20.0);
+        for round in 0..LAZY_SWEEP_CHECK {
+            let _ = limiter.is_allowed(format!("hot-{}", round % 4));
         }
+        assert!(!limiter.client_state().contains_key("recent"));
+        assert_eq!(limiter.client_state().len(), 4);
     }
 
-    impl Clock for TestClock {
-        fn now(&self) -> u64 {
-            self.time.load(Ordering::Relaxed)
+    #[test]
+    fn key_cap_evicts_least_recently_charged_keys() {
+        let clock = TestClock::new(0.0);
+        let mut limiter = RateLimiter::new(1.0, 2.0, clock.clone())
+            .unwrap()
+            .with_max_keys(10);
+        let evicted = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = Arc::clone(&evicted);
+        limiter.on_evict(move |key: &u32, reason| seen.lock().unwrap().push((*key, reason)));
+
+        for key in 0..10 {
+            limiter.is_allowed(key).unwrap();
+            clock.advance(0.1);
         }
+        // key 0 is the oldest but gets charged again; key 1 is now the oldest
+        limiter.is_allowed(0).unwrap();
+
+        limiter.is_allowed(10).unwrap();
+        assert_eq!(limiter.client_state().len(), 10);
+        assert_eq!(
+            *evicted.lock().unwrap(),
+            vec![(1, EvictionReason::Capacity)]
+        );
+
+        // spraying new keys never grows the map past the cap, and a client
+        // that exhausted its burst keeps its state throughout
+        while limiter.is_allowed(0).unwrap() {}
+        for key in 100..1_000 {
+            limiter.is_allowed(key).unwrap();
+            assert!(limiter.client_state().len() <= 10);
+        }
+        assert!(!limiter.is_allowed(0).unwrap());
     }
 
     #[test]
-    fn constructor_rejects_zero_rate() {
+    fn merge_respects_the_key_cap() {
         let clock = TestClock::new(0.0);
-        let result = RateLimiter::<String, _>::new(0.0, 1.0, clock);
-        assert!(result.is_err());
-        assert!(matches!(result.unwrap_err(), RateLimiterError::InvalidRate));
+        let limiter = RateLimiter::new(1.0, 2.0, clock.clone())
+            .unwrap()
+            .with_max_keys(10);
+        while limiter.is_allowed(0).unwrap() {}
+
+        // a peer's snapshot of many keys cannot grow the map past the cap,
+        // nor evict the client that exhausted its burst
+        let peer: Snapshot<u32> = (100..1_000).map(|key| (key, 1_000_000_000)).collect();
+        limiter.merge(&peer);
+        assert!(limiter.client_state().len() <= 10);
+        assert!(!limiter.is_allowed(0).unwrap());
     }
 
     #[test]
-    fn constructor_rejects_negative_rate() {
+    fn tat_horizon_bounds_recovery_time() {
         let clock = TestClock::new(0.0);
-        let result = RateLimiter::<String, _>::new(-1.0, 1.0, clock);
-        assert!(result.is_err());
-        assert!(matches!(result.unwrap_err(), RateLimiterError::InvalidRate));
+        let limiter = RateLimiter::new(1.0, 1.0, clock.clone())
+            .unwrap()
+            .with_tat_horizon(Duration::from_secs(10));
+
+        // a peer reports a TAT years ahead; without the cap the client
+        // would be locked out for that long
+        limiter.merge(&[("client", u64::MAX / 2)].into_iter().collect());
+        let denied = limiter.charge("client", 1).unwrap_err();
+        assert_eq!(denied.retry_after(), Some(Duration::from_secs(9)));
+        assert_eq!(
+            limiter.client_state().get("client").map(|tat| *tat),
+            Some(10_000_000_000)
+        );
+
+        clock.advance(9.0);
+        assert!(limiter.is_allowed("client").unwrap());
+
+        // a horizon below the burst window leaves the burst intact
+        let limiter = RateLimiter::new(1.0, 4.0, clock.clone())
+            .unwrap()
+            .with_tat_horizon(Duration::from_secs(1));
+        let admitted = (0..10)
+            .filter(|_| limiter.is_allowed("client").unwrap())
+            .count();
+        assert_eq!(admitted, 5);
     }
 
     #[test]
-    fn constructor_rejects_negative_burst() {
+    fn borrowed_keys_share_state_with_owned_keys() {
         let clock = TestClock::new(0.0);
-        let result = RateLimiter::<String, _>::new(1.0, -1.0, clock);
-        assert!(result.is_err());
+        let limiter: RateLimiter<String, _> = RateLimiter::new(1.0, 1.0, clock.clone()).unwrap();
+
+        assert!(limiter.is_allowed_ref("alice").unwrap());
+        assert!(limiter.is_allowed(String::from("alice")).unwrap());
+        assert!(!limiter.is_allowed_ref("alice").unwrap());
+        assert!(limiter.is_allowed_with_cost_ref("bob", 2).unwrap());
+        assert_eq!(limiter.len(), 2);
+
+        // overrides and bans are looked up by the borrowed key too
+        limiter
+            .set_override(String::from("carol"), 1.0, 0.0)
+            .unwrap();
+        assert!(limiter.is_allowed_ref("carol").unwrap());
+        assert!(!limiter.is_allowed_ref("carol").unwrap());
+        limiter.ban_for(String::from("bob"), Duration::from_secs(60));
+        clock.advance(10.0);
+        assert!(!limiter.is_allowed_ref("bob").unwrap());
+    }
+
+    #[test]
+    fn clamped_clock_regression_holds_time_at_the_latest_reading() {
+        let clock = crate::TestClock::new(10.0);
+        let allowing = RateLimiter::new(1.0, 0.0, clock.clone()).unwrap();
+        let clamping = RateLimiter::new(1.0, 0.0, clock.clone())
+            .unwrap()
+            .with_clock_regression(ClockRegression::Clamp);
+        assert!(allowing.is_allowed("client").unwrap());
+        assert!(clamping.is_allowed("client").unwrap());
+
+        // an NTP step back by 5s: taken as is, the wait grows by the step
+        clock.rewind_by(Duration::from_secs(5));
+        let wait = |limiter: &RateLimiter<&str, crate::TestClock>| {
+            limiter.charge("client", 1).unwrap_err().retry_after()
+        };
+        assert_eq!(wait(&allowing), Some(Duration::from_secs(6)));
+        assert_eq!(wait(&clamping), Some(Duration::from_secs(1)));
+
+        // clamped time stands still until the clock catches up
+        clock.advance(4.0);
+        assert_eq!(wait(&clamping), Some(Duration::from_secs(1)));
+        clock.advance(2.0);
+        assert!(clamping.is_allowed("client").unwrap());
+    }
+
+    #[test]
+    fn erroring_on_clock_regression_refuses_until_caught_up() {
+        let clock = crate::TestClock::new(10.0);
+        let limiter = RateLimiter::new(1.0, 4.0, clock.clone())
+            .unwrap()
+            .with_clock_regression(ClockRegression::Error);
+        assert!(limiter.is_allowed("client").unwrap());
+
+        clock.rewind_by(Duration::from_secs(3));
         assert!(matches!(
-            result.unwrap_err(),
-            RateLimiterError::InvalidBurst
+            limiter.is_allowed("client"),
+            Err(RateLimiterError::ClockWentBackwards(by)) if by == Duration::from_secs(3)
         ));
+        let denied = limiter.charge("client", 1).unwrap_err();
+        assert_eq!(denied.retry_after(), Some(Duration::from_secs(3)));
+        let decision = limiter.check("client");
+        assert!(!decision.is_allowed());
+        assert_eq!(decision.tat_nanos(), None);
+        assert!(!limiter.check_many([("other", 1)])[0].is_allowed());
+
+        // nothing was charged while refusing
+        clock.advance(3.0);
+        let admitted = (0..10)
+            .filter(|_| limiter.is_allowed("client").unwrap())
+            .count();
+        assert_eq!(admitted, 4);
     }
 
     #[test]
-    fn constructor_accepts_valid_parameters() {
+    fn sweeper_thread_evicts_in_the_background() {
         let clock = TestClock::new(0.0);
-        let result = RateLimiter::<String, _>::new(10.0, 5.0, clock);
-        assert!(result.is_ok());
+        let limiter = Arc::new(
+            RateLimiter::new(1.0, 0.0, clock.clone())
+                .unwrap()
+                .with_idle_ttl(Duration::from_secs(1)),
+        );
+        for key in 0..100 {
+            limiter.is_allowed(key).unwrap();
+        }
+        clock.advance(5.0);
+
+        let sweeper = RateLimiter::spawn_sweeper(Arc::clone(&limiter), Duration::from_millis(5));
+        for _ in 0..200 {
+            if limiter.client_state().is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        drop(sweeper);
+        assert!(limiter.client_state().is_empty());
     }
 
     #[test]
-    fn constructor_accepts_zero_burst() {
+    fn shadow_mode_reports_denials_but_admits() {
         let clock = TestClock::new(0.0);
-        let result = RateLimiter::<String, _>::new(1.0, 0.0, clock);
-        assert!(result.is_ok());
+        let mut limiter = RateLimiter::new(1.0, 0.0, clock.clone()).unwrap();
+        let denied = Arc::new(AtomicUsize::new(0));
+        let seen = Arc::clone(&denied);
+        limiter.on_denied(move |_, _| {
+            seen.fetch_add(1, Ordering::Relaxed);
+        });
+        limiter.set_shadow(true);
+        assert!(limiter.is_shadow());
+
+        assert!(limiter.is_allowed("a").unwrap());
+        let decision = limiter.check("a");
+        assert!(decision.is_allowed());
+        assert_eq!(decision.retry_after(), None);
+        assert!(limiter.is_allowed("a").unwrap());
+        assert_eq!(denied.load(Ordering::Relaxed), 2);
+
+        // denials charged nothing, so enforcing picks up where the limit was
+        clock.advance(1.0);
+        limiter.set_shadow(false);
+        assert!(limiter.is_allowed("a").unwrap());
+        assert!(!limiter.is_allowed("a").unwrap());
+        assert_eq!(denied.load(Ordering::Relaxed), 3);
     }
 
     #[test]
-    fn first_request_always_allowed() {
+    fn allow_all_mode_skips_state() {
         let clock = TestClock::new(0.0);
-        let limiter = RateLimiter::new(1.0, 1.0, clock).unwrap();
-        let result = limiter.is_allowed("client1");
-        assert!(result.unwrap());
+        let limiter = RateLimiter::new(1.0, 0.0, clock.clone()).unwrap();
+        limiter.is_allowed("client1").unwrap();
+
+        limiter.set_allow_all(true);
+        for _ in 0..5 {
+            assert!(limiter.is_allowed("client1").unwrap());
+            assert!(limiter.is_allowed("client2").unwrap());
+        }
+        assert!(limiter.check_many([("client1", 1)])[0].is_allowed());
+        // nothing was written while bypassing
+        assert_eq!(limiter.client_state().len(), 1);
+        assert_eq!(
+            *limiter.client_state().get("client1").unwrap(),
+            1_000_000_000
+        );
+
+        limiter.set_allow_all(false);
+        assert!(!limiter.is_allowed("client1").unwrap());
     }
 
     #[test]
-    fn rate_limiting_blocks_rapid_requests() {
+    fn simulate_does_not_consume_quota() {
         let clock = TestClock::new(0.0);
         let limiter = RateLimiter::new(1.0, 0.0, clock.clone()).unwrap(); // 1 req/sec, no burst
         let client = "client1";
 
-        // First request at time 0.0 should be allowed
-        assert!(limiter.is_allowed(client).unwrap());
+        // unknown clients would always be allowed
+        assert!(limiter.simulate(&client, 0));
+        assert!(limiter.simulate(&client, 0));
 
-        // Second request immediately after should be blocked
-        assert!(!limiter.is_allowed(client).unwrap());
+        assert!(limiter.is_allowed(client).unwrap());
 
-        // Request at 0.5 seconds should still be blocked
-        clock.set_time(0.5);
-        assert!(!limiter.is_allowed(client).unwrap());
+        // a request now would be blocked, one at t=1.0 would be allowed
+        assert!(!limiter.simulate(&client, 0));
+        assert!(!limiter.simulate(&client, 999_999_999));
+        assert!(limiter.simulate(&client, 1_000_000_000));
 
-        // Request at 1.0 seconds should be allowed (exactly 1 second later)
+        // simulating did not move the TAT
         clock.set_time(1.0);
         assert!(limiter.is_allowed(client).unwrap());
+    }
 
-        // Another immediate request should be blocked again
-        assert!(!limiter.is_allowed(client).unwrap());
+    #[test]
+    fn simulate_agrees_with_peek() {
+        let clock = TestClock::new(0.0);
+        let limiter = RateLimiter::new(1.0, 1.0, clock.clone())
+            .unwrap()
+            .with_tat_horizon(Duration::from_secs(10));
+        limiter.merge(&[("client", u64::MAX / 2)].into_iter().collect());
+        assert!(!limiter.simulate(&"client", 0));
+        assert_eq!(
+            limiter.simulate(&"client", 0),
+            limiter.peek(&"client").is_allowed()
+        );
+
+        // allow-all admits every simulated request, as it admits every check
+        limiter.set_allow_all(true);
+        assert!(limiter.simulate(&"client", 0));
+        assert!(limiter.peek(&"client").is_allowed());
     }
 
     #[test]
-    fn burst_allowance_works() {
+    fn concurrent_admissions_never_exceed_burst() {
+        use std::sync::Barrier;
+        use std::sync::atomic::AtomicUsize;
+        use std::thread;
+
+        const THREADS: usize = 8;
+        const ATTEMPTS: usize = 500;
+
+        for burst in [0u32, 1, 5, 50] {
+            // time never moves, so exactly burst + 1 requests can ever conform
+            let clock = TestClock::new(0.0);
+            let limiter = RateLimiter::new(1.0, burst as f64, clock).unwrap();
+            let admitted = AtomicUsize::new(0);
+            let barrier = Barrier::new(THREADS);
+
+            thread::scope(|scope| {
+                for thread_id in 0..THREADS {
+                    let (limiter, admitted, barrier) = (&limiter, &admitted, &barrier);
+                    scope.spawn(move || {
+                        barrier.wait();
+                        for attempt in 0..ATTEMPTS {
+                            // interleave traffic on other keys to mix shard contention
+                            if attempt % 4 == 0 {
+                                let other = format!("other-{}-{}", thread_id, attempt);
+                                assert!(limiter.is_allowed(other).unwrap());
+                            }
+                            if limiter.is_allowed(String::from("hot")).unwrap() {
+                                admitted.fetch_add(1, Ordering::Relaxed);
+                            }
+                        }
+                    });
+                }
+            });
+
+            assert_eq!(admitted.load(Ordering::Relaxed), burst as usize + 1);
+        }
+    }
+
+    #[test]
+    fn concurrent_weighted_and_handle_charges_never_exceed_burst() {
+        use std::sync::Barrier;
+        use std::sync::atomic::AtomicU32;
+        use std::thread;
+
+        const THREADS: usize = 8;
+        const ATTEMPTS: usize = 300;
+
+        // every entry point takes the per-key entry lock for its whole
+        // read-modify-write, so mixing them cannot admit more than 12 units
+        let limiter = RateLimiter::new(1.0, 11.0, TestClock::new(0.0)).unwrap();
+        let units = AtomicU32::new(0);
+        let barrier = Barrier::new(THREADS);
+
+        thread::scope(|scope| {
+            for thread_id in 0..THREADS {
+                let (limiter, units, barrier) = (&limiter, &units, &barrier);
+                scope.spawn(move || {
+                    let handle = limiter.handle("hot");
+                    barrier.wait();
+                    for attempt in 0..ATTEMPTS {
+                        let cost = match (thread_id + attempt) % 3 {
+                            0 => handle.charge(3).is_ok().then_some(3),
+                            1 => limiter.is_allowed_with_cost("hot", 2).unwrap().then_some(2),
+                            _ => limiter.check("hot").is_allowed().then_some(1),
+                        };
+                        units.fetch_add(cost.unwrap_or(0), Ordering::Relaxed);
+                    }
+                });
+            }
+        });
+
+        // single-unit checks fill whatever the larger charges leave over
+        assert_eq!(units.load(Ordering::Relaxed), 12);
+    }
+
+    #[test]
+    fn rounding_modes_set_emission_interval() {
         let clock = TestClock::new(0.0);
-        let limiter = RateLimiter::new(1.0, 3.0, clock.clone()).unwrap(); // 1 req/sec, burst of 3
-        let client = "client1";
+        let interval = |rate: f64, rounding: Rounding| {
+            RateLimiter::<String, _>::with_rounding(rate, 0.0, rounding, clock.clone())
+                .unwrap()
+                .increment_nanos()
+        };
+
+        assert_eq!(interval(3.0, Rounding::Floor), 333_333_333);
+        assert_eq!(interval(3.0, Rounding::Ceil), 333_333_334);
+        assert_eq!(interval(3.0, Rounding::Nearest), 333_333_333);
+        assert_eq!(interval(1.5, Rounding::Floor), 666_666_666);
+        assert_eq!(interval(1.5, Rounding::Nearest), 666_666_667);
+
+        // the default constructor keeps truncating
+        let limiter = RateLimiter::<String, _>::new(3.0, 0.0, clock.clone()).unwrap();
+        assert_eq!(limiter.increment_nanos(), 333_333_333);
+    }
 
-        // First 4 requests should all be allowed (burst capacity)
-        assert!(limiter.is_allowed(client).unwrap());
-        assert!(limiter.is_allowed(client).unwrap());
-        assert!(limiter.is_allowed(client).unwrap());
-        assert!(limiter.is_allowed(client).unwrap());
+    #[test]
+    fn long_run_admissions_match_configured_rate() {
+        let horizon_secs = 3_600.0;
+
+        for rate in [3.0, 7.0, 1.5] {
+            // a perfectly paced limiter admits one request at t=0 plus one per interval
+            let exact = (horizon_secs * rate) as i64 + 1;
+
+            for rounding in [Rounding::Floor, Rounding::Ceil, Rounding::Nearest] {
+                let clock = TestClock::new(0.0);
+                let limiter =
+                    RateLimiter::with_rounding(rate, 0.0, rounding, clock.clone()).unwrap();
+                let horizon_nanos = (horizon_secs * 1_000_000_000.0) as u64;
+
+                // a greedy client retries the moment its TAT is reached
+                let mut admitted = 0i64;
+                while clock.time.load(Ordering::Relaxed) <= horizon_nanos {
+                    assert!(limiter.is_allowed("client1").unwrap());
+                    admitted += 1;
+                    let tat = *limiter.client_state.get("client1").unwrap();
+                    clock.time.store(tat, Ordering::Relaxed);
+                }
+
+                assert!(
+                    (admitted - exact).abs() <= 1,
+                    "rate {} with {:?} admitted {} (expected {})",
+                    rate,
+                    rounding,
+                    admitted,
+                    exact
+                );
+                if rounding == Rounding::Ceil {
+                    assert!(admitted <= exact);
+                }
+            }
+        }
+    }
 
-        // 5th request at same time should be blocked (burst exhausted)
-        assert!(!limiter.is_allowed(client).unwrap());
+    #[test]
+    fn simultaneous_first_requests_initialize_key_once() {
+        use std::sync::Barrier;
+        use std::sync::atomic::AtomicUsize;
+        use std::thread;
 
-        // After 1 second, 1 more request should be allowed
-        clock.set_time(1.0);
-        assert!(limiter.is_allowed(client).unwrap());
+        const THREADS: usize = 8;
+        const ROUNDS: usize = 200;
 
-        // But immediate follow-up should be blocked
-        assert!(!limiter.is_allowed(client).unwrap());
+        let clock = TestClock::new(0.0);
+        let limiter = RateLimiter::new(1.0, 0.0, clock).unwrap(); // no burst
+        let barrier = Barrier::new(THREADS);
+        let admitted: Vec<AtomicUsize> = (0..ROUNDS).map(|_| AtomicUsize::new(0)).collect();
+
+        thread::scope(|scope| {
+            for _ in 0..THREADS {
+                let (limiter, barrier, admitted) = (&limiter, &barrier, &admitted);
+                scope.spawn(move || {
+                    for (round, count) in admitted.iter().enumerate() {
+                        // every thread fires the first request for a brand new key together
+                        barrier.wait();
+                        if limiter.is_allowed(round).unwrap() {
+                            count.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                });
+            }
+        });
+
+        for count in &admitted {
+            assert_eq!(count.load(Ordering::Relaxed), 1);
+        }
     }
 
     #[test]
-    fn multiple_clients_independent() {
+    fn overrides_expire_automatically() {
         let clock = TestClock::new(0.0);
         let limiter = RateLimiter::new(1.0, 0.0, clock.clone()).unwrap(); // 1 req/sec, no burst
 
-        // Both clients' first requests should be allowed
+        // double quota (with a burst of one) for client1 for ten seconds
+        limiter
+            .set_override_for("client1", 2.0, 1.0, Duration::from_secs(10))
+            .unwrap();
+        assert!(limiter.is_allowed("client1").unwrap());
         assert!(limiter.is_allowed("client1").unwrap());
-        assert!(limiter.is_allowed("client2").unwrap());
-
-        // Both clients' immediate second requests should be blocked
         assert!(!limiter.is_allowed("client1").unwrap());
-        assert!(!limiter.is_allowed("client2").unwrap());
 
-        // After 1 second, both should be allowed again
-        clock.set_time(1.0);
-        assert!(limiter.is_allowed("client1").unwrap());
+        // other clients keep the default
         assert!(limiter.is_allowed("client2").unwrap());
+        assert!(!limiter.is_allowed("client2").unwrap());
 
-        // Client1 exhausts their allowance, but client2 should still work
+        clock.set_time(4.0);
+        let active = limiter.active_overrides();
+        assert_eq!(active.len(), 1);
+        assert_eq!(active[0].client_id, "client1");
+        assert_eq!(active[0].rate, 2.0);
+        assert_eq!(active[0].remaining, Some(Duration::from_secs(6)));
+
+        // after expiry the default quota applies again
+        clock.set_time(20.0);
+        assert!(limiter.active_overrides().is_empty());
+        assert!(limiter.is_allowed("client1").unwrap());
         assert!(!limiter.is_allowed("client1").unwrap());
+    }
 
-        // Client3 (new client) should be allowed even though others are blocked
-        assert!(limiter.is_allowed("client3").unwrap());
+    #[test]
+    fn permanent_overrides_last_until_removed() {
+        let clock = TestClock::new(0.0);
+        let limiter = RateLimiter::new(1.0, 0.0, clock.clone()).unwrap();
+        limiter.set_override("premium", 10.0, 4.0).unwrap();
+
+        (0..5).for_each(|_| assert!(limiter.is_allowed("premium").unwrap()));
+        assert!(!limiter.is_allowed("premium").unwrap());
+        assert!(limiter.is_allowed("basic").unwrap());
+        assert!(!limiter.is_allowed("basic").unwrap());
+
+        clock.advance(1_000_000.0);
+        let active = limiter.active_overrides();
+        assert_eq!(active.len(), 1);
+        assert_eq!(active[0].remaining, None);
+        (0..5).for_each(|_| assert!(limiter.is_allowed("premium").unwrap()));
+
+        limiter.remove_override(&"premium");
+        assert!(limiter.active_overrides().is_empty());
+        assert!(limiter.set_override("premium", 0.0, 1.0).is_err());
     }
 
     #[test]
-    fn time_progression_allows_requests() {
+    fn bans_expire_automatically() {
         let clock = TestClock::new(0.0);
-        let limiter = RateLimiter::new(2.0, 0.0, clock.clone()).unwrap(); // 2 req/sec, no burst
-        let client = "client1";
+        let limiter = RateLimiter::new(10.0, 5.0, clock.clone()).unwrap();
+
+        limiter.ban_for("client1", Duration::from_secs(60));
+        let denied = limiter.try_begin("client1", 1).err().unwrap();
+        assert_eq!(denied.retry_after(), Some(Duration::from_secs(60)));
+        assert!(!limiter.simulate(&"client1", 0));
+
+        clock.set_time(30.0);
+        assert_eq!(
+            limiter.active_bans(),
+            vec![("client1", Duration::from_secs(30))]
+        );
+        assert!(!limiter.is_allowed("client1").unwrap());
 
-        // First request at t=0 should be allowed
-        assert!(limiter.is_allowed(client).unwrap());
+        clock.set_time(60.0);
+        assert!(limiter.active_bans().is_empty());
+        assert!(limiter.is_allowed("client1").unwrap());
 
-        // Immediate second request should be blocked
-        assert!(!limiter.is_allowed(client).unwrap());
+        // bans can also be lifted early
+        limiter.ban_for("client2", Duration::from_secs(60));
+        limiter.unban(&"client2");
+        assert!(limiter.is_allowed("client2").unwrap());
+    }
 
-        // Request at 0.25 seconds should still be blocked (need 0.5s interval for 2 req/sec)
-        clock.set_time(0.25);
-        assert!(!limiter.is_allowed(client).unwrap());
+    #[test]
+    fn check_n_admits_what_fits_and_charges_exactly_that() {
+        let clock = TestClock::new(0.0);
+        let limiter = RateLimiter::new(1.0, 4.0, clock.clone()).unwrap();
 
-        // Request at exactly 0.5 seconds should be allowed
-        clock.set_time(0.5);
-        assert!(limiter.is_allowed(client).unwrap());
+        let batch = limiter.check_n("queue", 3);
+        assert_eq!((batch.requested(), batch.admitted()), (3, 3));
+        assert!(batch.is_complete());
+        assert_eq!(batch.remaining_burst(), 2);
 
-        // Immediate follow-up should be blocked again
-        assert!(!limiter.is_allowed(client).unwrap());
+        // only two of the next ten fit; the rest wait a second for the next
+        let batch = limiter.check_n("queue", 10);
+        assert_eq!((batch.admitted(), batch.denied()), (2, 8));
+        assert_eq!(batch.retry_after(), Some(Duration::from_secs(1)));
+        assert_eq!(limiter.check_n("queue", 5).admitted(), 0);
 
-        // Another 0.5 seconds later (t=1.0) should be allowed
-        clock.set_time(1.0);
-        assert!(limiter.is_allowed(client).unwrap());
+        clock.advance(2.0);
+        assert_eq!(limiter.check_n("queue", 5).admitted(), 2);
 
-        // Long idle period - request at t=10.0 should definitely be allowed
-        clock.set_time(10.0);
-        assert!(limiter.is_allowed(client).unwrap());
+        limiter.denylist("blocked");
+        assert_eq!(limiter.check_n("blocked", 5).admitted(), 0);
+        assert_eq!(limiter.check_n("other", 0).admitted(), 0);
     }
 
     #[test]
-    fn test_clock_advances_time() {
-        let clock = TestClock::new(5.0);
-        assert_eq!(clock.time_as_f64(), 5.0);
+    fn check_n_handles_empty_and_oversized_batches() {
+        let clock = TestClock::new(0.0);
+        let limiter = RateLimiter::new(1.0, 4.0, clock.clone()).unwrap();
+
+        // an empty batch charges nothing and creates no state
+        let batch = limiter.check_n("queue", 0);
+        assert_eq!((batch.requested(), batch.admitted()), (0, 0));
+        assert!(batch.is_complete());
+        assert_eq!(
+            batch.remaining_burst(),
+            limiter.peek(&"queue").remaining_burst()
+        );
+        assert_eq!(batch.retry_after(), None);
+        assert!(limiter.is_empty());
+
+        // a batch past the burst takes the burst and waits for the rest
+        let batch = limiter.check_n("queue", 9);
+        assert_eq!((batch.admitted(), batch.denied()), (5, 4));
+        assert_eq!(batch.remaining_burst(), 0);
+        assert_eq!(batch.retry_after(), Some(Duration::from_secs(1)));
+        let batch = limiter.check_n("queue", 0);
+        assert_eq!(batch.remaining_burst(), 0);
+        assert_eq!(batch.retry_after(), Some(Duration::from_secs(1)));
+    }
 
-        clock.advance(2.5);
-        assert_eq!(clock.time_as_f64(), 7.5);
+    #[test]
+    fn next_available_plans_slots_without_charging() {
+        let clock = TestClock::new(0.0);
+        let limiter = RateLimiter::new(2.0, 1.0, clock.clone()).unwrap();
+        const SECOND: u64 = 1_000_000_000;
+
+        assert_eq!(limiter.next_available(&"mailer"), 0);
+        assert_eq!(
+            limiter
+                .next_available_slots(&"mailer", 4)
+                .collect::<Vec<_>>(),
+            [0, 0, SECOND / 2, SECOND]
+        );
+        assert!(limiter.is_empty());
+
+        assert!(limiter.is_allowed_with_cost("mailer", 2).unwrap());
+        assert_eq!(limiter.next_available(&"mailer"), SECOND / 2);
+        clock.set_time(0.5);
+        assert!(limiter.is_allowed("mailer").unwrap());
+
+        // bans and minimum spacing push the slots back too
+        limiter.ban_for("banned", Duration::from_secs(10));
+        assert_eq!(limiter.next_available(&"banned"), 10 * SECOND + SECOND / 2);
+        let spaced = RateLimiter::new(100.0, 10.0, TestClock::new(0.0))
+            .unwrap()
+            .with_min_interval(Duration::from_millis(300));
+        assert!(spaced.is_allowed("sms").unwrap());
+        assert_eq!(
+            spaced.next_available_slots(&"sms", 2).collect::<Vec<_>>(),
+            [3 * SECOND / 10, 6 * SECOND / 10]
+        );
+
+        limiter.denylist("spam");
+        assert_eq!(
+            limiter.next_available_slots(&"spam", 2).collect::<Vec<_>>(),
+            [u64::MAX; 2]
+        );
+        // a plan far longer than anyone reads costs only the slots taken
+        let mut plan = limiter.next_available_slots(&"mailer", usize::MAX);
+        assert_eq!(plan.size_hint().1, Some(usize::MAX));
+        let planned = limiter.next_available_slots(&"mailer", 3).last();
+        assert_eq!(plan.nth(2), planned);
+        let windowed = RateLimiter::new(0.5, 0.0, TestClock::new(0.0))
+            .unwrap()
+            .with_algorithm(crate::FixedWindow::new(Duration::from_secs(1)));
+        assert_eq!(windowed.next_available(&"any"), u64::MAX);
+    }
 
-        clock.set_time(0.0);
-        assert_eq!(clock.time_as_f64(), 0.0);
+    #[test]
+    fn refunds_hand_back_charges_but_no_more() {
+        let clock = TestClock::new(0.0);
+        let limiter = RateLimiter::new(1.0, 1.0, clock.clone()).unwrap();
+        assert!(limiter.is_allowed_with_cost("client", 2).unwrap());
+        assert!(!limiter.is_allowed("client").unwrap());
+
+        // the failed request gives its unit back
+        limiter.refund(&"client", 1);
+        assert!(limiter.is_allowed("client").unwrap());
+
+        // refunding more than was charged leaves just the full burst
+        limiter.refund(&"client", 10);
+        assert_eq!(limiter.peek(&"client").remaining_burst(), 1);
+        assert!(limiter.is_allowed_with_cost("client", 2).unwrap());
+        assert!(!limiter.is_allowed("client").unwrap());
+
+        limiter.refund(&"unseen", 1);
+        assert!(!limiter.client_state.contains_key("unseen"));
     }
 
     #[test]
-    fn accessor_methods_work() {
+    fn penalties_push_the_tat_forward() {
         let clock = TestClock::new(0.0);
-        let limiter = RateLimiter::<String, _>::new(10.0, 5.0, clock).unwrap();
+        let limiter = RateLimiter::new(1.0, 1.0, clock.clone()).unwrap();
+
+        // an unseen key is penalized from now, on top of its burst
+        limiter.penalize("bad-login", Duration::from_secs(10));
+        let denied = limiter.check("bad-login");
+        assert!(!denied.is_allowed());
+        assert_eq!(denied.retry_after(), Some(Duration::from_secs(9)));
+
+        // a penalty adds to what the key already consumed
+        assert!(limiter.is_allowed("client").unwrap());
+        limiter.penalize("client", Duration::from_secs(5));
+        assert_eq!(
+            limiter.check("client").retry_after(),
+            Some(Duration::from_secs(5))
+        );
+        clock.advance(5.0);
+        assert!(limiter.is_allowed("client").unwrap());
+    }
 
-        // Test that accessors return the original user-provided values
-        assert_eq!(limiter.rate(), 10.0);
-        assert_eq!(limiter.burst(), 5.0);
+    #[test]
+    fn penalties_follow_the_algorithm_and_skip_listed_keys() {
+        let clock = TestClock::new(0.0);
+        let limiter = RateLimiter::new(1.0, 4.0, clock.clone())
+            .unwrap()
+            .with_algorithm(crate::SlidingWindow::new(Duration::from_secs(10)));
+
+        // three seconds are worth three of the window's ten requests
+        limiter.penalize("client", Duration::from_secs(3));
+        for _ in 0..7 {
+            assert!(limiter.is_allowed("client").unwrap());
+        }
+        let denied = limiter.check("client");
+        assert!(!denied.is_allowed());
+        assert_eq!(denied.retry_after(), Some(Duration::from_secs(11)));
+
+        limiter.allowlist("health");
+        limiter.penalize("health", Duration::from_secs(60));
+        limiter.set_allow_all(true);
+        limiter.penalize("other", Duration::from_secs(60));
+        assert!(!limiter.client_state.contains_key("health"));
+        assert!(!limiter.client_state.contains_key("other"));
+    }
+
+    #[test]
+    fn listed_keys_skip_bans_and_quotas() {
+        let clock = TestClock::new(0.0);
+        let limiter = RateLimiter::new(1.0, 0.0, clock.clone()).unwrap();
+
+        limiter.allowlist("health");
+        limiter.ban_for("health", Duration::from_secs(60));
+        for _ in 0..10 {
+            assert!(limiter.is_allowed("health").unwrap());
+        }
+        assert!(limiter.handle("health").is_allowed());
+        assert!(limiter.peek(&"health").is_allowed());
+        assert!(limiter.is_empty());
+
+        limiter.denylist("scraper");
+        let denied = limiter.check("scraper");
+        assert!(!denied.is_allowed());
+        assert_eq!(denied.retry_after(), None);
+        assert!(!limiter.is_allowed_ref(&"scraper").unwrap());
+        assert!(!limiter.simulate(&"scraper", 0));
+
+        // listing a key on one list takes it off the other
+        limiter.allowlist("scraper");
+        assert_eq!(limiter.listing(&"scraper"), Some(Listing::Allow));
+        assert!(limiter.is_allowed("scraper").unwrap());
+        let mut listed = limiter.listed();
+        listed.sort();
+        assert_eq!(
+            listed,
+            vec![("health", Listing::Allow), ("scraper", Listing::Allow)]
+        );
+
+        // unlisted keys are limited again, bans included
+        limiter.unlist(&"health");
+        assert_eq!(limiter.listing(&"health"), None);
+        assert!(!limiter.is_allowed("health").unwrap());
     }
 
     #[test]
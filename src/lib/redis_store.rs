@@ -0,0 +1,253 @@
+// src/lib/redis_store.rs
+
+// dependencies
+use crate::persistence::StoreKey;
+use crate::store::{GcraCheck, StateStore, StoreOutcome};
+use redis::{Client, Connection, ErrorKind, RedisError, Script};
+use std::marker::PhantomData;
+use std::sync::Mutex;
+
+// the GCRA check, run atomically by Redis
+// Redis Lua numbers are doubles, which cannot hold nanosecond timestamps
+// exactly, so TATs are stored as "seconds:nanoseconds" and the script only
+// does arithmetic on the distance between the TAT and now, which is small.
+// ARGV: now seconds, now nanoseconds, increment, tolerance, cost. Returns
+// {allowed, nanoseconds the TAT lies ahead of now afterwards}. Keys expire
+// once their TAT has passed, since such keys are indistinguishable from new
+const CHECK_SCRIPT: &str = r#"
+local now_s, now_ns = tonumber(ARGV[1]), tonumber(ARGV[2])
+local increment, tolerance = tonumber(ARGV[3]), tonumber(ARGV[4])
+local cost = tonumber(ARGV[5])
+local ahead = 0
+local stored = redis.call('GET', KEYS[1])
+if stored then
+    local sep = string.find(stored, ':', 1, true)
+    local tat_s = tonumber(string.sub(stored, 1, sep - 1))
+    local tat_ns = tonumber(string.sub(stored, sep + 1))
+    ahead = math.max(0, (tat_s - now_s) * 1e9 + (tat_ns - now_ns))
+end
+local new_ahead = ahead + increment * cost
+if new_ahead > increment + tolerance then
+    return {0, ahead}
+end
+local total_ns = now_ns + new_ahead
+local tat = string.format('%.0f:%.0f', now_s + math.floor(total_ns / 1e9), total_ns % 1e9)
+redis.call('SET', KEYS[1], tat, 'PX', math.ceil(new_ahead / 1e6) + 1)
+return {1, new_ahead}
+"#;
+
+// hands back `increment * cost`, never moving the TAT behind now
+// ARGV as for CHECK_SCRIPT
+const REFUND_SCRIPT: &str = r#"
+local now_s, now_ns = tonumber(ARGV[1]), tonumber(ARGV[2])
+local charged = tonumber(ARGV[3]) * tonumber(ARGV[5])
+local stored = redis.call('GET', KEYS[1])
+if not stored then
+    return 0
+end
+local sep = string.find(stored, ':', 1, true)
+local tat_s = tonumber(string.sub(stored, 1, sep - 1))
+local tat_ns = tonumber(string.sub(stored, sep + 1))
+local ahead = (tat_s - now_s) * 1e9 + (tat_ns - now_ns) - charged
+if ahead <= 0 then
+    redis.call('DEL', KEYS[1])
+    return 0
+end
+local total_ns = now_ns + ahead
+local tat = string.format('%.0f:%.0f', now_s + math.floor(total_ns / 1e9), total_ns % 1e9)
+redis.call('SET', KEYS[1], tat, 'PX', math.ceil(ahead / 1e6) + 1)
+return 1
+"#;
+
+// struct type to represent a state store shared by every instance of a
+// service through one Redis server, so each client has a single limit
+// across instances; checks run as a Lua script, so each is one atomic round
+// trip, and batches are pipelined. The connection is shared behind a lock,
+// so threads that check concurrently should each open their own store
+pub struct RedisStore<K> {
+    connection: Mutex<Connection>,
+    prefix: Vec<u8>,
+    check: Script,
+    refund: Script,
+    keys: PhantomData<fn(&K)>,
+}
+
+// methods for the RedisStore struct
+impl<K> RedisStore<K>
+where
+    K: StoreKey,
+{
+    // method to connect to `url` (e.g. "redis://127.0.0.1/"), storing each
+    // key's TAT under `prefix` followed by the key's bytes
+    pub fn open(url: &str, prefix: &str) -> Result<Self, RedisError> {
+        let connection = Client::open(url)?.get_connection()?;
+        Ok(Self {
+            connection: Mutex::new(connection),
+            prefix: prefix.as_bytes().to_vec(),
+            check: Script::new(CHECK_SCRIPT),
+            refund: Script::new(REFUND_SCRIPT),
+            keys: PhantomData,
+        })
+    }
+
+    // internal method to build the Redis key for a limiter key
+    fn redis_key(&self, key: &K) -> Vec<u8> {
+        let mut redis_key = self.prefix.clone();
+        redis_key.extend(key.to_bytes());
+        redis_key
+    }
+}
+
+// split a time in nanoseconds into the seconds and nanoseconds the scripts
+// take, each small enough for a Lua double
+fn split_nanos(nanos: u64) -> (u64, u64) {
+    (nanos / 1_000_000_000, nanos % 1_000_000_000)
+}
+
+// parse a TAT stored as "seconds:nanoseconds"
+fn parse_tat(stored: &str) -> Option<u64> {
+    let (secs, nanos) = stored.split_once(':')?;
+    let secs: u64 = secs.parse().ok()?;
+    let nanos: u64 = nanos.parse().ok()?;
+    secs.checked_mul(1_000_000_000)?.checked_add(nanos)
+}
+
+// turn a script's reply into the outcome of a check
+fn outcome(check: &GcraCheck, (allowed, ahead): (u8, u64)) -> StoreOutcome {
+    StoreOutcome {
+        allowed: allowed == 1,
+        tat: check.now.saturating_add(ahead),
+    }
+}
+
+impl<K> StateStore<K> for RedisStore<K>
+where
+    K: StoreKey,
+{
+    type Error = RedisError;
+
+    fn check_and_update(&self, key: &K, check: GcraCheck) -> Result<StoreOutcome, Self::Error> {
+        let (now_s, now_ns) = split_nanos(check.now);
+        let reply = self
+            .check
+            .key(self.redis_key(key))
+            .arg(now_s)
+            .arg(now_ns)
+            .arg(check.increment)
+            .arg(check.tolerance)
+            .arg(check.cost)
+            .invoke(&mut *self.connection.lock().unwrap())?;
+        Ok(outcome(&check, reply))
+    }
+
+    // the checks are sent in one pipeline of EVALSHA calls, loading the
+    // script first if the server does not know it (e.g. after a restart)
+    fn check_and_update_many(
+        &self,
+        checks: &[(K, GcraCheck)],
+    ) -> Result<Vec<StoreOutcome>, Self::Error> {
+        let mut pipeline = redis::pipe();
+        for (key, check) in checks {
+            let (now_s, now_ns) = split_nanos(check.now);
+            pipeline
+                .cmd("EVALSHA")
+                .arg(self.check.get_hash())
+                .arg(1)
+                .arg(self.redis_key(key))
+                .arg(now_s)
+                .arg(now_ns)
+                .arg(check.increment)
+                .arg(check.tolerance)
+                .arg(check.cost);
+        }
+        let mut connection = self.connection.lock().unwrap();
+        let replies: Vec<(u8, u64)> = match pipeline.query(&mut *connection) {
+            Err(e) if e.kind() == ErrorKind::NoScriptError => {
+                self.check.prepare_invoke().load(&mut *connection)?;
+                pipeline.query(&mut *connection)?
+            }
+            replies => replies?,
+        };
+        Ok(checks
+            .iter()
+            .zip(replies)
+            .map(|((_, check), reply)| outcome(check, reply))
+            .collect())
+    }
+
+    fn refund(&self, key: &K, check: GcraCheck) -> Result<(), Self::Error> {
+        let (now_s, now_ns) = split_nanos(check.now);
+        self.refund
+            .key(self.redis_key(key))
+            .arg(now_s)
+            .arg(now_ns)
+            .arg(check.increment)
+            .arg(check.tolerance)
+            .arg(check.cost)
+            .invoke::<u8>(&mut *self.connection.lock().unwrap())?;
+        Ok(())
+    }
+
+    fn get(&self, key: &K) -> Result<Option<u64>, Self::Error> {
+        let stored: Option<String> = redis::cmd("GET")
+            .arg(self.redis_key(key))
+            .query(&mut *self.connection.lock().unwrap())?;
+        Ok(stored.as_deref().and_then(parse_tat))
+    }
+
+    fn remove(&self, key: &K) -> Result<(), Self::Error> {
+        redis::cmd("DEL")
+            .arg(self.redis_key(key))
+            .query::<()>(&mut *self.connection.lock().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tats_round_trip_through_the_stored_format() {
+        let now = 1_700_000_000_123_456_789;
+        assert_eq!(split_nanos(now), (1_700_000_000, 123_456_789));
+        assert_eq!(parse_tat("1700000000:123456789"), Some(now));
+        assert_eq!(parse_tat("17"), None);
+        assert_eq!(parse_tat("x:1"), None);
+        assert_eq!(parse_tat("18446744073709551615:0"), None);
+    }
+
+    // runs against a live server when GCRA_REDIS_URL is set, e.g.
+    // GCRA_REDIS_URL=redis://127.0.0.1/ cargo test --features redis
+    #[test]
+    fn checks_run_atomically_on_a_live_server() {
+        let Ok(url) = std::env::var("GCRA_REDIS_URL") else {
+            return;
+        };
+        let store = RedisStore::<String>::open(&url, "gcra-test:").unwrap();
+        let key = String::from("client");
+        store.remove(&key).unwrap();
+        let check = |now| GcraCheck {
+            now,
+            increment: 1_000_000_000,
+            tolerance: 1_000_000_000,
+            cost: 1,
+        };
+        let now = 1_700_000_000_000_000_000;
+
+        assert!(store.check_and_update(&key, check(now)).unwrap().allowed);
+        assert!(store.check_and_update(&key, check(now)).unwrap().allowed);
+        let denied = store.check_and_update(&key, check(now)).unwrap();
+        assert_eq!(denied, check(now).apply(Some(now + 2_000_000_000)));
+        assert_eq!(store.get(&key).unwrap(), Some(now + 2_000_000_000));
+
+        store.refund(&key, check(now)).unwrap();
+        let batch = [(key.clone(), check(now)), (key.clone(), check(now))];
+        let outcomes = store.check_and_update_many(&batch).unwrap();
+        assert_eq!(
+            outcomes.iter().map(|o| o.allowed).collect::<Vec<_>>(),
+            [true, false]
+        );
+        store.remove(&key).unwrap();
+        assert_eq!(store.get(&key).unwrap(), None);
+    }
+}
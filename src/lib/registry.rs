@@ -0,0 +1,391 @@
+// src/lib/registry.rs
+
+// dependencies
+use crate::SystemClock;
+use crate::clock::Clock;
+use crate::rate_limiter::{RateLimiter, RateLimiterError};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+// enum type to represent errors from the registry
+#[derive(Debug)]
+pub enum RegistryError {
+    UnknownNamespace(String),
+    Limiter(RateLimiterError),
+}
+
+// implement the Display trait for the RegistryError type
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RegistryError::UnknownNamespace(name) => write!(f, "unknown namespace: {}", name),
+            RegistryError::Limiter(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+// implement the Error trait for the RegistryError type
+impl Error for RegistryError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RegistryError::Limiter(e) => Some(e),
+            RegistryError::UnknownNamespace(_) => None,
+        }
+    }
+}
+
+// implement the From trait to convert limiter errors into registry errors
+impl From<RateLimiterError> for RegistryError {
+    fn from(e: RateLimiterError) -> Self {
+        RegistryError::Limiter(e)
+    }
+}
+
+// struct type to represent how often and how hard a namespace is cleaned up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GcSettings {
+    pub interval: Duration, // minimum time between passes
+    pub max_entries: usize, // keys examined per pass; passes resume the scan
+}
+
+// implement the Default trait for the GcSettings type
+impl Default for GcSettings {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(60),
+            max_entries: 10_000,
+        }
+    }
+}
+
+// struct type to represent a snapshot of one namespace's counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NamespaceStats {
+    pub keys: usize,
+    pub allowed: u64,
+    pub denied: u64,
+    pub evicted: u64,
+}
+
+// struct type to represent the keys one GC pass evicted from a namespace
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpiryBatch<T> {
+    pub namespace: String,
+    pub keys: Vec<T>,
+}
+
+// struct type to represent one tenant: its own limiter (and so its own map
+// and locks), counters and GC schedule
+#[derive(Debug)]
+struct Namespace<T, C>
+where
+    T: Hash + Eq + Clone,
+    C: Clock,
+{
+    limiter: RateLimiter<T, C>,
+    gc: RwLock<GcSettings>,
+    last_gc: AtomicU64,
+    allowed: AtomicU64,
+    denied: AtomicU64,
+    evicted: AtomicU64,
+    listeners: Mutex<Vec<Sender<ExpiryBatch<T>>>>,
+}
+
+// struct type to represent a set of named, isolated limiters
+// the registry lock is only held to look a namespace up; checks, GC passes,
+// stats and resets then run against that namespace alone, so one tenant's
+// large cleanup never blocks another tenant's requests
+#[derive(Debug)]
+pub struct Registry<T, C = SystemClock>
+where
+    T: Hash + Eq + Clone,
+    C: Clock,
+{
+    namespaces: RwLock<HashMap<String, Arc<Namespace<T, C>>>>,
+    clock: C,
+    gc_passes: AtomicUsize,
+}
+
+// methods for the Registry struct
+impl<T, C> Registry<T, C>
+where
+    T: Hash + Eq + Clone,
+    C: Clock + Clone,
+{
+    pub fn new(clock: C) -> Self {
+        Self {
+            namespaces: RwLock::new(HashMap::new()),
+            clock,
+            gc_passes: AtomicUsize::new(0),
+        }
+    }
+
+    // method to add or replace a namespace with its own quota and GC settings
+    pub fn register(
+        &self,
+        name: &str,
+        rate_per_second: f64,
+        burst_capacity: f64,
+        gc: GcSettings,
+    ) -> Result<(), RegistryError> {
+        let namespace = Namespace {
+            limiter: RateLimiter::new(rate_per_second, burst_capacity, self.clock.clone())?,
+            gc: RwLock::new(gc),
+            last_gc: AtomicU64::new(self.clock.now()),
+            allowed: AtomicU64::new(0),
+            denied: AtomicU64::new(0),
+            evicted: AtomicU64::new(0),
+            listeners: Mutex::new(Vec::new()),
+        };
+        self.namespaces
+            .write()
+            .unwrap()
+            .insert(name.to_string(), Arc::new(namespace));
+        Ok(())
+    }
+
+    // method to drop a namespace and all of its state
+    pub fn unregister(&self, name: &str) -> bool {
+        self.namespaces.write().unwrap().remove(name).is_some()
+    }
+
+    // internal method to look a namespace up without holding the registry lock
+    fn namespace(&self, name: &str) -> Result<Arc<Namespace<T, C>>, RegistryError> {
+        self.namespaces
+            .read()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| RegistryError::UnknownNamespace(name.to_string()))
+    }
+
+    // method to check a key against one namespace's limiter
+    pub fn is_allowed(&self, name: &str, client_id: T) -> Result<bool, RegistryError> {
+        let namespace = self.namespace(name)?;
+        let allowed = namespace.limiter.is_allowed(client_id)?;
+        let counter = if allowed {
+            &namespace.allowed
+        } else {
+            &namespace.denied
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        Ok(allowed)
+    }
+
+    // method to change how a namespace is garbage collected
+    pub fn set_gc(&self, name: &str, gc: GcSettings) -> Result<(), RegistryError> {
+        *self.namespace(name)?.gc.write().unwrap() = gc;
+        Ok(())
+    }
+
+    // method to run one GC pass over a namespace right away, within its budget
+    pub fn gc_namespace(&self, name: &str) -> Result<usize, RegistryError> {
+        let namespace = self.namespace(name)?;
+        Ok(self.run_gc(name, &namespace))
+    }
+
+    // method to run a GC pass over every namespace whose interval has elapsed
+    // returns the total number of keys evicted
+    pub fn gc_due(&self) -> usize {
+        let now = self.clock.now();
+        let due: Vec<_> = self
+            .namespaces
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, namespace)| {
+                let interval = namespace.gc.read().unwrap().interval.as_nanos() as u64;
+                now.saturating_sub(namespace.last_gc.load(Ordering::Relaxed)) >= interval
+            })
+            .map(|(name, namespace)| (name.clone(), Arc::clone(namespace)))
+            .collect();
+
+        due.iter()
+            .map(|(name, namespace)| self.run_gc(name, namespace))
+            .sum()
+    }
+
+    // method to listen for the keys GC evicts from a namespace, e.g. to
+    // close the sessions or upstream connections held for them
+    // each pass that evicts anything sends one batch to every listener, so
+    // cleanup happens off the GC path; a dropped receiver is forgotten on the
+    // next pass. Replacing the namespace with `register` drops its listeners
+    pub fn expiry_listener(&self, name: &str) -> Result<Receiver<ExpiryBatch<T>>, RegistryError> {
+        let (sender, receiver) = mpsc::channel();
+        self.namespace(name)?.listeners.lock().unwrap().push(sender);
+        Ok(receiver)
+    }
+
+    // internal method to evict idle keys from one namespace
+    fn run_gc(&self, name: &str, namespace: &Namespace<T, C>) -> usize {
+        let max_entries = namespace.gc.read().unwrap().max_entries;
+        let mut listeners = namespace.listeners.lock().unwrap();
+        let mut keys = Vec::new();
+        let evicted = if listeners.is_empty() {
+            namespace.limiter.gc_step(max_entries).evicted
+        } else {
+            let step = namespace
+                .limiter
+                .gc_step_with(max_entries, |key| keys.push(key.clone()));
+            step.evicted
+        };
+        if !keys.is_empty() {
+            let batch = ExpiryBatch {
+                namespace: name.to_string(),
+                keys,
+            };
+            listeners.retain(|listener| listener.send(batch.clone()).is_ok());
+        }
+        drop(listeners);
+        namespace.last_gc.store(self.clock.now(), Ordering::Relaxed);
+        namespace
+            .evicted
+            .fetch_add(evicted as u64, Ordering::Relaxed);
+        self.gc_passes.fetch_add(1, Ordering::Relaxed);
+        evicted
+    }
+
+    // accessor method to return how many GC passes have run across namespaces
+    pub fn gc_passes(&self) -> usize {
+        self.gc_passes.load(Ordering::Relaxed)
+    }
+
+    // method to return one namespace's counters
+    pub fn stats(&self, name: &str) -> Result<NamespaceStats, RegistryError> {
+        let namespace = self.namespace(name)?;
+        Ok(NamespaceStats {
+            keys: namespace.limiter.client_state().len(),
+            allowed: namespace.allowed.load(Ordering::Relaxed),
+            denied: namespace.denied.load(Ordering::Relaxed),
+            evicted: namespace.evicted.load(Ordering::Relaxed),
+        })
+    }
+
+    // method to forget every key and counter in one namespace
+    pub fn reset(&self, name: &str) -> Result<(), RegistryError> {
+        let namespace = self.namespace(name)?;
+        namespace.limiter.client_state().clear();
+        namespace.allowed.store(0, Ordering::Relaxed);
+        namespace.denied.store(0, Ordering::Relaxed);
+        namespace.evicted.store(0, Ordering::Relaxed);
+        Ok(())
+    }
+
+    // method to list the registered namespace names
+    pub fn namespaces(&self) -> Vec<String> {
+        self.namespaces.read().unwrap().keys().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestClock;
+
+    fn registry(clock: &TestClock) -> Registry<u64, TestClock> {
+        let registry = Registry::new(clock.clone());
+        registry
+            .register("a", 1.0, 0.0, GcSettings::default())
+            .unwrap();
+        registry
+            .register(
+                "b",
+                1.0,
+                0.0,
+                GcSettings {
+                    interval: Duration::from_secs(1),
+                    max_entries: 2,
+                },
+            )
+            .unwrap();
+        registry
+    }
+
+    #[test]
+    fn namespaces_are_isolated() {
+        let clock = TestClock::new(0.0);
+        let registry = registry(&clock);
+
+        assert!(registry.is_allowed("a", 1).unwrap());
+        assert!(!registry.is_allowed("a", 1).unwrap());
+        // the same key in another namespace has its own state
+        assert!(registry.is_allowed("b", 1).unwrap());
+
+        registry.reset("a").unwrap();
+        assert_eq!(registry.stats("a").unwrap(), NamespaceStats::default());
+        assert_eq!(registry.stats("b").unwrap().keys, 1);
+        assert_eq!(registry.stats("b").unwrap().allowed, 1);
+
+        assert!(matches!(
+            registry.is_allowed("c", 1),
+            Err(RegistryError::UnknownNamespace(_))
+        ));
+    }
+
+    #[test]
+    fn gc_runs_per_namespace_schedule_and_budget() {
+        let clock = TestClock::new(0.0);
+        let registry = registry(&clock);
+        for key in 0..5 {
+            registry.is_allowed("a", key).unwrap();
+            registry.is_allowed("b", key).unwrap();
+        }
+
+        // only "b" is due after a second, and it stops at its budget
+        clock.advance(1.0);
+        assert_eq!(registry.gc_due(), 2);
+        assert_eq!(registry.stats("a").unwrap().keys, 5);
+        assert_eq!(registry.stats("b").unwrap().keys, 3);
+        assert_eq!(registry.stats("b").unwrap().evicted, 2);
+
+        // "a" can still be cleaned on demand, without touching "b"
+        assert_eq!(registry.gc_namespace("a").unwrap(), 5);
+        assert_eq!(registry.stats("b").unwrap().keys, 3);
+    }
+
+    #[test]
+    fn expiry_listeners_receive_batches_per_namespace() {
+        let clock = TestClock::new(0.0);
+        let registry = registry(&clock);
+        let expired = registry.expiry_listener("b").unwrap();
+        let dropped = registry.expiry_listener("b").unwrap();
+        drop(dropped);
+        assert!(registry.expiry_listener("c").is_err());
+        for key in 0..3 {
+            registry.is_allowed("a", key).unwrap();
+            registry.is_allowed("b", key).unwrap();
+        }
+
+        clock.advance(1.0);
+        registry.gc_namespace("a").unwrap();
+        assert!(expired.try_recv().is_err());
+        registry.gc_due();
+        registry.gc_namespace("b").unwrap();
+        let mut batches = expired.try_iter().collect::<Vec<_>>();
+        assert_eq!(batches.len(), 2);
+        assert!(batches.iter().all(|batch| batch.namespace == "b"));
+        let mut keys: Vec<u64> = batches.drain(..).flat_map(|batch| batch.keys).collect();
+        keys.sort();
+        assert_eq!(keys, [0, 1, 2]);
+        assert_eq!(
+            registry
+                .namespace("b")
+                .unwrap()
+                .listeners
+                .lock()
+                .unwrap()
+                .len(),
+            1
+        );
+
+        // a pass that evicts nothing sends nothing
+        registry.gc_namespace("b").unwrap();
+        assert!(expired.try_recv().is_err());
+    }
+}
@@ -0,0 +1,160 @@
+// src/lib/retry_budget.rs
+
+// dependencies
+use crate::SystemClock;
+use crate::clock::Clock;
+use crate::rate_limiter::{Denied, RateLimiter, RateLimiterError};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// enum type to represent whether an outbound request is a first try or a retry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Attempt {
+    First,
+    Retry,
+}
+
+// struct type to represent the admission counts of an outbound limiter
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AttemptStats {
+    pub first_admitted: u64,
+    pub first_denied: u64,
+    pub retry_admitted: u64,
+    pub retry_denied: u64,
+}
+
+// struct type to represent a client-side pacer for calls to a rate-limited
+// upstream, with a retry budget
+// every attempt is charged to the upstream quota, and retries are also
+// charged to a budget holding `retry_share` of that quota, so an aggressive
+// retry policy can use at most that share and first attempts keep the rest
+#[derive(Debug)]
+pub struct RetryBudget<C = SystemClock>
+where
+    C: Clock,
+{
+    quota: RateLimiter<(), C>,
+    retries: RateLimiter<(), C>,
+    first_admitted: AtomicU64,
+    first_denied: AtomicU64,
+    retry_admitted: AtomicU64,
+    retry_denied: AtomicU64,
+}
+
+// methods for the RetryBudget struct
+impl<C> RetryBudget<C>
+where
+    C: Clock + Clone,
+{
+    // method to create a pacer for `rate_per_second` calls with up to
+    // `burst_capacity` extra at once, of which retries may use `retry_share`
+    // (between 0 and 1)
+    pub fn new(
+        rate_per_second: f64,
+        burst_capacity: f64,
+        retry_share: f64,
+        clock: C,
+    ) -> Result<Self, RateLimiterError> {
+        if !(retry_share > 0.0 && retry_share <= 1.0) {
+            return Err(RateLimiterError::InvalidRate);
+        }
+        Ok(Self {
+            quota: RateLimiter::new(rate_per_second, burst_capacity, clock.clone())?,
+            retries: RateLimiter::new(
+                rate_per_second * retry_share,
+                burst_capacity * retry_share,
+                clock,
+            )?,
+            first_admitted: AtomicU64::new(0),
+            first_denied: AtomicU64::new(0),
+            retry_admitted: AtomicU64::new(0),
+            retry_denied: AtomicU64::new(0),
+        })
+    }
+}
+
+impl<C> RetryBudget<C>
+where
+    C: Clock,
+{
+    // method to admit or deny an outbound attempt
+    // a retry that fits its budget but not the quota gets its budget back
+    pub fn acquire(&self, attempt: Attempt) -> Result<(), Denied> {
+        let result = match attempt {
+            Attempt::First => self.quota.charge((), 1),
+            Attempt::Retry => self.retries.charge((), 1).and_then(|()| {
+                self.quota
+                    .charge((), 1)
+                    .inspect_err(|_| self.retries.refund(&(), 1))
+            }),
+        };
+        let counter = match (attempt, result.is_ok()) {
+            (Attempt::First, true) => &self.first_admitted,
+            (Attempt::First, false) => &self.first_denied,
+            (Attempt::Retry, true) => &self.retry_admitted,
+            (Attempt::Retry, false) => &self.retry_denied,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        result
+    }
+
+    // method to return the admission counts so far
+    pub fn stats(&self) -> AttemptStats {
+        AttemptStats {
+            first_admitted: self.first_admitted.load(Ordering::Relaxed),
+            first_denied: self.first_denied.load(Ordering::Relaxed),
+            retry_admitted: self.retry_admitted.load(Ordering::Relaxed),
+            retry_denied: self.retry_denied.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestClock;
+
+    #[test]
+    fn retries_cannot_take_more_than_their_share() {
+        let clock = TestClock::new(0.0);
+        // 10/s with a burst of 10; retries get a fifth of that
+        let budget = RetryBudget::new(10.0, 10.0, 0.2, clock.clone()).unwrap();
+
+        let retries = (0..20)
+            .filter(|_| budget.acquire(Attempt::Retry).is_ok())
+            .count();
+        assert_eq!(retries, 3);
+        // first attempts still have the rest of the burst
+        let firsts = (0..20)
+            .filter(|_| budget.acquire(Attempt::First).is_ok())
+            .count();
+        assert_eq!(firsts, 8);
+
+        assert_eq!(
+            budget.stats(),
+            AttemptStats {
+                first_admitted: 8,
+                first_denied: 12,
+                retry_admitted: 3,
+                retry_denied: 17,
+            }
+        );
+    }
+
+    #[test]
+    fn retry_denied_by_the_quota_keeps_its_budget() {
+        let clock = TestClock::new(0.0);
+        let budget = RetryBudget::new(1.0, 0.0, 0.5, clock.clone()).unwrap();
+        assert!(budget.acquire(Attempt::First).is_ok());
+        assert!(budget.acquire(Attempt::Retry).is_err());
+
+        clock.advance(1.0);
+        assert!(budget.acquire(Attempt::Retry).is_ok());
+    }
+
+    #[test]
+    fn rejects_share_outside_unit_range() {
+        for share in [0.0, -0.5, 1.5, f64::NAN] {
+            assert!(RetryBudget::new(1.0, 1.0, share, TestClock::new(0.0)).is_err());
+        }
+    }
+}
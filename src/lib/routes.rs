@@ -0,0 +1,609 @@
+// src/lib/routes.rs
+
+// dependencies
+use crate::SystemClock;
+use crate::clock::Clock;
+use crate::config::{ConfigError, FieldError};
+use crate::http::HttpRequest;
+use crate::rate_limiter::{Denied, RateLimiter, RateLimiterError};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// struct type to represent the rate-limit configuration for a set of routes
+// rules are matched in order and the first match wins; `templates` holds
+// named quotas that rules (and other templates) build on
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RouteConfig {
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub templates: BTreeMap<String, QuotaConfig>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub routes: Vec<RouteRule>,
+}
+
+// struct type to represent a single route rule
+// `path` is matched segment by segment: `*` matches any one segment and a
+// trailing `**` matches the rest of the path; a missing method matches any
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RouteRule {
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub method: Option<String>,
+    pub path: String,
+    pub quota: QuotaConfig,
+    #[cfg_attr(feature = "serde", serde(default = "default_cost"))]
+    pub cost: u32,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub key: KeyStrategy,
+}
+
+// struct type to represent the rate and burst for a route or a template
+// values left out are inherited from the named template, whose own missing
+// values come from its template in turn; a burst missing everywhere is 0
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct QuotaConfig {
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub template: Option<String>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub rate: Option<f64>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub burst: Option<f64>,
+}
+
+// methods for the QuotaConfig struct
+impl QuotaConfig {
+    // method to create a quota that names its rate and burst directly
+    pub fn new(rate: f64, burst: f64) -> Self {
+        Self {
+            template: None,
+            rate: Some(rate),
+            burst: Some(burst),
+        }
+    }
+
+    // method to create a quota that takes everything from a template
+    pub fn from_template(name: &str) -> Self {
+        Self {
+            template: Some(name.to_string()),
+            ..Self::default()
+        }
+    }
+}
+
+// enum type to represent how a request is mapped to a rate-limit key
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum KeyStrategy {
+    #[default]
+    PeerIp, // the connected peer's IP address
+    Header(String), // the value of a request header, falling back to the peer IP
+    Global,         // one shared bucket for every client
+}
+
+#[cfg(feature = "serde")]
+fn default_cost() -> u32 {
+    1
+}
+
+// methods for the RouteConfig struct
+impl RouteConfig {
+    // method to parse a configuration from TOML
+    #[cfg(feature = "config")]
+    pub fn from_toml(text: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(text)
+    }
+
+    // method to parse and validate a configuration from TOML, reporting
+    // where the text is malformed or which fields hold unusable values;
+    // templates are resolved here, so every rule of the result carries its
+    // own rate and burst
+    #[cfg(feature = "config")]
+    pub fn load(text: &str) -> Result<Self, ConfigError> {
+        let mut config = Self::from_toml(text).map_err(|e| ConfigError::from_toml(e, text))?;
+        config.validate()?;
+        config.resolve_templates()?;
+        Ok(config)
+    }
+
+    // method to resolve a quota's rate and burst through its template chain;
+    // `path` names the quota in errors
+    pub fn resolve_quota(&self, quota: &QuotaConfig, path: &str) -> Result<(f64, f64), FieldError> {
+        let (mut rate, mut burst) = (quota.rate, quota.burst);
+        let mut parent = quota.template.as_deref();
+        let mut field = format!("{}.template", path);
+        let mut seen = Vec::new();
+        while let Some(name) = parent {
+            if seen.contains(&name) {
+                return Err(FieldError::new(
+                    field,
+                    name,
+                    "template inherits from itself",
+                ));
+            }
+            seen.push(name);
+            let Some(template) = self.templates.get(name) else {
+                return Err(FieldError::new(field, name, "unknown template"));
+            };
+            rate = rate.or(template.rate);
+            burst = burst.or(template.burst);
+            parent = template.template.as_deref();
+            field = format!("templates.{}.template", name);
+        }
+        match rate {
+            Some(rate) => Ok((rate, burst.unwrap_or(0.0))),
+            None => Err(FieldError::new(
+                format!("{}.rate", path),
+                rate,
+                "must be set here or in a template",
+            )),
+        }
+    }
+
+    // method to fill in every rule's rate and burst from its templates
+    pub fn resolve_templates(&mut self) -> Result<(), ConfigError> {
+        let mut fields = Vec::new();
+        let mut resolved = Vec::with_capacity(self.routes.len());
+        for (i, rule) in self.routes.iter().enumerate() {
+            match self.resolve_quota(&rule.quota, &format!("routes[{}].quota", i)) {
+                Ok(values) => resolved.push(values),
+                Err(field) => fields.push(field),
+            }
+        }
+        ConfigError::check(fields)?;
+        for (rule, (rate, burst)) in self.routes.iter_mut().zip(resolved) {
+            rule.quota.rate = Some(rate);
+            rule.quota.burst = Some(burst);
+        }
+        Ok(())
+    }
+
+    // method to check every rule, collecting all invalid fields rather than
+    // stopping at the first one; each rate and burst is checked where it is
+    // written, and every rule must resolve through its templates to a rate
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut fields = Vec::new();
+        for (name, template) in &self.templates {
+            check_quota(template, &format!("templates.{}", name), &mut fields);
+            if let Err(field) = self.resolve_quota(template, &format!("templates.{}", name))
+                && field.reason != "must be set here or in a template"
+            {
+                fields.push(field);
+            }
+        }
+        for (i, rule) in self.routes.iter().enumerate() {
+            let field = |name: &str| format!("routes[{}].{}", i, name);
+            if let Some(method) = &rule.method
+                && method.trim().is_empty()
+            {
+                fields.push(FieldError::new(
+                    field("method"),
+                    method,
+                    "must not be empty",
+                ));
+            }
+            if !rule.path.starts_with('/') {
+                fields.push(FieldError::new(
+                    field("path"),
+                    &rule.path,
+                    "must start with /",
+                ));
+            }
+            check_quota(&rule.quota, &field("quota"), &mut fields);
+            if let Err(error) = self.resolve_quota(&rule.quota, &field("quota"))
+                && !fields.contains(&error)
+            {
+                fields.push(error);
+            }
+            if rule.cost == 0 {
+                fields.push(FieldError::new(field("cost"), rule.cost, "must be > 0"));
+            }
+            if let KeyStrategy::Header(name) = &rule.key
+                && name.trim().is_empty()
+            {
+                fields.push(FieldError::new(
+                    field("key.header"),
+                    name,
+                    "must not be empty",
+                ));
+            }
+        }
+        ConfigError::check(fields)
+    }
+}
+
+// check the rate and burst a quota sets itself
+fn check_quota(quota: &QuotaConfig, path: &str, fields: &mut Vec<FieldError>) {
+    if let Some(rate) = quota.rate
+        && !(rate > 0.0 && rate.is_finite())
+    {
+        fields.push(FieldError::new(
+            format!("{}.rate", path),
+            rate,
+            "must be > 0",
+        ));
+    }
+    if let Some(burst) = quota.burst
+        && !(burst >= 0.0 && burst.is_finite())
+    {
+        fields.push(FieldError::new(
+            format!("{}.burst", path),
+            burst,
+            "must be >= 0",
+        ));
+    }
+}
+
+// methods for the KeyStrategy enum
+impl KeyStrategy {
+    // method to compute the rate-limit key for a request
+    pub fn key_for(&self, request: &HttpRequest) -> String {
+        match self {
+            KeyStrategy::PeerIp => request.peer().to_string(),
+            KeyStrategy::Header(name) => match request.header(name) {
+                Some(value) => value.to_string(),
+                None => request.peer().to_string(),
+            },
+            KeyStrategy::Global => String::from("global"),
+        }
+    }
+}
+
+// enum type to represent the result of checking a request against the table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteOutcome {
+    NoMatch,        // no rule matched; the caller applies its own default
+    Allowed,        // a rule matched and the request conforms
+    Denied(Denied), // a rule matched and the request was rejected
+}
+
+// struct type to represent a compiled route rule with its own limiter
+#[derive(Debug)]
+struct CompiledRoute<C: Clock> {
+    method: Option<String>,
+    segments: Vec<String>,
+    cost: u32,
+    key: KeyStrategy,
+    limiter: RateLimiter<String, C>,
+}
+
+// methods for the CompiledRoute struct
+impl<C: Clock> CompiledRoute<C> {
+    // method to test whether the rule applies to a request
+    fn matches(&self, request: &HttpRequest) -> bool {
+        if let Some(method) = &self.method
+            && !method.eq_ignore_ascii_case(request.method())
+        {
+            return false;
+        }
+
+        let mut path = request.path().split('/').filter(|s| !s.is_empty());
+        for segment in &self.segments {
+            match segment.as_str() {
+                "**" => return true,
+                "*" => {
+                    if path.next().is_none() {
+                        return false;
+                    }
+                }
+                literal => {
+                    if path.next() != Some(literal) {
+                        return false;
+                    }
+                }
+            }
+        }
+        path.next().is_none()
+    }
+}
+
+// struct type to represent a route configuration ready to check requests
+#[derive(Debug)]
+pub struct RouteTable<C: Clock = SystemClock> {
+    routes: Vec<CompiledRoute<C>>,
+}
+
+// methods for the RouteTable struct
+impl<C: Clock + Clone> RouteTable<C> {
+    // method to build the table, creating one limiter per rule
+    pub fn new(config: &RouteConfig, clock: C) -> Result<Self, RateLimiterError> {
+        let mut routes = Vec::with_capacity(config.routes.len());
+        for rule in &config.routes {
+            let (rate, burst) = config
+                .resolve_quota(&rule.quota, "quota")
+                .map_err(|_| RateLimiterError::InvalidRate)?;
+            routes.push(CompiledRoute {
+                method: rule.method.clone(),
+                segments: rule
+                    .path
+                    .split('/')
+                    .filter(|s| !s.is_empty())
+                    .map(String::from)
+                    .collect(),
+                cost: rule.cost,
+                key: rule.key.clone(),
+                limiter: RateLimiter::new(rate, burst, clock.clone())?,
+            });
+        }
+        Ok(Self { routes })
+    }
+}
+
+impl<C: Clock> RouteTable<C> {
+    // method to charge the first matching rule for a request
+    pub fn check(&self, request: &HttpRequest) -> RouteOutcome {
+        match self.routes.iter().find(|route| route.matches(request)) {
+            Some(route) => match route.limiter.charge(route.key.key_for(request), route.cost) {
+                Ok(()) => RouteOutcome::Allowed,
+                Err(denied) => RouteOutcome::Denied(denied),
+            },
+            None => RouteOutcome::NoMatch,
+        }
+    }
+
+    // method to return the key the first matching rule would limit a request under
+    pub fn key_for(&self, request: &HttpRequest) -> Option<String> {
+        self.routes
+            .iter()
+            .find(|route| route.matches(request))
+            .map(|route| route.key.key_for(request))
+    }
+
+    // accessor method to return the number of rules
+    pub fn len(&self) -> usize {
+        self.routes.len()
+    }
+
+    // method to check whether the table has no rules
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestClock;
+    use std::net::IpAddr;
+
+    fn rule(method: Option<&str>, path: &str, rate: f64, cost: u32, key: KeyStrategy) -> RouteRule {
+        RouteRule {
+            method: method.map(String::from),
+            path: path.to_string(),
+            quota: QuotaConfig::new(rate, 0.0),
+            cost,
+            key,
+        }
+    }
+
+    fn request(method: &str, path: &str) -> HttpRequest {
+        let peer: IpAddr = "10.0.0.1".parse().unwrap();
+        HttpRequest::new(method, path, peer)
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let config = RouteConfig {
+            templates: BTreeMap::new(),
+            routes: vec![
+                rule(Some("POST"), "/api/upload", 1.0, 1, KeyStrategy::PeerIp),
+                rule(None, "/api/**", 100.0, 1, KeyStrategy::PeerIp),
+            ],
+        };
+        let table = RouteTable::new(&config, TestClock::new(0.0)).unwrap();
+
+        assert_eq!(
+            table.check(&request("POST", "/api/upload")),
+            RouteOutcome::Allowed
+        );
+        assert!(matches!(
+            table.check(&request("POST", "/api/upload")),
+            RouteOutcome::Denied(_)
+        ));
+
+        assert_eq!(
+            table.key_for(&request("POST", "/api/upload")),
+            Some(String::from("10.0.0.1"))
+        );
+        assert_eq!(table.key_for(&request("GET", "/other")), None);
+
+        // GET falls through to the catch-all rule
+        assert_eq!(
+            table.check(&request("GET", "/api/upload")),
+            RouteOutcome::Allowed
+        );
+        assert_eq!(
+            table.check(&request("GET", "/other")),
+            RouteOutcome::NoMatch
+        );
+    }
+
+    #[test]
+    fn wildcards_match_segments() {
+        let config = RouteConfig {
+            templates: BTreeMap::new(),
+            routes: vec![rule(None, "/users/*/posts", 1.0, 1, KeyStrategy::Global)],
+        };
+        let table = RouteTable::new(&config, TestClock::new(0.0)).unwrap();
+
+        assert_eq!(
+            table.check(&request("GET", "/users/42/posts")),
+            RouteOutcome::Allowed
+        );
+        assert_eq!(
+            table.check(&request("GET", "/users/42")),
+            RouteOutcome::NoMatch
+        );
+        assert_eq!(
+            table.check(&request("GET", "/users/42/posts/1")),
+            RouteOutcome::NoMatch
+        );
+
+        // the global key means a different user shares the same bucket
+        assert!(matches!(
+            table.check(&request("GET", "/users/7/posts")),
+            RouteOutcome::Denied(_)
+        ));
+    }
+
+    #[test]
+    fn cost_and_header_keys_are_applied() {
+        let config = RouteConfig {
+            templates: BTreeMap::new(),
+            routes: vec![RouteRule {
+                quota: QuotaConfig::new(1.0, 4.0),
+                ..rule(
+                    None,
+                    "/bulk",
+                    1.0,
+                    5,
+                    KeyStrategy::Header("x-api-key".into()),
+                )
+            }],
+        };
+        let table = RouteTable::new(&config, TestClock::new(0.0)).unwrap();
+
+        // one call uses the whole burst of 5 units for that API key
+        let alice = request("GET", "/bulk").with_header("X-Api-Key", "alice");
+        assert_eq!(table.check(&alice), RouteOutcome::Allowed);
+        assert!(matches!(table.check(&alice), RouteOutcome::Denied(_)));
+
+        let bob = request("GET", "/bulk").with_header("X-Api-Key", "bob");
+        assert_eq!(table.check(&bob), RouteOutcome::Allowed);
+    }
+
+    #[test]
+    fn invalid_quota_is_rejected() {
+        let config = RouteConfig {
+            templates: BTreeMap::new(),
+            routes: vec![rule(None, "/", 0.0, 1, KeyStrategy::PeerIp)],
+        };
+        assert!(RouteTable::new(&config, TestClock::new(0.0)).is_err());
+    }
+
+    #[test]
+    fn validation_reports_every_bad_field() {
+        let config = RouteConfig {
+            templates: BTreeMap::new(),
+            routes: vec![
+                rule(Some("GET"), "/ok", 1.0, 1, KeyStrategy::PeerIp),
+                rule(Some(" "), "api", 0.0, 0, KeyStrategy::Header(String::new())),
+            ],
+        };
+        let Err(ConfigError::Invalid { fields }) = config.validate() else {
+            panic!("expected field errors");
+        };
+        let paths: Vec<&str> = fields.iter().map(|field| field.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "routes[1].method",
+                "routes[1].path",
+                "routes[1].quota.rate",
+                "routes[1].cost",
+                "routes[1].key.header",
+            ]
+        );
+        assert_eq!(
+            fields[2].to_string(),
+            "routes[1].quota.rate: must be > 0 (got 0.0)"
+        );
+    }
+
+    #[cfg(feature = "config")]
+    #[test]
+    fn parses_toml_config() {
+        let config = RouteConfig::from_toml(
+            r#"
+            [[routes]]
+            method = "POST"
+            path = "/api/upload"
+            quota = { rate = 1.0, burst = 2.0 }
+            cost = 5
+            key = { header = "x-api-key" }
+
+            [[routes]]
+            path = "/api/**"
+            quota = { rate = 10.0 }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.routes.len(), 2);
+        assert_eq!(config.routes[0].cost, 5);
+        assert_eq!(
+            config.routes[0].key,
+            KeyStrategy::Header("x-api-key".into())
+        );
+        assert_eq!(config.routes[1].method, None);
+        assert_eq!(config.routes[1].quota.burst, None);
+        assert_eq!(config.routes[1].cost, 1);
+        assert_eq!(config.routes[1].key, KeyStrategy::PeerIp);
+    }
+
+    #[cfg(feature = "config")]
+    #[test]
+    fn templates_are_inherited_and_overridden_at_load() {
+        let config = RouteConfig::load(
+            r#"
+            [templates.standard-api]
+            rate = 10.0
+            burst = 20.0
+
+            [templates.bulk-api]
+            template = "standard-api"
+            rate = 2.0
+
+            [[routes]]
+            path = "/api/export"
+            quota = { template = "bulk-api" }
+
+            [[routes]]
+            path = "/api/**"
+            quota = { template = "standard-api", burst = 5.0 }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.routes[0].quota.rate, Some(2.0));
+        assert_eq!(config.routes[0].quota.burst, Some(20.0));
+        assert_eq!(config.routes[1].quota.rate, Some(10.0));
+        assert_eq!(config.routes[1].quota.burst, Some(5.0));
+    }
+
+    #[test]
+    fn template_errors_name_the_reference() {
+        let mut config = RouteConfig::default();
+        config
+            .templates
+            .insert("a".into(), QuotaConfig::from_template("b"));
+        config
+            .templates
+            .insert("b".into(), QuotaConfig::from_template("a"));
+        config.routes = vec![
+            rule(None, "/a", 1.0, 1, KeyStrategy::Global),
+            rule(None, "/b", 1.0, 1, KeyStrategy::Global),
+            rule(None, "/c", 1.0, 1, KeyStrategy::Global),
+        ];
+        config.routes[0].quota = QuotaConfig::from_template("a");
+        config.routes[1].quota = QuotaConfig::from_template("missing");
+        config.routes[2].quota = QuotaConfig::default();
+
+        let Err(ConfigError::Invalid { fields }) = config.validate() else {
+            panic!("expected invalid fields");
+        };
+        let messages: Vec<String> = fields.iter().map(|f| f.to_string()).collect();
+        assert_eq!(
+            messages,
+            [
+                "templates.a.template: template inherits from itself (got \"b\")",
+                "templates.b.template: template inherits from itself (got \"a\")",
+                "routes[1].quota.template: unknown template (got \"missing\")",
+                "routes[2].quota.rate: must be set here or in a template (got None)",
+            ]
+        );
+        assert!(RouteTable::new(&config, TestClock::new(0.0)).is_err());
+    }
+}
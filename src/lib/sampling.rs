@@ -0,0 +1,189 @@
+// src/lib/sampling.rs
+
+// dependencies
+use crate::rate_limiter::Decision;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+// struct type to represent the share of decisions recorded, per outcome
+// a share of 0.01 records every 100th decision; 0 records none
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sampling {
+    pub allowed: f64,
+    pub denied: f64,
+}
+
+// methods for the Sampling struct
+impl Sampling {
+    // method to record every decision
+    pub fn all() -> Self {
+        Self {
+            allowed: 1.0,
+            denied: 1.0,
+        }
+    }
+
+    // turn a share into "record one in every n", 0 meaning never
+    fn period(share: f64) -> u64 {
+        if share.is_nan() || share <= 0.0 {
+            0
+        } else {
+            (1.0 / share.min(1.0)).round() as u64
+        }
+    }
+}
+
+// implement the Default trait to record every decision
+impl Default for Sampling {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+// struct type to represent one recorded rate-limit decision
+// `weight` is how many decisions with this outcome the event stands for, so
+// an analytics pipeline can scale sampled counts back up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecisionEvent<'a, T> {
+    pub at_nanos: u64,
+    pub key: &'a T,
+    pub cost: u32,
+    pub allowed: bool,
+    pub retry_after: Option<Duration>,
+    pub weight: u64,
+}
+
+// trait for destinations of decision events
+pub trait DecisionSink<T>: Send + Sync {
+    fn record(&self, event: &DecisionEvent<'_, T>);
+}
+
+// closures can be used directly as sinks, e.g. to push events onto a queue
+impl<T, F> DecisionSink<T> for F
+where
+    F: Fn(&DecisionEvent<'_, T>) + Send + Sync,
+{
+    fn record(&self, event: &DecisionEvent<'_, T>) {
+        self(event)
+    }
+}
+
+// struct type to represent one outcome's sampling period and counter
+#[derive(Debug)]
+struct Sampler {
+    period: u64,
+    seen: AtomicU64,
+}
+
+// methods for the Sampler struct
+impl Sampler {
+    fn new(share: f64) -> Self {
+        Self {
+            period: Sampling::period(share),
+            seen: AtomicU64::new(0),
+        }
+    }
+
+    // every `period`-th decision is kept, starting with the first
+    fn keep(&self) -> bool {
+        self.period > 0
+            && self
+                .seen
+                .fetch_add(1, Ordering::Relaxed)
+                .is_multiple_of(self.period)
+    }
+}
+
+// struct type to represent the optional sampled decision stream of a limiter
+pub(crate) struct DecisionLog<T>(Option<(Arc<dyn DecisionSink<T>>, Sampler, Sampler)>);
+
+// methods for the DecisionLog struct
+impl<T> DecisionLog<T> {
+    pub(crate) fn new() -> Self {
+        Self(None)
+    }
+
+    pub(crate) fn set(&mut self, sink: Arc<dyn DecisionSink<T>>, sampling: Sampling) {
+        let allowed = Sampler::new(sampling.allowed);
+        let denied = Sampler::new(sampling.denied);
+        self.0 = Some((sink, allowed, denied));
+    }
+
+    // only the counter of the decision's outcome is touched, so a deny
+    // sampled at 100% costs nothing extra on the allow path
+    pub(crate) fn record(&self, at_nanos: u64, key: &T, cost: u32, decision: &Decision) {
+        let Some((sink, allowed, denied)) = &self.0 else {
+            return;
+        };
+        let sampler = if decision.is_allowed() {
+            allowed
+        } else {
+            denied
+        };
+        if sampler.keep() {
+            sink.record(&DecisionEvent {
+                at_nanos,
+                key,
+                cost,
+                allowed: decision.is_allowed(),
+                retry_after: decision.retry_after(),
+                weight: sampler.period,
+            });
+        }
+    }
+}
+
+// implement the Debug trait for the DecisionLog type
+impl<T> fmt::Debug for DecisionLog<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.0 {
+            Some((_, allowed, denied)) => write!(
+                f,
+                "DecisionLog(1/{} allowed, 1/{} denied)",
+                allowed.period, denied.period
+            ),
+            None => f.write_str("DecisionLog(disabled)"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{RateLimiter, TestClock};
+    use std::sync::Mutex;
+
+    #[test]
+    fn samples_allows_and_keeps_every_deny() {
+        let clock = TestClock::new(0.0);
+        let mut limiter = RateLimiter::new(1.0, 99.0, clock.clone()).unwrap();
+        let events: Arc<Mutex<Vec<(bool, u64)>>> = Arc::default();
+        let sink = Arc::clone(&events);
+        limiter.set_decision_sink(
+            move |event: &DecisionEvent<'_, &str>| {
+                sink.lock().unwrap().push((event.allowed, event.weight))
+            },
+            Sampling {
+                allowed: 0.01,
+                denied: 1.0,
+            },
+        );
+
+        // the burst admits 100 requests, then three are denied
+        for _ in 0..103 {
+            let _ = limiter.is_allowed("client");
+        }
+        let events = events.lock().unwrap();
+        assert_eq!(*events, [(true, 100), (false, 1), (false, 1), (false, 1),]);
+    }
+
+    #[test]
+    fn zero_share_records_nothing() {
+        assert_eq!(Sampling::period(0.0), 0);
+        assert_eq!(Sampling::period(f64::NAN), 0);
+        assert_eq!(Sampling::period(0.3), 3);
+        assert_eq!(Sampling::period(2.0), 1);
+    }
+}
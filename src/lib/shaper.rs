@@ -0,0 +1,174 @@
+// src/lib/shaper.rs
+
+// dependencies
+use crate::SystemClock;
+use crate::clock::Clock;
+use crate::rate_limiter::{RateLimiter, RateLimiterError};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+// struct type to represent the queue metrics of one priority class
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClassStats {
+    pub depth: usize,         // items waiting now
+    pub released: u64,        // items handed out so far
+    pub total_wait: Duration, // summed queueing time of released items
+    pub max_wait: Duration,   // longest queueing time of a released item
+}
+
+// struct type to represent one priority class's queue
+#[derive(Debug)]
+struct ClassQueue<I> {
+    items: VecDeque<(I, u64)>, // item and the time it was queued
+    stats: ClassStats,
+}
+
+// struct type to represent a traffic shaper that queues work and releases it
+// at the limiter's rate, highest priority first
+// class 0 is the highest priority; an item moves up one class for every
+// `aging` it has waited, so low-priority work is delayed but never starved
+#[derive(Debug)]
+pub struct Shaper<I, C = SystemClock>
+where
+    C: Clock,
+{
+    limiter: RateLimiter<(), C>,
+    aging_nanos: u64,
+    classes: Mutex<Vec<ClassQueue<I>>>,
+}
+
+// methods for the Shaper struct
+impl<I, C> Shaper<I, C>
+where
+    C: Clock,
+{
+    // method to create a shaper with `classes` priority classes that releases
+    // `rate_per_second` items with up to `burst_capacity` extra at once
+    pub fn new(
+        rate_per_second: f64,
+        burst_capacity: f64,
+        classes: usize,
+        aging: Duration,
+        clock: C,
+    ) -> Result<Self, RateLimiterError> {
+        let queues = (0..classes.max(1))
+            .map(|_| ClassQueue {
+                items: VecDeque::new(),
+                stats: ClassStats::default(),
+            })
+            .collect();
+        Ok(Self {
+            limiter: RateLimiter::new(rate_per_second, burst_capacity, clock)?,
+            aging_nanos: (aging.as_nanos() as u64).max(1),
+            classes: Mutex::new(queues),
+        })
+    }
+
+    // method to queue an item in a priority class (clamped to the lowest class)
+    pub fn push(&self, item: I, class: usize) {
+        let now = self.limiter.now();
+        let mut classes = self.classes.lock().unwrap();
+        let class = class.min(classes.len() - 1);
+        classes[class].items.push_back((item, now));
+        classes[class].stats.depth += 1;
+    }
+
+    // method to release the most urgent item if the rate allows one now
+    // urgency is the class minus one step per `aging` waited; ties go to the
+    // item that has waited longest
+    pub fn pop(&self) -> Option<I> {
+        let now = self.limiter.now();
+        let mut classes = self.classes.lock().unwrap();
+
+        let (class, _) = classes
+            .iter()
+            .enumerate()
+            .filter_map(|(class, queue)| {
+                let (_, queued_at) = queue.items.front()?;
+                let steps = (now.saturating_sub(*queued_at) / self.aging_nanos) as usize;
+                Some((class, (class.saturating_sub(steps), *queued_at)))
+            })
+            .min_by_key(|(_, urgency)| *urgency)?;
+
+        if !self.limiter.is_allowed(()).unwrap_or(false) {
+            return None;
+        }
+
+        let queue = &mut classes[class];
+        let (item, queued_at) = queue.items.pop_front()?;
+        let waited = Duration::from_nanos(now.saturating_sub(queued_at));
+        queue.stats.depth -= 1;
+        queue.stats.released += 1;
+        queue.stats.total_wait += waited;
+        queue.stats.max_wait = queue.stats.max_wait.max(waited);
+        Some(item)
+    }
+
+    // method to return the metrics of one class, if it exists
+    pub fn class_stats(&self, class: usize) -> Option<ClassStats> {
+        self.classes
+            .lock()
+            .unwrap()
+            .get(class)
+            .map(|queue| queue.stats)
+    }
+
+    // accessor method to return the number of items waiting in all classes
+    pub fn len(&self) -> usize {
+        let classes = self.classes.lock().unwrap();
+        classes.iter().map(|queue| queue.items.len()).sum()
+    }
+
+    // method to check whether nothing is waiting
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestClock;
+
+    #[test]
+    fn releases_by_priority_at_the_shaping_rate() {
+        let clock = TestClock::new(0.0);
+        let shaper = Shaper::new(1.0, 0.0, 2, Duration::from_secs(60), clock.clone()).unwrap();
+        shaper.push("background", 1);
+        shaper.push("interactive", 0);
+
+        assert_eq!(shaper.pop(), Some("interactive"));
+        // the rate allows one item per second
+        assert_eq!(shaper.pop(), None);
+        clock.advance(1.0);
+        assert_eq!(shaper.pop(), Some("background"));
+        assert!(shaper.is_empty());
+    }
+
+    #[test]
+    fn aging_promotes_waiting_low_priority_items() {
+        let clock = TestClock::new(0.0);
+        let shaper = Shaper::new(1.0, 0.0, 3, Duration::from_secs(2), clock.clone()).unwrap();
+        shaper.push("batch", 2);
+
+        // a steady stream of high-priority work arrives every second
+        let mut released = Vec::new();
+        for second in 0..6 {
+            shaper.push("urgent", 0);
+            if let Some(item) = shaper.pop() {
+                released.push((second, item));
+            }
+            clock.advance(1.0);
+        }
+
+        // after 4s the batch item has aged into class 0 and, having waited
+        // longest, goes ahead of the newest urgent item
+        assert!(released.contains(&(4, "batch")));
+        let stats = shaper.class_stats(2).unwrap();
+        assert_eq!(stats.released, 1);
+        assert_eq!(stats.depth, 0);
+        assert_eq!(stats.max_wait, Duration::from_secs(4));
+        assert_eq!(shaper.class_stats(0).unwrap().depth, 1);
+    }
+}
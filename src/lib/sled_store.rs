@@ -0,0 +1,423 @@
+// src/lib/sled_store.rs
+
+// dependencies
+use crate::clock::Clock;
+use crate::persistence::{StoreKey, decode_tat, encode_tat};
+use crate::rate_limiter::RateLimiter;
+use dashmap::DashMap;
+use std::error::Error;
+use std::fmt;
+use std::hash::Hash;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+// enum type to represent errors related to the embedded store
+#[derive(Debug)]
+pub enum StoreError {
+    Sled(sled::Error), // error reported by the underlying database
+    CorruptEntry,      // an entry on disk could not be decoded
+}
+
+// implement the Display trait for the StoreError type
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StoreError::Sled(e) => write!(f, "Embedded store error: {}", e),
+            StoreError::CorruptEntry => write!(f, "Embedded store contains a corrupt entry"),
+        }
+    }
+}
+
+// implement the Error trait for the StoreError type
+impl Error for StoreError {}
+
+impl From<sled::Error> for StoreError {
+    fn from(e: sled::Error) -> Self {
+        StoreError::Sled(e)
+    }
+}
+
+// struct type to represent a sled database mirroring a rate limiter's state
+// decisions are still made against the in-memory map; TATs are copied to disk
+// in batches, either on demand or from a background flush thread
+pub struct SledStore<T>
+where
+    T: StoreKey + Hash + Eq + Clone + Send + Sync + 'static,
+{
+    db: sled::Db,
+    client_state: Arc<DashMap<T, u64>>,
+    shutdown: Arc<(Mutex<bool>, Condvar)>,
+    flusher: Option<JoinHandle<()>>,
+    flush_failed: Arc<AtomicBool>,
+}
+
+// methods for the SledStore struct
+impl<T> SledStore<T>
+where
+    T: StoreKey + Hash + Eq + Clone + Send + Sync + 'static,
+{
+    // method to open (or create) a store at the given path and restore any
+    // persisted TATs into the limiter
+    pub fn open<C: Clock>(
+        path: impl AsRef<Path>,
+        limiter: &RateLimiter<T, C>,
+    ) -> Result<Self, StoreError> {
+        let db = sled::open(path)?;
+        let client_state = Arc::clone(limiter.client_state());
+
+        for item in db.iter() {
+            let (key_bytes, tat_bytes) = item?;
+            let key = T::from_bytes(&key_bytes).ok_or(StoreError::CorruptEntry)?;
+            let tat = decode_tat(&tat_bytes).ok_or(StoreError::CorruptEntry)?;
+
+            // never move a TAT backwards if the limiter already saw this key
+            client_state
+                .entry(key)
+                .and_modify(|current| *current = (*current).max(tat))
+                .or_insert(tat);
+        }
+
+        Ok(Self {
+            db,
+            client_state,
+            shutdown: Arc::new((Mutex::new(false), Condvar::new())),
+            flusher: None,
+            flush_failed: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    // method to write every tracked TAT to disk as a single batch
+    pub fn flush(&self) -> Result<usize, StoreError> {
+        flush_state(&self.db, &self.client_state)
+    }
+
+    // method to start a background thread that flushes at the given interval
+    // calling it again replaces the previous thread
+    pub fn spawn_flusher(&mut self, interval: Duration) {
+        self.stop_flusher();
+
+        let db = self.db.clone();
+        let client_state = Arc::clone(&self.client_state);
+        let shutdown = Arc::clone(&self.shutdown);
+        let flush_failed = Arc::clone(&self.flush_failed);
+
+        self.flusher = Some(thread::spawn(move || {
+            let (lock, signal) = &*shutdown;
+            let mut stopped = lock.lock().unwrap();
+            while !*stopped {
+                let (guard, _) = signal.wait_timeout(stopped, interval).unwrap();
+                stopped = guard;
+                if flush_state(&db, &client_state).is_err() {
+                    flush_failed.store(true, Ordering::Relaxed);
+                }
+            }
+        }));
+    }
+
+    // accessor method reporting whether a background flush has failed
+    pub fn flush_failed(&self) -> bool {
+        self.flush_failed.load(Ordering::Relaxed)
+    }
+
+    // internal method to stop the background thread, if one is running
+    fn stop_flusher(&mut self) {
+        if let Some(handle) = self.flusher.take() {
+            let (lock, signal) = &*self.shutdown;
+            *lock.lock().unwrap() = true;
+            signal.notify_all();
+            let _ = handle.join();
+            *lock.lock().unwrap() = false;
+        }
+    }
+}
+
+// stop the flush thread and write out the final state when the store is dropped
+impl<T> Drop for SledStore<T>
+where
+    T: StoreKey + Hash + Eq + Clone + Send + Sync + 'static,
+{
+    fn drop(&mut self) {
+        self.stop_flusher();
+        let _ = self.flush();
+    }
+}
+
+// enum type to represent a problem found in a store by `SledStore::fsck`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FsckIssue {
+    BadKey(Vec<u8>),                        // the key does not decode as the key type
+    BadTat(Vec<u8>),                        // the value is not an 8-byte TAT
+    TooFarAhead { key: Vec<u8>, tat: u64 }, // the TAT is past `now + max_ahead`
+}
+
+// implement the Display trait for the FsckIssue type
+impl fmt::Display for FsckIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FsckIssue::BadKey(key) => write!(f, "undecodable key {:02x?}", key),
+            FsckIssue::BadTat(key) => write!(f, "undecodable TAT for key {:02x?}", key),
+            FsckIssue::TooFarAhead { key, tat } => {
+                write!(f, "TAT {} too far ahead for key {:02x?}", tat, key)
+            }
+        }
+    }
+}
+
+// struct type to represent the result of a store check
+// idle entries (TAT at or before `now`) are not problems, since they behave
+// like absent keys, but they are counted and dropped on repair to compact
+// the store
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FsckReport {
+    pub scanned: usize,
+    pub idle: usize,
+    pub issues: Vec<FsckIssue>,
+    pub repaired: bool,
+}
+
+// methods for the SledStore struct used for maintenance
+impl<T> SledStore<T>
+where
+    T: StoreKey + Hash + Eq + Clone + Send + Sync + 'static,
+{
+    // method to check a store that is not open elsewhere, at `now` on the
+    // clock its limiter used; no TAT can legitimately be more than the
+    // largest burst window (`max_ahead`) in the future
+    // with `repair`, undecodable entries and idle entries are removed and
+    // TATs too far ahead are clamped to `now + max_ahead`, so a client whose
+    // entry was damaged stays limited rather than being locked out for years
+    pub fn fsck(
+        path: impl AsRef<Path>,
+        now: u64,
+        max_ahead: Duration,
+        repair: bool,
+    ) -> Result<FsckReport, StoreError> {
+        let db = sled::open(path)?;
+        let limit = now.saturating_add(max_ahead.as_nanos() as u64);
+        let mut report = FsckReport::default();
+        let mut batch = sled::Batch::default();
+
+        for item in db.iter() {
+            let (key, value) = item?;
+            report.scanned += 1;
+            if T::from_bytes(&key).is_none() {
+                report.issues.push(FsckIssue::BadKey(key.to_vec()));
+                batch.remove(key);
+                continue;
+            }
+            match decode_tat(&value) {
+                None => {
+                    report.issues.push(FsckIssue::BadTat(key.to_vec()));
+                    batch.remove(key);
+                }
+                Some(tat) if tat > limit => {
+                    report.issues.push(FsckIssue::TooFarAhead {
+                        key: key.to_vec(),
+                        tat,
+                    });
+                    batch.insert(key, &encode_tat(limit));
+                }
+                Some(tat) if tat <= now => {
+                    report.idle += 1;
+                    batch.remove(key);
+                }
+                Some(_) => {}
+            }
+        }
+
+        if repair {
+            db.apply_batch(batch)?;
+            db.flush()?;
+            report.repaired = true;
+        }
+        Ok(report)
+    }
+}
+
+// write the current map contents to the database in one batch, removing
+// keys the map no longer holds (reset, removed or evicted), so they do not
+// come back with their old TAT on restart; entries that do not decode are
+// left for fsck
+fn flush_state<T>(db: &sled::Db, client_state: &DashMap<T, u64>) -> Result<usize, StoreError>
+where
+    T: StoreKey + Hash + Eq,
+{
+    let mut batch = sled::Batch::default();
+    for key in db.iter().keys() {
+        let key = key?;
+        if T::from_bytes(&key).is_some_and(|key| !client_state.contains_key(&key)) {
+            batch.remove(key);
+        }
+    }
+    let mut written = 0;
+    for entry in client_state.iter() {
+        batch.insert(entry.key().to_bytes(), &encode_tat(*entry.value()));
+        written += 1;
+    }
+    db.apply_batch(batch)?;
+    db.flush()?;
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestClock;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("gcra-sled-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        path
+    }
+
+    // sled releases its file lock from a background thread after the last
+    // handle is dropped, so reopening right away can briefly find it held
+    fn reopen(path: &Path, limiter: &RateLimiter<String, TestClock>) -> SledStore<String> {
+        for _ in 0..50 {
+            match SledStore::open(path, limiter) {
+                Ok(store) => return store,
+                Err(_) => thread::sleep(Duration::from_millis(20)),
+            }
+        }
+        SledStore::open(path, limiter).unwrap()
+    }
+
+    #[test]
+    fn state_survives_reopen() {
+        let path = temp_path("reopen");
+        let clock = TestClock::new(0.0);
+
+        {
+            let limiter = RateLimiter::new(1.0, 0.0, clock.clone()).unwrap();
+            let store = SledStore::open(&path, &limiter).unwrap();
+            assert!(limiter.is_allowed(String::from("client1")).unwrap());
+            assert_eq!(store.flush().unwrap(), 1);
+        }
+
+        // a fresh limiter restored from disk still remembers the client
+        let limiter = RateLimiter::new(1.0, 0.0, clock.clone()).unwrap();
+        let _store = reopen(&path, &limiter);
+        assert!(!limiter.is_allowed(String::from("client1")).unwrap());
+
+        clock.advance(1.0);
+        assert!(limiter.is_allowed(String::from("client1")).unwrap());
+
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn reset_keys_stay_reset_after_reopen() {
+        let path = temp_path("reset");
+        let clock = TestClock::new(0.0);
+
+        {
+            let limiter = RateLimiter::new(1.0, 0.0, clock.clone()).unwrap();
+            let store = SledStore::open(&path, &limiter).unwrap();
+            assert!(limiter.is_allowed(String::from("client1")).unwrap());
+            assert!(limiter.is_allowed(String::from("client2")).unwrap());
+            assert_eq!(store.flush().unwrap(), 2);
+
+            // the reset is persisted by the next flush
+            limiter.reset(&String::from("client1"));
+            assert_eq!(store.flush().unwrap(), 1);
+        }
+
+        let limiter = RateLimiter::new(1.0, 0.0, clock).unwrap();
+        let _store = reopen(&path, &limiter);
+        assert!(limiter.is_allowed(String::from("client1")).unwrap());
+        assert!(!limiter.is_allowed(String::from("client2")).unwrap());
+
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    #[should_panic(expected = "before attaching a store")]
+    fn rebuilding_the_state_map_after_attaching_a_store_panics() {
+        let path = temp_path("rebuild");
+        let limiter = RateLimiter::<String, _>::new(1.0, 0.0, TestClock::new(0.0)).unwrap();
+        let _store = SledStore::open(&path, &limiter).unwrap();
+        let _ = limiter.with_shards(16);
+    }
+
+    #[test]
+    fn fsck_reports_and_repairs_corrupt_entries() {
+        let path = temp_path("fsck");
+        let second = 1_000_000_000;
+        {
+            let db = sled::open(&path).unwrap();
+            db.insert(b"healthy", &encode_tat(12 * second)).unwrap();
+            db.insert(b"idle", &encode_tat(3 * second)).unwrap();
+            db.insert(b"runaway", &encode_tat(u64::MAX / 2)).unwrap();
+            db.insert(b"truncated", &[1, 2, 3][..]).unwrap();
+            db.insert(&[0xff, 0xfe][..], &encode_tat(12 * second))
+                .unwrap();
+            db.flush().unwrap();
+        }
+
+        let max_ahead = Duration::from_secs(60);
+        let fsck = |repair| {
+            for _ in 0..50 {
+                if let Ok(report) = SledStore::<String>::fsck(&path, 10 * second, max_ahead, repair)
+                {
+                    return report;
+                }
+                thread::sleep(Duration::from_millis(20));
+            }
+            panic!("store stayed locked");
+        };
+
+        let report = fsck(false);
+        assert_eq!(report.scanned, 5);
+        assert_eq!(report.idle, 1);
+        assert_eq!(
+            report.issues,
+            [
+                FsckIssue::TooFarAhead {
+                    key: b"runaway".to_vec(),
+                    tat: u64::MAX / 2,
+                },
+                FsckIssue::BadTat(b"truncated".to_vec()),
+                FsckIssue::BadKey(vec![0xff, 0xfe]),
+            ]
+        );
+        assert!(!report.repaired);
+
+        assert!(fsck(true).repaired);
+        let report = fsck(false);
+        assert_eq!(report.scanned, 2);
+        assert!(report.issues.is_empty());
+
+        // the runaway client is clamped to the horizon, not released
+        let clock = TestClock::new(10.0);
+        let limiter = RateLimiter::new(1.0, 0.0, clock).unwrap();
+        let _store = reopen(&path, &limiter);
+        assert_eq!(
+            limiter.client_state().get("runaway").map(|tat| *tat),
+            Some(70 * second)
+        );
+
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn background_flusher_writes_state() {
+        let path = temp_path("flusher");
+        let clock = TestClock::new(0.0);
+        let limiter = RateLimiter::new(1.0, 0.0, clock).unwrap();
+
+        let mut store = SledStore::open(&path, &limiter).unwrap();
+        store.spawn_flusher(Duration::from_millis(10));
+        assert!(limiter.is_allowed(String::from("client1")).unwrap());
+
+        thread::sleep(Duration::from_millis(100));
+        assert!(!store.flush_failed());
+        assert!(store.db.contains_key(b"client1").unwrap());
+
+        drop(store);
+        let _ = std::fs::remove_dir_all(&path);
+    }
+}
@@ -0,0 +1,412 @@
+// src/lib/snapshot.rs
+
+// dependencies
+use crate::persistence::StoreKey;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::hash::Hash;
+
+// leading bytes of an encoded snapshot
+pub const SNAPSHOT_MAGIC: [u8; 4] = *b"GCRA";
+
+// newest snapshot format version this crate writes and reads
+pub const SNAPSHOT_VERSION: u16 = 1;
+
+// enum type to represent errors decoding a snapshot
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotError {
+    BadMagic,                // the bytes do not start with SNAPSHOT_MAGIC
+    UnsupportedVersion(u16), // written by a newer, incompatible format version
+    Truncated,               // the bytes end before the snapshot does
+    BadKey(usize),           // the entry at this index has an undecodable key
+}
+
+// implement the Display trait for the SnapshotError type
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::BadMagic => write!(f, "Not a rate limiter snapshot"),
+            SnapshotError::UnsupportedVersion(version) => {
+                write!(f, "Snapshot format version {} is not supported", version)
+            }
+            SnapshotError::Truncated => write!(f, "Snapshot is truncated"),
+            SnapshotError::BadKey(index) => write!(f, "Snapshot entry {} has a bad key", index),
+        }
+    }
+}
+
+// implement the Error trait for the SnapshotError type
+impl Error for SnapshotError {}
+
+// struct type to represent a point-in-time copy of a limiter's TATs
+// snapshots are meant to be exchanged between active-active instances that
+// share a clock timeline (e.g. SystemClock); merging takes the element-wise
+// maximum, the conservative union, so each instance ends up at least as
+// strict as every instance it has heard from
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct Snapshot<T>
+where
+    T: Hash + Eq,
+{
+    pub(crate) tats: HashMap<T, u64>,
+}
+
+// methods for the Snapshot struct
+impl<T> Snapshot<T>
+where
+    T: Hash + Eq + Clone,
+{
+    // method to create an empty snapshot
+    pub fn new() -> Self {
+        Self {
+            tats: HashMap::new(),
+        }
+    }
+
+    // accessor method to return the TAT held for a key, in nanoseconds
+    pub fn get(&self, key: &T) -> Option<u64> {
+        self.tats.get(key).copied()
+    }
+
+    // method to record a key's TAT, keeping the later one if already held
+    pub fn insert(&mut self, key: T, tat: u64) {
+        let held = self.tats.entry(key).or_insert(tat);
+        *held = (*held).max(tat);
+    }
+
+    // accessor method to return the number of keys held
+    pub fn len(&self) -> usize {
+        self.tats.len()
+    }
+
+    // method to check whether the snapshot holds no keys
+    pub fn is_empty(&self) -> bool {
+        self.tats.is_empty()
+    }
+
+    // method to iterate over the keys and their TATs
+    pub fn iter(&self) -> impl Iterator<Item = (&T, u64)> {
+        self.tats.iter().map(|(key, tat)| (key, *tat))
+    }
+
+    // method to fold another snapshot into this one, keeping the later TAT
+    // per key; merging is idempotent, commutative and associative, so peers
+    // converge however often and in whatever order they exchange snapshots
+    pub fn merge(&mut self, other: &Snapshot<T>) {
+        for (key, tat) in other.iter() {
+            self.insert(key.clone(), tat);
+        }
+    }
+
+    // method to return the entries that are newer here than in `base`
+    // merging the diff into `base` gives the same result as merging all of
+    // `self`, so peers can send only what changed since their last exchange
+    pub fn diff(&self, base: &Snapshot<T>) -> Snapshot<T> {
+        let tats = self
+            .iter()
+            .filter(|(key, tat)| base.get(key).is_none_or(|held| *tat > held))
+            .map(|(key, tat)| (key.clone(), tat))
+            .collect();
+        Snapshot { tats }
+    }
+}
+
+// methods for the Snapshot struct used to persist or transfer it
+// format version 1, all integers big-endian:
+//   magic        4 bytes  "GCRA"
+//   version      u16      1
+//   header_len   u16      bytes of header that follow, 16 in version 1
+//   epoch        u64      Unix time in nanoseconds at which the clock read 0
+//   entry_count  u64
+//   entries      entry_count times: key_len u32, key (StoreKey bytes), TAT u64
+// later versions may only append header fields, so readers skip any header
+// bytes past the fields they know; a change that older readers cannot skip
+// gets a new version, which older readers refuse
+impl<T> Snapshot<T>
+where
+    T: StoreKey + Hash + Eq + Clone,
+{
+    // method to encode the snapshot; `epoch_nanos` is the Unix time at which
+    // the writer's clock read 0 (0 for SystemClock)
+    pub fn encode(&self, epoch_nanos: u64) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(28 + self.len() * 20);
+        bytes.extend_from_slice(&SNAPSHOT_MAGIC);
+        bytes.extend_from_slice(&SNAPSHOT_VERSION.to_be_bytes());
+        bytes.extend_from_slice(&16u16.to_be_bytes());
+        bytes.extend_from_slice(&epoch_nanos.to_be_bytes());
+        bytes.extend_from_slice(&(self.len() as u64).to_be_bytes());
+        for (key, tat) in self.iter() {
+            let key = key.to_bytes();
+            bytes.extend_from_slice(&(key.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(&key);
+            bytes.extend_from_slice(&tat.to_be_bytes());
+        }
+        bytes
+    }
+
+    // method to decode a snapshot onto a reader whose clock read 0 at Unix
+    // time `epoch_nanos`; TATs are shifted by the difference in epochs, so
+    // state moves between clocks that count from different points
+    pub fn decode(bytes: &[u8], epoch_nanos: u64) -> Result<Self, SnapshotError> {
+        let mut reader = Reader(bytes);
+        if reader.take(4)? != SNAPSHOT_MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+        let version = u16::from_be_bytes(reader.array()?);
+        if version == 0 || version > SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        let header_len = u16::from_be_bytes(reader.array()?) as usize;
+        let mut header = Reader(reader.take(header_len)?);
+        let written_epoch = u64::from_be_bytes(header.array()?);
+        let count = u64::from_be_bytes(header.array()?);
+
+        let mut snapshot = Self::new();
+        for index in 0..count as usize {
+            let key_len = u32::from_be_bytes(reader.array()?) as usize;
+            let key = T::from_bytes(reader.take(key_len)?).ok_or(SnapshotError::BadKey(index))?;
+            let tat = u64::from_be_bytes(reader.array()?);
+            let tat = (tat as i128 + written_epoch as i128 - epoch_nanos as i128)
+                .clamp(0, u64::MAX as i128) as u64;
+            snapshot.insert(key, tat);
+        }
+        Ok(snapshot)
+    }
+}
+
+// struct type to represent a cursor over the bytes of an encoded snapshot
+struct Reader<'a>(&'a [u8]);
+
+// methods for the Reader struct
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SnapshotError> {
+        if self.0.len() < len {
+            return Err(SnapshotError::Truncated);
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], SnapshotError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+}
+
+// struct type to represent everything a limiter needs to pick up where it
+// left off after a restart: its default quota and the TAT of every key still
+// holding state, so clients do not get a fresh burst from the new process
+// overrides, bans and the algorithm are configuration, not state, and are
+// left to the code building the limiter
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LimiterSnapshot<T>
+where
+    T: Hash + Eq,
+{
+    pub increment_nanos: u64,
+    pub tolerance_nanos: u64,
+    pub tats: Snapshot<T>,
+}
+
+// implement the Default trait for an empty Snapshot
+impl<T> Default for Snapshot<T>
+where
+    T: Hash + Eq + Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// implement the FromIterator trait to build a Snapshot from (key, TAT) pairs
+impl<T> FromIterator<(T, u64)> for Snapshot<T>
+where
+    T: Hash + Eq + Clone,
+{
+    fn from_iter<I: IntoIterator<Item = (T, u64)>>(iter: I) -> Self {
+        let mut snapshot = Self::new();
+        for (key, tat) in iter {
+            snapshot.insert(key, tat);
+        }
+        snapshot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{RateLimiter, TestClock};
+
+    fn snapshot(entries: &[(&'static str, u64)]) -> Snapshot<&'static str> {
+        entries.iter().copied().collect()
+    }
+
+    #[test]
+    fn merge_is_idempotent_and_commutative() {
+        let a = snapshot(&[("x", 5), ("y", 9)]);
+        let b = snapshot(&[("x", 7), ("z", 3)]);
+
+        let mut twice = a.clone();
+        twice.merge(&a);
+        assert_eq!(twice, a);
+
+        let mut ab = a.clone();
+        ab.merge(&b);
+        let mut ba = b.clone();
+        ba.merge(&a);
+        assert_eq!(ab, ba);
+        assert_eq!(ab, snapshot(&[("x", 7), ("y", 9), ("z", 3)]));
+
+        let mut again = ab.clone();
+        again.merge(&b);
+        assert_eq!(again, ab);
+    }
+
+    #[test]
+    fn diff_carries_only_newer_entries() {
+        let base = snapshot(&[("x", 5), ("y", 9)]);
+        let current = snapshot(&[("x", 8), ("y", 9), ("z", 1)]);
+
+        let diff = current.diff(&base);
+        assert_eq!(diff, snapshot(&[("x", 8), ("z", 1)]));
+
+        let mut merged = base.clone();
+        merged.merge(&diff);
+        let mut full = base.clone();
+        full.merge(&current);
+        assert_eq!(merged, full);
+    }
+
+    // a version 1 snapshot, epoch 1000, holding "ab" -> 5000; kept byte for
+    // byte so every later release must still read what 0.5 wrote
+    const VERSION_1: &[u8] = &[
+        b'G', b'C', b'R', b'A', 0, 1, 0, 16, // magic, version, header_len
+        0, 0, 0, 0, 0, 0, 0x03, 0xe8, // epoch
+        0, 0, 0, 0, 0, 0, 0, 1, // entry count
+        0, 0, 0, 2, b'a', b'b', // key
+        0, 0, 0, 0, 0, 0, 0x13, 0x88, // TAT
+    ];
+
+    #[test]
+    fn encoding_round_trips_and_matches_version_1() {
+        let original: Snapshot<String> = [(String::from("ab"), 5000)].into_iter().collect();
+        assert_eq!(original.encode(1000), VERSION_1);
+        assert_eq!(Snapshot::decode(VERSION_1, 1000), Ok(original));
+
+        let many: Snapshot<u64> = (0..100).map(|key| (key, key * 7)).collect();
+        assert_eq!(Snapshot::decode(&many.encode(0), 0), Ok(many));
+    }
+
+    #[test]
+    fn decoding_rebases_onto_the_reader_epoch() {
+        // the writer's clock started 1000ns after the reader's
+        let snapshot = Snapshot::<String>::decode(VERSION_1, 0).unwrap();
+        assert_eq!(snapshot.get(&String::from("ab")), Some(6000));
+        let snapshot = Snapshot::<String>::decode(VERSION_1, 10_000).unwrap();
+        assert_eq!(snapshot.get(&String::from("ab")), Some(0));
+    }
+
+    #[test]
+    fn readers_skip_header_fields_they_do_not_know() {
+        // a later writer appending an 8-byte header field
+        let mut extended = VERSION_1.to_vec();
+        extended[7] = 24;
+        extended.splice(24..24, [0xaa; 8]);
+        let snapshot = Snapshot::<String>::decode(&extended, 1000).unwrap();
+        assert_eq!(snapshot.get(&String::from("ab")), Some(5000));
+    }
+
+    #[test]
+    fn rejects_foreign_newer_and_damaged_bytes() {
+        let decode = |bytes: &[u8]| Snapshot::<String>::decode(bytes, 0);
+        assert_eq!(decode(b"JSON{}"), Err(SnapshotError::BadMagic));
+
+        let mut newer = VERSION_1.to_vec();
+        newer[5] = 2;
+        assert_eq!(decode(&newer), Err(SnapshotError::UnsupportedVersion(2)));
+
+        for len in 0..VERSION_1.len() {
+            assert!(decode(&VERSION_1[..len]).is_err());
+        }
+        let mut bad_key = VERSION_1.to_vec();
+        bad_key[28] = 0xff;
+        assert_eq!(decode(&bad_key), Err(SnapshotError::BadKey(0)));
+    }
+
+    #[test]
+    fn limiters_converge_by_exchanging_snapshots() {
+        let clock = TestClock::new(0.0);
+        let east = RateLimiter::new(1.0, 1.0, clock.clone()).unwrap();
+        let west = RateLimiter::new(1.0, 1.0, clock.clone()).unwrap();
+
+        // each instance admits half of one client's burst on its own
+        assert!(east.is_allowed("client").unwrap());
+        assert!(west.is_allowed("client").unwrap());
+        assert!(west.is_allowed("client").unwrap());
+
+        let (east_snapshot, west_snapshot) = (east.snapshot(), west.snapshot());
+        east.merge(&west_snapshot);
+        west.merge(&east_snapshot);
+        assert_eq!(east.snapshot(), west.snapshot());
+
+        // the merged state is the stricter one, so the burst is spent on both
+        assert!(!east.is_allowed("client").unwrap());
+        assert!(!west.is_allowed("client").unwrap());
+
+        // merging the same snapshot again changes nothing
+        let before = east.snapshot();
+        east.merge(&west_snapshot);
+        assert_eq!(east.snapshot(), before);
+    }
+
+    #[test]
+    fn limiters_resume_from_a_full_snapshot() {
+        let clock = TestClock::new(0.0);
+        let before = RateLimiter::new(2.0, 1.0, clock.clone()).unwrap();
+        assert!(before.is_allowed("spent").unwrap());
+        assert!(before.is_allowed("spent").unwrap());
+        assert!(before.is_allowed("half").unwrap());
+
+        let saved = before.full_snapshot();
+        assert_eq!(saved.increment_nanos, 500_000_000);
+        assert_eq!(saved.tats.len(), 2);
+        let after = RateLimiter::from_snapshot(saved.clone(), clock.clone()).unwrap();
+        assert_eq!(after.rate(), 2.0);
+        assert_eq!(after.snapshot(), saved.tats);
+        assert!(!after.is_allowed("spent").unwrap());
+        assert!(after.is_allowed("half").unwrap());
+        assert!(!after.is_allowed("half").unwrap());
+
+        let broken = LimiterSnapshot {
+            increment_nanos: 0,
+            ..saved
+        };
+        assert!(matches!(
+            RateLimiter::from_snapshot(broken, clock),
+            Err(crate::RateLimiterError::InvalidRate)
+        ));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn full_snapshots_serialize_with_serde() {
+        let clock = TestClock::new(0.0);
+        let limiter = RateLimiter::new(1.0, 0.0, clock.clone()).unwrap();
+        assert!(limiter.is_allowed(String::from("client")).unwrap());
+
+        let json = serde_json::to_string(&limiter.full_snapshot()).unwrap();
+        assert_eq!(
+            json,
+            r#"{"increment_nanos":1000000000,"tolerance_nanos":0,"tats":{"client":1000000000}}"#
+        );
+        let saved: LimiterSnapshot<String> = serde_json::from_str(&json).unwrap();
+        let restored = RateLimiter::from_snapshot(saved, clock).unwrap();
+        assert!(!restored.is_allowed(String::from("client")).unwrap());
+    }
+}
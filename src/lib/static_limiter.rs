@@ -0,0 +1,145 @@
+// src/lib/static_limiter.rs
+
+// dependencies
+use crate::SystemClock;
+use crate::clock::Clock;
+use crate::gcra;
+use dashmap::DashMap;
+use std::hash::Hash;
+
+// struct type to represent a rate limiter whose quota is fixed at compile time
+// PERMITS requests are allowed per PER_MS milliseconds, and up to PERMITS
+// requests may arrive at once before spacing kicks in; quotas that are zero,
+// overflow, or round to a zero emission interval fail to compile
+#[derive(Debug)]
+pub struct StaticRateLimiter<const PERMITS: u32, const PER_MS: u64, T, C = SystemClock>
+where
+    T: Hash + Eq + Clone,
+    C: Clock,
+{
+    client_state: DashMap<T, u64>,
+    clock: C,
+}
+
+// methods for the StaticRateLimiter struct
+impl<const PERMITS: u32, const PER_MS: u64, T, C> StaticRateLimiter<PERMITS, PER_MS, T, C>
+where
+    T: Hash + Eq + Clone,
+    C: Clock,
+{
+    // emission interval in nanoseconds, checked when the type is instantiated
+    const INCREMENT_NANOS: u64 = {
+        assert!(PERMITS > 0, "PERMITS must be positive");
+        assert!(PER_MS > 0, "PER_MS must be positive");
+        assert!(
+            PER_MS <= u64::MAX / 1_000_000,
+            "PER_MS overflows when converted to nanoseconds"
+        );
+        let increment = PER_MS * 1_000_000 / PERMITS as u64;
+        assert!(
+            increment > 0,
+            "emission interval rounds to zero nanoseconds"
+        );
+        increment
+    };
+
+    // tolerance in nanoseconds, allowing PERMITS requests in a single burst
+    const TOLERANCE_NANOS: u64 = Self::INCREMENT_NANOS * (PERMITS as u64 - 1);
+
+    // method to create a new static rate limiter with the given clock
+    pub fn new(clock: C) -> Self {
+        // referencing the constants forces the quota checks at compile time
+        let _ = (Self::INCREMENT_NANOS, Self::TOLERANCE_NANOS);
+
+        Self {
+            client_state: DashMap::new(),
+            clock,
+        }
+    }
+
+    // Convenience constructor with default system clock
+    pub fn with_system_clock() -> Self
+    where
+        C: Default,
+    {
+        Self::new(C::default())
+    }
+
+    // accessor method to return the rate in requests per second
+    pub fn rate(&self) -> f64 {
+        1_000_000_000.0 / Self::INCREMENT_NANOS as f64
+    }
+
+    // accessor method to return the burst (extra requests beyond the first)
+    pub fn burst(&self) -> f64 {
+        Self::TOLERANCE_NANOS as f64 / Self::INCREMENT_NANOS as f64
+    }
+
+    // method that implements the GCRA algorithm; no runtime validation is needed
+    pub fn is_allowed(&self, client_id: T) -> bool {
+        let current_time_nanos = self.clock.now();
+
+        let mut tat = self
+            .client_state
+            .entry(client_id)
+            .or_insert(current_time_nanos);
+        match gcra::conform(
+            current_time_nanos,
+            *tat,
+            Self::INCREMENT_NANOS,
+            Self::TOLERANCE_NANOS,
+            1,
+        ) {
+            Some(new_tat_nanos) => {
+                *tat = new_tat_nanos;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestClock;
+
+    #[test]
+    fn quota_is_computed_at_compile_time() {
+        let limiter = StaticRateLimiter::<10, 1000, &str, _>::new(TestClock::new(0.0));
+        assert_eq!(limiter.rate(), 10.0);
+        assert_eq!(limiter.burst(), 9.0);
+    }
+
+    #[test]
+    fn allows_permits_then_spaces_requests() {
+        let clock = TestClock::new(0.0);
+        let limiter = StaticRateLimiter::<3, 3000, &str, _>::new(clock.clone());
+
+        // three permits at once, then the fourth is rejected
+        assert!(limiter.is_allowed("client1"));
+        assert!(limiter.is_allowed("client1"));
+        assert!(limiter.is_allowed("client1"));
+        assert!(!limiter.is_allowed("client1"));
+
+        // other clients are unaffected
+        assert!(limiter.is_allowed("client2"));
+
+        // one permit is restored every second
+        clock.advance(1.0);
+        assert!(limiter.is_allowed("client1"));
+        assert!(!limiter.is_allowed("client1"));
+    }
+
+    #[test]
+    fn single_permit_has_no_burst() {
+        let clock = TestClock::new(0.0);
+        let limiter = StaticRateLimiter::<1, 500, &str, _>::new(clock.clone());
+
+        assert!(limiter.is_allowed("client1"));
+        assert!(!limiter.is_allowed("client1"));
+
+        clock.advance(0.5);
+        assert!(limiter.is_allowed("client1"));
+    }
+}
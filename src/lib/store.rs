@@ -0,0 +1,606 @@
+// src/lib/store.rs
+
+// dependencies
+use crate::SystemClock;
+use crate::clock::Clock;
+use crate::gcra;
+use crate::rate_limiter::{RateLimiterError, Rounding, quota_nanos};
+#[cfg(feature = "async")]
+use async_trait::async_trait;
+use dashmap::DashMap;
+use std::convert::Infallible;
+use std::error::Error;
+use std::hash::Hash;
+use std::sync::Mutex;
+
+// struct type to represent one GCRA check sent to a store, in nanoseconds
+// stores run the whole check themselves so that backends like Redis can do
+// the read-test-write atomically on their side in one round trip
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GcraCheck {
+    pub now: u64,
+    pub increment: u64,
+    pub tolerance: u64,
+    pub cost: u32,
+}
+
+// methods for the GcraCheck struct
+impl GcraCheck {
+    // method to apply the check to a stored TAT (None for unknown keys)
+    // returns the outcome and the TAT to store if the request conforms
+    pub fn apply(&self, tat: Option<u64>) -> StoreOutcome {
+        let tat = tat.unwrap_or(self.now);
+        match gcra::conform(self.now, tat, self.increment, self.tolerance, self.cost) {
+            Some(new_tat) => StoreOutcome {
+                allowed: true,
+                tat: new_tat,
+            },
+            None => StoreOutcome {
+                allowed: false,
+                tat,
+            },
+        }
+    }
+}
+
+// methods for the GcraCheck struct used when compensating
+impl GcraCheck {
+    // method to compute the TAT after handing back what this check charged
+    // the result never moves behind `now`, so a refund cannot create credit
+    pub fn refund(&self, tat: u64) -> u64 {
+        let charged = self.increment.saturating_mul(self.cost as u64);
+        tat.saturating_sub(charged).max(self.now)
+    }
+}
+
+// struct type to represent the result of a check: the decision and the TAT
+// the key holds afterwards
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StoreOutcome {
+    pub allowed: bool,
+    pub tat: u64,
+}
+
+// trait for backends holding TATs; every operation must be atomic per key
+pub trait StateStore<K> {
+    type Error: Error + Send + Sync + 'static;
+
+    // method to run a check against one key, recording the new TAT if it conforms
+    fn check_and_update(&self, key: &K, check: GcraCheck) -> Result<StoreOutcome, Self::Error>;
+
+    // method to run many checks in one call; networked stores should override
+    // this to pipeline the whole batch in a single round trip
+    fn check_and_update_many(
+        &self,
+        checks: &[(K, GcraCheck)],
+    ) -> Result<Vec<StoreOutcome>, Self::Error> {
+        checks
+            .iter()
+            .map(|(key, check)| self.check_and_update(key, *check))
+            .collect()
+    }
+
+    // method to hand back what an admitted check charged, using
+    // `GcraCheck::refund`; used to compensate partially applied batches
+    fn refund(&self, key: &K, check: GcraCheck) -> Result<(), Self::Error>;
+
+    // method to read the TAT stored for a key
+    fn get(&self, key: &K) -> Result<Option<u64>, Self::Error>;
+
+    // method to forget a key
+    fn remove(&self, key: &K) -> Result<(), Self::Error>;
+}
+
+// async variant of StateStore for backends reached over the network
+#[cfg(feature = "async")]
+#[async_trait]
+pub trait AsyncStateStore<K: Sync>: Send + Sync {
+    type Error: Error + Send + Sync + 'static;
+
+    // method to run a check against one key, recording the new TAT if it conforms
+    async fn check_and_update(
+        &self,
+        key: &K,
+        check: GcraCheck,
+    ) -> Result<StoreOutcome, Self::Error>;
+
+    // method to run many checks in one call; override to pipeline the batch
+    async fn check_and_update_many(
+        &self,
+        checks: &[(K, GcraCheck)],
+    ) -> Result<Vec<StoreOutcome>, Self::Error> {
+        let mut outcomes = Vec::with_capacity(checks.len());
+        for (key, check) in checks {
+            outcomes.push(self.check_and_update(key, *check).await?);
+        }
+        Ok(outcomes)
+    }
+
+    // method to hand back what an admitted check charged
+    async fn refund(&self, key: &K, check: GcraCheck) -> Result<(), Self::Error>;
+
+    // method to read the TAT stored for a key
+    async fn get(&self, key: &K) -> Result<Option<u64>, Self::Error>;
+
+    // method to forget a key
+    async fn remove(&self, key: &K) -> Result<(), Self::Error>;
+}
+
+// struct type to represent an in-process store backed by a DashMap
+#[derive(Debug, Default)]
+pub struct MemoryStore<K>
+where
+    K: Hash + Eq + Clone,
+{
+    state: DashMap<K, u64>,
+}
+
+// methods for the MemoryStore struct
+impl<K> MemoryStore<K>
+where
+    K: Hash + Eq + Clone,
+{
+    pub fn new() -> Self {
+        Self {
+            state: DashMap::new(),
+        }
+    }
+}
+
+impl<K> StateStore<K> for MemoryStore<K>
+where
+    K: Hash + Eq + Clone,
+{
+    type Error = Infallible;
+
+    fn check_and_update(&self, key: &K, check: GcraCheck) -> Result<StoreOutcome, Self::Error> {
+        // hold the entry lock across the read and the write
+        let mut tat = self.state.entry(key.clone()).or_insert(check.now);
+        let outcome = check.apply(Some(*tat));
+        *tat = outcome.tat;
+        Ok(outcome)
+    }
+
+    fn refund(&self, key: &K, check: GcraCheck) -> Result<(), Self::Error> {
+        if let Some(mut tat) = self.state.get_mut(key) {
+            *tat = check.refund(*tat);
+        }
+        Ok(())
+    }
+
+    fn get(&self, key: &K) -> Result<Option<u64>, Self::Error> {
+        Ok(self.state.get(key).map(|entry| *entry.value()))
+    }
+
+    fn remove(&self, key: &K) -> Result<(), Self::Error> {
+        self.state.remove(key);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "async")]
+#[async_trait]
+impl<K> AsyncStateStore<K> for MemoryStore<K>
+where
+    K: Hash + Eq + Clone + Send + Sync,
+{
+    type Error = Infallible;
+
+    async fn check_and_update(
+        &self,
+        key: &K,
+        check: GcraCheck,
+    ) -> Result<StoreOutcome, Self::Error> {
+        StateStore::check_and_update(self, key, check)
+    }
+
+    async fn refund(&self, key: &K, check: GcraCheck) -> Result<(), Self::Error> {
+        StateStore::refund(self, key, check)
+    }
+
+    async fn get(&self, key: &K) -> Result<Option<u64>, Self::Error> {
+        StateStore::get(self, key)
+    }
+
+    async fn remove(&self, key: &K) -> Result<(), Self::Error> {
+        StateStore::remove(self, key)
+    }
+}
+
+// struct type to represent refunds that could not be applied when a
+// multi-key check was compensated; they are retried by `reconcile`
+#[derive(Debug, Default)]
+pub struct CompensationLog<K> {
+    pending: Mutex<Vec<(K, GcraCheck)>>,
+}
+
+// methods for the CompensationLog struct
+impl<K: Clone> CompensationLog<K> {
+    pub fn new() -> Self {
+        Self {
+            pending: Mutex::new(Vec::new()),
+        }
+    }
+
+    // internal method to remember a refund that failed
+    fn push(&self, key: K, check: GcraCheck) {
+        self.pending.lock().unwrap().push((key, check));
+    }
+
+    // method to list the keys still owed a refund
+    pub fn pending(&self) -> Vec<K> {
+        let pending = self.pending.lock().unwrap();
+        pending.iter().map(|(key, _)| key.clone()).collect()
+    }
+
+    // accessor method to return the number of refunds still owed
+    pub fn len(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+
+    // method to check whether every refund has been applied
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // method to retry the owed refunds, keeping the ones that fail again
+    // returns how many are still owed
+    pub fn reconcile<S: StateStore<K>>(&self, store: &S) -> usize {
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|(key, check)| store.refund(key, *check).is_err());
+        pending.len()
+    }
+}
+
+// struct type to represent a rate limiter whose state lives in a StateStore
+#[derive(Debug)]
+pub struct StoreLimiter<S, C = SystemClock>
+where
+    C: Clock,
+{
+    rate_nanos: u64,
+    tolerance_nanos: u64,
+    store: S,
+    clock: C,
+}
+
+// methods for the StoreLimiter struct
+impl<S, C> StoreLimiter<S, C>
+where
+    C: Clock,
+{
+    // method to create a limiter over a store given a rate and burst value
+    pub fn new(
+        rate_per_second: f64,
+        burst_capacity: f64,
+        store: S,
+        clock: C,
+    ) -> Result<Self, RateLimiterError> {
+        Self::with_rounding(
+            rate_per_second,
+            burst_capacity,
+            Rounding::Floor,
+            store,
+            clock,
+        )
+    }
+
+    // method to create a limiter over a store, choosing how the emission
+    // interval is rounded to whole nanoseconds, as RateLimiter::with_rounding
+    pub fn with_rounding(
+        rate_per_second: f64,
+        burst_capacity: f64,
+        rounding: Rounding,
+        store: S,
+        clock: C,
+    ) -> Result<Self, RateLimiterError> {
+        let (rate_nanos, tolerance_nanos) = quota_nanos(rate_per_second, burst_capacity, rounding)?;
+        Ok(Self {
+            rate_nanos,
+            tolerance_nanos,
+            store,
+            clock,
+        })
+    }
+
+    // accessor method to return the underlying store
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    // internal method to build the check for a request arriving now
+    fn check(&self, cost: u32) -> GcraCheck {
+        GcraCheck {
+            now: self.clock.now(),
+            increment: self.rate_nanos,
+            tolerance: self.tolerance_nanos,
+            cost,
+        }
+    }
+
+    // method to check a single key against the store
+    pub fn is_allowed<K>(&self, client_id: &K) -> Result<bool, S::Error>
+    where
+        S: StateStore<K>,
+    {
+        Ok(self
+            .store
+            .check_and_update(client_id, self.check(1))?
+            .allowed)
+    }
+
+    // method to check many keys in one batch, all at the same instant
+    pub fn is_allowed_many<K>(&self, client_ids: &[K]) -> Result<Vec<bool>, S::Error>
+    where
+        S: StateStore<K>,
+        K: Clone,
+    {
+        let check = self.check(1);
+        let checks: Vec<(K, GcraCheck)> = client_ids.iter().map(|id| (id.clone(), check)).collect();
+        let outcomes = self.store.check_and_update_many(&checks)?;
+        Ok(outcomes.iter().map(|outcome| outcome.allowed).collect())
+    }
+
+    // method to admit a request only if every key conforms, charging all or none
+    // stores cannot apply several keys atomically, so keys are charged one at
+    // a time and compensated: on a denial or a store error, the keys already
+    // charged are refunded, and refunds that fail are recorded in `log` for
+    // `CompensationLog::reconcile`; until then those keys stay over-charged,
+    // never under-charged
+    pub fn is_allowed_all<K>(
+        &self,
+        client_ids: &[K],
+        log: &CompensationLog<K>,
+    ) -> Result<bool, S::Error>
+    where
+        S: StateStore<K>,
+        K: Clone,
+    {
+        let check = self.check(1);
+        let mut charged: Vec<&K> = Vec::with_capacity(client_ids.len());
+        let mut result = Ok(true);
+        for client_id in client_ids {
+            match self.store.check_and_update(client_id, check) {
+                Ok(outcome) if outcome.allowed => charged.push(client_id),
+                Ok(_) => {
+                    result = Ok(false);
+                    break;
+                }
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+
+        if !matches!(result, Ok(true)) {
+            for client_id in charged {
+                if self.store.refund(client_id, check).is_err() {
+                    log.push(client_id.clone(), check);
+                }
+            }
+        }
+        result
+    }
+
+    // async version of is_allowed
+    #[cfg(feature = "async")]
+    pub async fn is_allowed_async<K>(&self, client_id: &K) -> Result<bool, S::Error>
+    where
+        S: AsyncStateStore<K>,
+        K: Sync,
+    {
+        let outcome = self
+            .store
+            .check_and_update(client_id, self.check(1))
+            .await?;
+        Ok(outcome.allowed)
+    }
+
+    // async version of is_allowed_many
+    #[cfg(feature = "async")]
+    pub async fn is_allowed_many_async<K>(&self, client_ids: &[K]) -> Result<Vec<bool>, S::Error>
+    where
+        S: AsyncStateStore<K>,
+        K: Clone + Sync,
+    {
+        let check = self.check(1);
+        let checks: Vec<(K, GcraCheck)> = client_ids.iter().map(|id| (id.clone(), check)).collect();
+        let outcomes = self.store.check_and_update_many(&checks).await?;
+        Ok(outcomes.iter().map(|outcome| outcome.allowed).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestClock;
+    use std::time::Duration;
+
+    #[test]
+    fn memory_store_applies_gcra() {
+        let clock = TestClock::new(0.0);
+        let limiter = StoreLimiter::new(1.0, 1.0, MemoryStore::new(), clock.clone()).unwrap();
+
+        assert!(limiter.is_allowed(&"client1").unwrap());
+        assert!(limiter.is_allowed(&"client1").unwrap());
+        assert!(!limiter.is_allowed(&"client1").unwrap());
+
+        clock.advance(1.0);
+        assert!(limiter.is_allowed(&"client1").unwrap());
+        assert_eq!(
+            StateStore::get(limiter.store(), &"client1").unwrap(),
+            Some(3_000_000_000)
+        );
+    }
+
+    #[test]
+    fn rounding_and_validation_match_the_rate_limiter() {
+        let clock = TestClock::new(0.0);
+        let store = MemoryStore::new();
+        let limiter = StoreLimiter::with_rounding(3.0, 0.0, Rounding::Ceil, store, clock.clone());
+        let limiter = limiter.unwrap();
+
+        // a ceiling interval of 333,333,334ns, where Floor would give one less
+        assert!(limiter.is_allowed(&"client1").unwrap());
+        clock.advance_by(Duration::from_nanos(333_333_333));
+        assert!(!limiter.is_allowed(&"client1").unwrap());
+        clock.advance_by(Duration::from_nanos(1));
+        assert!(limiter.is_allowed(&"client1").unwrap());
+
+        let invalid = StoreLimiter::new(0.0, 1.0, MemoryStore::<&str>::new(), clock.clone());
+        assert!(matches!(invalid, Err(RateLimiterError::InvalidRate)));
+        let invalid = StoreLimiter::new(1.0, -1.0, MemoryStore::<&str>::new(), clock);
+        assert!(matches!(invalid, Err(RateLimiterError::InvalidBurst)));
+    }
+
+    #[test]
+    fn batch_checks_share_one_instant() {
+        let clock = TestClock::new(0.0);
+        let limiter = StoreLimiter::new(1.0, 0.0, MemoryStore::new(), clock).unwrap();
+
+        let decisions = limiter.is_allowed_many(&["a", "b", "a", "c", "b"]).unwrap();
+        assert_eq!(decisions, vec![true, true, false, true, false]);
+    }
+
+    #[test]
+    fn removed_keys_start_fresh() {
+        let clock = TestClock::new(0.0);
+        let limiter = StoreLimiter::new(1.0, 0.0, MemoryStore::new(), clock).unwrap();
+
+        assert!(limiter.is_allowed(&"client1").unwrap());
+        StateStore::remove(limiter.store(), &"client1").unwrap();
+        assert!(limiter.is_allowed(&"client1").unwrap());
+    }
+
+    // the memory store never suspends, so polling once completes its futures
+    #[cfg(feature = "async")]
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        use std::task::{Context, Poll, Waker};
+        let mut future = std::pin::pin!(future);
+        let mut cx = Context::from_waker(Waker::noop());
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("memory store future did not complete"),
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn async_variants_match_sync_behavior() {
+        let clock = TestClock::new(0.0);
+        let limiter = StoreLimiter::new(1.0, 0.0, MemoryStore::new(), clock).unwrap();
+
+        assert!(block_on(limiter.is_allowed_async(&"client1")).unwrap());
+        assert!(!block_on(limiter.is_allowed_async(&"client1")).unwrap());
+        assert_eq!(
+            block_on(limiter.is_allowed_many_async(&["client1", "client2"])).unwrap(),
+            vec![false, true]
+        );
+    }
+
+    // store wrapper that fails every call once `fail_after` calls have been made
+    struct FlakyStore {
+        inner: MemoryStore<&'static str>,
+        calls: std::sync::atomic::AtomicUsize,
+        fail_after: usize,
+        healthy: std::sync::atomic::AtomicBool,
+    }
+
+    #[derive(Debug)]
+    struct StoreDown;
+
+    impl std::fmt::Display for StoreDown {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(f, "store down")
+        }
+    }
+
+    impl Error for StoreDown {}
+
+    impl FlakyStore {
+        fn new(fail_after: usize) -> Self {
+            Self {
+                inner: MemoryStore::new(),
+                calls: Default::default(),
+                fail_after,
+                healthy: Default::default(),
+            }
+        }
+
+        fn call(&self) -> Result<(), StoreDown> {
+            use std::sync::atomic::Ordering;
+            let calls = self.calls.fetch_add(1, Ordering::Relaxed);
+            if calls >= self.fail_after && !self.healthy.load(Ordering::Relaxed) {
+                return Err(StoreDown);
+            }
+            Ok(())
+        }
+    }
+
+    impl StateStore<&'static str> for FlakyStore {
+        type Error = StoreDown;
+
+        fn check_and_update(
+            &self,
+            key: &&'static str,
+            check: GcraCheck,
+        ) -> Result<StoreOutcome, StoreDown> {
+            self.call()?;
+            Ok(StateStore::check_and_update(&self.inner, key, check).unwrap())
+        }
+
+        fn refund(&self, key: &&'static str, check: GcraCheck) -> Result<(), StoreDown> {
+            self.call()?;
+            StateStore::refund(&self.inner, key, check).unwrap();
+            Ok(())
+        }
+
+        fn get(&self, key: &&'static str) -> Result<Option<u64>, StoreDown> {
+            Ok(StateStore::get(&self.inner, key).unwrap())
+        }
+
+        fn remove(&self, key: &&'static str) -> Result<(), StoreDown> {
+            StateStore::remove(&self.inner, key).unwrap();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn all_or_nothing_refunds_on_denial() {
+        let clock = TestClock::new(0.0);
+        let limiter = StoreLimiter::new(1.0, 0.0, MemoryStore::new(), clock).unwrap();
+        let log = CompensationLog::new();
+
+        assert!(limiter.is_allowed(&"b").unwrap());
+        assert!(!limiter.is_allowed_all(&["a", "b"], &log).unwrap());
+        assert!(log.is_empty());
+
+        // "a" was refunded, so it still has its slot
+        assert!(limiter.is_allowed(&"a").unwrap());
+    }
+
+    #[test]
+    fn store_failure_mid_batch_is_compensated_and_logged() {
+        let clock = TestClock::new(0.0);
+        // the third call (checking "c") fails, and so does every refund after it
+        let limiter = StoreLimiter::new(1.0, 0.0, FlakyStore::new(2), clock).unwrap();
+        let log = CompensationLog::new();
+
+        assert!(limiter.is_allowed_all(&["a", "b", "c"], &log).is_err());
+        assert_eq!(log.pending(), vec!["a", "b"]);
+        assert_eq!(
+            StateStore::get(limiter.store(), &"a").unwrap(),
+            Some(1_000_000_000)
+        );
+
+        // once the store recovers, reconciliation hands the quota back
+        limiter
+            .store()
+            .healthy
+            .store(true, std::sync::atomic::Ordering::Relaxed);
+        assert_eq!(log.reconcile(limiter.store()), 0);
+        assert_eq!(StateStore::get(limiter.store(), &"a").unwrap(), Some(0));
+        assert!(limiter.is_allowed_all(&["a", "b", "c"], &log).unwrap());
+    }
+}
@@ -0,0 +1,237 @@
+// src/lib/stream.rs
+
+// dependencies
+use crate::clock::{AsyncClock, Clock};
+use crate::rate_limiter::{Denied, RateLimiter};
+use futures_core::Stream;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::future::Future;
+use std::hash::{BuildHasher, Hash};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+// the wait for an item's slot, owning its limiter so the stream stays 'static
+type SlotWait = Pin<Box<dyn Future<Output = Result<(), Denied>> + Send>>;
+
+// the function mapping an item to the key it is charged to
+type KeyFn<I, T> = Box<dyn FnMut(&I) -> T + Send>;
+
+// struct type to represent a stream whose items are let through no faster
+// than the limiter allows; each item is charged once, to the key returned
+// by the key function, before it is yielded
+// an item whose request can never conform is dropped and the stream moves on
+pub struct Throttled<St, T, C, S = RandomState>
+where
+    St: Stream,
+    T: Hash + Eq + Clone,
+    C: Clock,
+    S: BuildHasher + Clone,
+{
+    stream: Pin<Box<St>>,
+    limiter: Arc<RateLimiter<T, C, S>>,
+    key_fn: KeyFn<St::Item, T>,
+    waiting: Option<(St::Item, SlotWait)>,
+}
+
+// methods for the Throttled struct
+impl<St, T, C, S> Throttled<St, T, C, S>
+where
+    St: Stream,
+    T: Hash + Eq + Clone,
+    C: Clock,
+    S: BuildHasher + Clone,
+{
+    // accessor method to return the limiter the stream is charged against
+    pub fn limiter(&self) -> &Arc<RateLimiter<T, C, S>> {
+        &self.limiter
+    }
+
+    // method to unwrap the inner stream; an item waiting for its slot is
+    // dropped uncharged
+    pub fn into_inner(self) -> Pin<Box<St>> {
+        self.stream
+    }
+}
+
+// the wrapped stream is boxed, so the adapter never needs to be pinned itself
+impl<St, T, C, S> Unpin for Throttled<St, T, C, S>
+where
+    St: Stream,
+    T: Hash + Eq + Clone,
+    C: Clock,
+    S: BuildHasher + Clone,
+{
+}
+
+// implement the Stream trait for the Throttled type
+impl<St, T, C, S> Stream for Throttled<St, T, C, S>
+where
+    St: Stream,
+    T: Hash + Eq + Clone + Send + Sync + 'static,
+    C: AsyncClock + Send + Sync + 'static,
+    S: BuildHasher + Clone + Send + Sync + 'static,
+{
+    type Item = St::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<St::Item>> {
+        let this = self.get_mut();
+        loop {
+            if let Some((_, wait)) = &mut this.waiting {
+                let admitted = match wait.as_mut().poll(cx) {
+                    Poll::Ready(result) => result.is_ok(),
+                    Poll::Pending => return Poll::Pending,
+                };
+                let (item, _) = this.waiting.take().unwrap();
+                match admitted {
+                    true => return Poll::Ready(Some(item)),
+                    false => continue,
+                }
+            }
+
+            let item = match this.stream.as_mut().poll_next(cx) {
+                Poll::Ready(Some(item)) => item,
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            };
+
+            // conforming items go straight through without allocating a wait
+            let key = (this.key_fn)(&item);
+            match this.limiter.charge(key.clone(), 1) {
+                Ok(()) => return Poll::Ready(Some(item)),
+                Err(denied) if denied.retry_after().is_none() => continue,
+                Err(_) => {
+                    let limiter = Arc::clone(&this.limiter);
+                    let wait = Box::pin(async move { limiter.until_key_ready(key).await });
+                    this.waiting = Some((item, wait));
+                }
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let waiting = usize::from(self.waiting.is_some());
+        let (_, upper) = self.stream.size_hint();
+        (0, upper.and_then(|upper| upper.checked_add(waiting)))
+    }
+}
+
+// implement the Debug trait for the Throttled type
+impl<St, T, C, S> fmt::Debug for Throttled<St, T, C, S>
+where
+    St: Stream,
+    T: Hash + Eq + Clone,
+    C: Clock,
+    S: BuildHasher + Clone,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Throttled")
+            .field("waiting", &self.waiting.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+// extension trait to throttle any Stream with a RateLimiter
+pub trait RateLimitedStream: Stream + Sized {
+    // method to let items through at the rate of one key, e.g. the quota of
+    // a single downstream API
+    fn rate_limited<T, C, S>(
+        self,
+        limiter: Arc<RateLimiter<T, C, S>>,
+        key: T,
+    ) -> Throttled<Self, T, C, S>
+    where
+        T: Hash + Eq + Clone + Send + 'static,
+        C: Clock,
+        S: BuildHasher + Clone,
+    {
+        self.rate_limited_by(limiter, move |_| key.clone())
+    }
+
+    // method to let items through at the rate of the key each one maps to,
+    // e.g. one quota per tenant; an item waiting for its slot holds back the
+    // items behind it, whatever their key
+    fn rate_limited_by<T, C, S>(
+        self,
+        limiter: Arc<RateLimiter<T, C, S>>,
+        key_fn: impl FnMut(&Self::Item) -> T + Send + 'static,
+    ) -> Throttled<Self, T, C, S>
+    where
+        T: Hash + Eq + Clone,
+        C: Clock,
+        S: BuildHasher + Clone,
+    {
+        Throttled {
+            stream: Box::pin(self),
+            limiter,
+            key_fn: Box::new(key_fn),
+            waiting: None,
+        }
+    }
+}
+
+impl<St: Stream> RateLimitedStream for St {}
+
+#[cfg(all(test, feature = "tokio"))]
+mod tests {
+    use super::*;
+    use crate::TokioClock;
+
+    // a stream over an iterator, since futures-util is not a dependency
+    struct Iter<I>(I);
+
+    impl<I: Iterator + Unpin> Stream for Iter<I> {
+        type Item = I::Item;
+
+        fn poll_next(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Option<I::Item>> {
+            Poll::Ready(self.0.next())
+        }
+    }
+
+    async fn next<St: Stream + Unpin>(stream: &mut St) -> Option<St::Item> {
+        std::future::poll_fn(|cx| Pin::new(&mut *stream).poll_next(cx)).await
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn paces_items_to_the_limiter_rate() {
+        let clock = TokioClock::starting_at(0);
+        let limiter = Arc::new(RateLimiter::new(2.0, 1.0, clock).unwrap());
+        let mut stream = Iter(0..5).rate_limited(Arc::clone(&limiter), "api");
+
+        // the burst of two goes through at once, then one item every 500ms
+        let mut seen = Vec::new();
+        while let Some(item) = next(&mut stream).await {
+            seen.push((item, clock.now() / 1_000_000));
+        }
+        assert_eq!(seen, [(0, 0), (1, 0), (2, 500), (3, 1000), (4, 1500)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn keys_items_by_the_key_function() {
+        let clock = TokioClock::starting_at(0);
+        let limiter = Arc::new(RateLimiter::new(1.0, 0.0, clock).unwrap());
+        let items = ["a", "b", "a", "b"].into_iter();
+        let mut stream = Iter(items).rate_limited_by(Arc::clone(&limiter), |item| *item);
+
+        // each key has its own slot, so only the repeats wait
+        let mut seen = Vec::new();
+        while let Some(item) = next(&mut stream).await {
+            seen.push((item, clock.now() / 1_000_000));
+        }
+        assert_eq!(seen, [("a", 0), ("b", 0), ("a", 1000), ("b", 1000)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn drops_items_that_can_never_conform() {
+        let clock = TokioClock::starting_at(0);
+        let limiter = Arc::new(RateLimiter::new(1.0, 0.0, clock).unwrap());
+        limiter.denylist("blocked");
+        let items = ["ok", "blocked", "other"].into_iter();
+        let mut stream = Iter(items).rate_limited_by(limiter, |item| *item);
+
+        assert_eq!(next(&mut stream).await, Some("ok"));
+        assert_eq!(next(&mut stream).await, Some("other"));
+        assert_eq!(next(&mut stream).await, None);
+    }
+}
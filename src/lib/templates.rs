@@ -0,0 +1,269 @@
+// src/lib/templates.rs
+
+// dependencies
+use std::error::Error;
+use std::fmt;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::Path;
+
+// enum type to represent a value that can be substituted into a template
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Placeholder {
+    RetryAfter, // {retry_after}
+    DecisionId, // {decision_id}
+    KeyHash,    // {key_hash}
+}
+
+// methods for the Placeholder enum
+impl Placeholder {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "retry_after" => Some(Placeholder::RetryAfter),
+            "decision_id" => Some(Placeholder::DecisionId),
+            "key_hash" => Some(Placeholder::KeyHash),
+            _ => None,
+        }
+    }
+}
+
+// enum type to represent one piece of a parsed template
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Text(String),
+    Value(Placeholder),
+}
+
+// enum type to represent an error in a template
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplateError {
+    UnknownPlaceholder(String),
+    Unclosed,
+}
+
+// implement the Display trait for the TemplateError type
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TemplateError::UnknownPlaceholder(name) => {
+                write!(f, "unknown template placeholder: {{{}}}", name)
+            }
+            TemplateError::Unclosed => write!(f, "unclosed `{{` in template"),
+        }
+    }
+}
+
+// implement the Error trait for the TemplateError type
+impl Error for TemplateError {}
+
+// struct type to represent the values available to a template
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TemplateVars {
+    pub retry_after: Option<u64>, // whole seconds, rendered empty when unknown
+    pub decision_id: u64,
+    pub key_hash: u64,
+}
+
+// methods for the TemplateVars struct
+impl TemplateVars {
+    // method to hash a rate-limit key so it can be shown without revealing it
+    pub fn hash_key(key: &str) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+// struct type to represent a response body with `{retry_after}`,
+// `{decision_id}` and `{key_hash}` placeholders; `{{` renders a literal brace
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    segments: Vec<Segment>,
+    content_type: String,
+}
+
+// methods for the Template struct
+impl Template {
+    // method to parse a template, rejecting unknown placeholders up front
+    pub fn new(text: &str, content_type: &str) -> Result<Self, TemplateError> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut rest = text;
+        while let Some(start) = rest.find('{') {
+            literal.push_str(&rest[..start]);
+            rest = &rest[start + 1..];
+            if let Some(escaped) = rest.strip_prefix('{') {
+                literal.push('{');
+                rest = escaped;
+                continue;
+            }
+
+            let end = rest.find('}').ok_or(TemplateError::Unclosed)?;
+            let name = &rest[..end];
+            let placeholder = Placeholder::parse(name)
+                .ok_or_else(|| TemplateError::UnknownPlaceholder(name.to_string()))?;
+            if !literal.is_empty() {
+                segments.push(Segment::Text(std::mem::take(&mut literal)));
+            }
+            segments.push(Segment::Value(placeholder));
+            rest = &rest[end + 1..];
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            segments.push(Segment::Text(literal));
+        }
+
+        Ok(Self {
+            segments,
+            content_type: content_type.to_string(),
+        })
+    }
+
+    // method to load a template from a file, picking the content type from
+    // the extension (`.html` or `.json`, otherwise plain text)
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let path = path.as_ref();
+        let content_type = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("html") | Some("htm") => "text/html; charset=utf-8",
+            Some("json") => "application/json",
+            _ => "text/plain; charset=utf-8",
+        };
+        Ok(Self::new(&std::fs::read_to_string(path)?, content_type)?)
+    }
+
+    // accessor method to return the content type to send the body with
+    pub fn content_type(&self) -> &str {
+        &self.content_type
+    }
+
+    // method to render the template with the given values
+    pub fn render(&self, vars: &TemplateVars) -> String {
+        let mut out = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Text(text) => out.push_str(text),
+                Segment::Value(Placeholder::RetryAfter) => {
+                    if let Some(secs) = vars.retry_after {
+                        out.push_str(&secs.to_string());
+                    }
+                }
+                Segment::Value(Placeholder::DecisionId) => {
+                    out.push_str(&vars.decision_id.to_string())
+                }
+                Segment::Value(Placeholder::KeyHash) => {
+                    out.push_str(&format!("{:016x}", vars.key_hash))
+                }
+            }
+        }
+        out
+    }
+}
+
+// struct type to represent the bodies a server sends for allowed, rate-limited
+// and failed requests
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseTemplates {
+    pub allowed: Template,
+    pub rate_limited: Template,
+    pub error: Template,
+}
+
+// implement the Default trait for the ResponseTemplates type
+impl Default for ResponseTemplates {
+    fn default() -> Self {
+        let plain = |text: &str| Template::new(text, "text/plain").unwrap();
+        Self {
+            allowed: plain("Hello from Rust GCRA rate-limited server!\n"),
+            rate_limited: plain("Rate limit exceeded. Please try again later.\n"),
+            error: plain("Internal server error\n"),
+        }
+    }
+}
+
+// methods for the ResponseTemplates struct
+impl ResponseTemplates {
+    // method to load templates named `200`, `429` and `500` from a directory,
+    // with an `.html`, `.json` or `.txt` extension; missing ones keep the default
+    pub fn load_dir(dir: impl AsRef<Path>) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let dir = dir.as_ref();
+        let mut templates = Self::default();
+        let slots = [
+            ("200", &mut templates.allowed),
+            ("429", &mut templates.rate_limited),
+            ("500", &mut templates.error),
+        ];
+        for (status, slot) in slots {
+            for ext in ["html", "json", "txt"] {
+                let path = dir.join(format!("{}.{}", status, ext));
+                if path.exists() {
+                    *slot = Template::from_file(&path)
+                        .map_err(|e| format!("{}: {}", path.display(), e))?;
+                    break;
+                }
+            }
+        }
+        Ok(templates)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_placeholders() {
+        let template = Template::new(
+            "{{\"retry\": \"{retry_after}\", \"id\": {decision_id}, \"key\": \"{key_hash}\"}",
+            "application/json",
+        )
+        .unwrap();
+        let vars = TemplateVars {
+            retry_after: Some(3),
+            decision_id: 42,
+            key_hash: 0xabc,
+        };
+        assert_eq!(
+            template.render(&vars),
+            "{\"retry\": \"3\", \"id\": 42, \"key\": \"0000000000000abc\"}"
+        );
+    }
+
+    #[test]
+    fn rejects_bad_templates() {
+        assert_eq!(
+            Template::new("wait {retry}", "text/plain"),
+            Err(TemplateError::UnknownPlaceholder(String::from("retry")))
+        );
+        assert_eq!(
+            Template::new("wait {retry_after", "text/plain"),
+            Err(TemplateError::Unclosed)
+        );
+    }
+
+    #[test]
+    fn loads_templates_from_directory() {
+        let dir = std::env::temp_dir().join(format!("gcra-templates-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("429.html"),
+            "<p>Réessayez dans {retry_after} s</p>",
+        )
+        .unwrap();
+
+        let templates = ResponseTemplates::load_dir(&dir).unwrap();
+        assert_eq!(
+            templates.rate_limited.content_type(),
+            "text/html; charset=utf-8"
+        );
+        let vars = TemplateVars {
+            retry_after: Some(2),
+            ..TemplateVars::default()
+        };
+        assert_eq!(
+            templates.rate_limited.render(&vars),
+            "<p>Réessayez dans 2 s</p>"
+        );
+        assert_eq!(templates.allowed, ResponseTemplates::default().allowed);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,175 @@
+// src/lib/view.rs
+
+// dependencies
+use crate::clock::Clock;
+use crate::rate_limiter::RateLimiter;
+use arc_swap::ArcSwap;
+use std::hash::{BuildHasher, Hash};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+// struct type to represent aggregate stats of a limiter at one time
+// `active` keys hold a TAT in the future, `limited` ones would be denied a
+// request right now, and `top_keys` are the most throttled keys with how far
+// their TAT lies ahead, most throttled first
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LimiterStats<T> {
+    pub taken_at: u64,
+    pub keys: usize,
+    pub active: usize,
+    pub limited: usize,
+    pub top_keys: Vec<(T, Duration)>,
+}
+
+// methods for the LimiterStats struct
+impl<T> LimiterStats<T>
+where
+    T: Hash + Eq + Clone,
+{
+    // method to scan a limiter once, keeping the `top_n` most throttled keys
+    // the candidates are trimmed whenever they reach twice `top_n`, so the
+    // scan costs O(keys) time and O(top_n) memory
+    pub fn capture<C, S>(limiter: &RateLimiter<T, C, S>, top_n: usize) -> Self
+    where
+        C: Clock,
+        S: BuildHasher + Clone,
+    {
+        let now = limiter.now();
+        let tolerance = limiter.tolerance_nanos();
+        let (mut keys, mut active, mut limited) = (0, 0, 0);
+        let mut top: Vec<(T, u64)> = Vec::new();
+        let trim = |top: &mut Vec<(T, u64)>| {
+            if top.len() > top_n {
+                top.select_nth_unstable_by_key(top_n, |(_, ahead)| u64::MAX - ahead);
+                top.truncate(top_n);
+            }
+        };
+        for (key, tat) in limiter.iter() {
+            keys += 1;
+            let ahead = tat.saturating_sub(now);
+            if ahead == 0 {
+                continue;
+            }
+            active += 1;
+            if ahead > tolerance {
+                limited += 1;
+            }
+            if top_n > 0 {
+                top.push((key, ahead));
+                if top.len() >= top_n * 2 {
+                    trim(&mut top);
+                }
+            }
+        }
+        trim(&mut top);
+        top.sort_unstable_by_key(|(_, ahead)| u64::MAX - ahead);
+        Self {
+            taken_at: now,
+            keys,
+            active,
+            limited,
+            top_keys: top
+                .into_iter()
+                .map(|(key, ahead)| (key, Duration::from_nanos(ahead)))
+                .collect(),
+        }
+    }
+}
+
+// struct type to represent a read-mostly view of a limiter for dashboards
+// a background thread captures fresh stats every interval and swaps them in
+// atomically; readers load the latest stats without taking a lock or
+// touching the limiter's map, and clones share the same stats. The thread
+// stops within one interval of the last clone being dropped
+#[derive(Debug, Clone)]
+pub struct SnapshotView<T> {
+    stats: Arc<ArcSwap<LimiterStats<T>>>,
+}
+
+// methods for the SnapshotView struct
+impl<T> SnapshotView<T>
+where
+    T: Hash + Eq + Clone + Send + Sync + 'static,
+{
+    // method to capture stats now and then every `interval` on a thread
+    pub fn spawn<C, S>(limiter: Arc<RateLimiter<T, C, S>>, interval: Duration, top_n: usize) -> Self
+    where
+        C: Clock + Send + Sync + 'static,
+        S: BuildHasher + Clone + Send + Sync + 'static,
+    {
+        let stats = Arc::new(ArcSwap::from_pointee(LimiterStats::capture(
+            &limiter, top_n,
+        )));
+        let weak = Arc::downgrade(&stats);
+        thread::spawn(move || {
+            loop {
+                thread::sleep(interval);
+                let Some(stats) = weak.upgrade() else {
+                    break;
+                };
+                stats.store(Arc::new(LimiterStats::capture(&limiter, top_n)));
+            }
+        });
+        Self { stats }
+    }
+}
+
+impl<T> SnapshotView<T> {
+    // method to return the latest stats; holding on to them never blocks
+    // the next refresh
+    pub fn load(&self) -> Arc<LimiterStats<T>> {
+        self.stats.load_full()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestClock;
+
+    #[test]
+    fn captures_aggregates_and_most_throttled_keys() {
+        let clock = TestClock::new(0.0);
+        let limiter = RateLimiter::new(1.0, 1.0, clock.clone()).unwrap();
+        for key in 0..10u32 {
+            // key k is charged k times; the burst admits two
+            for _ in 0..key {
+                let _ = limiter.is_allowed(key);
+            }
+        }
+        clock.advance(0.5);
+
+        let stats = LimiterStats::capture(&limiter, 3);
+        assert_eq!(stats.taken_at, 500_000_000);
+        assert_eq!(stats.keys, 9);
+        assert_eq!(stats.active, 9);
+        // two charges leave 1.5s ahead, past the 1s tolerance
+        assert_eq!(stats.limited, 8);
+        assert_eq!(stats.top_keys.len(), 3);
+        assert!(
+            stats
+                .top_keys
+                .iter()
+                .all(|(_, ahead)| *ahead == Duration::from_millis(1500))
+        );
+        assert_eq!(LimiterStats::capture(&limiter, 0).top_keys, []);
+    }
+
+    #[test]
+    fn refreshes_on_an_interval() {
+        let clock = TestClock::new(0.0);
+        let limiter = Arc::new(RateLimiter::new(1.0, 0.0, clock.clone()).unwrap());
+        let view = RateLimiter::snapshot_view(Arc::clone(&limiter), Duration::from_millis(5), 1);
+        let reader = view.clone();
+        assert_eq!(reader.load().keys, 0);
+
+        limiter.is_allowed("k").unwrap();
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while reader.load().keys == 0 {
+            assert!(std::time::Instant::now() < deadline, "view never refreshed");
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(view.load().top_keys, [("k", Duration::from_secs(1))]);
+    }
+}